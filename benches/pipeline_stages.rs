@@ -0,0 +1,119 @@
+// Throughput of each enhancement stage on a 1920x1280 frame.
+// `cargo bench --bench pipeline_stages`; compare against a saved baseline with
+// `-- --save-baseline main` and `-- --baseline main`.
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use image::{DynamicImage, Rgb, RgbImage};
+
+use f_ai_backend::backend::image_processor::{
+    denoise::denoise,
+    edit_recipe::EditRecipe,
+    histogram::analyze_histogram,
+    image_utils::{detect_edges, detect_quality_issues},
+    memory::{to_work_image, JobMemory},
+    processor::{
+        apply_smart_sharpening, enhance_architectural_details, grade, ContentType, ImageEnhancementConfig,
+        PROCESSING_VERSION,
+    },
+};
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1280;
+
+// Same position hash as the unit tests' fixtures, so every run sees the same pixels
+fn grain(x: u32, y: u32, range: u32) -> u32 {
+    (x * 7919 + y * 104729) % range
+}
+
+// Walls, window frames and sensor noise, so the edge and threshold paths all run
+fn room() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let window = (600..1300).contains(&x) && (200..700).contains(&y);
+        let base: u8 = if window { 235 } else if x % 240 < 6 { 60 } else { 170 };
+        let noise = grain(x, y, 11) as u8;
+        Rgb([base.saturating_add(noise), base.saturating_sub(noise), (base / 2).saturating_add(noise)])
+    }))
+}
+
+fn recipe() -> EditRecipe {
+    EditRecipe {
+        content_type: ContentType::LivingRoom,
+        preset: "bench".to_string(),
+        preset_version: 1,
+        processing_version: PROCESSING_VERSION.to_string(),
+        enhancement: ImageEnhancementConfig {
+            contrast_boost: 1.1,
+            color_enhancement_strength: 1.05,
+            shadow_recovery: 0.3,
+            highlight_protection: 0.4,
+            sharpening_threshold: 8.0,
+            brightness_adjustment: 0.0,
+            window_recovery_strength: 1.0,
+            white_balance_temp: 0.0,
+            exterior_sky_enhancement: 1.0,
+        },
+        lens: None,
+        perspective: None,
+        crop: None,
+        twilight_grade: false,
+        denoise: 0.4,
+        sharpen: true,
+        listing_look: None,
+        sky_replacement: None,
+        trace_svg: false,
+    }
+}
+
+fn banded_stages(c: &mut Criterion) {
+    let img = room();
+    let recipe = recipe();
+    let memory = JobMemory::unbounded();
+    let mut group = c.benchmark_group("banded");
+    group.throughput(Throughput::Elements(WIDTH as u64 * HEIGHT as u64));
+
+    group.bench_function("to_work_image", |b| b.iter(|| to_work_image(black_box(&img), &memory).unwrap()));
+    group.bench_function("grade", |b| {
+        b.iter_batched_ref(
+            || to_work_image(&img, &memory).unwrap(),
+            |work| grade(work, &recipe),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("denoise", |b| {
+        b.iter_batched_ref(
+            || to_work_image(&img, &memory).unwrap(),
+            |work| denoise(work, recipe.denoise, recipe.enhancement.shadow_recovery, &memory).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("architectural_details", |b| {
+        b.iter_batched_ref(
+            || to_work_image(&img, &memory).unwrap(),
+            |work| enhance_architectural_details(work, &memory).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("sharpening", |b| {
+        b.iter_batched_ref(
+            || to_work_image(&img, &memory).unwrap(),
+            |work| apply_smart_sharpening(work, &recipe.enhancement, &memory).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn analysis_stages(c: &mut Criterion) {
+    let img = room();
+    let mut group = c.benchmark_group("analysis");
+    group.throughput(Throughput::Elements(WIDTH as u64 * HEIGHT as u64));
+
+    group.bench_function("histogram", |b| b.iter(|| analyze_histogram(black_box(&img))));
+    group.bench_function("edges", |b| b.iter(|| detect_edges(black_box(&img))));
+    group.sample_size(10);
+    group.bench_function("quality", |b| b.iter(|| detect_quality_issues(black_box(&img))));
+    group.finish();
+}
+
+criterion_group!(benches, banded_stages, analysis_stages);
+criterion_main!(benches);
@@ -0,0 +1,191 @@
+use axum::{
+    extract::{State, Path, Query, Multipart},
+    Extension,
+    Json,
+    http::{StatusCode, header},
+    response::IntoResponse,
+    Router,
+    routing::{get, post},
+};
+use std::sync::Arc;
+use tracing::{info, instrument};
+use serde::Deserialize;
+use crate::backend::{
+    common::{
+        error::error::{Result, AppError},
+        types::id_types::{DocumentId, ListingId},
+    },
+    f_ai_core::state::AppState,
+    f_ai_database::document_model::{AssignedAgent, DocumentAccess, DocumentModel, DocumentRecord},
+    image_processor::{processor::ContentType, redaction::RedactionRegion},
+    key_logic_auth::auth::Caller,
+};
+
+// Title deeds and contracts. Owner and assigned agents only; every call is audited.
+pub fn document_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/:listing_id", get(list_documents).post(upload_document))
+        .route("/:listing_id/access-log", get(get_document_access_log))
+        .route("/:listing_id/agents", get(list_document_agents))
+        .route("/:listing_id/agents/:agent_id", post(assign_document_agent).delete(remove_document_agent))
+        .route("/:listing_id/:document_id", get(download_document))
+        .route("/:listing_id/:document_id/redactions", post(redact_document))
+}
+
+// The routes are only mounted with a document service, so this is a backstop
+fn documents(state: &AppState) -> Result<&DocumentModel> {
+    state.document_service.as_deref().ok_or_else(|| {
+        AppError::Configuration("Documents are disabled; set documents.encryption_key".into())
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DocumentUploadQuery {
+    pub content_type: ContentType,
+}
+
+#[instrument(skip(state, caller, multipart))]
+#[axum::debug_handler]
+pub async fn upload_document(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(listing_id): Path<String>,
+    Query(query): Query<DocumentUploadQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<DocumentRecord>> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    let field = multipart.next_field().await?
+        .ok_or_else(|| AppError::Validation("No file provided".into()))?;
+    let filename = field.file_name()
+        .ok_or_else(|| AppError::Validation("No filename provided".into()))?
+        .to_string();
+    let data = field.bytes().await?;
+
+    info!(listing_id = %listing_id, content_type = ?query.content_type, "Starting document upload");
+    let record = documents(&state)?
+        .upload(&caller.user_id, &listing_id, query.content_type, &filename, &data)
+        .await?;
+    Ok(Json(record))
+}
+
+#[instrument(skip(state, caller))]
+#[axum::debug_handler]
+pub async fn list_documents(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(listing_id): Path<String>,
+) -> Result<Json<Vec<DocumentRecord>>> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    let documents = documents(&state)?.list(&caller.user_id, &listing_id).await?;
+    Ok(Json(documents))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DocumentDownloadQuery {
+    // Unredacted file; owner only
+    #[serde(default)]
+    pub original: bool,
+}
+
+#[instrument(skip(state, caller))]
+#[axum::debug_handler]
+pub async fn download_document(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path((listing_id, document_id)): Path<(String, String)>,
+    Query(query): Query<DocumentDownloadQuery>,
+) -> Result<impl IntoResponse> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    let document_id = DocumentId::from_string(document_id)?;
+    let file = documents(&state)?
+        .download(&caller.user_id, &listing_id, &document_id, query.original)
+        .await?;
+
+    // Decrypted on the way out, so nothing along the way may keep a copy
+    let disposition = format!("attachment; filename=\"{}\"", file.filename.replace('"', ""));
+    Ok((
+        [
+            (header::CONTENT_TYPE, file.mime_type),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        file.data,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RedactionRequest {
+    // The full set; regions left out are un-redacted
+    pub regions: Vec<RedactionRegion>,
+}
+
+#[instrument(skip(state, caller, request))]
+#[axum::debug_handler]
+pub async fn redact_document(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path((listing_id, document_id)): Path<(String, String)>,
+    Json(request): Json<RedactionRequest>,
+) -> Result<Json<DocumentRecord>> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    let document_id = DocumentId::from_string(document_id)?;
+    let record = documents(&state)?
+        .redact(&caller.user_id, &listing_id, &document_id, request.regions)
+        .await?;
+    Ok(Json(record))
+}
+
+#[instrument(skip(state, caller))]
+#[axum::debug_handler]
+pub async fn list_document_agents(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(listing_id): Path<String>,
+) -> Result<Json<Vec<AssignedAgent>>> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    let agents = documents(&state)?.list_agents(&caller.user_id, &listing_id).await?;
+    Ok(Json(agents))
+}
+
+#[instrument(skip(state, caller))]
+#[axum::debug_handler]
+pub async fn assign_document_agent(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path((listing_id, agent_id)): Path<(String, String)>,
+) -> Result<StatusCode> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    documents(&state)?.assign_agent(&caller.user_id, &listing_id, &agent_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(skip(state, caller))]
+#[axum::debug_handler]
+pub async fn remove_document_agent(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path((listing_id, agent_id)): Path<(String, String)>,
+) -> Result<StatusCode> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    documents(&state)?.remove_agent(&caller.user_id, &listing_id, &agent_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessLogQuery {
+    pub limit: Option<usize>,
+}
+
+#[instrument(skip(state, caller))]
+#[axum::debug_handler]
+pub async fn get_document_access_log(
+    State(state): State<Arc<AppState>>,
+    Extension(caller): Extension<Caller>,
+    Path(listing_id): Path<String>,
+    Query(query): Query<AccessLogQuery>,
+) -> Result<Json<Vec<DocumentAccess>>> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    let limit = query.limit.unwrap_or(100).min(1000);
+    let log = documents(&state)?.access_log(&caller.user_id, &listing_id, limit).await?;
+    Ok(Json(log))
+}
@@ -3,7 +3,7 @@ use axum::{
     Json,
     http::StatusCode,
     Router,
-    routing::{get, post, delete, patch},
+    routing::{get, post, put, delete, patch},
 };
 use std::sync::Arc;
 use tracing::{info, instrument};
@@ -18,24 +18,42 @@ use crate::backend::{
                 Image,
                 ImageUploadOptions,
                 ImageSearchQuery, 
-                ImageSearchResponse
+                ImageSearchResponse,
+                ImageUploadResponse,
             },
             batch_types::{BatchProcessingStatus, BatchStatus},
-            id_types::{BatchId, ImageId},
+            id_types::{BatchId, ImageId, ListingId},
         },
         validation::image_validation::validate_image,
     },
+    f_ai_database::image_model::{ContentReview, CrossListingMatch, RecipeSource, StoredImage},
+    image_processor::{
+        processor::{ContentType, TimeOfDay, UploadFile, UploadOutcome},
+        dimension_policy::DimensionPolicy,
+        exposure_fusion::{MIN_BRACKET_FRAMES, MAX_BRACKET_FRAMES},
+        edit_recipe::{EditRecipe, RecipeRevision},
+        content_classifier::ContentClassification,
+        sky_replacement::SKY_LIBRARY,
+        gallery_order::{self, GalleryOrder, GalleryOverride},
+    },
 };
 use bytes::Bytes;
+use serde::{Deserialize, Serialize};
 
 struct ValidatedFile {
     filename: String,
     data: Bytes,
 }
 
-async fn extract_and_validate_image(multipart: &mut Multipart) -> Result<ValidatedFile> {
-    let field = multipart.next_field().await?
-        .ok_or_else(|| AppError::Validation("No file provided".into()))?;
+async fn extract_and_validate_image(
+    multipart: &mut Multipart,
+    policy: &DimensionPolicy,
+    content_type: Option<ContentType>,
+) -> Result<Option<ValidatedFile>> {
+    // A file that fails validation fails the request, so the rule it broke reaches the uploader
+    let Some(field) = multipart.next_field().await? else {
+        return Ok(None);
+    };
     
     let filename = field.file_name()
         .ok_or_else(|| AppError::Validation("No filename provided".into()))?
@@ -44,17 +62,23 @@ async fn extract_and_validate_image(multipart: &mut Multipart) -> Result<Validat
     let data = field.bytes().await?;
 
     // Validate the image
-    validate_image(&data, &filename)?;
+    validate_image(&data, &filename, policy, content_type)?;
 
-    Ok(ValidatedFile { filename, data })
+    Ok(Some(ValidatedFile { filename, data }))
 }
 
 pub fn image_routes() -> Router<Arc<AppState>> {
     Router::new()
         // Main image operations
         .route("/upload/:listing_id", post(process_image_upload))
+        .route("/upload/:listing_id/bracket", post(process_bracket_upload))
         .route("/search", get(search_images_by_criteria))
+        .route("/skies", get(list_sky_options))
+        .route("/:listing_id/gallery", get(get_gallery_order).put(set_gallery_override))
         .route("/:listing_id/:image_id", delete(delete_image_record))
+        .route("/:listing_id/:image_id/recipe", get(get_image_recipe_history).patch(update_image_recipe))
+        .route("/:listing_id/:image_id/recipe/revert", post(revert_image_recipe))
+        .route("/:listing_id/:image_id/content-type", post(set_image_content_type))
         
         // Batch status operations
         .route("/batch/:batch_id", get(get_batch_processing_status))
@@ -65,6 +89,10 @@ pub fn image_routes() -> Router<Arc<AppState>> {
         .route("/transform/:listing_id/:image_id", get(transform_image_with_options))
         .route("/optimize/:listing_id/:image_id", post(optimize_image_with_options))
         .route("/metadata/:listing_id/:image_id", patch(update_image_metadata))
+
+        // Moderation
+        .route("/moderation/reused", get(get_reused_photo_report))
+        .route("/moderation/content-types", get(get_content_review_queue))
 }
 
 #[instrument(skip(state))]
@@ -74,25 +102,278 @@ pub async fn process_image_upload(
     Path(listing_id): Path<String>,
     Query(options): Query<ImageUploadOptions>,
     mut multipart: Multipart,
-) -> Result<Json<BatchProcessingStatus>> {
+) -> Result<Json<ImageUploadResponse>> {
     let trace_id = uuid7::uuid7();
     info!(trace_id = %trace_id, listing_id = %listing_id, "Starting image upload");
+    let listing_id = ListingId::from_string(listing_id)?;
 
     let mut files = Vec::new();
-    while let Some(validated_file) = extract_and_validate_image(&mut multipart).await.ok() {
-        files.push((validated_file.filename, validated_file.data));
+    let policy = state.image_processor.dimension_policy();
+    while let Some(validated_file) = extract_and_validate_image(&mut multipart, policy, options.content_type).await? {
+        files.push(UploadFile {
+            filename: validated_file.filename,
+            data: validated_file.data.to_vec(),
+            content_type: options.content_type,
+        });
     }
 
     if files.is_empty() {
         return Err(AppError::Validation("No valid files provided".into()));
     }
 
-    let batch_id = state.image_service
-        .process_batch_upload(listing_id, files, options)
+    let response = state.process_upload(&listing_id, files, options.agency_id.clone()).await?;
+    Ok(Json(response))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BracketUploadQuery {
+    // Omit to have the fused image classified
+    pub content_type: Option<ContentType>,
+    pub agency_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BracketUploadResponse {
+    Stored(StoredImage),
+    // Fused into something that looks like paperwork; private until reviewed
+    Held { image_id: ImageId, classification: ContentClassification },
+}
+
+// One multipart request per bracket group; every file is a frame of the same shot
+#[instrument(skip(state, multipart))]
+#[axum::debug_handler]
+pub async fn process_bracket_upload(
+    State(state): State<Arc<AppState>>,
+    Path(listing_id): Path<String>,
+    Query(query): Query<BracketUploadQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<BracketUploadResponse>> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    info!(listing_id = %listing_id.as_str(), "Starting bracket upload");
+
+    let mut frames = Vec::new();
+    let policy = state.image_processor.dimension_policy();
+    while let Some(validated_file) = extract_and_validate_image(&mut multipart, policy, query.content_type).await? {
+        frames.push(validated_file.data.to_vec());
+    }
+
+    if !(MIN_BRACKET_FRAMES..=MAX_BRACKET_FRAMES).contains(&frames.len()) {
+        return Err(AppError::Validation(format!(
+            "Bracket uploads need {}-{} frames, got {}",
+            MIN_BRACKET_FRAMES, MAX_BRACKET_FRAMES, frames.len()
+        )));
+    }
+
+    // Sunset time for frames without GPS of their own
+    let pin = state.listing_service.get_gps_pin(&listing_id).await?;
+    let outcome = state.image_processor
+        .process_bracket_group(&listing_id, frames, query.content_type, query.agency_id.as_deref(), pin.as_ref())
         .await?;
+    // The group is a single photo, so its rejection is the request's
+    match outcome {
+        UploadOutcome::Processed(processed) => {
+            let stored = state.image_service.store_processed(&processed).await?;
+            Ok(Json(BracketUploadResponse::Stored(stored)))
+        }
+        UploadOutcome::Rejected(report) => Err(AppError::QualityRejected(report)),
+        UploadOutcome::HeldForReview(held) => {
+            state.image_service.hold_for_review(&held).await?;
+            Ok(Json(BracketUploadResponse::Held { image_id: held.id, classification: held.classification }))
+        }
+    }
+}
 
-    let status = state.image_service.get_batch_status(&batch_id).await?;
-    Ok(Json(status))
+#[derive(Debug, Deserialize)]
+pub struct RecipePatch {
+    // JSON merge patch applied to the current recipe
+    pub changes: serde_json::Value,
+    pub note: Option<String>,
+    pub agency_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecipeRevert {
+    pub version: u32,
+    pub agency_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecipeUpdateResponse {
+    pub revision: RecipeRevision,
+    pub image: StoredImage,
+}
+
+// Newest revision first; the first entry is the recipe currently rendered
+#[instrument(skip(state))]
+#[axum::debug_handler]
+pub async fn get_image_recipe_history(
+    State(state): State<Arc<AppState>>,
+    Path((listing_id, image_id)): Path<(String, String)>,
+) -> Result<Json<Vec<RecipeRevision>>> {
+    let image_id = ImageId::from_string(image_id)?;
+    recipe_source(&state, &listing_id, &image_id).await?;
+
+    let history = state.image_service.get_recipe_history(&image_id).await?;
+    Ok(Json(history))
+}
+
+#[instrument(skip(state, patch))]
+#[axum::debug_handler]
+pub async fn update_image_recipe(
+    State(state): State<Arc<AppState>>,
+    Path((listing_id, image_id)): Path<(String, String)>,
+    Json(patch): Json<RecipePatch>,
+) -> Result<Json<RecipeUpdateResponse>> {
+    let image_id = ImageId::from_string(image_id)?;
+    let source = recipe_source(&state, &listing_id, &image_id).await?;
+    let recipe = source.recipe.merge_patch(&patch.changes)?;
+
+    rerender_with_recipe(&state, image_id, source, recipe, patch.note, None, patch.agency_id).await
+}
+
+// Re-renders an earlier recipe as a new revision, so history is never rewritten
+#[instrument(skip(state))]
+#[axum::debug_handler]
+pub async fn revert_image_recipe(
+    State(state): State<Arc<AppState>>,
+    Path((listing_id, image_id)): Path<(String, String)>,
+    Json(revert): Json<RecipeRevert>,
+) -> Result<Json<RecipeUpdateResponse>> {
+    let image_id = ImageId::from_string(image_id)?;
+    let source = recipe_source(&state, &listing_id, &image_id).await?;
+    let earlier = state.image_service.get_recipe_revision(&image_id, revert.version).await?;
+
+    let note = Some(format!("Reverted to version {}", revert.version));
+    rerender_with_recipe(&state, image_id, source, earlier.recipe, note, Some(revert.version), revert.agency_id).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentTypeUpdate {
+    pub content_type: ContentType,
+    pub agency_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContentTypeUpdateResponse {
+    pub classification: ContentClassification,
+    // Present when the type changed and the image was re-rendered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerender: Option<RecipeUpdateResponse>,
+}
+
+// Confirms or corrects the content type, usually from the review queue. A new type
+// means a different preset, so the image is re-rendered as a new recipe revision.
+#[instrument(skip(state))]
+#[axum::debug_handler]
+pub async fn set_image_content_type(
+    State(state): State<Arc<AppState>>,
+    Path((listing_id, image_id)): Path<(String, String)>,
+    Json(update): Json<ContentTypeUpdate>,
+) -> Result<Json<ContentTypeUpdateResponse>> {
+    let image_id = ImageId::from_string(image_id)?;
+    let source = recipe_source(&state, &listing_id, &image_id).await?;
+    let classification = ContentClassification::from_reviewer(update.content_type);
+
+    // A held document confirmed as some other paperwork stays held; there is nothing to render
+    let still_document = source.recipe.content_type.is_document() && update.content_type.is_document();
+    let rerender = if source.recipe.content_type != update.content_type && !still_document {
+        let listing_id = ListingId::from_string(source.listing_id.clone())?;
+        let original: Vec<u8> = state.image_service.download_original(&source).await?;
+        let pin = state.listing_service.get_gps_pin(&listing_id).await?;
+        let processed = state.image_processor
+            .reclassify(&listing_id, image_id.clone(), &original, update.content_type, update.agency_id.as_deref(), pin.as_ref())
+            .await?;
+        let note = Some(format!("Content type changed to {:?}", update.content_type));
+        let (revision, image) = state.image_service
+            .store_rerender(&processed, source.recipe_version + 1, note, None)
+            .await?;
+        Some(RecipeUpdateResponse { revision, image })
+    } else {
+        None
+    };
+
+    state.image_service.resolve_content_review(&image_id, &classification).await?;
+    Ok(Json(ContentTypeUpdateResponse { classification, rerender }))
+}
+
+// Proposed cover and photo order with the reasons for each place, after the
+// listing's override if it has one
+#[instrument(skip(state))]
+#[axum::debug_handler]
+pub async fn get_gallery_order(
+    State(state): State<Arc<AppState>>,
+    Path(listing_id): Path<String>,
+) -> Result<Json<GalleryOrder>> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    let candidates = state.image_service.get_gallery_candidates(&listing_id).await?;
+    let choice = state.listing_service.get_gallery_override(&listing_id).await?.unwrap_or_default();
+
+    Ok(Json(gallery_order::apply_override(gallery_order::propose(&candidates), &choice)))
+}
+
+// Replaces the listing's override; an empty body goes back to the proposed order
+#[instrument(skip(state))]
+#[axum::debug_handler]
+pub async fn set_gallery_override(
+    State(state): State<Arc<AppState>>,
+    Path(listing_id): Path<String>,
+    Json(choice): Json<GalleryOverride>,
+) -> Result<Json<GalleryOrder>> {
+    let listing_id = ListingId::from_string(listing_id)?;
+    let candidates = state.image_service.get_gallery_candidates(&listing_id).await?;
+    choice.validate(&candidates)?;
+
+    state.listing_service.update_gallery_override(&listing_id, choice.clone()).await?;
+    Ok(Json(gallery_order::apply_override(gallery_order::propose(&candidates), &choice)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkyOption {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub time_of_day: TimeOfDay,
+}
+
+// Skies an editor can put in a recipe's `sky_replacement`
+#[instrument]
+#[axum::debug_handler]
+pub async fn list_sky_options() -> Json<Vec<SkyOption>> {
+    let skies = SKY_LIBRARY
+        .iter()
+        .map(|sky| SkyOption { id: sky.id, name: sky.name, time_of_day: sky.time_of_day })
+        .collect();
+    Json(skies)
+}
+
+async fn recipe_source(state: &AppState, listing_id: &str, image_id: &ImageId) -> Result<RecipeSource> {
+    let source = state.image_service.get_recipe_source(image_id).await?;
+    if source.listing_id != listing_id {
+        return Err(AppError::NotFound(format!("Image {} not found in listing {}", image_id, listing_id)));
+    }
+    Ok(source)
+}
+
+async fn rerender_with_recipe(
+    state: &AppState,
+    image_id: ImageId,
+    source: RecipeSource,
+    recipe: EditRecipe,
+    note: Option<String>,
+    reverted_from: Option<u32>,
+    agency_id: Option<String>,
+) -> Result<Json<RecipeUpdateResponse>> {
+    let listing_id = ListingId::from_string(source.listing_id.clone())?;
+    let original: Vec<u8> = state.image_service.download_original(&source).await?;
+
+    let processed = state.image_processor
+        .rerender(&listing_id, image_id, &original, recipe, agency_id.as_deref())
+        .await?;
+    let (revision, image) = state.image_service
+        .store_rerender(&processed, source.recipe_version + 1, note, reverted_from)
+        .await?;
+
+    Ok(Json(RecipeUpdateResponse { revision, image }))
 }
 
 #[instrument(skip(state))]
@@ -105,6 +386,40 @@ pub async fn search_images_by_criteria(
     Ok(Json(results))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ReusedPhotoQuery {
+    pub limit: Option<usize>,
+}
+
+// Photos that also appear in another listing, newest first
+#[instrument(skip(state))]
+#[axum::debug_handler]
+pub async fn get_reused_photo_report(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReusedPhotoQuery>,
+) -> Result<Json<Vec<CrossListingMatch>>> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+    let matches = state.image_service.get_cross_listing_matches(limit).await?;
+    Ok(Json(matches))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentReviewQuery {
+    pub limit: Option<usize>,
+}
+
+// Untagged uploads the classifier wasn't sure about, oldest first
+#[instrument(skip(state))]
+#[axum::debug_handler]
+pub async fn get_content_review_queue(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ContentReviewQuery>,
+) -> Result<Json<Vec<ContentReview>>> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+    let reviews = state.image_service.get_pending_content_reviews(limit).await?;
+    Ok(Json(reviews))
+}
+
 #[instrument(skip(state))]
 #[axum::debug_handler]
 pub async fn get_batch_processing_status(
@@ -1,3 +1,4 @@
+pub mod document;
 pub mod health;
 pub mod image;
 pub mod key;
@@ -3,10 +3,10 @@ use std::sync::Arc;
 use crate::backend::f_ai_core::state::AppState;
 use crate::backend::key_logic_auth::{auth::RequireAuth, rate_limit::RateLimit};
 
-use super::{health, image, key, listing, metrics, search};
+use super::{document, health, image, key, listing, metrics, search};
 
 pub fn create_router(state: Arc<AppState>) -> Router {
-    Router::new()
+    let router = Router::new()
         .route("/health", get(health::check_health))
         .route("/ready", get(health::check_readiness))
         .route("/live", get(health::check_liveness))
@@ -21,7 +21,15 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         .route("/keys/:id/validate", get(key::validate_key))
         .route("/search/images", get(search::search_images))
         .route("/search/embedding", post(search::search_by_embedding))
-        .route("/metrics", get(metrics::serve_metrics))
+        .route("/metrics", get(metrics::serve_metrics));
+    // Only with an encryption key configured; see documents.encryption_key
+    let router = if state.document_service.is_some() {
+        router.nest("/documents", document::document_routes())
+    } else {
+        router
+    };
+
+    router
         .layer(RequireAuth::new())
         .layer(RateLimit::new("api", 100, 60))
         .with_state(state)
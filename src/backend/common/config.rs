@@ -1,8 +1,11 @@
 use config::{Config as ConfigBuilder, Environment, File};
 use dotenv::dotenv;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 use crate::backend::common::error::error::{Result, AppError};
+use crate::backend::image_processor::processor::ContentType;
+use crate::backend::image_processor::panorama::TILE_SIZE;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -10,6 +13,20 @@ pub struct Config {
     pub storage: StorageConfig,
     pub openai: OpenAIConfig,
     pub email: EmailConfig,
+    #[serde(default)]
+    pub watermark: WatermarkConfig,
+    #[serde(default)]
+    pub metadata_policy: MetadataPolicyConfig,
+    #[serde(default)]
+    pub presets: PresetConfig,
+    #[serde(default)]
+    pub quality_gate: QualityGateConfig,
+    #[serde(default)]
+    pub documents: DocumentConfig,
+    #[serde(default)]
+    pub image_memory: ImageMemoryConfig,
+    #[serde(default)]
+    pub dimension_policy: DimensionPolicyConfig,
 }
 
 impl Config {
@@ -130,4 +147,472 @@ pub enum SmtpEncryption {
     None,
     Ssl,
     StartTls,
-} 
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatermarkConfig {
+    pub enabled: bool,
+    pub default: WatermarkStyle,
+    #[serde(default)]
+    pub agencies: HashMap<String, WatermarkStyle>,
+    // Panorama tiles can't carry a mark (it would repeat on every tile), so while
+    // watermarking is on, only pyramid levels up to this cube face size are published
+    #[serde(default = "default_panorama_tile_max_face")]
+    pub panorama_tile_max_face: u32,
+}
+
+fn default_panorama_tile_max_face() -> u32 {
+    1024
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            default: WatermarkStyle::default(),
+            agencies: HashMap::new(),
+            panorama_tile_max_face: default_panorama_tile_max_face(),
+        }
+    }
+}
+
+impl WatermarkConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.default.validate()?;
+        if self.panorama_tile_max_face < TILE_SIZE {
+            return Err(AppError::Configuration(format!(
+                "watermark.panorama_tile_max_face must be at least one tile ({} px)", TILE_SIZE
+            )));
+        }
+        for (agency_id, style) in &self.agencies {
+            style.validate()
+                .map_err(|e| AppError::Configuration(format!("Watermark for agency {}: {}", agency_id, e)))?;
+        }
+        Ok(())
+    }
+
+    // Agencies without an override fall back to the default style
+    pub fn style_for(&self, agency_id: Option<&str>) -> &WatermarkStyle {
+        agency_id
+            .and_then(|id| self.agencies.get(id))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatermarkStyle {
+    pub opacity: f32,         // 0.0 - 1.0
+    pub corner: WatermarkCorner,
+    pub width_ratio: f32,     // Watermark width relative to image width
+    pub margin_ratio: f32,    // Margin relative to the shorter image side
+    pub min_width: u32,       // Never render smaller than this (px)
+}
+
+impl Default for WatermarkStyle {
+    fn default() -> Self {
+        Self {
+            opacity: 0.6,
+            corner: WatermarkCorner::BottomRight,
+            width_ratio: 0.18,
+            margin_ratio: 0.03,
+            min_width: 120,
+        }
+    }
+}
+
+impl WatermarkStyle {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.opacity) {
+            return Err(AppError::Configuration("Watermark opacity must be between 0.0 and 1.0".into()));
+        }
+        if self.width_ratio <= 0.0 || self.width_ratio > 1.0 {
+            return Err(AppError::Configuration("Watermark width_ratio must be in (0.0, 1.0]".into()));
+        }
+        if !(0.0..0.5).contains(&self.margin_ratio) {
+            return Err(AppError::Configuration("Watermark margin_ratio must be in [0.0, 0.5)".into()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+// Tags that are never allowed onto a public file, whatever the whitelist says
+pub const GPS_TAG_PREFIXES: [&str; 2] = ["Exif.GPSInfo.", "Xmp.exif.GPS"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetadataPolicyConfig {
+    // Tag prefixes (exiv2 key syntax) kept on published files; everything else is stripped
+    pub keep_prefixes: Vec<String>,
+    #[serde(default = "default_true")]
+    pub strip_thumbnail: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for MetadataPolicyConfig {
+    fn default() -> Self {
+        Self {
+            keep_prefixes: vec!["Xmp.neural-reef.".to_string(), "Xmp.dc.".to_string()],
+            strip_thumbnail: true,
+        }
+    }
+}
+
+impl MetadataPolicyConfig {
+    pub fn validate(&self) -> Result<()> {
+        for prefix in &self.keep_prefixes {
+            if prefix.is_empty() {
+                return Err(AppError::Configuration("Metadata keep_prefixes cannot contain an empty prefix".into()));
+            }
+            let overlaps_gps = GPS_TAG_PREFIXES
+                .iter()
+                .any(|gps| gps.starts_with(prefix.as_str()) || prefix.starts_with(gps));
+            if overlaps_gps {
+                return Err(AppError::Configuration(format!("Metadata prefix {} would publish GPS tags", prefix)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn keeps(&self, tag: &str) -> bool {
+        !GPS_TAG_PREFIXES.iter().any(|gps| tag.starts_with(gps))
+            && self.keep_prefixes.iter().any(|prefix| tag.starts_with(prefix.as_str()))
+    }
+}
+
+// Decides per upload whether to reject it, accept it with a warning or accept it
+#[derive(Debug, Clone, Deserialize)]
+pub struct QualityGateConfig {
+    pub enabled: bool,
+    pub default: QualityThresholds,
+    #[serde(default)]
+    pub agencies: HashMap<String, QualityThresholds>,
+}
+
+impl Default for QualityGateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            default: QualityThresholds::default(),
+            agencies: HashMap::new(),
+        }
+    }
+}
+
+impl QualityGateConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.default.validate()?;
+        for (agency_id, thresholds) in &self.agencies {
+            thresholds.validate()
+                .map_err(|e| AppError::Configuration(format!("Quality gate for agency {}: {}", agency_id, e)))?;
+        }
+        Ok(())
+    }
+
+    // Agencies without an override fall back to the default thresholds
+    pub fn thresholds_for(&self, agency_id: Option<&str>) -> &QualityThresholds {
+        agency_id
+            .and_then(|id| self.agencies.get(id))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct QualityThresholds {
+    pub min_quality_score: f32,      // Reject below this overall score (0.0 - 1.0)
+    pub warn_quality_score: f32,     // Warn below this overall score
+    pub reject_on_critical: bool,    // Critical issues (blur) reject regardless of score
+    pub min_mean_brightness: f32,    // Warn below this mean luminance (0 - 255)
+    pub max_exposure_bias: f32,      // Warn when the mean is further than this from mid-grey (0.0 - 1.0)
+    pub max_highlight_clipping: f32, // Warn above this fraction of clipped pixels
+}
+
+impl Default for QualityThresholds {
+    fn default() -> Self {
+        Self {
+            min_quality_score: 0.3,
+            warn_quality_score: 0.55,
+            reject_on_critical: true,
+            min_mean_brightness: 60.0,
+            max_exposure_bias: 0.5,
+            max_highlight_clipping: 0.08,
+        }
+    }
+}
+
+impl QualityThresholds {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.min_quality_score) || !(0.0..=1.0).contains(&self.warn_quality_score) {
+            return Err(AppError::Configuration("Quality scores must be between 0.0 and 1.0".into()));
+        }
+        if self.warn_quality_score < self.min_quality_score {
+            return Err(AppError::Configuration("warn_quality_score cannot be below min_quality_score".into()));
+        }
+        if !(0.0..=255.0).contains(&self.min_mean_brightness) {
+            return Err(AppError::Configuration("min_mean_brightness must be between 0 and 255".into()));
+        }
+        if !(0.0..=1.0).contains(&self.max_exposure_bias) || !(0.0..=1.0).contains(&self.max_highlight_clipping) {
+            return Err(AppError::Configuration("max_exposure_bias and max_highlight_clipping must be between 0.0 and 1.0".into()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresetConfig {
+    // TOML file with the enhancement presets, re-read when it changes
+    pub path: String,
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval_secs: u64,
+    // Market this deployment serves (buckets are per country), used to pick country presets
+    pub country: Option<String>,
+}
+
+fn default_reload_interval() -> u64 {
+    30
+}
+
+impl Default for PresetConfig {
+    fn default() -> Self {
+        Self {
+            path: "config/presets.toml".to_string(),
+            reload_interval_secs: default_reload_interval(),
+            country: None,
+        }
+    }
+}
+
+impl PresetConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.path.is_empty() {
+            return Err(AppError::Configuration("Preset path cannot be empty".into()));
+        }
+        if self.reload_interval_secs == 0 {
+            return Err(AppError::Configuration("Preset reload_interval_secs must be positive".into()));
+        }
+        Ok(())
+    }
+}
+
+// Title deeds, contracts and other private documents
+#[derive(Debug, Clone, Deserialize)]
+pub struct DocumentConfig {
+    // Base64 AES-256 key; comes from the environment or local config, never the repo
+    #[serde(default)]
+    pub encryption_key: String,
+    // Stored with every document so keys can be rotated
+    pub key_id: String,
+    pub max_size_mb: usize,
+    // Directory holding the PDFium library for PDF redaction; the system one when unset
+    #[serde(default)]
+    pub pdfium_library: Option<String>,
+}
+
+impl Default for DocumentConfig {
+    fn default() -> Self {
+        Self {
+            encryption_key: String::new(),
+            key_id: "v1".to_string(),
+            max_size_mb: 25,
+            pdfium_library: None,
+        }
+    }
+}
+
+impl DocumentConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.encryption_key.is_empty() {
+            return Err(AppError::Configuration("documents.encryption_key must be set".into()));
+        }
+        if self.key_id.is_empty() {
+            return Err(AppError::Configuration("documents.key_id cannot be empty".into()));
+        }
+        if self.max_size_mb == 0 {
+            return Err(AppError::Configuration("documents.max_size_mb must be positive".into()));
+        }
+        Ok(())
+    }
+
+    pub fn max_size_bytes(&self) -> usize {
+        self.max_size_mb * 1024 * 1024
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImageMemoryConfig {
+    // Working memory shared by every image job running on this worker
+    pub total_mb: usize,
+    // Largest single job; uploads that would need more are refused before decoding
+    pub max_job_mb: usize,
+    // Intermediates at least this big go in file-backed maps instead of the heap
+    pub mmap_threshold_mb: usize,
+}
+
+impl Default for ImageMemoryConfig {
+    fn default() -> Self {
+        Self {
+            total_mb: 2048,
+            max_job_mb: 1024,
+            mmap_threshold_mb: 16,
+        }
+    }
+}
+
+impl ImageMemoryConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_job_mb == 0 || self.total_mb == 0 {
+            return Err(AppError::Configuration("image_memory budgets must be positive".into()));
+        }
+        if self.max_job_mb > self.total_mb {
+            return Err(AppError::Configuration(format!(
+                "image_memory.max_job_mb ({}) cannot exceed total_mb ({})", self.max_job_mb, self.total_mb
+            )));
+        }
+        // Permits are handed out per MiB
+        if self.total_mb > u32::MAX as usize {
+            return Err(AppError::Configuration("image_memory.total_mb is too large".into()));
+        }
+        Ok(())
+    }
+
+    pub fn max_job_bytes(&self) -> usize {
+        self.max_job_mb * 1024 * 1024
+    }
+
+    pub fn mmap_threshold_bytes(&self) -> usize {
+        self.mmap_threshold_mb * 1024 * 1024
+    }
+}
+
+// Size bounds for uploads, one rule per content type. Every upload path checks
+// against the same rules; see `image_processor::dimension_policy`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DimensionPolicyConfig {
+    pub default: DimensionRule,
+    // Content types without an entry use the default rule
+    #[serde(default)]
+    pub content_types: HashMap<ContentType, DimensionRule>,
+}
+
+impl Default for DimensionPolicyConfig {
+    fn default() -> Self {
+        Self {
+            default: DimensionRule::default(),
+            content_types: HashMap::from([
+                // 360° cameras shoot far wider than 4K, and downscaling loses the detail
+                // viewers zoom into. The maximum is the largest whose tile pyramid still
+                // fits the default image_memory.max_job_mb.
+                (ContentType::Panorama, DimensionRule {
+                    min_long_edge: 2048,
+                    min_short_edge: 1024,
+                    max_long_edge: 10240,
+                    max_short_edge: 5120,
+                    ..DimensionRule::default()
+                }),
+                // Scans come in at any size and aspect ratio; linework survives upscaling well
+                (ContentType::FloorPlan, DimensionRule {
+                    min_long_edge: 800,
+                    min_short_edge: 800,
+                    max_long_edge: 8192,
+                    max_short_edge: 8192,
+                    too_small: TooSmallAction::Upscale,
+                    max_upscale: 2.0,
+                    ..DimensionRule::default()
+                }),
+            ]),
+        }
+    }
+}
+
+impl DimensionPolicyConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.default.validate()
+            .map_err(|e| AppError::Configuration(format!("dimension_policy.default: {}", e)))?;
+        for (content_type, rule) in &self.content_types {
+            rule.validate()
+                .map_err(|e| AppError::Configuration(format!("dimension_policy.content_types.{:?}: {}", content_type, e)))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TooSmallAction {
+    Reject,
+    // Lanczos resampling then an unsharp mask, up to the rule's max_upscale
+    Upscale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TooLargeAction {
+    Reject,
+    Downscale,
+}
+
+// Bounds are on the long and short edge, so portrait and landscape shots of the
+// same room pass the same rule
+#[derive(Debug, Clone, Deserialize)]
+pub struct DimensionRule {
+    pub min_long_edge: u32,
+    pub min_short_edge: u32,
+    pub max_long_edge: u32,
+    pub max_short_edge: u32,
+    pub too_small: TooSmallAction,
+    pub too_large: TooLargeAction,
+    // Largest factor an upscale may apply; smaller images are rejected
+    #[serde(default = "default_max_upscale")]
+    pub max_upscale: f32,
+}
+
+fn default_max_upscale() -> f32 {
+    1.5
+}
+
+impl Default for DimensionRule {
+    fn default() -> Self {
+        Self {
+            min_long_edge: 1920,
+            min_short_edge: 1080,
+            max_long_edge: 3840,
+            max_short_edge: 2160,
+            too_small: TooSmallAction::Reject,
+            too_large: TooLargeAction::Downscale,
+            max_upscale: default_max_upscale(),
+        }
+    }
+}
+
+impl DimensionRule {
+    pub fn validate(&self) -> Result<()> {
+        if self.min_long_edge == 0 || self.min_short_edge == 0 {
+            return Err(AppError::Configuration("min_long_edge and min_short_edge must be positive".into()));
+        }
+        if self.min_short_edge > self.min_long_edge || self.max_short_edge > self.max_long_edge {
+            return Err(AppError::Configuration("a short edge bound can't exceed the long edge one".into()));
+        }
+        if self.min_long_edge > self.max_long_edge || self.min_short_edge > self.max_short_edge {
+            return Err(AppError::Configuration(format!(
+                "minimum {}x{} exceeds maximum {}x{}",
+                self.min_long_edge, self.min_short_edge, self.max_long_edge, self.max_short_edge
+            )));
+        }
+        if !self.max_upscale.is_finite() || self.max_upscale < 1.0 {
+            return Err(AppError::Configuration("max_upscale must be at least 1.0".into()));
+        }
+        Ok(())
+    }
+}
@@ -18,6 +18,7 @@ use aws_sdk_s3::{
 use tokio::sync::mpsc::error::SendError;
 use axum::response::{IntoResponse, Response};
 use axum::http::StatusCode;
+use crate::backend::image_processor::quality_report::QualityReport;
 use lettre::address::AddressError;
 use config::ConfigError;
 
@@ -66,6 +67,9 @@ pub enum AppError {
     
     #[error("Unauthorized")]
     Unauthorized,
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
     
     #[error("Invalid input: {0}")]
     InvalidInput(String),
@@ -75,6 +79,13 @@ pub enum AppError {
 
     #[error("Image processing error: {0}")]
     ImageProcessing(String),
+
+    // Returned as JSON so the photographer sees the issues and what to reshoot
+    #[error("Image rejected by quality gate: {}", .0.summary())]
+    QualityRejected(Box<QualityReport>),
+
+    #[error("Image needs {required} bytes of working memory, the job budget is {budget}")]
+    MemoryBudgetExceeded { required: usize, budget: usize },
 }
 
 #[derive(Debug, Error)]
@@ -103,10 +114,13 @@ pub enum ImageError {
 
 #[derive(Debug, Error)]
 pub enum ImageValidationError {
-    #[error("Image dimensions must be between 1080p and 4K, got {width}x{height}")]
-    InvalidDimensions {
+    // `rule` is the config key that failed, e.g. dimension_policy.default.min_long_edge
+    #[error("{width}x{height} breaks dimension rule {rule}: {reason}")]
+    DimensionRule {
+        rule: String,
         width: u32,
         height: u32,
+        reason: String,
     },
 
     #[error("Invalid image format: {0}")]
@@ -223,10 +237,15 @@ pub enum StorageError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        if let AppError::QualityRejected(report) = self {
+            return (StatusCode::UNPROCESSABLE_ENTITY, axum::Json(report)).into_response();
+        }
+
         let status = match self {
             AppError::Configuration(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
             AppError::Validation(_) => StatusCode::BAD_REQUEST,
             AppError::Storage(_) => StatusCode::BAD_REQUEST,
             AppError::ImageError(_) => StatusCode::BAD_REQUEST,
@@ -242,6 +261,8 @@ impl IntoResponse for AppError {
             AppError::InvalidInput(_) => StatusCode::BAD_REQUEST,
             AppError::ImageValidation(_) => StatusCode::BAD_REQUEST,
             AppError::ImageProcessing(_) => StatusCode::BAD_REQUEST,
+            AppError::QualityRejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::MemoryBudgetExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
         };
 
         (status, self.to_string()).into_response()
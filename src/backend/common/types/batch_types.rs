@@ -48,6 +48,9 @@ pub struct BatchProcessingStatus {
     pub total: usize,
     pub processed: usize,
     pub failed: usize,
+    // Images that look like a re-upload of another photo in the same listing
+    #[serde(default)]
+    pub duplicate_images: Vec<ImageId>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -72,10 +72,12 @@ impl_id_type!(BatchId, "FB");
 impl_id_type!(ImageId, "FI");
 impl_id_type!(ObjectId, "FO");
 impl_id_type!(UserId, "FU");
+impl_id_type!(DocumentId, "FD");
 
 impl ImageId {
+    // The UUID is the last segment, after the prefix (and batch parts, if any)
     pub fn to_uuid7(&self) -> Result<Uuid7> {
-        self.0.parse::<Uuid7>()
+        self.0.rsplit('_').next().unwrap_or_default().parse::<Uuid7>()
             .map_err(|e| AppError::ParseError(e.to_string()))
     }
 
@@ -199,4 +201,11 @@ mod tests {
         let image = ImageId::from_batch(&batch, 1);
         assert!(image.as_str().contains(batch.as_str()));
     }
+
+    #[test]
+    fn test_image_id_to_uuid7() {
+        let image = ImageId::generate();
+        assert_eq!(image.to_uuid7().unwrap().to_string(), &image.as_str()[3..]);
+        assert!(ImageId::from_batch(&BatchId::generate(), 2).to_uuid7().is_ok());
+    }
 } 
\ No newline at end of file
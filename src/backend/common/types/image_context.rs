@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use crate::backend::common::types::id_types::ImageId;
+use crate::backend::common::types::batch_types::BatchProcessingStatus;
+use crate::backend::f_ai_database::image_model::StoredImage;
+use crate::backend::image_processor::processor::{ContentType, RejectedUpload, TimeOfDay};
+use crate::backend::image_processor::batch_report::BatchQualityReport;
+use crate::backend::image_processor::content_classifier::ContentClassification;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ImageContext {
@@ -57,12 +62,39 @@ pub struct Image {
 pub struct ImageUploadOptions {
     pub optimize: bool,
     pub max_size: Option<u32>,
+    // Applies to every file in the request; omit to have each one classified
+    pub content_type: Option<ContentType>,
+    pub agency_id: Option<String>,
+}
+
+// Returned from uploads so the gallery can build srcset without a second request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageUploadResponse {
+    pub batch: BatchProcessingStatus,
+    pub images: Vec<StoredImage>,
+    // Files the quality gate turned away; the rest of the upload is still stored
+    pub rejected: Vec<RejectedUpload>,
+    // Files that looked like paperwork; kept private and queued for content-type review
+    pub held: Vec<HeldUploadSummary>,
+    pub report: BatchQualityReport,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeldUploadSummary {
+    pub index: usize,
+    pub filename: String,
+    pub image_id: ImageId,
+    pub classification: ContentClassification,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ImageSearchQuery {
     pub listing_id: Option<String>,
     pub filename: Option<String>,
+    pub content_type: Option<ContentType>,
+    // Detected on upload, e.g. Twilight for dusk exteriors
+    pub time_of_day: Option<TimeOfDay>,
+    pub limit: Option<usize>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,6 +27,8 @@ pub enum UploadStatus {
     Uploading { chunks_received: u32, total_chunks: u32 },
     Processing,
     Completed,
+    // Looked like a document; kept private until a reviewer confirms its type
+    HeldForReview { reason: String },
     Failed { reason: String },
 }
 
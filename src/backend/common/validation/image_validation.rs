@@ -1,12 +1,13 @@
-use image::{ImageFormat, GenericImageView};
+use image::ImageFormat;
 use crate::backend::common::error::error::{Result, AppError, ImageError};
+use crate::backend::image_processor::{
+    dimension_policy::DimensionPolicy,
+    memory::probe_image,
+    processor::ContentType,
+};
 use bytes::Bytes;
 
 pub const MAX_FILE_SIZE: usize = 10 * 1024 * 1024; // 10MB
-pub const MIN_WIDTH: u32 = 100;
-pub const MIN_HEIGHT: u32 = 100;
-pub const MAX_WIDTH: u32 = 8192;
-pub const MAX_HEIGHT: u32 = 8192;
 
 pub const ALLOWED_FORMATS: &[ImageFormat] = &[
     ImageFormat::Jpeg,
@@ -21,7 +22,14 @@ pub const ALLOWED_MIME_TYPES: &[&str] = &[
     "image/webp",
 ];
 
-pub fn validate_image(data: &Bytes, filename: &str) -> Result<()> {
+// Dimensions are checked from the header against the same policy the processor
+// applies, so an upload that passes here isn't rejected for its size later
+pub fn validate_image(
+    data: &Bytes,
+    filename: &str,
+    policy: &DimensionPolicy,
+    content_type: Option<ContentType>,
+) -> Result<()> {
     // Check file size
     if data.len() > MAX_FILE_SIZE {
         return Err(AppError::ImageError(ImageError::FileTooLarge {
@@ -42,27 +50,6 @@ pub fn validate_image(data: &Bytes, filename: &str) -> Result<()> {
     }
 
     // Check dimensions
-    let img = image::load_from_memory(data)
-        .map_err(|e| AppError::ImageError(ImageError::LoadError(e.to_string())))?;
-
-    let (width, height) = img.dimensions();
-    if width < MIN_WIDTH || height < MIN_HEIGHT {
-        return Err(AppError::ImageError(ImageError::TooSmall {
-            width,
-            height,
-            min_width: MIN_WIDTH,
-            min_height: MIN_HEIGHT,
-        }));
-    }
-
-    if width > MAX_WIDTH || height > MAX_HEIGHT {
-        return Err(AppError::ImageError(ImageError::TooLarge {
-            width,
-            height,
-            max_width: MAX_WIDTH,
-            max_height: MAX_HEIGHT,
-        }));
-    }
-
-    Ok(())
-} 
\ No newline at end of file
+    let probe = probe_image(data)?;
+    policy.precheck(content_type, probe.width, probe.height)
+}
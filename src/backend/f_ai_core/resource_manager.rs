@@ -1,7 +1,10 @@
-use tokio::sync::Semaphore;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use std::sync::Arc;
-use crate::backend::common::error::error::{Result, AppError};
-use tracing::info;
+use crate::backend::common::{
+    config::ImageMemoryConfig,
+    error::error::{Result, AppError},
+};
+use tracing::{info, debug};
 use crate::backend::f_ai_core::SemaphorePermit;
 
 #[derive(Debug)]
@@ -10,6 +13,19 @@ pub struct ResourceConfig {
     pub max_concurrent_processing: usize,
     pub max_concurrent_searches: usize,
     pub max_concurrent_embeddings: usize,
+    pub image_memory: ImageMemoryConfig,
+}
+
+impl Default for ResourceConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_uploads: 10,
+            max_concurrent_processing: 4,
+            max_concurrent_searches: 20,
+            max_concurrent_embeddings: 5,
+            image_memory: ImageMemoryConfig::default(),
+        }
+    }
 }
 
 pub struct ResourceManager {
@@ -17,18 +33,38 @@ pub struct ResourceManager {
     processing_semaphore: Arc<Semaphore>,
     search_semaphore: Arc<Semaphore>,
     embedding_semaphore: Arc<Semaphore>,
+    // One permit per MiB of image working memory
+    memory_semaphore: Arc<Semaphore>,
+    image_memory: ImageMemoryConfig,
+}
+
+// Held for the whole image job; the memory goes back to the pool when dropped
+pub struct MemoryReservation {
+    _permit: OwnedSemaphorePermit,
+    bytes: usize,
+}
+
+impl MemoryReservation {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
 }
 
 impl ResourceManager {
-    pub fn new(config: ResourceConfig) -> Self {
+    // A per-job limit above the pool would leave the largest jobs waiting for
+    // permits that never exist, so the memory budgets are checked up front
+    pub fn new(config: ResourceConfig) -> Result<Self> {
         info!("Initializing resource manager with config: {:?}", config);
-        
-        Self {
+        config.image_memory.validate()?;
+
+        Ok(Self {
             upload_semaphore: Arc::new(Semaphore::new(config.max_concurrent_uploads)),
             processing_semaphore: Arc::new(Semaphore::new(config.max_concurrent_processing)),
             search_semaphore: Arc::new(Semaphore::new(config.max_concurrent_searches)),
             embedding_semaphore: Arc::new(Semaphore::new(config.max_concurrent_embeddings)),
-        }
+            memory_semaphore: Arc::new(Semaphore::new(config.image_memory.total_mb)),
+            image_memory: config.image_memory,
+        })
     }
 
     pub async fn acquire_upload(&self) -> Result<SemaphorePermit> {
@@ -54,4 +90,27 @@ impl ResourceManager {
             .map_err(|e| AppError::Internal(e.to_string()))?;
         Ok(SemaphorePermit::new(permit))
     }
-} 
\ No newline at end of file
+
+    // Refuses jobs over the per-job limit outright, otherwise waits until enough
+    // of the shared pool is free. Rounded up to whole MiB.
+    pub async fn reserve_image_memory(&self, bytes: usize) -> Result<MemoryReservation> {
+        let budget = self.image_memory.max_job_bytes();
+        if bytes > budget {
+            return Err(AppError::MemoryBudgetExceeded { required: bytes, budget });
+        }
+
+        let mib = bytes.div_ceil(1024 * 1024).max(1) as u32;
+        let permit = self.memory_semaphore.clone().acquire_many_owned(mib).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        debug!(mib, available = self.memory_semaphore.available_permits(), "Reserved image memory");
+        Ok(MemoryReservation { _permit: permit, bytes })
+    }
+
+    pub fn max_job_bytes(&self) -> usize {
+        self.image_memory.max_job_bytes()
+    }
+
+    pub fn mmap_threshold(&self) -> usize {
+        self.image_memory.mmap_threshold_bytes()
+    }
+}
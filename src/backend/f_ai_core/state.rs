@@ -1,17 +1,23 @@
 use std::sync::Arc;
 use std::time::Instant;
+use chrono::Utc;
 use tokio::sync::RwLock;
+use tracing::{info, instrument};
 use crate::backend::{
     common::{
         error::error::Result,
         types::{
             listing_types::{Listing, AgentListingRequest},
-            batch_types::BatchStatus,
+            batch_types::{BatchProcessingStatus, BatchStatus},
+            id_types::{BatchId, ListingId},
+            image_context::{HeldUploadSummary, ImageUploadResponse},
         },
     },
     f_ai_database::{
         database::DatabaseManager,
         listing_model::ListingService,
+        document_model::DocumentModel,
+        image_model::ImageModel,
     },
     monitoring::{
         metrics::MetricsManager,
@@ -20,7 +26,8 @@ use crate::backend::{
     },
     image_processor::{
         job_scheduler::ImageJobScheduler,
-        processor::ImageProcessor,
+        processor::{BatchMetadata, ImageProcessor, ListingBatch, UploadFile, PROCESSING_VERSION},
+        derivatives::WEBP_QUALITY,
     },
     llm_caller::batch_analysis_service::BatchAnalysisService,
     key_logic_auth::key_service::KeyService,
@@ -33,12 +40,15 @@ pub struct AppState {
     pub event_logger: Arc<EventLogger>,
     pub image_scheduler: Arc<ImageJobScheduler>,
     pub image_processor: Arc<ImageProcessor>,
+    pub image_service: Arc<ImageModel>,
     pub batch_analyzer: Arc<BatchAnalysisService>,
     pub key_service: Arc<KeyService>,
     pub email_service: Arc<EmailService>,
     pub start_time: Instant,
     pub active_jobs: Arc<RwLock<Vec<String>>>,
     pub listing_service: Arc<ListingService>,
+    // None without documents.encryption_key; the /documents routes are left out then
+    pub document_service: Option<Arc<DocumentModel>>,
 }
 
 impl AppState {
@@ -46,12 +56,14 @@ impl AppState {
         db: DatabaseManager,
         metrics: MetricsManager,
         event_logger: EventLogger,
+        image_processor: Arc<ImageProcessor>,
+        image_service: Arc<ImageModel>,
+        document_service: Option<Arc<DocumentModel>>,
     ) -> Self {
         let db = Arc::new(db);
         let metrics = Arc::new(metrics);
         let event_logger = Arc::new(event_logger);
         
-        let image_processor = Arc::new(ImageProcessor::new());
         let batch_analyzer = Arc::new(BatchAnalysisService::new(db.clone(), event_logger.clone()));
         let image_scheduler = Arc::new(ImageJobScheduler::new(
             image_processor.clone(),
@@ -73,12 +85,14 @@ impl AppState {
             event_logger,
             image_scheduler,
             image_processor,
+            image_service,
             batch_analyzer,
             key_service,
             email_service,
             start_time: Instant::now(),
             active_jobs: Arc::new(RwLock::new(Vec::new())),
             listing_service,
+            document_service,
         }
     }
 
@@ -102,6 +116,85 @@ impl AppState {
         self.active_jobs.read().await.clone()
     }
 
+    // Processes, stores and records one upload as a batch; files the quality gate
+    // turns away come back in `rejected`, guessed documents are held privately in
+    // `held`, and the rest are stored without them.
+    // Shared by the multipart route and finished WebSocket sessions.
+    #[instrument(skip(self, files))]
+    pub async fn process_upload(
+        &self,
+        listing_id: &ListingId,
+        files: Vec<UploadFile>,
+        agency_id: Option<String>,
+    ) -> Result<ImageUploadResponse> {
+        // The whole upload is one batch, so every photo is harmonized against the others
+        let created_at = Utc::now();
+        let metadata = BatchMetadata {
+            listing_id: listing_id.clone(),
+            batch_id: BatchId::create_for_listing(listing_id),
+            agency_id,
+            room_groups: Vec::new(),
+            quality: WEBP_QUALITY,
+            processing_version: PROCESSING_VERSION.to_string(),
+            // Sunset times for photos without GPS of their own
+            listing_pin: self.listing_service.get_gps_pin(listing_id).await?,
+            created_at,
+            updated_at: created_at,
+        };
+        let batch_id = metadata.batch_id.clone();
+        let total = files.len();
+        let ListingBatch { images: processed, rejected, held, report } = self.image_processor
+            .process_listing_batch(metadata, files)
+            .await?;
+
+        let mut images = Vec::with_capacity(processed.len());
+        for image in &processed {
+            images.push(self.image_service.store_processed(image).await?);
+        }
+        let mut held_uploads = Vec::with_capacity(held.len());
+        for file in held {
+            self.image_service.hold_for_review(&file.upload).await?;
+            held_uploads.push(HeldUploadSummary {
+                index: file.index,
+                filename: file.filename,
+                image_id: file.upload.id,
+                classification: file.upload.classification,
+            });
+        }
+
+        // EXIF GPS fills an empty listing pin and flags photos shot somewhere else
+        for image in images.iter_mut() {
+            let Some(gps) = image.gps.clone() else { continue };
+            let check = self.listing_service.check_photo_location(listing_id, gps).await?;
+            self.image_service.record_location_check(&image.image_id, &check).await?;
+            image.location_check = Some(check);
+        }
+
+        let batch = BatchProcessingStatus {
+            batch_id,
+            status: BatchStatus::Completed,
+            total,
+            processed: images.len(),
+            failed: rejected.len(),
+            duplicate_images: images.iter()
+                .filter(|image| !image.duplicates.is_empty())
+                .map(|image| image.image_id.clone())
+                .collect(),
+            created_at,
+            updated_at: Utc::now(),
+        };
+        self.image_service.record_batch(&batch).await?;
+        info!(
+            batch_id = %batch.batch_id,
+            stored = images.len(),
+            rejected = rejected.len(),
+            held = held_uploads.len(),
+            "Upload processed"
+        );
+
+        Ok(ImageUploadResponse { batch, images, rejected, held: held_uploads, report })
+    }
+
     #[instrument(skip(self))]
     pub async fn create_agent_listing(&self, request: AgentListingRequest) -> Result<Listing> {
         // 1. Create initial listing
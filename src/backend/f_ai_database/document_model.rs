@@ -0,0 +1,469 @@
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use surrealdb::{Surreal, engine::remote::ws::Client};
+use tracing::{info, warn, instrument};
+use chrono::{DateTime, Utc};
+use image::ImageFormat;
+use pdfium_render::prelude::Pdfium;
+use crate::backend::{
+    common::{
+        config::DocumentConfig,
+        error::error::{Result, AppError},
+        types::id_types::{DocumentId, ListingId},
+    },
+    image_processor::{
+        processor::ContentType,
+        redaction::{bind_pdfium, redact_pdf, redact_scan, RedactionRegion},
+    },
+    trans_storage::{b2_storage::B2Storage, encryption::DocumentCipher, storage_keys},
+};
+
+// Everything is sealed before upload, so the bucket only ever sees this
+const ENCRYPTED_MIME_TYPE: &str = "application/octet-stream";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentFormat {
+    Pdf,
+    Scan,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentRecord {
+    pub document_id: DocumentId,
+    pub listing_id: String,
+    pub content_type: ContentType,
+    pub filename: String,
+    pub format: DocumentFormat,
+    pub mime_type: String,
+    pub size: usize,
+    pub original_path: String,
+    // The copy the owner released, with their regions blacked out (possibly none).
+    // Agents only ever get this; until it exists they get nothing.
+    pub redacted_path: Option<String>,
+    pub redactions: Vec<RedactionRegion>,
+    pub key_id: String,
+    pub uploaded_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentAction {
+    Upload,
+    List,
+    View,
+    ViewOriginal,
+    Redact,
+    ListAgents,
+    AssignAgent,
+    RemoveAgent,
+    ReadAccessLog,
+}
+
+impl DocumentAction {
+    // The rest are open to assigned agents too
+    fn owner_only(&self) -> bool {
+        matches!(
+            self,
+            DocumentAction::ViewOriginal
+                | DocumentAction::Redact
+                | DocumentAction::AssignAgent
+                | DocumentAction::RemoveAgent
+                | DocumentAction::ReadAccessLog
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentRole {
+    Owner,
+    Agent,
+}
+
+// One row per attempt, allowed or not. Rows are only ever added.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentAccess {
+    pub listing_id: String,
+    pub document_id: Option<String>,
+    pub user_id: String,
+    pub action: DocumentAction,
+    pub granted: bool,
+    pub role: Option<DocumentRole>,
+    pub detail: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssignedAgent {
+    pub agent_id: String,
+    pub assigned_by: String,
+    pub assigned_at: DateTime<Utc>,
+}
+
+pub struct DocumentFile {
+    pub filename: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+// Title deeds and contracts. Kept apart from the image pipeline: stored encrypted
+// under the private prefix, never watermarked, published or given a rendition, and
+// only the listing owner and the agents they assign can reach them.
+pub struct DocumentModel {
+    db: Arc<Surreal<Client>>,
+    storage: Arc<B2Storage>,
+    cipher: DocumentCipher,
+    max_size: usize,
+    // None when the library can't be loaded; scans can still be redacted then
+    pdfium: Option<Pdfium>,
+}
+
+impl DocumentModel {
+    pub fn new(db: Arc<Surreal<Client>>, storage: Arc<B2Storage>, config: &DocumentConfig) -> Result<Self> {
+        let pdfium = bind_pdfium(config.pdfium_library.as_deref())
+            .inspect_err(|e| warn!("PDF redaction is unavailable: {}", e))
+            .ok();
+        Ok(Self {
+            db,
+            storage,
+            cipher: DocumentCipher::new(config)?,
+            max_size: config.max_size_bytes(),
+            pdfium,
+        })
+    }
+
+    #[instrument(skip(self, data), fields(size = data.len()))]
+    pub async fn upload(
+        &self,
+        user_id: &str,
+        listing_id: &ListingId,
+        content_type: ContentType,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<DocumentRecord> {
+        self.authorize(user_id, listing_id, None, DocumentAction::Upload).await?;
+
+        if !content_type.is_document() {
+            return Err(AppError::Validation(format!("{:?} is not a document type", content_type)));
+        }
+        if data.len() > self.max_size {
+            return Err(AppError::Validation(format!(
+                "Documents can be at most {} MB", self.max_size / (1024 * 1024)
+            )));
+        }
+        let (format, extension, mime_type) = detect_format(data)?;
+
+        let document_id = DocumentId::generate();
+        let original_path = storage_keys::document_key(
+            listing_id.as_str(), document_id.as_str(), &format!("original.{}", extension),
+        );
+        let sealed = self.cipher.encrypt(data, &original_path)?;
+        self.storage.upload_file(&original_path, &sealed, ENCRYPTED_MIME_TYPE).await?;
+
+        let record = DocumentRecord {
+            document_id: document_id.clone(),
+            listing_id: listing_id.as_str().to_string(),
+            content_type,
+            filename: filename.to_string(),
+            format,
+            mime_type: mime_type.to_string(),
+            size: data.len(),
+            original_path,
+            redacted_path: None,
+            redactions: Vec::new(),
+            key_id: self.cipher.key_id().to_string(),
+            uploaded_by: user_id.to_string(),
+            created_at: Utc::now(),
+        };
+
+        self.db
+            .query("CREATE type::thing('documents', $id) CONTENT $record")
+            .bind(("id", document_id.to_string()))
+            .bind(("record", record.clone()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        info!(listing_id = %listing_id, document_id = %document_id, ?content_type, "Stored encrypted document");
+        Ok(record)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list(&self, user_id: &str, listing_id: &ListingId) -> Result<Vec<DocumentRecord>> {
+        self.authorize(user_id, listing_id, None, DocumentAction::List).await?;
+
+        let mut response = self.db
+            .query("SELECT * FROM documents WHERE listing_id = $listing_id ORDER BY created_at DESC")
+            .bind(("listing_id", listing_id.as_str().to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))
+    }
+
+    // Agents get the copy the owner released and nothing else; the owner gets that
+    // copy too, or the original when they ask for it or nothing was released yet
+    #[instrument(skip(self))]
+    pub async fn download(
+        &self,
+        user_id: &str,
+        listing_id: &ListingId,
+        document_id: &DocumentId,
+        original: bool,
+    ) -> Result<DocumentFile> {
+        let action = if original { DocumentAction::ViewOriginal } else { DocumentAction::View };
+        let role = self.authorize(user_id, listing_id, Some(document_id), action).await?;
+        let record = self.get_record(listing_id, document_id).await?;
+
+        let (path, filename, mime_type) = match &record.redacted_path {
+            Some(path) if !original => {
+                let (extension, mime_type) = redacted_copy_format(record.format);
+                (path, redacted_filename(&record.filename, extension), mime_type.to_string())
+            }
+            _ => {
+                // Nothing released yet, so the only file is the original. Checked as
+                // a request for it, which refuses agents and logs the refusal.
+                if role != DocumentRole::Owner {
+                    self.authorize(user_id, listing_id, Some(document_id), DocumentAction::ViewOriginal).await?;
+                }
+                (&record.original_path, record.filename.clone(), record.mime_type.clone())
+            }
+        };
+
+        let sealed = self.storage.download_file(path).await?;
+        let data = self.cipher.decrypt(&sealed, path)?;
+        Ok(DocumentFile { filename, mime_type, data })
+    }
+
+    // Replaces the document's redactions and releases the result to assigned agents.
+    // The original is untouched; the regions are burned into a separate copy, which
+    // with an empty list is a clean re-render of the original with nothing blacked out.
+    #[instrument(skip(self, regions), fields(regions = regions.len()))]
+    pub async fn redact(
+        &self,
+        user_id: &str,
+        listing_id: &ListingId,
+        document_id: &DocumentId,
+        regions: Vec<RedactionRegion>,
+    ) -> Result<DocumentRecord> {
+        self.authorize(user_id, listing_id, Some(document_id), DocumentAction::Redact).await?;
+        let mut record = self.get_record(listing_id, document_id).await?;
+
+        let sealed = self.storage.download_file(&record.original_path).await?;
+        let original = self.cipher.decrypt(&sealed, &record.original_path)?;
+        let redacted = match record.format {
+            DocumentFormat::Scan => redact_scan(&original, &regions)?,
+            DocumentFormat::Pdf => {
+                let pdfium = self.pdfium.as_ref().ok_or_else(|| {
+                    AppError::Configuration("PDF redaction needs PDFium; see documents.pdfium_library".into())
+                })?;
+                redact_pdf(pdfium, &original, &regions)?
+            }
+        };
+
+        let (extension, _) = redacted_copy_format(record.format);
+        let path = storage_keys::document_key(listing_id.as_str(), document_id.as_str(), &format!("redacted.{}", extension));
+        let sealed = self.cipher.encrypt(&redacted, &path)?;
+        self.storage.upload_file(&path, &sealed, ENCRYPTED_MIME_TYPE).await?;
+        record.redacted_path = Some(path);
+        record.redactions = regions;
+
+        self.db
+            .query("UPDATE type::thing('documents', $id) SET redacted_path = $redacted_path, redactions = $redactions")
+            .bind(("id", document_id.to_string()))
+            .bind(("redacted_path", record.redacted_path.clone()))
+            .bind(("redactions", record.redactions.clone()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        info!(document_id = %document_id, regions = record.redactions.len(), "Updated document redactions");
+        Ok(record)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list_agents(&self, user_id: &str, listing_id: &ListingId) -> Result<Vec<AssignedAgent>> {
+        self.authorize(user_id, listing_id, None, DocumentAction::ListAgents).await?;
+
+        let mut response = self.db
+            .query("SELECT agent_id, assigned_by, assigned_at FROM listing_agents
+                   WHERE listing_id = $listing_id ORDER BY assigned_at")
+            .bind(("listing_id", listing_id.as_str().to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn assign_agent(&self, user_id: &str, listing_id: &ListingId, agent_id: &str) -> Result<()> {
+        self.authorize(user_id, listing_id, None, DocumentAction::AssignAgent).await?;
+
+        self.db
+            .query("IF (SELECT * FROM listing_agents WHERE listing_id = $listing_id AND agent_id = $agent_id) = [] {
+                        CREATE listing_agents CONTENT {
+                            listing_id: $listing_id,
+                            agent_id: $agent_id,
+                            assigned_by: $assigned_by,
+                            assigned_at: time::now()
+                        };
+                    }")
+            .bind(("listing_id", listing_id.as_str().to_string()))
+            .bind(("agent_id", agent_id.to_string()))
+            .bind(("assigned_by", user_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        info!(listing_id = %listing_id, agent_id, "Assigned agent to listing documents");
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn remove_agent(&self, user_id: &str, listing_id: &ListingId, agent_id: &str) -> Result<()> {
+        self.authorize(user_id, listing_id, None, DocumentAction::RemoveAgent).await?;
+
+        self.db
+            .query("DELETE listing_agents WHERE listing_id = $listing_id AND agent_id = $agent_id")
+            .bind(("listing_id", listing_id.as_str().to_string()))
+            .bind(("agent_id", agent_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        info!(listing_id = %listing_id, agent_id, "Removed agent from listing documents");
+        Ok(())
+    }
+
+    // Newest first
+    #[instrument(skip(self))]
+    pub async fn access_log(&self, user_id: &str, listing_id: &ListingId, limit: usize) -> Result<Vec<DocumentAccess>> {
+        self.authorize(user_id, listing_id, None, DocumentAction::ReadAccessLog).await?;
+
+        let mut response = self.db
+            .query("SELECT * FROM document_access_log WHERE listing_id = $listing_id ORDER BY at DESC LIMIT $limit")
+            .bind(("listing_id", listing_id.as_str().to_string()))
+            .bind(("limit", limit))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))
+    }
+
+    // Works out the caller's role and records the attempt before anything is read.
+    // A request that can't be audited isn't served.
+    async fn authorize(
+        &self,
+        user_id: &str,
+        listing_id: &ListingId,
+        document_id: Option<&DocumentId>,
+        action: DocumentAction,
+    ) -> Result<DocumentRole> {
+        let role = self.role(user_id, listing_id).await?;
+        let granted = match role {
+            Some(DocumentRole::Owner) => true,
+            Some(DocumentRole::Agent) => !action.owner_only(),
+            None => false,
+        };
+        let detail = match role {
+            None if !granted => Some("not the owner or an assigned agent".to_string()),
+            Some(DocumentRole::Agent) if !granted => Some("owner only".to_string()),
+            _ => None,
+        };
+
+        self.db
+            .query("CREATE document_access_log CONTENT $entry")
+            .bind(("entry", DocumentAccess {
+                listing_id: listing_id.as_str().to_string(),
+                document_id: document_id.map(|id| id.to_string()),
+                user_id: user_id.to_string(),
+                action,
+                granted,
+                role,
+                detail,
+                at: Utc::now(),
+            }))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        match role {
+            Some(role) if granted => Ok(role),
+            _ => {
+                warn!(listing_id = %listing_id, user_id, ?action, "Denied document access");
+                Err(AppError::Forbidden(format!("No access to documents of listing {}", listing_id)))
+            }
+        }
+    }
+
+    async fn role(&self, user_id: &str, listing_id: &ListingId) -> Result<Option<DocumentRole>> {
+        let mut response = self.db
+            .query("SELECT VALUE owner_id FROM listings WHERE listing_id = $listing_id;
+                    SELECT VALUE agent_id FROM listing_agents WHERE listing_id = $listing_id AND agent_id = $user_id;")
+            .bind(("listing_id", listing_id.as_str().to_string()))
+            .bind(("user_id", user_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let owners: Vec<String> = response.take(0).map_err(|e| AppError::Database(e.to_string()))?;
+        let agents: Vec<String> = response.take(1).map_err(|e| AppError::Database(e.to_string()))?;
+        let Some(owner_id) = owners.into_iter().next() else {
+            return Err(AppError::NotFound(format!("Listing {} not found", listing_id)));
+        };
+
+        Ok(if owner_id == user_id {
+            Some(DocumentRole::Owner)
+        } else if !agents.is_empty() {
+            Some(DocumentRole::Agent)
+        } else {
+            None
+        })
+    }
+
+    async fn get_record(&self, listing_id: &ListingId, document_id: &DocumentId) -> Result<DocumentRecord> {
+        let mut response = self.db
+            .query("SELECT * FROM type::thing('documents', $id)")
+            .bind(("id", document_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let record: Option<DocumentRecord> = response.take(0).map_err(|e| AppError::Database(e.to_string()))?;
+        record
+            .filter(|r| r.listing_id == listing_id.as_str())
+            .ok_or_else(|| AppError::NotFound(format!("Document {} not found in listing {}", document_id, listing_id)))
+    }
+}
+
+// PDFs, and scans in the formats phones and scanners produce
+fn detect_format(data: &[u8]) -> Result<(DocumentFormat, &'static str, &'static str)> {
+    if data.starts_with(b"%PDF-") {
+        return Ok((DocumentFormat::Pdf, "pdf", "application/pdf"));
+    }
+    match image::guess_format(data) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Tiff | ImageFormat::WebP)) => {
+            // Make sure it is a readable page, not just the right magic bytes
+            image::load_from_memory_with_format(data, format)?;
+            let extension = format.extensions_str().first().copied().unwrap_or("bin");
+            Ok((DocumentFormat::Scan, extension, format.to_mime_type()))
+        }
+        _ => Err(AppError::Validation(
+            "Documents must be PDFs or scans (JPEG, PNG, TIFF or WebP)".into(),
+        )),
+    }
+}
+
+// Scans come back as PNG and PDFs as image-only PDFs
+fn redacted_copy_format(format: DocumentFormat) -> (&'static str, &'static str) {
+    match format {
+        DocumentFormat::Scan => ("png", "image/png"),
+        DocumentFormat::Pdf => ("pdf", "application/pdf"),
+    }
+}
+
+fn redacted_filename(filename: &str, extension: &str) -> String {
+    let stem = filename.rsplit_once('.').map_or(filename, |(stem, _)| stem);
+    format!("{}-redacted.{}", stem, extension)
+}
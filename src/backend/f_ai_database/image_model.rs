@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::collections::BTreeMap;
 use serde::{Serialize, Deserialize};
 use surrealdb::{Surreal, engine::remote::ws::Client};
 use rexiv2::Metadata as XmpMetadata;
@@ -10,13 +11,42 @@ use crate::backend::{
         types::{
             id_types::{BatchId, ImageId, ListingId},
             image_types::{ImageMetadata, ImageContext},
+            image_context::{Image, ImageSearchQuery, ImageSearchResponse},
             batch_types::{BatchProcessingStatus, BatchStatus},
+            listing_types::GpsCoordinates,
         },
     },
-    trans_storage::b2_storage::B2Storage,
+    f_ai_database::listing_model::PhotoLocationCheck,
+    image_processor::{
+        processor::{ContentType, HeldUpload, ProcessedImage, TimeOfDay},
+        derivatives::{Rendition, RenditionFormat},
+        exif_metadata::ExifData,
+        perceptual_hash::{self, NEAR_DUPLICATE_DISTANCE},
+        exposure_fusion::{BracketFrame, FrameOffset},
+        lens_correction::AppliedLensCorrection,
+        perspective::PerspectiveCorrection,
+        edit_recipe::{EditRecipe, RecipeRevision},
+        content_classifier::ContentClassification,
+        sky_replacement::SkyReplacement,
+        quality_report::QualityReport,
+        panorama::PanoramaTiles,
+        floor_plan::FloorPlanNormalization,
+        color_management::SourceColorSpace,
+        time_of_day::TimeOfDayClassification,
+        gallery_order::GalleryCandidate,
+    },
+    trans_storage::{b2_storage::B2Storage, storage_keys},
 };
 use serde_json::Value as JsonValue;
 
+#[derive(Debug, Deserialize)]
+struct SearchRow {
+    #[serde(flatten)]
+    context: ImageContext,
+    #[serde(default)]
+    metadata: JsonValue,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BatchImageGroup {
     pub content_type: String,
@@ -59,12 +89,171 @@ pub struct ImageUploadMetadata {
     pub b2_url: String,
     pub content_type: Option<String>,
     pub gps_coordinates: Option<String>,
+    pub perceptual_hash: Option<String>,
+    pub phash_bands: Vec<String>,
     pub processing_version: i32,
     pub enhancement_preset: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageDimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProcessedImageRecord {
+    pub image_id: String,
+    pub listing_id: String,
+    pub original_path: String,
+    pub processed_path: String,
+    pub watermarked_path: Option<String>,
+    pub mime_type: String,
+    pub size: i64,
+    pub dimensions: ImageDimensions,
+    pub status: String,
+    pub exif: Option<ExifData>,
+    pub source_metadata: BTreeMap<String, String>,
+    pub source_color_space: SourceColorSpace,
+    pub perceptual_hash: String,
+    pub phash_bands: Vec<String>,
+    pub bracket_size: usize,
+    pub lens_correction: Option<AppliedLensCorrection>,
+    pub perspective_correction: Option<PerspectiveCorrection>,
+    pub sky_replacement: Option<SkyReplacement>,
+    pub panorama: Option<PanoramaRecord>,
+    pub floor_plan: Option<FloorPlanRecord>,
+    pub recipe: EditRecipe,
+    pub recipe_version: u32,
+    pub classification: Option<ContentClassification>,
+    pub time_of_day: Option<TimeOfDayClassification>,
+    pub quality: QualityReport,
+    pub processed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BracketFrameRecord {
+    pub index: usize,
+    pub path: String,
+    pub exposure_time: Option<f64>,
+    pub exposure_bias: Option<f64>,
+    pub mean_luminance: f32,
+    pub offset: FrameOffset,
+    pub is_reference: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenditionRecord {
+    pub path: String,
+    pub format: RenditionFormat,
+    pub width: u32,
+    pub height: u32,
+    pub size: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredImage {
+    pub image_id: ImageId,
+    pub published_path: String,
+    pub width: u32,
+    pub height: u32,
+    pub renditions: Vec<RenditionRecord>,
+    pub gps: Option<GpsCoordinates>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location_check: Option<PhotoLocationCheck>,
+    // Earlier photos in the same listing this one looks like
+    pub duplicates: Vec<ImageMatch>,
+    // Set when the uploader left the type out; low confidence means it's queued for review
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub classification: Option<ContentClassification>,
+    // Listing pages show a disclosure when this is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sky_replacement: Option<SkyReplacement>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panorama: Option<PanoramaRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub floor_plan: Option<FloorPlanRecord>,
+    // Quality gate result; a warning here means the photographer may want to reshoot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityReport>,
+}
+
+// Where a 360° image's tiles live, in the shape web panorama viewers take as their
+// multi-resolution config (`{base_path}/{level}/{face}{y}_{x}.{extension}`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PanoramaRecord {
+    pub base_path: String,
+    pub extension: String,
+    pub tile_resolution: u32,
+    pub cube_resolution: u32,
+    pub max_level: u32,
+    // Flat view for galleries and link previews
+    pub preview_path: String,
+}
+
+// How a floor plan was normalized, and where its SVG trace is when one was made
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FloorPlanRecord {
+    #[serde(flatten)]
+    pub normalization: FloorPlanNormalization,
+    pub svg_path: Option<String>,
+}
+
+// An untagged upload the classifier wasn't sure about
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContentReview {
+    pub image_id: String,
+    pub listing_id: String,
+    pub suggested: ContentClassification,
+    pub created_at: DateTime<Utc>,
+}
+
+// What a re-render needs from the stored image
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecipeSource {
+    pub listing_id: String,
+    pub original_path: String,
+    pub recipe: EditRecipe,
+    pub recipe_version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageMatch {
+    pub image_id: String,
+    pub listing_id: String,
+    pub distance: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HashCandidate {
+    image_id: String,
+    listing_id: String,
+    perceptual_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GalleryRow {
+    image_id: String,
+    content_type: ContentType,
+    time_of_day: Option<TimeOfDay>,
+    quality_score: f32,
+    perceptual_hash: Option<String>,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CrossListingMatch {
+    pub image_id: String,
+    pub listing_id: String,
+    pub matched_image_id: String,
+    pub matched_listing_id: String,
+    pub distance: u32,
+    pub created_at: DateTime<Utc>,
+}
+
 impl ImageModel {
     pub fn new(db: Arc<Surreal<Client>>, storage: Arc<B2Storage>) -> Self {
         Self { db, storage }
@@ -312,6 +501,38 @@ impl ImageModel {
         Ok(())
     }
 
+    // Gallery filters, e.g. twilight exteriors with `content_type=Exterior&time_of_day=Twilight`
+    #[instrument(skip(self))]
+    pub async fn search_images(&self, query: ImageSearchQuery) -> Result<Vec<ImageSearchResponse>> {
+        let limit = query.limit.unwrap_or(50).min(500);
+        let mut response = self.db
+            .query("SELECT * FROM images
+                   WHERE ($listing_id = NONE OR listing_id = $listing_id)
+                   AND ($filename = NONE OR string::contains(filename, $filename))
+                   AND ($content_type = NONE OR recipe.content_type = $content_type)
+                   AND ($time_of_day = NONE OR time_of_day.time_of_day = $time_of_day)
+                   ORDER BY created_at DESC
+                   LIMIT $limit")
+            .bind(("listing_id", query.listing_id))
+            .bind(("filename", query.filename))
+            .bind(("content_type", query.content_type))
+            .bind(("time_of_day", query.time_of_day))
+            .bind(("limit", limit))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let rows: Vec<SearchRow> = response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        Ok(rows
+            .into_iter()
+            .map(|row| ImageSearchResponse {
+                image: Image { id: row.context.id.clone(), context: row.context },
+                metadata: row.metadata,
+            })
+            .collect())
+    }
+
     #[instrument(skip(self))]
     pub async fn search_by_features(&self, features: Vec<String>) -> Result<Vec<ImageContext>> {
         info!(features = ?features, "Searching images by features");
@@ -365,6 +586,7 @@ impl ImageModel {
         // Generate unique image ID
         let image_id = ImageId::generate();
         let storage_path = format!("{}/{}.webp", listing_id, image_id);
+        let hash = image::load_from_memory(&data).ok().map(|img| perceptual_hash::dhash(&img));
 
         // Upload to B2
         let b2_url = self.storage.upload_file(&storage_path, &data, "image/webp").await?;
@@ -381,6 +603,8 @@ impl ImageModel {
             b2_url,
             content_type: Some(content_type),
             gps_coordinates: None,
+            perceptual_hash: hash.map(perceptual_hash::to_hex),
+            phash_bands: hash.map(perceptual_hash::hash_bands).unwrap_or_default(),
             processing_version: 1,
             enhancement_preset: "standard".to_string(),
             created_at: Utc::now(),
@@ -403,6 +627,705 @@ impl ImageModel {
         }
     }
 
+    #[instrument(skip(self, processed), fields(image_id = %processed.id))]
+    pub async fn store_processed(&self, processed: &ProcessedImage) -> Result<StoredImage> {
+        let listing_id = processed.listing_id.as_str();
+        let image_id = processed.id.as_str();
+
+        // Untouched original and clean master go to the private prefix
+        let original_path = self.store_original(listing_id, image_id, &processed.original).await?;
+
+        let master_path = storage_keys::master_key(listing_id, image_id);
+        self.storage.upload_file(&master_path, &processed.data, "image/webp").await?;
+
+        // Published copy is scrubbed, and watermarked unless watermarking is off
+        let published_path = storage_keys::published_key(listing_id, image_id);
+        self.storage.upload_file(&published_path, &processed.published_data, "image/webp").await?;
+        let watermarked_path = processed.watermarked.then(|| published_path.clone());
+
+        let panorama = match &processed.panorama {
+            Some(tiles) => Some(self.store_panorama(listing_id, image_id, tiles).await?),
+            None => None,
+        };
+        let floor_plan = self.store_floor_plan(listing_id, image_id, processed).await?;
+
+        let record = ProcessedImageRecord {
+            image_id: image_id.to_string(),
+            listing_id: listing_id.to_string(),
+            original_path,
+            processed_path: master_path,
+            watermarked_path,
+            mime_type: "image/webp".to_string(),
+            size: processed.size,
+            dimensions: ImageDimensions {
+                width: processed.width,
+                height: processed.height,
+            },
+            status: "completed".to_string(),
+            exif: processed.exif.clone(),
+            source_metadata: processed.source_metadata.clone(),
+            source_color_space: processed.source_color_space,
+            perceptual_hash: perceptual_hash::to_hex(processed.perceptual_hash),
+            phash_bands: perceptual_hash::hash_bands(processed.perceptual_hash),
+            bracket_size: processed.bracket_frames.len(),
+            lens_correction: processed.lens_correction.clone(),
+            perspective_correction: processed.perspective_correction,
+            sky_replacement: processed.sky_replacement.clone(),
+            panorama: panorama.clone(),
+            floor_plan: floor_plan.clone(),
+            recipe: processed.recipe.clone(),
+            recipe_version: 1,
+            classification: processed.classification.clone(),
+            time_of_day: processed.time_of_day,
+            quality: processed.quality.clone(),
+            processed_at: Utc::now(),
+        };
+
+        self.db
+            .query("CREATE type::thing('images', $id) CONTENT $record")
+            .bind(("id", image_id.to_string()))
+            .bind(("record", record))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        self.record_recipe_revision(image_id, &RecipeRevision {
+            version: 1,
+            recipe: processed.recipe.clone(),
+            note: None,
+            reverted_from: None,
+            created_at: Utc::now(),
+        }).await?;
+
+        if let Some(classification) = processed.classification.as_ref().filter(|c| c.needs_review()) {
+            self.queue_content_review(listing_id, image_id, classification).await?;
+        }
+
+        let duplicates = self.record_hash_matches(listing_id, image_id, processed.perceptual_hash).await?;
+
+        for frame in &processed.bracket_frames {
+            self.store_bracket_frame(listing_id, image_id, frame).await?;
+        }
+
+        let renditions = self.store_renditions(listing_id, image_id, &processed.renditions).await?;
+
+        info!(listing_id, renditions = renditions.len(), "Stored processed image master and published variants");
+        Ok(StoredImage {
+            image_id: processed.id.clone(),
+            published_path,
+            width: processed.width,
+            height: processed.height,
+            renditions,
+            gps: processed.exif
+                .as_ref()
+                .and_then(ExifData::good_gps)
+                .map(|fix| fix.to_coordinates()),
+            location_check: None,
+            duplicates,
+            classification: processed.classification.clone(),
+            sky_replacement: processed.sky_replacement.clone(),
+            panorama,
+            floor_plan,
+            quality: Some(processed.quality.clone()),
+        })
+    }
+
+    // A suspected document keeps only its untouched original, under the private
+    // prefix; nothing is published until a reviewer says what it is
+    #[instrument(skip(self, held), fields(image_id = %held.id))]
+    pub async fn hold_for_review(&self, held: &HeldUpload) -> Result<()> {
+        let listing_id = held.listing_id.as_str();
+        let image_id = held.id.as_str();
+        let original_path = self.store_original(listing_id, image_id, &held.original).await?;
+
+        // Out of the gallery until a review re-renders it as a photo
+        self.db
+            .query("CREATE type::thing('images', $id) CONTENT {
+                listing_id: $listing_id,
+                original_path: $original_path,
+                status: 'held',
+                recipe: $recipe,
+                recipe_version: 1,
+                classification: $classification,
+                quality: $quality,
+                processed_at: time::now()
+            }")
+            .bind(("id", image_id.to_string()))
+            .bind(("listing_id", listing_id.to_string()))
+            .bind(("original_path", original_path))
+            .bind(("recipe", held.recipe.clone()))
+            .bind(("classification", held.classification.clone()))
+            .bind(("quality", held.quality.clone()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        self.record_recipe_revision(image_id, &RecipeRevision {
+            version: 1,
+            recipe: held.recipe.clone(),
+            note: None,
+            reverted_from: None,
+            created_at: Utc::now(),
+        }).await?;
+        // Queued however sure the guess was; only a person decides a file is paperwork
+        self.queue_content_review(listing_id, image_id, &held.classification).await?;
+
+        info!(listing_id, content_type = ?held.classification.content_type, "Held suspected document for content review");
+        Ok(())
+    }
+
+    async fn store_original(&self, listing_id: &str, image_id: &str, original: &[u8]) -> Result<String> {
+        let format = image::guess_format(original).ok();
+        let extension = format
+            .and_then(|f| f.extensions_str().first().copied())
+            .unwrap_or("bin");
+        let mime_type = format.map_or("application/octet-stream", |f| f.to_mime_type());
+        let original_path = storage_keys::original_key(listing_id, image_id, extension);
+        self.storage.upload_file(&original_path, original, mime_type).await?;
+        Ok(original_path)
+    }
+
+    async fn store_panorama(&self, listing_id: &str, image_id: &str, tiles: &PanoramaTiles) -> Result<PanoramaRecord> {
+        for tile in &tiles.tiles {
+            let path = storage_keys::panorama_tile_key(listing_id, image_id, tile.level, tile.face.letter(), tile.y, tile.x);
+            self.storage.upload_file(&path, &tile.data, "image/webp").await?;
+        }
+
+        let preview_path = storage_keys::panorama_preview_key(listing_id, image_id);
+        self.storage.upload_file(&preview_path, &tiles.preview, "image/webp").await?;
+
+        info!(listing_id, tiles = tiles.tiles.len(), levels = tiles.levels, "Stored panorama tiles");
+        Ok(PanoramaRecord {
+            base_path: storage_keys::panorama_base_key(listing_id, image_id),
+            extension: "webp".to_string(),
+            tile_resolution: tiles.tile_size,
+            cube_resolution: tiles.face_size,
+            max_level: tiles.levels,
+            preview_path,
+        })
+    }
+
+    async fn store_renditions(
+        &self,
+        listing_id: &str,
+        image_id: &str,
+        renditions: &[Rendition],
+    ) -> Result<Vec<RenditionRecord>> {
+        let mut records = Vec::with_capacity(renditions.len());
+        for rendition in renditions {
+            let path = storage_keys::rendition_key(
+                listing_id,
+                image_id,
+                rendition.width,
+                rendition.format.extension(),
+            );
+            self.storage.upload_file(&path, &rendition.data, rendition.format.mime_type()).await?;
+
+            let record = RenditionRecord {
+                path,
+                format: rendition.format,
+                width: rendition.width,
+                height: rendition.height,
+                size: rendition.size,
+            };
+
+            self.db
+                .query("CREATE image_renditions CONTENT {
+                    master: type::thing('images', $image_id),
+                    path: $record.path,
+                    format: $record.format,
+                    width: $record.width,
+                    height: $record.height,
+                    size: $record.size,
+                    created_at: time::now()
+                }")
+                .bind(("image_id", image_id.to_string()))
+                .bind(("record", record.clone()))
+                .await
+                .map_err(|e| AppError::Database(e.to_string()))?;
+
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
+    // Source frames are archived privately and linked to the fused master
+    async fn store_bracket_frame(&self, listing_id: &str, image_id: &str, frame: &BracketFrame) -> Result<()> {
+        let format = image::guess_format(&frame.data).ok();
+        let extension = format
+            .and_then(|f| f.extensions_str().first().copied())
+            .unwrap_or("bin");
+        let mime_type = format.map_or("application/octet-stream", |f| f.to_mime_type());
+
+        let path = storage_keys::bracket_frame_key(listing_id, image_id, frame.index, extension);
+        self.storage.upload_file(&path, &frame.data, mime_type).await?;
+
+        let record = BracketFrameRecord {
+            index: frame.index,
+            path,
+            exposure_time: frame.exposure_time,
+            exposure_bias: frame.exposure_bias,
+            mean_luminance: frame.mean_luminance,
+            offset: frame.offset,
+            is_reference: frame.is_reference,
+        };
+
+        self.db
+            .query("CREATE image_brackets CONTENT {
+                master: type::thing('images', $image_id),
+                index: $record.index,
+                path: $record.path,
+                exposure_time: $record.exposure_time,
+                exposure_bias: $record.exposure_bias,
+                mean_luminance: $record.mean_luminance,
+                offset: $record.offset,
+                is_reference: $record.is_reference,
+                created_at: time::now()
+            }")
+            .bind(("image_id", image_id.to_string()))
+            .bind(("record", record))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_bracket_frames(&self, image_id: &ImageId) -> Result<Vec<BracketFrameRecord>> {
+        let mut response = self.db
+            .query("SELECT index, path, exposure_time, exposure_bias, mean_luminance, offset, is_reference
+                   FROM image_brackets
+                   WHERE master = type::thing('images', $id)
+                   ORDER BY index")
+            .bind(("id", image_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))
+    }
+
+    // Replaces the master, published copy and renditions of an existing image and
+    // appends the recipe that produced them to its history
+    #[instrument(skip(self, processed), fields(image_id = %processed.id))]
+    pub async fn store_rerender(
+        &self,
+        processed: &ProcessedImage,
+        version: u32,
+        note: Option<String>,
+        reverted_from: Option<u32>,
+    ) -> Result<(RecipeRevision, StoredImage)> {
+        let listing_id = processed.listing_id.as_str();
+        let image_id = processed.id.as_str();
+
+        // The unique (master, version) index rejects a concurrent edit of the same version
+        let revision = RecipeRevision {
+            version,
+            recipe: processed.recipe.clone(),
+            note,
+            reverted_from,
+            created_at: Utc::now(),
+        };
+        self.record_recipe_revision(image_id, &revision).await?;
+
+        let master_path = storage_keys::master_key(listing_id, image_id);
+        self.storage.upload_file(&master_path, &processed.data, "image/webp").await?;
+
+        let published_path = storage_keys::published_key(listing_id, image_id);
+        self.storage.upload_file(&published_path, &processed.published_data, "image/webp").await?;
+        let watermarked_path = processed.watermarked.then(|| published_path.clone());
+
+        // Same keys as before, so the new tiles replace the old ones
+        let panorama = match &processed.panorama {
+            Some(tiles) => Some(self.store_panorama(listing_id, image_id, tiles).await?),
+            None => None,
+        };
+        let floor_plan = self.store_floor_plan(listing_id, image_id, processed).await?;
+
+        self.db
+            .query("UPDATE type::thing('images', $id) SET
+                    status = 'completed',
+                    processed_path = $processed_path,
+                    size = $size,
+                    dimensions = { width: $width, height: $height },
+                    watermarked_path = $watermarked_path,
+                    lens_correction = $lens_correction,
+                    perspective_correction = $perspective_correction,
+                    sky_replacement = $sky_replacement,
+                    panorama = $panorama,
+                    floor_plan = $floor_plan,
+                    quality = $quality,
+                    time_of_day = $time_of_day ?? time_of_day,
+                    recipe = $recipe,
+                    recipe_version = $version,
+                    processed_at = time::now();
+                    DELETE image_renditions WHERE master = type::thing('images', $id);")
+            .bind(("id", image_id.to_string()))
+            // A held upload gets its first master here
+            .bind(("processed_path", master_path))
+            .bind(("size", processed.size))
+            .bind(("width", processed.width))
+            .bind(("height", processed.height))
+            .bind(("watermarked_path", watermarked_path))
+            .bind(("lens_correction", processed.lens_correction.clone()))
+            .bind(("perspective_correction", processed.perspective_correction))
+            .bind(("sky_replacement", processed.sky_replacement.clone()))
+            .bind(("panorama", panorama.clone()))
+            .bind(("floor_plan", floor_plan.clone()))
+            .bind(("quality", processed.quality.clone()))
+            // Only reclassification decides it again; recipe edits keep the stored one
+            .bind(("time_of_day", processed.time_of_day))
+            .bind(("recipe", processed.recipe.clone()))
+            .bind(("version", version))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let renditions = self.store_renditions(listing_id, image_id, &processed.renditions).await?;
+
+        info!(listing_id, version, "Re-rendered image from its original");
+        let stored = StoredImage {
+            image_id: processed.id.clone(),
+            published_path,
+            width: processed.width,
+            height: processed.height,
+            renditions,
+            gps: None,
+            location_check: None,
+            duplicates: Vec::new(),
+            classification: None,
+            sky_replacement: processed.sky_replacement.clone(),
+            panorama,
+            floor_plan,
+            quality: Some(processed.quality.clone()),
+        };
+        Ok((revision, stored))
+    }
+
+    async fn store_floor_plan(&self, listing_id: &str, image_id: &str, processed: &ProcessedImage) -> Result<Option<FloorPlanRecord>> {
+        let Some(normalization) = processed.floor_plan else {
+            return Ok(None);
+        };
+        let svg_path = match &processed.floor_plan_svg {
+            Some(svg) => {
+                let path = storage_keys::floor_plan_svg_key(listing_id, image_id);
+                self.storage.upload_file(&path, svg.as_bytes(), "image/svg+xml").await?;
+                Some(path)
+            }
+            None => None,
+        };
+        Ok(Some(FloorPlanRecord { normalization, svg_path }))
+    }
+
+    async fn record_recipe_revision(&self, image_id: &str, revision: &RecipeRevision) -> Result<()> {
+        self.db
+            .query("CREATE image_recipes CONTENT {
+                master: type::thing('images', $image_id),
+                version: $revision.version,
+                recipe: $revision.recipe,
+                note: $revision.note,
+                reverted_from: $revision.reverted_from,
+                created_at: time::now()
+            }")
+            .bind(("image_id", image_id.to_string()))
+            .bind(("revision", revision.clone()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?
+            .check()
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_recipe_source(&self, image_id: &ImageId) -> Result<RecipeSource> {
+        let mut response = self.db
+            .query("SELECT listing_id, original_path, recipe, recipe_version FROM type::thing('images', $id)")
+            .bind(("id", image_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let source: Option<RecipeSource> = response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        source.ok_or_else(|| AppError::NotFound(format!("No edit recipe for image {}", image_id)))
+    }
+
+    pub async fn download_original(&self, source: &RecipeSource) -> Result<Vec<u8>> {
+        self.storage.download_file(&source.original_path).await
+    }
+
+    // Newest first
+    #[instrument(skip(self))]
+    pub async fn get_recipe_history(&self, image_id: &ImageId) -> Result<Vec<RecipeRevision>> {
+        let mut response = self.db
+            .query("SELECT version, recipe, note, reverted_from, created_at
+                   FROM image_recipes
+                   WHERE master = type::thing('images', $id)
+                   ORDER BY version DESC")
+            .bind(("id", image_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_recipe_revision(&self, image_id: &ImageId, version: u32) -> Result<RecipeRevision> {
+        let mut response = self.db
+            .query("SELECT version, recipe, note, reverted_from, created_at
+                   FROM image_recipes
+                   WHERE master = type::thing('images', $id) AND version = $version")
+            .bind(("id", image_id.to_string()))
+            .bind(("version", version))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let revision: Option<RecipeRevision> = response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        revision.ok_or_else(|| AppError::NotFound(format!("Image {} has no recipe version {}", image_id, version)))
+    }
+
+    async fn queue_content_review(
+        &self,
+        listing_id: &str,
+        image_id: &str,
+        classification: &ContentClassification,
+    ) -> Result<()> {
+        self.db
+            .query("CREATE content_reviews CONTENT {
+                image: type::thing('images', $image_id),
+                listing_id: $listing_id,
+                suggested: $suggested,
+                status: 'pending',
+                created_at: time::now()
+            }")
+            .bind(("image_id", image_id.to_string()))
+            .bind(("listing_id", listing_id.to_string()))
+            .bind(("suggested", classification.clone()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        Ok(())
+    }
+
+    // Oldest first, so the queue is worked in upload order
+    #[instrument(skip(self))]
+    pub async fn get_pending_content_reviews(&self, limit: usize) -> Result<Vec<ContentReview>> {
+        let mut response = self.db
+            .query("SELECT meta::id(image) AS image_id, listing_id, suggested, created_at
+                   FROM content_reviews
+                   WHERE status = 'pending'
+                   ORDER BY created_at
+                   LIMIT $limit")
+            .bind(("limit", limit))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))
+    }
+
+    // Records the confirmed type on the image and closes its review, if it had one
+    #[instrument(skip(self, classification))]
+    pub async fn resolve_content_review(
+        &self,
+        image_id: &ImageId,
+        classification: &ContentClassification,
+    ) -> Result<()> {
+        self.db
+            .query("UPDATE type::thing('images', $id) SET classification = $classification;
+                    UPDATE content_reviews SET
+                        status = 'resolved',
+                        resolved_type = $classification.content_type,
+                        resolved_at = time::now()
+                    WHERE image = type::thing('images', $id) AND status = 'pending';")
+            .bind(("id", image_id.to_string()))
+            .bind(("classification", classification.clone()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        Ok(())
+    }
+
+    // Candidates share at least one hash band; the exact distance is checked here
+    #[instrument(skip(self))]
+    pub async fn find_similar_images(&self, hash: u64, exclude_image_id: &str) -> Result<Vec<ImageMatch>> {
+        let mut response = self.db
+            .query("SELECT meta::id(id) AS image_id, listing_id, perceptual_hash FROM images
+                   WHERE phash_bands CONTAINSANY $bands AND meta::id(id) != $exclude")
+            .bind(("bands", perceptual_hash::hash_bands(hash)))
+            .bind(("exclude", exclude_image_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let candidates: Vec<HashCandidate> = response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        Ok(candidates
+            .into_iter()
+            .filter_map(|candidate| {
+                let other = perceptual_hash::from_hex(&candidate.perceptual_hash)?;
+                let distance = perceptual_hash::hamming_distance(hash, other);
+                (distance <= NEAR_DUPLICATE_DISTANCE).then(|| ImageMatch {
+                    image_id: candidate.image_id,
+                    listing_id: candidate.listing_id,
+                    distance,
+                })
+            })
+            .collect())
+    }
+
+    // Same-listing matches flag the image as a duplicate; matches in other listings
+    // go to image_matches for the moderation report
+    async fn record_hash_matches(&self, listing_id: &str, image_id: &str, hash: u64) -> Result<Vec<ImageMatch>> {
+        let (duplicates, reused): (Vec<_>, Vec<_>) = self
+            .find_similar_images(hash, image_id)
+            .await?
+            .into_iter()
+            .partition(|m| m.listing_id == listing_id);
+
+        if !duplicates.is_empty() {
+            warn!(listing_id, image_id, count = duplicates.len(), "Near-duplicate photo in listing");
+            self.db
+                .query("UPDATE type::thing('images', $id) SET duplicate_of = $duplicates")
+                .bind(("id", image_id.to_string()))
+                .bind(("duplicates", duplicates.clone()))
+                .await
+                .map_err(|e| AppError::Database(e.to_string()))?;
+        }
+
+        for matched in &reused {
+            warn!(listing_id, image_id, matched_listing_id = %matched.listing_id, "Photo matches another listing");
+            self.db
+                .query("CREATE image_matches CONTENT {
+                    image: type::thing('images', $image_id),
+                    matched: type::thing('images', $matched.image_id),
+                    image_id: $image_id,
+                    listing_id: $listing_id,
+                    matched_image_id: $matched.image_id,
+                    matched_listing_id: $matched.listing_id,
+                    distance: $matched.distance,
+                    created_at: time::now()
+                }")
+                .bind(("image_id", image_id.to_string()))
+                .bind(("listing_id", listing_id.to_string()))
+                .bind(("matched", matched.clone()))
+                .await
+                .map_err(|e| AppError::Database(e.to_string()))?;
+        }
+
+        Ok(duplicates)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn record_batch(&self, status: &BatchProcessingStatus) -> Result<()> {
+        self.db
+            .query("CREATE type::thing('batches', $id) CONTENT $status")
+            .bind(("id", status.batch_id.to_string()))
+            .bind(("status", status.clone()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_cross_listing_matches(&self, limit: usize) -> Result<Vec<CrossListingMatch>> {
+        let mut response = self.db
+            .query("SELECT image_id, listing_id, matched_image_id, matched_listing_id, distance, created_at
+                   FROM image_matches
+                   ORDER BY created_at DESC
+                   LIMIT $limit")
+            .bind(("limit", limit))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))
+    }
+
+    // Processed photos of a listing with what gallery ordering ranks them on
+    #[instrument(skip(self))]
+    pub async fn get_gallery_candidates(&self, listing_id: &ListingId) -> Result<Vec<GalleryCandidate>> {
+        let mut response = self.db
+            .query("SELECT meta::id(id) AS image_id,
+                          recipe.content_type AS content_type,
+                          time_of_day.time_of_day AS time_of_day,
+                          quality.overall_score AS quality_score,
+                          perceptual_hash,
+                          dimensions.width AS width,
+                          dimensions.height AS height
+                   FROM images
+                   WHERE listing_id = $listing_id AND status = 'completed' AND recipe != NONE")
+            .bind(("listing_id", listing_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let rows: Vec<GalleryRow> = response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        Ok(rows
+            .into_iter()
+            .map(|row| GalleryCandidate {
+                image_id: row.image_id,
+                content_type: row.content_type,
+                time_of_day: row.time_of_day,
+                quality_score: row.quality_score,
+                perceptual_hash: row.perceptual_hash.as_deref().and_then(perceptual_hash::from_hex),
+                width: row.width,
+                height: row.height,
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn record_location_check(&self, image_id: &ImageId, check: &PhotoLocationCheck) -> Result<()> {
+        self.db
+            .query("UPDATE type::thing('images', $id) SET location_check = $check, location_flagged = $flagged")
+            .bind(("id", image_id.to_string()))
+            .bind(("check", check.clone()))
+            .bind(("flagged", check.is_flagged()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_renditions(&self, image_id: &ImageId) -> Result<Vec<RenditionRecord>> {
+        let mut response = self.db
+            .query("SELECT path, format, width, height, size FROM image_renditions
+                   WHERE master = type::thing('images', $id)
+                   ORDER BY width, format")
+            .bind(("id", image_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_published_path(&self, image_id: &ImageId) -> Result<Option<String>> {
+        let mut response = self.db
+            .query("SELECT VALUE watermarked_path FROM type::thing('images', $id)")
+            .bind(("id", image_id.to_string()))
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+
+        let path: Option<String> = response
+            .take(0)
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        Ok(path)
+    }
+
     #[instrument(skip(self))]
     pub async fn update_batch_group(
         &self,
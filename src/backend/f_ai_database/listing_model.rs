@@ -15,8 +15,12 @@ use crate::backend::{
     f_ai_database::database::DatabaseManager,
     monitoring::events::{EventLogger, SystemEvent, Severity},
     f_ai_database::location_schema::{Location, LocationProperties},
+    image_processor::{exif_metadata::distance_m, gallery_order::GalleryOverride},
 };
 
+// Photos taken further than this from the listing pin get flagged for review
+pub const MAX_PHOTO_DISTANCE_M: f64 = 1_500.0;
+
 // Add new ListingService for handling agent requests
 pub struct ListingService {
     db: Arc<DatabaseManager>,
@@ -191,6 +195,71 @@ impl ListingService {
         Ok(())
     }
 
+    #[instrument(skip(self))]
+    pub async fn get_gps_pin(&self, listing_id: &ListingId) -> Result<Option<GpsCoordinates>> {
+        let mut result = self.db.client().query(
+            "SELECT VALUE gps_pin FROM listings WHERE listing_id = $listing_id"
+        )
+        .bind(("listing_id", listing_id.as_str()))
+        .await?;
+
+        Ok(result.take::<Option<GpsCoordinates>>(0)?)
+    }
+
+    // An empty override clears it, so the proposed order applies again
+    #[instrument(skip(self))]
+    pub async fn update_gallery_override(&self, listing_id: &ListingId, choice: GalleryOverride) -> Result<()> {
+        let choice = (choice != GalleryOverride::default()).then_some(choice);
+
+        self.db.client().query(
+            "UPDATE listings 
+             SET gallery_override = $choice, updated_at = $updated_at 
+             WHERE listing_id = $listing_id"
+        )
+        .bind(("choice", choice))
+        .bind(("updated_at", Utc::now()))
+        .bind(("listing_id", listing_id.as_str().to_string()))
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_gallery_override(&self, listing_id: &ListingId) -> Result<Option<GalleryOverride>> {
+        let mut result = self.db.client().query(
+            "SELECT VALUE gallery_override FROM listings WHERE listing_id = $listing_id"
+        )
+        .bind(("listing_id", listing_id.as_str().to_string()))
+        .await?;
+
+        Ok(result.take::<Option<GalleryOverride>>(0)?)
+    }
+
+    // Adopts the first good photo fix as the pin, otherwise checks the photo against it
+    #[instrument(skip(self))]
+    pub async fn check_photo_location(
+        &self,
+        listing_id: &ListingId,
+        photo_gps: GpsCoordinates,
+    ) -> Result<PhotoLocationCheck> {
+        let pin = match self.get_gps_pin(listing_id).await? {
+            Some(pin) => pin,
+            None => {
+                info!(listing_id = %listing_id.as_str(), "Setting listing GPS pin from photo EXIF");
+                self.update_gps_coordinates(listing_id, photo_gps).await?;
+                return Ok(PhotoLocationCheck::AdoptedAsPin);
+            }
+        };
+
+        let distance_m = distance_m(&pin, &photo_gps);
+        if distance_m > MAX_PHOTO_DISTANCE_M {
+            warn!(listing_id = %listing_id.as_str(), distance_m, "Photo GPS is far from listing pin");
+            return Ok(PhotoLocationCheck::FarFromListing { distance_m });
+        }
+
+        Ok(PhotoLocationCheck::NearListing { distance_m })
+    }
+
     pub async fn update_api_key(&self, listing_id: &str, api_key: &str) -> Result<Listing> {
         let now = Utc::now();
         
@@ -421,6 +490,20 @@ impl ListingService {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PhotoLocationCheck {
+    AdoptedAsPin,
+    NearListing { distance_m: f64 },
+    FarFromListing { distance_m: f64 },
+}
+
+impl PhotoLocationCheck {
+    pub fn is_flagged(&self) -> bool {
+        matches!(self, PhotoLocationCheck::FarFromListing { .. })
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BatchSummary {
     pub total: i64,
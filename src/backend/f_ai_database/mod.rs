@@ -5,6 +5,7 @@ pub mod user_database;
 pub mod batch_model;
 pub mod image_model;
 pub mod image_service;
+pub mod document_model;
 pub mod listing_model;
 pub mod listing_service;
 pub mod schema;
@@ -14,6 +15,7 @@ pub use config::{DatabaseConfig, LoggingConfig, LogFormat};
 pub use database::DatabaseManager;
 pub use batch_model::BatchService;
 pub use image_model::ImageModel;
+pub use document_model::DocumentModel;
 pub use image_service::ImageService;
 pub use listing_model::ListingId;
 pub use listing_service::ListingService;
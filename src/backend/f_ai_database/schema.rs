@@ -12,6 +12,14 @@ pub async fn initialize_schema(client: &Surreal<Client>) -> Result<()> {
     init_temp_files_schema(client).await?;
     init_monitoring_schema(client).await?;
     init_images_schema(client).await?;
+    init_image_renditions_schema(client).await?;
+    init_image_matches_schema(client).await?;
+    init_image_brackets_schema(client).await?;
+    init_image_recipes_schema(client).await?;
+    init_content_reviews_schema(client).await?;
+    init_documents_schema(client).await?;
+    init_listing_agents_schema(client).await?;
+    init_document_access_log_schema(client).await?;
     Ok(())
 }
 
@@ -50,6 +58,7 @@ async fn init_listings_schema(client: &Surreal<Client>) -> Result<()> {
         DEFINE FIELD version.major ON listings TYPE number ASSERT $value >= 0;
         DEFINE FIELD version.minor ON listings TYPE number ASSERT $value >= 0;
         DEFINE FIELD version.patch ON listings TYPE number ASSERT $value >= 0;
+        DEFINE FIELD gallery_override ON listings TYPE option<object>;
         DEFINE INDEX idx_listings_status ON listings FIELDS status;
         DEFINE INDEX idx_listings_location ON listings FIELDS location.country, location.district;
         DEFINE INDEX idx_listings_price ON listings FIELDS price;
@@ -150,14 +159,170 @@ async fn init_images_schema(client: &Surreal<Client>) -> Result<()> {
         DEFINE FIELD dimensions.width ON images TYPE number;
         DEFINE FIELD dimensions.height ON images TYPE number;
         DEFINE FIELD metadata ON images TYPE object;
+        DEFINE FIELD exif ON images TYPE option<object>;
+        DEFINE FIELD source_metadata ON images TYPE option<object>;
+        DEFINE FIELD source_color_space ON images TYPE option<string>;
+        DEFINE FIELD perceptual_hash ON images TYPE option<string>;
+        DEFINE FIELD phash_bands ON images TYPE array DEFAULT [];
+        DEFINE FIELD duplicate_of ON images TYPE option<array>;
+        DEFINE FIELD bracket_size ON images TYPE number DEFAULT 0;
+        DEFINE FIELD lens_correction ON images TYPE option<object>;
+        DEFINE FIELD perspective_correction ON images TYPE option<object>;
+        DEFINE FIELD sky_replacement ON images TYPE option<object>;
+        DEFINE FIELD panorama ON images TYPE option<object>;
+        DEFINE FIELD floor_plan ON images TYPE option<object>;
+        DEFINE FIELD recipe ON images TYPE option<object>;
+        DEFINE FIELD recipe_version ON images TYPE number DEFAULT 0;
+        DEFINE FIELD classification ON images TYPE option<object>;
+        DEFINE FIELD time_of_day ON images TYPE option<object>;
+        DEFINE FIELD quality ON images TYPE option<object>;
+        DEFINE FIELD location_check ON images TYPE option<object>;
+        DEFINE FIELD location_flagged ON images TYPE bool DEFAULT false;
         DEFINE FIELD created_at ON images TYPE datetime DEFAULT time::now();
         DEFINE FIELD processed_at ON images TYPE datetime;
         DEFINE FIELD status ON images TYPE string ASSERT $value INSIDE ['pending', 'processing', 'completed', 'failed'];
         DEFINE INDEX idx_images_status ON images FIELDS status;
+        DEFINE INDEX idx_images_location_flagged ON images FIELDS location_flagged;
+        DEFINE INDEX idx_images_phash_bands ON images FIELDS phash_bands;
+        DEFINE INDEX idx_images_time_of_day ON images FIELDS recipe.content_type, time_of_day.time_of_day;
+    "#).await?
+        .check()?;
+    Ok(())
+}
+
+async fn init_image_renditions_schema(client: &Surreal<Client>) -> Result<()> {
+    client.query(r#"
+        DEFINE TABLE image_renditions SCHEMALESS;
+        DEFINE FIELD master ON image_renditions TYPE record(images) ASSERT $value != NONE;
+        DEFINE FIELD path ON image_renditions TYPE string ASSERT $value != NONE;
+        DEFINE FIELD format ON image_renditions TYPE string ASSERT $value INSIDE ['webp', 'jpeg'];
+        DEFINE FIELD width ON image_renditions TYPE number;
+        DEFINE FIELD height ON image_renditions TYPE number;
+        DEFINE FIELD size ON image_renditions TYPE number;
+        DEFINE FIELD created_at ON image_renditions TYPE datetime DEFAULT time::now();
+        DEFINE INDEX idx_renditions_master ON image_renditions FIELDS master;
+        DEFINE INDEX idx_renditions_variant ON image_renditions FIELDS master, width, format UNIQUE;
+    "#).await?
+        .check()?;
+    Ok(())
+}
+
+async fn init_image_matches_schema(client: &Surreal<Client>) -> Result<()> {
+    client.query(r#"
+        DEFINE TABLE image_matches SCHEMALESS;
+        DEFINE FIELD image ON image_matches TYPE record(images) ASSERT $value != NONE;
+        DEFINE FIELD matched ON image_matches TYPE record(images) ASSERT $value != NONE;
+        DEFINE FIELD listing_id ON image_matches TYPE string;
+        DEFINE FIELD matched_listing_id ON image_matches TYPE string;
+        DEFINE FIELD distance ON image_matches TYPE number;
+        DEFINE FIELD created_at ON image_matches TYPE datetime DEFAULT time::now();
+        DEFINE INDEX idx_matches_listing ON image_matches FIELDS listing_id;
+        DEFINE INDEX idx_matches_matched_listing ON image_matches FIELDS matched_listing_id;
+        DEFINE INDEX idx_matches_pair ON image_matches FIELDS image, matched UNIQUE;
+    "#).await?
+        .check()?;
+    Ok(())
+}
+
+async fn init_image_brackets_schema(client: &Surreal<Client>) -> Result<()> {
+    client.query(r#"
+        DEFINE TABLE image_brackets SCHEMALESS;
+        DEFINE FIELD master ON image_brackets TYPE record(images) ASSERT $value != NONE;
+        DEFINE FIELD index ON image_brackets TYPE number ASSERT $value >= 0;
+        DEFINE FIELD path ON image_brackets TYPE string ASSERT $value != NONE;
+        DEFINE FIELD exposure_time ON image_brackets TYPE option<number>;
+        DEFINE FIELD exposure_bias ON image_brackets TYPE option<number>;
+        DEFINE FIELD mean_luminance ON image_brackets TYPE number;
+        DEFINE FIELD offset ON image_brackets TYPE object;
+        DEFINE FIELD is_reference ON image_brackets TYPE bool DEFAULT false;
+        DEFINE FIELD created_at ON image_brackets TYPE datetime DEFAULT time::now();
+        DEFINE INDEX idx_brackets_master ON image_brackets FIELDS master, index UNIQUE;
+    "#).await?
+        .check()?;
+    Ok(())
+}
+
+async fn init_image_recipes_schema(client: &Surreal<Client>) -> Result<()> {
+    client.query(r#"
+        DEFINE TABLE image_recipes SCHEMALESS;
+        DEFINE FIELD master ON image_recipes TYPE record(images) ASSERT $value != NONE;
+        DEFINE FIELD version ON image_recipes TYPE number ASSERT $value >= 1;
+        DEFINE FIELD recipe ON image_recipes TYPE object;
+        DEFINE FIELD note ON image_recipes TYPE option<string>;
+        DEFINE FIELD reverted_from ON image_recipes TYPE option<number>;
+        DEFINE FIELD created_at ON image_recipes TYPE datetime DEFAULT time::now();
+        DEFINE INDEX idx_recipes_master ON image_recipes FIELDS master, version UNIQUE;
+    "#).await?
+        .check()?;
+    Ok(())
+}
+
+async fn init_content_reviews_schema(client: &Surreal<Client>) -> Result<()> {
+    client.query(r#"
+        DEFINE TABLE content_reviews SCHEMALESS;
+        DEFINE FIELD image ON content_reviews TYPE record(images) ASSERT $value != NONE;
+        DEFINE FIELD listing_id ON content_reviews TYPE string;
+        DEFINE FIELD suggested ON content_reviews TYPE object;
+        DEFINE FIELD status ON content_reviews TYPE string ASSERT $value INSIDE ['pending', 'resolved'];
+        DEFINE FIELD resolved_type ON content_reviews TYPE option<string>;
+        DEFINE FIELD created_at ON content_reviews TYPE datetime DEFAULT time::now();
+        DEFINE FIELD resolved_at ON content_reviews TYPE option<datetime>;
+        DEFINE INDEX idx_content_reviews_status ON content_reviews FIELDS status, created_at;
+        DEFINE INDEX idx_content_reviews_image ON content_reviews FIELDS image UNIQUE;
     "#).await?
         .check()?;
     Ok(())
 }
 
 // Copy all other init_*_schema functions from database.rs
-// Keep the same implementation but change self.client to client parameter 
\ No newline at end of file
+// Keep the same implementation but change self.client to client parameter 
+
+// Private documents; the files themselves are encrypted in storage
+async fn init_documents_schema(client: &Surreal<Client>) -> Result<()> {
+    client.query(r#"
+        DEFINE TABLE documents SCHEMALESS;
+        DEFINE FIELD document_id ON documents TYPE string ASSERT $value != NONE;
+        DEFINE FIELD listing_id ON documents TYPE string ASSERT $value != NONE;
+        DEFINE FIELD content_type ON documents TYPE string ASSERT $value INSIDE ['TitlePaper', 'SPAContract', 'Reservation', 'RentalAgreement', 'ListingAgreement'];
+        DEFINE FIELD format ON documents TYPE string ASSERT $value INSIDE ['pdf', 'scan'];
+        DEFINE FIELD original_path ON documents TYPE string ASSERT string::starts_with($value, 'private/');
+        DEFINE FIELD redacted_path ON documents TYPE option<string>;
+        DEFINE FIELD redactions ON documents TYPE array DEFAULT [];
+        DEFINE FIELD key_id ON documents TYPE string ASSERT $value != NONE;
+        DEFINE FIELD uploaded_by ON documents TYPE string;
+        DEFINE FIELD created_at ON documents TYPE datetime DEFAULT time::now();
+        DEFINE INDEX idx_documents_listing ON documents FIELDS listing_id;
+    "#).await?
+        .check()?;
+    Ok(())
+}
+
+// Agents a listing owner has given access to the listing's documents
+async fn init_listing_agents_schema(client: &Surreal<Client>) -> Result<()> {
+    client.query(r#"
+        DEFINE TABLE listing_agents SCHEMALESS;
+        DEFINE FIELD listing_id ON listing_agents TYPE string ASSERT $value != NONE;
+        DEFINE FIELD agent_id ON listing_agents TYPE string ASSERT $value != NONE;
+        DEFINE FIELD assigned_by ON listing_agents TYPE string;
+        DEFINE FIELD assigned_at ON listing_agents TYPE datetime DEFAULT time::now();
+        DEFINE INDEX idx_listing_agents ON listing_agents FIELDS listing_id, agent_id UNIQUE;
+    "#).await?
+        .check()?;
+    Ok(())
+}
+
+// Every attempt to reach a document, allowed or denied
+async fn init_document_access_log_schema(client: &Surreal<Client>) -> Result<()> {
+    client.query(r#"
+        DEFINE TABLE document_access_log SCHEMALESS;
+        DEFINE FIELD listing_id ON document_access_log TYPE string ASSERT $value != NONE;
+        DEFINE FIELD document_id ON document_access_log TYPE option<string>;
+        DEFINE FIELD user_id ON document_access_log TYPE string ASSERT $value != NONE;
+        DEFINE FIELD action ON document_access_log TYPE string ASSERT $value != NONE;
+        DEFINE FIELD granted ON document_access_log TYPE bool;
+        DEFINE FIELD at ON document_access_log TYPE datetime DEFAULT time::now();
+        DEFINE INDEX idx_document_access ON document_access_log FIELDS listing_id, at;
+    "#).await?
+        .check()?;
+    Ok(())
+}
@@ -1,8 +1,9 @@
 use super::quality_report::{QualityReport, IssueSeverity, IssueCategory};
-use serde::Serialize;
+use super::listing_look::LookAdjustmentRecord;
+use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct BatchQualityReport {
     pub overall_batch_score: f32,
     pub total_images: usize,
@@ -10,9 +11,11 @@ pub struct BatchQualityReport {
     pub quality_distribution: QualityDistribution,
     pub content_type_analysis: HashMap<String, ContentTypeStats>,
     pub recommendations: Vec<String>,
+    // Photos nudged toward their content type's look by the listing pass
+    pub look_adjustments: Vec<LookAdjustmentRecord>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct IssueSummary {
     pub critical_issues: usize,
     pub major_issues: usize,
@@ -20,7 +23,7 @@ pub struct IssueSummary {
     pub issues_by_category: HashMap<IssueCategory, usize>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct QualityDistribution {
     pub excellent: usize,  // Score > 0.8
     pub good: usize,      // Score 0.6-0.8
@@ -28,7 +31,7 @@ pub struct QualityDistribution {
     pub poor: usize,      // Score < 0.4
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ContentTypeStats {
     pub count: usize,
     pub avg_score: f32,
@@ -44,9 +47,14 @@ impl BatchQualityReport {
             quality_distribution: QualityDistribution::new(),
             content_type_analysis: HashMap::new(),
             recommendations: Vec::new(),
+            look_adjustments: Vec::new(),
         }
     }
 
+    pub fn add_look_adjustment(&mut self, adjustment: LookAdjustmentRecord) {
+        self.look_adjustments.push(adjustment);
+    }
+
     pub fn add_report(&mut self, report: &QualityReport, content_type: &str) {
         self.total_images += 1;
         
@@ -0,0 +1,360 @@
+use std::io::Cursor;
+use image::{DynamicImage, ImageBuffer, ImageDecoder, ImageFormat, ImageReader, Pixel};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformExecutor, TransformOptions, Xyzd};
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, warn};
+
+use crate::backend::common::error::error::{Result, AppError, ImageError};
+
+// Rows converted per parallel task
+const TRANSFORM_ROWS: usize = 64;
+
+// Colorants this close to a reference profile's (D50 XYZ) count as that space;
+// vendors' profiles differ from the computed ones only in rounding
+const COLORANT_TOLERANCE: f64 = 0.005;
+
+// JPEG stores ICC data in APP2 segments of at most this many profile bytes
+const JPEG_ICC_CHUNK: usize = 65_519;
+const JPEG_ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+
+// VP8X flag for an ICCP chunk, and for alpha in the image
+const VP8X_ICC: u8 = 0x20;
+const VP8X_ALPHA: u8 = 0x10;
+
+static SRGB: Lazy<ColorProfile> = Lazy::new(ColorProfile::new_srgb);
+static DISPLAY_P3: Lazy<ColorProfile> = Lazy::new(ColorProfile::new_display_p3);
+static ADOBE_RGB: Lazy<ColorProfile> = Lazy::new(ColorProfile::new_adobe_rgb);
+
+// Embedded in everything we encode
+static SRGB_ICC: Lazy<Vec<u8>> = Lazy::new(|| SRGB.encode().expect("built-in sRGB profile encodes"));
+
+// Colour space the upload was shot in, as read from its embedded ICC profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceColorSpace {
+    // No profile; treated as sRGB, as browsers do
+    Untagged,
+    Srgb,
+    // iPhones and recent Android phones
+    DisplayP3,
+    // Pro cameras set to their wide-gamut mode
+    AdobeRgb,
+    // Any other RGB profile, converted through its own data
+    OtherRgb,
+    // Grey and CMYK profiles, or profile data we can't read; pixels are used as decoded
+    Unsupported,
+}
+
+// The embedded profile, from the header only. None when there is none or the
+// container can't be read; decoding reports that properly.
+pub fn read_icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    match decoder.icc_profile() {
+        Ok(profile) => profile.filter(|profile| !profile.is_empty()),
+        Err(e) => {
+            debug!(error = %e, "Upload has no readable ICC profile");
+            None
+        }
+    }
+}
+
+pub fn identify(profile: &ColorProfile) -> SourceColorSpace {
+    if profile.color_space != DataColorSpace::Rgb {
+        return SourceColorSpace::Unsupported;
+    }
+    let matches = |reference: &ColorProfile| {
+        [
+            (profile.red_colorant, reference.red_colorant),
+            (profile.green_colorant, reference.green_colorant),
+            (profile.blue_colorant, reference.blue_colorant),
+        ]
+        .iter()
+        .all(|(a, b)| colorant_distance(*a, *b) <= COLORANT_TOLERANCE)
+    };
+
+    if matches(&SRGB) {
+        SourceColorSpace::Srgb
+    } else if matches(&DISPLAY_P3) {
+        SourceColorSpace::DisplayP3
+    } else if matches(&ADOBE_RGB) {
+        SourceColorSpace::AdobeRgb
+    } else {
+        SourceColorSpace::OtherRgb
+    }
+}
+
+fn colorant_distance(a: Xyzd, b: Xyzd) -> f64 {
+    (a.x - b.x).abs().max((a.y - b.y).abs()).max((a.z - b.z).abs())
+}
+
+// Converts decoded pixels from the embedded profile to sRGB, so the analysis and
+// colour heuristics see the values a browser would show. 8 and 16-bit RGB(A) keep
+// their depth; other layouts are converted through 8-bit RGB(A).
+pub fn to_srgb(img: DynamicImage, icc: Option<&[u8]>) -> Result<(DynamicImage, SourceColorSpace)> {
+    let Some(icc) = icc else {
+        return Ok((img, SourceColorSpace::Untagged));
+    };
+    let profile = match ColorProfile::new_from_slice(icc) {
+        Ok(profile) => profile,
+        Err(e) => {
+            warn!(error = %e, "Unreadable ICC profile, treating upload as sRGB");
+            return Ok((img, SourceColorSpace::Unsupported));
+        }
+    };
+
+    let color_space = identify(&profile);
+    // Grey pixels can't carry an RGB profile's gamut
+    if matches!(color_space, SourceColorSpace::Srgb | SourceColorSpace::Unsupported) || img.color().channel_count() < 3 {
+        return Ok((img, color_space));
+    }
+
+    let converted = match img {
+        DynamicImage::ImageRgb8(buffer) => DynamicImage::ImageRgb8(convert_8bit(&profile, buffer, Layout::Rgb)?),
+        DynamicImage::ImageRgba8(buffer) => DynamicImage::ImageRgba8(convert_8bit(&profile, buffer, Layout::Rgba)?),
+        DynamicImage::ImageRgb16(buffer) => DynamicImage::ImageRgb16(convert_16bit(&profile, buffer, Layout::Rgb)?),
+        DynamicImage::ImageRgba16(buffer) => DynamicImage::ImageRgba16(convert_16bit(&profile, buffer, Layout::Rgba)?),
+        other if other.color().has_alpha() => {
+            DynamicImage::ImageRgba8(convert_8bit(&profile, other.into_rgba8(), Layout::Rgba)?)
+        }
+        other => DynamicImage::ImageRgb8(convert_8bit(&profile, other.into_rgb8(), Layout::Rgb)?),
+    };
+    debug!(?color_space, "Converted upload to sRGB");
+    Ok((converted, color_space))
+}
+
+fn convert_8bit<P>(profile: &ColorProfile, src: ImageBuffer<P, Vec<u8>>, layout: Layout) -> Result<ImageBuffer<P, Vec<u8>>>
+where
+    P: Pixel<Subpixel = u8>,
+{
+    let transform = profile
+        .create_transform_8bit(layout, &SRGB, layout, TransformOptions::default())
+        .map_err(cms_error)?;
+    convert_rows(transform.as_ref(), src)
+}
+
+fn convert_16bit<P>(profile: &ColorProfile, src: ImageBuffer<P, Vec<u16>>, layout: Layout) -> Result<ImageBuffer<P, Vec<u16>>>
+where
+    P: Pixel<Subpixel = u16>,
+{
+    let transform = profile
+        .create_transform_16bit(layout, &SRGB, layout, TransformOptions::default())
+        .map_err(cms_error)?;
+    convert_rows(transform.as_ref(), src)
+}
+
+// Row chunks in parallel; every pixel is converted on its own, so the output
+// doesn't depend on the thread count
+fn convert_rows<P, T>(
+    transform: &(dyn TransformExecutor<T> + Send + Sync),
+    src: ImageBuffer<P, Vec<T>>,
+) -> Result<ImageBuffer<P, Vec<T>>>
+where
+    P: Pixel<Subpixel = T>,
+    T: image::Primitive + Default + Send + Sync,
+{
+    let (width, height) = src.dimensions();
+    let stride = (width as usize * P::CHANNEL_COUNT as usize).max(1);
+    let mut dst = vec![T::default(); src.as_raw().len()];
+    dst.par_chunks_mut(stride * TRANSFORM_ROWS)
+        .zip(src.as_raw().par_chunks(stride * TRANSFORM_ROWS))
+        .try_for_each(|(dst, src)| transform.transform(src, dst))
+        .map_err(cms_error)?;
+    ImageBuffer::from_raw(width, height, dst)
+        .ok_or_else(|| AppError::ImageError(ImageError::ConversionError("ICC conversion changed the buffer size".into())))
+}
+
+fn cms_error(e: moxcms::CmsError) -> AppError {
+    AppError::ImageError(ImageError::ConversionError(format!("ICC conversion failed: {}", e)))
+}
+
+// Tags an encoded WebP or JPEG as sRGB, replacing any profile it already has.
+// Untagged files are shown as sRGB by most browsers but not all colour-managed
+// viewers, so our outputs always say so.
+pub fn tag_srgb(data: Vec<u8>, format: ImageFormat) -> Result<Vec<u8>> {
+    embed_icc(data, format, &SRGB_ICC)
+}
+
+pub fn embed_icc(data: Vec<u8>, format: ImageFormat, icc: &[u8]) -> Result<Vec<u8>> {
+    match format {
+        ImageFormat::WebP => embed_icc_webp(&data, icc),
+        ImageFormat::Jpeg => embed_icc_jpeg(&data, icc),
+        other => Err(AppError::ImageError(ImageError::ConversionError(format!(
+            "Can't embed an ICC profile in {:?}", other
+        )))),
+    }
+}
+
+fn malformed(format: &str) -> AppError {
+    AppError::ImageError(ImageError::InvalidFormat(format!("Malformed {} while embedding ICC profile", format)))
+}
+
+// The profile goes in APP2 segments after the existing APPn segments, so JFIF/EXIF stay first
+fn embed_icc_jpeg(data: &[u8], icc: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 4 || data[..2] != [0xFF, 0xD8] {
+        return Err(malformed("JPEG"));
+    }
+
+    let mut out = Vec::with_capacity(data.len() + icc.len() + 64);
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF && (0xE0..=0xEF).contains(&data[pos + 1]) {
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + length;
+        if length < 2 || end > data.len() {
+            return Err(malformed("JPEG"));
+        }
+        let is_icc = data[pos + 1] == 0xE2 && data[pos + 4..end].starts_with(JPEG_ICC_SIGNATURE);
+        if !is_icc {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+
+    let chunks: Vec<&[u8]> = icc.chunks(JPEG_ICC_CHUNK).collect();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let length = 2 + JPEG_ICC_SIGNATURE.len() + 2 + chunk.len();
+        out.extend_from_slice(&[0xFF, 0xE2]);
+        out.extend_from_slice(&(length as u16).to_be_bytes());
+        out.extend_from_slice(JPEG_ICC_SIGNATURE);
+        out.extend_from_slice(&[index as u8 + 1, chunks.len() as u8]);
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&data[pos..]);
+    Ok(out)
+}
+
+// Simple WebP files (a lone VP8/VP8L chunk) are rewritten in the extended format,
+// which is the only one that can carry an ICCP chunk; it has to follow VP8X
+fn embed_icc_webp(data: &[u8], icc: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err(malformed("WebP"));
+    }
+
+    let mut chunks: Vec<([u8; 4], &[u8])> = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let fourcc: [u8; 4] = data[pos..pos + 4].try_into().unwrap();
+        let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let end = pos + 8 + size;
+        if end > data.len() {
+            return Err(malformed("WebP"));
+        }
+        chunks.push((fourcc, &data[pos + 8..end]));
+        pos = end + size % 2;
+    }
+
+    let mut vp8x = match chunks.first() {
+        Some((fourcc, payload)) if fourcc == b"VP8X" && payload.len() >= 10 => payload[..10].to_vec(),
+        Some((fourcc, payload)) => simple_vp8x(fourcc, payload).ok_or_else(|| malformed("WebP"))?,
+        None => return Err(malformed("WebP")),
+    };
+    vp8x[0] |= VP8X_ICC;
+
+    let mut body = Vec::with_capacity(data.len() + icc.len() + 32);
+    body.extend_from_slice(b"WEBP");
+    push_chunk(&mut body, b"VP8X", &vp8x);
+    push_chunk(&mut body, b"ICCP", icc);
+    for (fourcc, payload) in chunks.iter().filter(|(fourcc, _)| fourcc != b"VP8X" && fourcc != b"ICCP") {
+        push_chunk(&mut body, fourcc, payload);
+    }
+
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+// VP8X payload for a simple file: flags, 3 reserved bytes, then the canvas
+// width and height minus one as 24-bit little-endian values
+fn simple_vp8x(fourcc: &[u8; 4], payload: &[u8]) -> Option<Vec<u8>> {
+    let (width, height, alpha) = match fourcc {
+        b"VP8 " if payload.len() >= 10 => {
+            let width = u16::from_le_bytes([payload[6], payload[7]]) as u32 & 0x3FFF;
+            let height = u16::from_le_bytes([payload[8], payload[9]]) as u32 & 0x3FFF;
+            (width, height, false)
+        }
+        b"VP8L" if payload.len() >= 5 && payload[0] == 0x2F => {
+            let bits = u32::from_le_bytes(payload[1..5].try_into().ok()?);
+            ((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1, (bits >> 28) & 1 == 1)
+        }
+        _ => return None,
+    };
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let mut vp8x = vec![if alpha { VP8X_ALPHA } else { 0 }, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    Some(vp8x)
+}
+
+fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use crate::backend::image_processor::derivatives::{encode_mozjpeg, encode_webp, encode_webp_lossless};
+
+    fn p3_icc() -> Vec<u8> {
+        DISPLAY_P3.encode().unwrap()
+    }
+
+    #[test]
+    fn test_display_p3_upload_is_converted_to_srgb() {
+        // A saturated P3 red lies outside sRGB, so its green clips to zero; a neutral grey maps to itself
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, _| {
+            if x < 32 { Rgb([230, 40, 30]) } else { Rgb([128, 128, 128]) }
+        }));
+        let (converted, color_space) = to_srgb(img, Some(&p3_icc())).unwrap();
+        let converted = converted.to_rgb8();
+
+        assert_eq!(color_space, SourceColorSpace::DisplayP3);
+        let red = converted.get_pixel(0, 0);
+        assert!(red[0] > 240 && red[1] == 0, "{:?}", red);
+        let grey = converted.get_pixel(63, 0);
+        assert!(grey.0.iter().all(|&c| (c as i32 - 128).abs() <= 1), "{:?}", grey);
+
+        let adobe = ADOBE_RGB.encode().unwrap();
+        let (_, color_space) = to_srgb(DynamicImage::new_rgb8(4, 4), Some(&adobe)).unwrap();
+        assert_eq!(color_space, SourceColorSpace::AdobeRgb);
+        let (_, color_space) = to_srgb(DynamicImage::new_rgb8(4, 4), None).unwrap();
+        assert_eq!(color_space, SourceColorSpace::Untagged);
+    }
+
+    #[test]
+    fn test_profile_survives_encode_and_decode() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(48, 32, Rgb([200, 120, 60])));
+        let with_alpha = DynamicImage::ImageRgba8(RgbaImage::from_pixel(48, 32, Rgba([200, 120, 60, 128])));
+
+        for (data, format, alpha) in [
+            (encode_webp(&img).unwrap(), ImageFormat::WebP, false),
+            (encode_webp_lossless(&with_alpha).unwrap(), ImageFormat::WebP, true),
+            (encode_mozjpeg(&img, 80.0).unwrap(), ImageFormat::Jpeg, false),
+        ] {
+            // Encoders tag as sRGB; re-tagging replaces rather than adds
+            assert_eq!(read_icc_profile(&data).as_deref(), Some(SRGB_ICC.as_slice()), "{:?}", format);
+            let retagged = embed_icc(data, format, &p3_icc()).unwrap();
+            assert_eq!(read_icc_profile(&retagged), Some(p3_icc()), "{:?}", format);
+
+            let decoded = image::load_from_memory(&retagged).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (48, 32));
+            assert_eq!(decoded.color().has_alpha(), alpha);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use image::{DynamicImage, GrayImage};
+use imageproc::gradients::{horizontal_sobel, vertical_sobel};
+use serde::{Serialize, Deserialize};
+
+use crate::backend::llm_caller::types::{ImageAnalysis, LocationContext};
+use super::panorama::is_equirectangular;
+use super::processor::ContentType;
+
+// Below this a person confirms the type before the listing goes out
+pub const REVIEW_CONFIDENCE: f32 = 0.6;
+// Local signals this sure skip the LLM call
+pub const LOCAL_ACCEPT_CONFIDENCE: f32 = 0.75;
+
+// Large enough that text strokes and plan walls survive the downscale
+const SIGNAL_SIZE: u32 = 768;
+const EDGE_GRADIENT: f32 = 200.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassificationSource {
+    Uploader,
+    Heuristic,
+    Llm,
+    Reviewer,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentClassification {
+    pub content_type: ContentType,
+    // 0-1; uploader and reviewer choices are 1
+    pub confidence: f32,
+    pub source: ClassificationSource,
+    pub reason: String,
+}
+
+impl ContentClassification {
+    pub fn from_uploader(content_type: ContentType) -> Self {
+        Self::certain(content_type, ClassificationSource::Uploader, "set by uploader")
+    }
+
+    pub fn from_reviewer(content_type: ContentType) -> Self {
+        Self::certain(content_type, ClassificationSource::Reviewer, "confirmed in review")
+    }
+
+    fn certain(content_type: ContentType, source: ClassificationSource, reason: &str) -> Self {
+        Self { content_type, confidence: 1.0, source, reason: reason.to_string() }
+    }
+
+    pub fn needs_review(&self) -> bool {
+        self.confidence < REVIEW_CONFIDENCE
+    }
+
+    // Agreement raises confidence; on disagreement the more confident answer wins
+    pub fn combine(self, llm: Option<ContentClassification>) -> ContentClassification {
+        match llm {
+            None => self,
+            Some(llm) if llm.content_type == self.content_type => ContentClassification {
+                confidence: (self.confidence.max(llm.confidence) + 0.1).min(0.95),
+                reason: format!("{}; {}", self.reason, llm.reason),
+                ..llm
+            },
+            Some(llm) if llm.confidence >= self.confidence => llm,
+            Some(_) => self,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ContentSignals {
+    // Share of the top third that looks like sky
+    pub sky_fraction: f32,
+    // Bright, unsaturated pixels: paper
+    pub paper_fraction: f32,
+    // Dark, unsaturated pixels: text or linework
+    pub ink_fraction: f32,
+    pub mean_saturation: f32,
+    // Share of pixels on a strong edge
+    pub edge_density: f32,
+    // Share of ink on long horizontal or vertical runs, i.e. walls rather than text
+    pub line_ink_fraction: f32,
+    // 2:1 frame as written by 360° cameras
+    pub equirectangular: bool,
+}
+
+impl ContentSignals {
+    // Sky comes from the processor's detector so both agree on what sky looks like
+    pub fn measure(img: &DynamicImage, sky_fraction: f32) -> Self {
+        let small = img.thumbnail(SIGNAL_SIZE, SIGNAL_SIZE).to_rgb8();
+        let (width, height) = small.dimensions();
+        let pixels = (width * height).max(1) as f32;
+
+        let mut paper = 0usize;
+        let mut saturation_sum = 0.0;
+        let mut ink = vec![false; (width * height) as usize];
+        for (x, y, pixel) in small.enumerate_pixels() {
+            let [r, g, b] = pixel.0;
+            let max = r.max(g).max(b);
+            let min = r.min(g).min(b);
+            let saturation = if max > 0 { (max - min) as f32 / max as f32 } else { 0.0 };
+            saturation_sum += saturation;
+
+            if saturation < 0.15 {
+                if min >= 200 {
+                    paper += 1;
+                } else if max < 128 {
+                    ink[(y * width + x) as usize] = true;
+                }
+            }
+        }
+        let ink_count = ink.iter().filter(|&&dark| dark).count();
+
+        let gray = image::imageops::grayscale(&small);
+        let edge_density = strong_edges(&gray) as f32 / pixels;
+        let line_ink = long_run_pixels(&ink, width as usize, height as usize);
+
+        Self {
+            sky_fraction,
+            paper_fraction: paper as f32 / pixels,
+            ink_fraction: ink_count as f32 / pixels,
+            mean_saturation: saturation_sum / pixels,
+            edge_density,
+            line_ink_fraction: if ink_count > 0 { line_ink as f32 / ink_count as f32 } else { 0.0 },
+            equirectangular: is_equirectangular(img.width(), img.height()),
+        }
+    }
+}
+
+fn strong_edges(gray: &GrayImage) -> usize {
+    let gx = horizontal_sobel(gray);
+    let gy = vertical_sobel(gray);
+    gx.pixels()
+        .zip(gy.pixels())
+        .filter(|(x, y)| (x[0] as f32).hypot(y[0] as f32) > EDGE_GRADIENT)
+        .count()
+}
+
+// Ink pixels on a horizontal or vertical run of at least a tenth of the image
+fn long_run_pixels(ink: &[bool], width: usize, height: usize) -> usize {
+    let mut on_line = vec![false; ink.len()];
+    for y in 0..height {
+        mark_runs(ink, &mut on_line, (0..width).map(|x| y * width + x), (width / 10).max(2));
+    }
+    for x in 0..width {
+        mark_runs(ink, &mut on_line, (0..height).map(|y| y * width + x), (height / 10).max(2));
+    }
+    on_line.iter().filter(|&&line| line).count()
+}
+
+fn mark_runs(ink: &[bool], on_line: &mut [bool], cells: impl Iterator<Item = usize>, min_run: usize) {
+    let mut run = Vec::new();
+    let mut flush = |run: &mut Vec<usize>| {
+        if run.len() >= min_run {
+            run.iter().for_each(|&i| on_line[i] = true);
+        }
+        run.clear();
+    };
+
+    for i in cells {
+        if ink[i] {
+            run.push(i);
+        } else {
+            flush(&mut run);
+        }
+    }
+    flush(&mut run);
+}
+
+// Cheap first pass. Documents and outdoor shots stand out on their own; telling
+// rooms apart needs the image analysis, so interiors come back unsure.
+pub fn classify_local(signals: &ContentSignals) -> ContentClassification {
+    let heuristic = |content_type, confidence: f32, reason: String| ContentClassification {
+        content_type,
+        confidence,
+        source: ClassificationSource::Heuristic,
+        reason,
+    };
+
+    let document = signals.paper_fraction + signals.ink_fraction;
+    if signals.paper_fraction > 0.5 && document > 0.8 && signals.mean_saturation < 0.1 {
+        if signals.line_ink_fraction > 0.5 {
+            let confidence = 0.6 + 0.7 * (signals.line_ink_fraction - 0.5);
+            return heuristic(
+                ContentType::FloorPlan,
+                confidence.min(0.95),
+                format!("line drawing ({:.0}% of ink on long lines)", signals.line_ink_fraction * 100.0),
+            );
+        }
+        // Which kind of paperwork it is takes someone who can read it
+        return heuristic(
+            ContentType::TitlePaper,
+            0.5,
+            format!("text document ({:.0}% paper)", signals.paper_fraction * 100.0),
+        );
+    }
+
+    // Ordinary photos are almost never exactly 2:1, and sky checks don't hold on a sphere
+    if signals.equirectangular {
+        return heuristic(ContentType::Panorama, 0.8, "2:1 equirectangular frame".to_string());
+    }
+
+    if signals.sky_fraction >= 0.2 {
+        if signals.sky_fraction > 0.6 && signals.edge_density < 0.04 {
+            return heuristic(
+                ContentType::View,
+                0.6,
+                format!("mostly open sky ({:.0}% of the top third)", signals.sky_fraction * 100.0),
+            );
+        }
+        return heuristic(
+            ContentType::Exterior,
+            (0.55 + 0.6 * signals.sky_fraction).min(0.9),
+            format!("sky in {:.0}% of the top third", signals.sky_fraction * 100.0),
+        );
+    }
+
+    heuristic(ContentType::OtherInterior, 0.35, "no sky or document features".to_string())
+}
+
+// Earliest keyword in the description wins, so "kitchen opening onto the living
+// area" is a kitchen
+const FOCUS_KEYWORDS: &[(&str, ContentType)] = &[
+    ("equirectangular", ContentType::Panorama),
+    ("360 panorama", ContentType::Panorama),
+    ("floor plan", ContentType::FloorPlan),
+    ("floorplan", ContentType::FloorPlan),
+    ("title deed", ContentType::TitlePaper),
+    ("chanote", ContentType::TitlePaper),
+    ("sale and purchase", ContentType::SPAContract),
+    ("reservation", ContentType::Reservation),
+    ("rental agreement", ContentType::RentalAgreement),
+    ("lease", ContentType::RentalAgreement),
+    ("listing agreement", ContentType::ListingAgreement),
+    ("kitchen", ContentType::Kitchen),
+    ("bathroom", ContentType::Bathroom),
+    ("toilet", ContentType::Bathroom),
+    ("shower", ContentType::Bathroom),
+    ("bedroom", ContentType::Bedroom),
+    ("living", ContentType::LivingRoom),
+    ("lounge", ContentType::LivingRoom),
+    ("view", ContentType::View),
+    ("skyline", ContentType::View),
+    ("facade", ContentType::Exterior),
+    ("exterior", ContentType::Exterior),
+    ("pool", ContentType::Exterior),
+    ("garden", ContentType::Exterior),
+    ("building", ContentType::Exterior),
+];
+
+// Whole words only, so "overview" isn't a view and "please" isn't a lease; a
+// plural "s" is allowed
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let boundary = |c: Option<char>| !c.is_some_and(char::is_alphanumeric);
+    text.match_indices(word).map(|(at, _)| at).find(|&at| {
+        let rest = &text[at + word.len()..];
+        boundary(text[..at].chars().next_back())
+            && (boundary(rest.chars().next()) || (rest.starts_with('s') && boundary(rest[1..].chars().next())))
+    })
+}
+
+fn keyword_type(text: &str) -> Option<ContentType> {
+    let text = text.to_lowercase();
+    FOCUS_KEYWORDS
+        .iter()
+        .filter_map(|(keyword, content_type)| find_word(&text, keyword).map(|at| (at, *content_type)))
+        .min_by_key(|(at, _)| *at)
+        .map(|(_, content_type)| content_type)
+}
+
+pub fn classify_description(primary_focus: &str, area_type: &str) -> Option<ContentClassification> {
+    let llm = |content_type, confidence| ContentClassification {
+        content_type,
+        confidence,
+        source: ClassificationSource::Llm,
+        reason: format!("image analysis: {}", primary_focus),
+    };
+
+    match (keyword_type(primary_focus), keyword_type(area_type)) {
+        (Some(focus), Some(area)) if focus == area => Some(llm(focus, 0.9)),
+        (Some(focus), _) => Some(llm(focus, 0.8)),
+        (None, Some(area)) => Some(llm(area, 0.65)),
+        (None, None) => None,
+    }
+}
+
+pub fn classify_analysis(analysis: &ImageAnalysis) -> Option<ContentClassification> {
+    classify_description(&analysis.primary_focus, &analysis.area_details.area_type).or_else(|| {
+        let content_type = match analysis.location_context {
+            LocationContext::Outdoor => ContentType::Exterior,
+            LocationContext::Indoor => ContentType::OtherInterior,
+            LocationContext::Mixed => return None,
+        };
+        Some(ContentClassification {
+            content_type,
+            confidence: 0.5,
+            source: ClassificationSource::Llm,
+            reason: format!("image analysis: {}", analysis.primary_focus),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn page(draw: impl Fn(u32, u32) -> bool) -> DynamicImage {
+        let img = RgbImage::from_fn(1200, 900, |x, y| {
+            if draw(x, y) { Rgb([20, 20, 20]) } else { Rgb([250, 250, 248]) }
+        });
+        DynamicImage::ImageRgb8(img)
+    }
+
+    #[test]
+    fn test_documents_are_told_apart_by_linework() {
+        // Walls: an outer box and two partitions, 8px thick
+        let plan = page(|x, y| {
+            let wall = |v: u32, at: u32| v.abs_diff(at) < 4;
+            let inside = (100..1100).contains(&x) && (100..800).contains(&y);
+            inside && (wall(x, 100) || wall(x, 1096) || wall(y, 100) || wall(y, 796) || wall(x, 600) || wall(y, 450))
+        });
+        let result = classify_local(&ContentSignals::measure(&plan, 0.0));
+        assert_eq!(result.content_type, ContentType::FloorPlan);
+        assert!(!result.needs_review(), "{:?}", result);
+
+        // Lines of short "words"
+        let text = page(|x, y| (100..1100).contains(&x) && y % 40 < 10 && (x / 6) % 7 < 5 && y > 80 && y < 820);
+        let result = classify_local(&ContentSignals::measure(&text, 0.0));
+        assert_eq!(result.content_type, ContentType::TitlePaper);
+        assert!(result.needs_review());
+    }
+
+    #[test]
+    fn test_description_keywords() {
+        let kitchen = classify_description("Kitchen island opening onto the living area", "kitchen").unwrap();
+        assert_eq!(kitchen.content_type, ContentType::Kitchen);
+        assert_eq!(kitchen.confidence, 0.9);
+
+        let unsure = classify_local(&ContentSignals {
+            sky_fraction: 0.0,
+            paper_fraction: 0.1,
+            ink_fraction: 0.1,
+            mean_saturation: 0.3,
+            edge_density: 0.1,
+            line_ink_fraction: 0.0,
+            equirectangular: false,
+        });
+        let combined = unsure.combine(Some(kitchen));
+        assert_eq!(combined.content_type, ContentType::Kitchen);
+        assert!(!combined.needs_review());
+
+        assert!(classify_description("Close-up of a door handle", "detail").is_none());
+
+        // Keywords inside other words don't count
+        let overview = classify_description("Overview of the bedrooms upstairs", "interior").unwrap();
+        assert_eq!(overview.content_type, ContentType::Bedroom);
+        assert!(classify_description("Please note the whirlpool tub", "detail").is_none());
+    }
+}
@@ -0,0 +1,210 @@
+use image::RgbaImage;
+use super::memory::{for_each_band_with_halo, JobMemory, WorkImage};
+use crate::backend::common::error::error::Result;
+
+// Bilateral filter over a 5x5 window; grain on phone interiors is a pixel or two wide
+const RADIUS: i32 = 2;
+const SPATIAL_SIGMA: f32 = 1.5;
+
+// Grain below this standard deviation (8-bit luma) isn't visible after encoding;
+// by the upper one the filter is at full strength
+const NOISE_FLOOR: f32 = 2.0;
+const NOISE_CEILING: f32 = 10.0;
+// Lifting shadows by the full recovery amount roughly doubles the grain in them
+const SHADOW_GAIN: f32 = 1.0;
+
+// Range sigma (8-bit levels) at the weakest and strongest setting. Edges between
+// walls and frames differ by far more than the top one, so they stay crisp.
+const MIN_RANGE_SIGMA: f32 = 4.0;
+const MAX_RANGE_SIGMA: f32 = 24.0;
+// Luma tiers for the shadow boost; darker tiers get a wider range sigma
+const TIERS: usize = 4;
+// Below this strength the filtered result is blended in proportionally, so a
+// barely-noisy photo gets a touch of the filter rather than a full pass at the
+// narrowest range sigma
+const FULL_BLEND_STRENGTH: f32 = 0.25;
+
+// Filter strength in 0..=1 from `estimate_noise_level` (3x3 luma variance in flat
+// areas) and the recipe's shadow recovery, which lifts the grain along with the shadows
+pub fn denoise_strength(noise_level: f32, shadow_recovery: f32) -> f32 {
+    let noise = noise_level.max(0.0).sqrt();
+    let base = ((noise - NOISE_FLOOR) / (NOISE_CEILING - NOISE_FLOOR)).clamp(0.0, 1.0);
+    (base * (1.0 + SHADOW_GAIN * shadow_recovery.clamp(0.0, 1.0))).min(1.0)
+}
+
+// Edge-preserving denoise, run before sharpening so the grain isn't amplified.
+// Shadows are smoothed harder than midtones, since grading lifts them the most.
+pub fn denoise(img: &mut WorkImage, strength: f32, shadow_recovery: f32, memory: &JobMemory) -> Result<()> {
+    if strength <= 0.0 {
+        return Ok(());
+    }
+    let bilateral = Bilateral::new(strength, shadow_recovery);
+    let stride = img.width() as usize * 4;
+
+    // Reads RADIUS rows either side, from the unfiltered band tile
+    for_each_band_with_halo(img, RADIUS as u32, memory, |band, rows| {
+        for (row, output) in rows.chunks_exact_mut(stride).enumerate() {
+            let ty = band.top as i32 + row as i32;
+            for (x, pixel) in output.chunks_exact_mut(4).enumerate() {
+                // Alpha is left alone
+                pixel[..3].copy_from_slice(&bilateral.filter(&band.tile, x as i32, ty));
+            }
+        }
+        Ok(())
+    })
+}
+
+// Weights for one strength and shadow recovery setting
+struct Bilateral {
+    spatial: Vec<f32>,
+    // Range weight by RMS channel difference, one table per luma tier
+    range: Vec<[f32; 256]>,
+    blend: f32,
+}
+
+impl Bilateral {
+    fn new(strength: f32, shadow_recovery: f32) -> Self {
+        let spatial = (-RADIUS..=RADIUS)
+            .flat_map(|dy| (-RADIUS..=RADIUS).map(move |dx| (dx, dy)))
+            .map(|(dx, dy)| (-((dx * dx + dy * dy) as f32) / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA)).exp())
+            .collect();
+        let range = (0..TIERS)
+            .map(|tier| {
+                let darkness = (TIERS - 1 - tier) as f32 / (TIERS - 1) as f32;
+                let sigma = (MIN_RANGE_SIGMA + strength.min(1.0) * (MAX_RANGE_SIGMA - MIN_RANGE_SIGMA))
+                    * (1.0 + SHADOW_GAIN * shadow_recovery.clamp(0.0, 1.0) * darkness);
+                let mut table = [0.0; 256];
+                for (d, weight) in table.iter_mut().enumerate() {
+                    *weight = (-((d * d) as f32) / (2.0 * sigma * sigma)).exp();
+                }
+                table
+            })
+            .collect();
+        Self { spatial, range, blend: (strength / FULL_BLEND_STRENGTH).min(1.0) }
+    }
+
+    // Filtered colour of the pixel at (x, y); neighbours outside the tile are left out
+    fn filter(&self, tile: &RgbaImage, x: i32, y: i32) -> [u8; 3] {
+        let (width, height) = (tile.width() as i32, tile.height() as i32);
+        let center = tile.get_pixel(x as u32, y as u32);
+        let luma = (299 * center[0] as u32 + 587 * center[1] as u32 + 114 * center[2] as u32) / 1000;
+        let table = &self.range[(luma as usize * TIERS / 256).min(TIERS - 1)];
+
+        let mut sum = [0.0f32; 3];
+        let mut total = 0.0f32;
+        for dy in -RADIUS..=RADIUS {
+            let ny = y + dy;
+            if ny < 0 || ny >= height {
+                continue;
+            }
+            for dx in -RADIUS..=RADIUS {
+                let nx = x + dx;
+                if nx < 0 || nx >= width {
+                    continue;
+                }
+                let p = tile.get_pixel(nx as u32, ny as u32);
+                let diff: i32 = (0..3).map(|c| (p[c] as i32 - center[c] as i32).pow(2)).sum();
+                let distance = ((diff as f32 / 3.0).sqrt() as usize).min(255);
+                let weight = self.spatial[((dy + RADIUS) * (2 * RADIUS + 1) + dx + RADIUS) as usize] * table[distance];
+                for c in 0..3 {
+                    sum[c] += weight * p[c] as f32;
+                }
+                total += weight;
+            }
+        }
+
+        std::array::from_fn(|c| {
+            let filtered = center[c] as f32 + self.blend * (sum[c] / total - center[c] as f32);
+            filtered.round().clamp(0.0, 255.0) as u8
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::image_processor::memory::{into_dynamic, to_work_image};
+    use crate::backend::image_processor::test_fixtures::{grain, same_on_any_thread_count};
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    // Dark wall left, lit wall right, both with grain
+    fn grainy_room() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(120, 150, |x, y| {
+            let wall: i32 = if x < 60 { 50 } else { 190 };
+            let value = (wall + grain(x, y, 13) as i32 - 6) as u8;
+            Rgb([value, value, value])
+        }))
+    }
+
+    fn spread(img: &WorkImage, xs: std::ops::Range<u32>) -> f32 {
+        let values: Vec<f32> = (10..140)
+            .flat_map(|y| xs.clone().map(move |x| img.get_pixel(x, y)[0] as f32))
+            .collect();
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        (values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_strength_follows_noise_and_shadow_recovery() {
+        assert_eq!(denoise_strength(1.0, 0.5), 0.0);
+        let grainy = denoise_strength(36.0, 0.0);
+        assert!(grainy > 0.0 && grainy < 1.0);
+        assert!(denoise_strength(36.0, 0.8) > grainy);
+        assert_eq!(denoise_strength(400.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_grain_is_smoothed_and_the_edge_kept() {
+        let memory = JobMemory::unbounded();
+        let mut work = to_work_image(&grainy_room(), &memory).unwrap();
+        let before = (spread(&work, 5..55), spread(&work, 65..115));
+
+        denoise(&mut work, 0.6, 0.5, &memory).unwrap();
+        let after = (spread(&work, 5..55), spread(&work, 65..115));
+        assert!(after.0 < before.0 * 0.5, "{:?} -> {:?}", before, after);
+        assert!(after.1 < before.1 * 0.5, "{:?} -> {:?}", before, after);
+
+        // Straight step between the walls, no halo bleeding across
+        for y in 0..150 {
+            assert!(work.get_pixel(59, y)[0] < 70, "row {}", y);
+            assert!(work.get_pixel(60, y)[0] > 170, "row {}", y);
+        }
+    }
+
+    #[test]
+    fn test_near_zero_strength_is_nearly_a_no_op() {
+        let memory = JobMemory::unbounded();
+        let mut work = to_work_image(&grainy_room(), &memory).unwrap();
+        denoise(&mut work, 0.01, 0.5, &memory).unwrap();
+
+        let before = grainy_room().to_rgba8();
+        let after = into_dynamic(work).to_rgba8();
+        let moved = before.pixels().zip(after.pixels())
+            .map(|(a, b)| (0..3).map(|c| (a[c] as i32 - b[c] as i32).abs()).max().unwrap())
+            .max()
+            .unwrap();
+        assert!(moved <= 1, "moved by {}", moved);
+    }
+
+    // Every pixel filtered in turn from the whole unfiltered frame, no bands
+    fn serial_denoise(img: &DynamicImage, strength: f32, shadow_recovery: f32) -> Vec<u8> {
+        let bilateral = Bilateral::new(strength, shadow_recovery);
+        let whole = img.to_rgba8();
+        let mut output = whole.clone();
+        for (x, y, pixel) in output.enumerate_pixels_mut() {
+            pixel.0[..3].copy_from_slice(&bilateral.filter(&whole, x as i32, y as i32));
+        }
+        output.into_raw()
+    }
+
+    #[test]
+    fn test_denoise_matches_on_any_thread_count() {
+        let memory = JobMemory::unbounded();
+        let banded = same_on_any_thread_count(|| {
+            let mut work = to_work_image(&grainy_room(), &memory).unwrap();
+            denoise(&mut work, 0.8, 0.6, &memory).unwrap();
+            into_dynamic(work).to_rgba8().into_raw()
+        });
+        assert_eq!(banded, serial_denoise(&grainy_room(), 0.8, 0.6));
+    }
+}
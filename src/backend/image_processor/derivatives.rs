@@ -0,0 +1,144 @@
+use image::{DynamicImage, GenericImageView, ImageFormat, imageops::FilterType};
+use serde::{Serialize, Deserialize};
+use tracing::{info, instrument};
+use webp::Encoder;
+
+use crate::backend::common::error::error::{Result, AppError, ImageError};
+use crate::backend::image_processor::watermark::Watermarker;
+use crate::backend::image_processor::color_management::tag_srgb;
+use crate::backend::image_processor::memory::rgba_bytes;
+
+// Gallery breakpoints used by the frontends to build `srcset`
+pub const RENDITION_WIDTHS: [u32; 4] = [320, 640, 1280, 1920];
+
+pub const WEBP_QUALITY: f32 = 82.0;  // webp crate expects 0-100
+const JPEG_QUALITY: f32 = 80.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenditionFormat {
+    WebP,
+    Jpeg,
+}
+
+impl RenditionFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            RenditionFormat::WebP => "image/webp",
+            RenditionFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RenditionFormat::WebP => "webp",
+            RenditionFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Rendition {
+    pub width: u32,
+    pub height: u32,
+    pub format: RenditionFormat,
+    pub size: usize,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+// Builds every gallery size as WebP plus a JPEG fallback. Widths larger than the
+// source are skipped instead of upscaled; the watermark is applied per size so it
+// stays legible on small thumbnails. Renditions are always public, so every file
+// goes through `publish` (given the file extension) before it is returned.
+#[instrument(skip(img, watermarker, publish))]
+pub fn generate_renditions(
+    img: &DynamicImage,
+    watermarker: Option<&Watermarker>,
+    agency_id: Option<&str>,
+    publish: impl Fn(&[u8], &str) -> Result<Vec<u8>>,
+) -> Result<Vec<Rendition>> {
+    let (source_width, _) = img.dimensions();
+    let mut renditions = Vec::new();
+
+    for &target_width in RENDITION_WIDTHS.iter().filter(|&&w| w <= source_width) {
+        let resized = img.resize(target_width, u32::MAX, FilterType::Lanczos3);
+        let resized = match watermarker {
+            Some(watermarker) => watermarker.apply(&resized, agency_id)?,
+            None => resized,
+        };
+        let (width, height) = resized.dimensions();
+
+        let webp = publish(&encode_webp(&resized)?, RenditionFormat::WebP.extension())?;
+        renditions.push(Rendition {
+            width,
+            height,
+            format: RenditionFormat::WebP,
+            size: webp.len(),
+            data: webp,
+        });
+
+        let jpeg = publish(&encode_mozjpeg(&resized, JPEG_QUALITY)?, RenditionFormat::Jpeg.extension())?;
+        renditions.push(Rendition {
+            width,
+            height,
+            format: RenditionFormat::Jpeg,
+            size: jpeg.len(),
+            data: jpeg,
+        });
+    }
+
+    info!(count = renditions.len(), source_width, "Generated responsive renditions");
+    Ok(renditions)
+}
+
+// Pixel buffers `generate_renditions` holds at its largest size: the resized
+// copy and its watermarked version
+pub fn renditions_bytes(width: u32, height: u32) -> usize {
+    let Some(&target) = RENDITION_WIDTHS.iter().rev().find(|&&w| w <= width) else {
+        return 0;
+    };
+    let target_height = (height as u64 * target as u64).div_ceil(width as u64) as u32;
+    rgba_bytes(target, target_height) * 2
+}
+
+// Every encoder here tags its output as sRGB; the pipeline converts uploads to it on decode
+pub fn encode_webp(img: &DynamicImage) -> Result<Vec<u8>> {
+    let encoder = Encoder::from_image(img)
+        .map_err(|e| AppError::ImageError(ImageError::ConversionError(e.to_string())))?;
+    tag_srgb(encoder.encode(WEBP_QUALITY).to_vec(), ImageFormat::WebP)
+}
+
+// For linework, where lossy artefacts show up as grey fringes around every line
+pub fn encode_webp_lossless(img: &DynamicImage) -> Result<Vec<u8>> {
+    // The encoder only takes RGB(A)
+    let img = if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(img.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    };
+    let encoder = Encoder::from_image(&img)
+        .map_err(|e| AppError::ImageError(ImageError::ConversionError(e.to_string())))?;
+    tag_srgb(encoder.encode_lossless().to_vec(), ImageFormat::WebP)
+}
+
+pub fn encode_mozjpeg(img: &DynamicImage, quality: f32) -> Result<Vec<u8>> {
+    let rgb = img.to_rgb8();
+    let (width, height) = rgb.dimensions();
+
+    // mozjpeg reports libjpeg errors by unwinding, so contain them here
+    std::panic::catch_unwind(|| -> std::io::Result<Vec<u8>> {
+        let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+        compress.set_size(width as usize, height as usize);
+        compress.set_quality(quality);
+        compress.set_progressive_mode();
+        compress.set_optimize_coding(true);
+
+        let mut started = compress.start_compress(Vec::new())?;
+        started.write_scanlines(rgb.as_raw())?;
+        started.finish()
+    })
+    .map_err(|_| AppError::ImageError(ImageError::ConversionError("mozjpeg encoder panicked".into())))?
+    .map_err(|e| AppError::ImageError(ImageError::ConversionError(e.to_string())))
+    .and_then(|data| tag_srgb(data, ImageFormat::Jpeg))
+}
@@ -0,0 +1,253 @@
+use image::{imageops::FilterType, DynamicImage};
+use serde::Serialize;
+
+use crate::backend::common::{
+    config::{DimensionPolicyConfig, DimensionRule, TooLargeAction, TooSmallAction},
+    error::error::{Result, AppError, ImageValidationError},
+};
+use super::processor::ContentType;
+
+// Unsharp mask after upscaling, to take the Lanczos softness back off
+const UPSCALE_SHARPEN_SIGMA: f32 = 0.8;
+const UPSCALE_SHARPEN_THRESHOLD: i32 = 2;
+
+// Rounding slack, so a scale that lands exactly on a bound doesn't round past it
+const SCALE_EPSILON: f64 = 1e-9;
+
+// What a rule does with an image that passed it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DimensionPlan {
+    Keep,
+    Downscale { width: u32, height: u32 },
+    Upscale { width: u32, height: u32 },
+}
+
+impl DimensionPlan {
+    // Output size for an input of `width` x `height`
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            DimensionPlan::Keep => (width, height),
+            DimensionPlan::Downscale { width, height } | DimensionPlan::Upscale { width, height } => (width, height),
+        }
+    }
+
+    pub fn apply(self, img: DynamicImage) -> DynamicImage {
+        match self {
+            DimensionPlan::Keep => img,
+            DimensionPlan::Downscale { width, height } => img.resize_exact(width, height, FilterType::Lanczos3),
+            DimensionPlan::Upscale { width, height } => {
+                let resized = img.resize_exact(width, height, FilterType::Lanczos3);
+                drop(img);
+                resized.unsharpen(UPSCALE_SHARPEN_SIGMA, UPSCALE_SHARPEN_THRESHOLD)
+            }
+        }
+    }
+}
+
+// The one set of size rules for uploads, per content type. Errors name the
+// config key of the rule that failed so support can tell a photographer exactly
+// what to change.
+pub struct DimensionPolicy {
+    config: DimensionPolicyConfig,
+}
+
+impl DimensionPolicy {
+    pub fn new(config: DimensionPolicyConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+
+    // Uploads with no content type at all (the chunked section uploads) use the default rule
+    pub fn plan(&self, content_type: Option<ContentType>, width: u32, height: u32) -> Result<DimensionPlan> {
+        let (scope, rule) = self.rule_for(content_type);
+        plan(&scope, rule, width, height)
+    }
+
+    // Header-only check before anything is decoded. Untagged uploads only have to
+    // fit some content type's rule until they are classified.
+    pub fn precheck(&self, content_type: Option<ContentType>, width: u32, height: u32) -> Result<()> {
+        if content_type.is_some() {
+            return self.plan(content_type, width, height).map(|_| ());
+        }
+        if self.rules().any(|(scope, rule)| plan(&scope, rule, width, height).is_ok()) {
+            return Ok(());
+        }
+        self.plan(None, width, height).map(|_| ())
+    }
+
+    // Largest size the image could be resized to, for sizing the job's memory
+    // before the content type is known
+    pub fn largest_output(&self, content_type: Option<ContentType>, width: u32, height: u32) -> (u32, u32) {
+        let sizes: Vec<(u32, u32)> = match content_type {
+            Some(_) => vec![self.rule_for(content_type)],
+            None => self.rules().collect(),
+        }
+        .into_iter()
+        .filter_map(|(scope, rule)| plan(&scope, rule, width, height).ok())
+        .map(|plan| plan.size(width, height))
+        .collect();
+
+        sizes.into_iter()
+            .max_by_key(|&(w, h)| w as u64 * h as u64)
+            .unwrap_or((width, height))
+    }
+
+    // Largest image the rule for `content_type` lets through, as landscape, and the
+    // rule's config key
+    pub fn largest_allowed(&self, content_type: Option<ContentType>) -> (String, u32, u32) {
+        let (scope, rule) = self.rule_for(content_type);
+        (scope, rule.max_long_edge, rule.max_short_edge)
+    }
+
+    fn rule_for(&self, content_type: Option<ContentType>) -> (String, &DimensionRule) {
+        match content_type.and_then(|ct| self.config.content_types.get(&ct).map(|rule| (ct, rule))) {
+            Some((ct, rule)) => (format!("dimension_policy.content_types.{:?}", ct), rule),
+            None => ("dimension_policy.default".to_string(), &self.config.default),
+        }
+    }
+
+    fn rules(&self) -> impl Iterator<Item = (String, &DimensionRule)> {
+        std::iter::once(("dimension_policy.default".to_string(), &self.config.default)).chain(
+            self.config.content_types.iter()
+                .map(|(ct, rule)| (format!("dimension_policy.content_types.{:?}", ct), rule)),
+        )
+    }
+}
+
+fn plan(scope: &str, rule: &DimensionRule, width: u32, height: u32) -> Result<DimensionPlan> {
+    let violation = |field: &str, reason: String| {
+        AppError::ImageValidation(ImageValidationError::DimensionRule {
+            rule: format!("{}.{}", scope, field),
+            width,
+            height,
+            reason,
+        })
+    };
+    let (w, h) = (width.max(1) as f64, height.max(1) as f64);
+    let edges = |width: u32, height: u32| (width.max(height), width.min(height));
+    let (long, short) = edges(width, height);
+
+    if long < rule.min_long_edge || short < rule.min_short_edge {
+        let field = if long < rule.min_long_edge { "min_long_edge" } else { "min_short_edge" };
+        if rule.too_small == TooSmallAction::Reject {
+            return Err(violation(field, format!(
+                "needs edges of at least {} and {}", rule.min_long_edge, rule.min_short_edge
+            )));
+        }
+
+        let scale = (rule.min_long_edge as f64 / long.max(1) as f64).max(rule.min_short_edge as f64 / short.max(1) as f64);
+        if scale > rule.max_upscale as f64 {
+            return Err(violation("max_upscale", format!(
+                "reaching edges of {} and {} needs a {:.2}x upscale, the limit is {:.2}x",
+                rule.min_long_edge, rule.min_short_edge, scale, rule.max_upscale
+            )));
+        }
+        let (up_w, up_h) = ((w * scale - SCALE_EPSILON).ceil() as u32, (h * scale - SCALE_EPSILON).ceil() as u32);
+        let (up_long, up_short) = edges(up_w, up_h);
+        if up_long > rule.max_long_edge || up_short > rule.max_short_edge {
+            let field = if up_long > rule.max_long_edge { "max_long_edge" } else { "max_short_edge" };
+            return Err(violation(field, format!(
+                "the aspect ratio can't fit within edges of {} and {} once upscaled to {}x{}",
+                rule.max_long_edge, rule.max_short_edge, up_w, up_h
+            )));
+        }
+        return Ok(DimensionPlan::Upscale { width: up_w, height: up_h });
+    }
+
+    if long > rule.max_long_edge || short > rule.max_short_edge {
+        let field = if long > rule.max_long_edge { "max_long_edge" } else { "max_short_edge" };
+        if rule.too_large == TooLargeAction::Reject {
+            return Err(violation(field, format!(
+                "allows edges of at most {} and {}", rule.max_long_edge, rule.max_short_edge
+            )));
+        }
+
+        let scale = (rule.max_long_edge as f64 / long as f64).min(rule.max_short_edge as f64 / short.max(1) as f64);
+        let (down_w, down_h) = ((w * scale + SCALE_EPSILON).floor() as u32, (h * scale + SCALE_EPSILON).floor() as u32);
+        let (down_long, down_short) = edges(down_w, down_h);
+        if down_long < rule.min_long_edge || down_short < rule.min_short_edge {
+            let field = if down_long < rule.min_long_edge { "min_long_edge" } else { "min_short_edge" };
+            return Err(violation(field, format!(
+                "the aspect ratio can't reach edges of {} and {} once downscaled to {}x{}",
+                rule.min_long_edge, rule.min_short_edge, down_w, down_h
+            )));
+        }
+        return Ok(DimensionPlan::Downscale { width: down_w, height: down_h });
+    }
+
+    Ok(DimensionPlan::Keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn policy() -> DimensionPolicy {
+        DimensionPolicy::new(DimensionPolicyConfig::default()).unwrap()
+    }
+
+    fn rule_of(err: AppError) -> String {
+        match err {
+            AppError::ImageValidation(ImageValidationError::DimensionRule { rule, .. }) => rule,
+            other => panic!("expected a dimension rule error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rules_per_content_type() {
+        let policy = policy();
+        let kitchen = Some(ContentType::Kitchen);
+
+        assert_eq!(policy.plan(kitchen, 2400, 1600).unwrap(), DimensionPlan::Keep);
+        assert_eq!(policy.plan(kitchen, 6000, 4000).unwrap(), DimensionPlan::Downscale { width: 3240, height: 2160 });
+        // Portraits are held to the same edges
+        assert_eq!(policy.plan(kitchen, 1600, 2400).unwrap(), DimensionPlan::Keep);
+        assert_eq!(policy.plan(kitchen, 4000, 6000).unwrap(), DimensionPlan::Downscale { width: 2160, height: 3240 });
+        assert_eq!(
+            rule_of(policy.plan(kitchen, 1600, 1200).unwrap_err()),
+            "dimension_policy.default.min_long_edge"
+        );
+        // Too wide to keep a 1080 short edge with a 3840 long one
+        assert_eq!(
+            rule_of(policy.plan(kitchen, 8000, 1500).unwrap_err()),
+            "dimension_policy.default.min_short_edge"
+        );
+
+        // Panoramas keep their resolution; floor plans upscale, within limits
+        assert_eq!(policy.plan(Some(ContentType::Panorama), 6080, 3040).unwrap(), DimensionPlan::Keep);
+        assert_eq!(
+            policy.plan(Some(ContentType::FloorPlan), 600, 500).unwrap(),
+            DimensionPlan::Upscale { width: 960, height: 800 }
+        );
+        assert_eq!(
+            rule_of(policy.plan(Some(ContentType::FloorPlan), 300, 500).unwrap_err()),
+            "dimension_policy.content_types.FloorPlan.max_upscale"
+        );
+
+        // Untagged uploads pass the precheck if any rule would take them
+        assert!(policy.precheck(None, 1000, 1000).is_ok());
+        assert!(policy.precheck(Some(ContentType::Kitchen), 1000, 1000).is_err());
+        assert_eq!(
+            rule_of(policy.precheck(None, 200, 200).unwrap_err()),
+            "dimension_policy.default.min_long_edge"
+        );
+        assert_eq!(policy.largest_output(None, 600, 500), (960, 800));
+    }
+
+    #[test]
+    fn test_upscale_is_sharpened_to_the_planned_size() {
+        let plan = DimensionPlan::Upscale { width: 300, height: 200 };
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(150, 100, |x, _| {
+            if x < 75 { Rgb([40, 40, 40]) } else { Rgb([220, 220, 220]) }
+        }));
+        let plain = img.resize_exact(300, 200, FilterType::Lanczos3).to_rgb8();
+        let upscaled = plan.apply(img).to_rgb8();
+
+        assert_eq!(upscaled.dimensions(), (300, 200));
+        // Steeper across the edge than resampling alone; a few pixels out both
+        // have settled back to the walls
+        let step = |img: &RgbImage| img.get_pixel(150, 100)[0] as i32 - img.get_pixel(149, 100)[0] as i32;
+        assert!(step(&upscaled) > step(&plain), "{} vs {}", step(&upscaled), step(&plain));
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::Value as JsonValue;
+
+use crate::backend::common::error::error::{Result, AppError};
+use super::lens_correction::LensCorrection;
+use super::listing_look::LookAdjustment;
+use super::perspective::{CropRect, PerspectiveAngles};
+use super::sky_replacement::find_sky;
+use super::processor::{ContentType, ImageEnhancementConfig};
+
+// Everything needed to re-render an image from its untouched original
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EditRecipe {
+    pub content_type: ContentType,
+    pub preset: String,
+    // Recipes stored before presets were versioned read as 0
+    #[serde(default)]
+    pub preset_version: u32,
+    pub processing_version: String,
+    pub enhancement: ImageEnhancementConfig,
+    // Barrel/pincushion correction, applied before perspective; recipes from before
+    // lens correction read as None
+    #[serde(default)]
+    pub lens: Option<LensCorrection>,
+    pub perspective: Option<PerspectiveAngles>,
+    // Applied after perspective correction, in corrected-image pixels
+    pub crop: Option<CropRect>,
+    // Blue-hour colour grade, only used for exteriors
+    pub twilight_grade: bool,
+    // Edge-preserving denoise strength, 0 to 1; 0 (and recipes from before it) skips the stage
+    #[serde(default)]
+    pub denoise: f32,
+    pub sharpen: bool,
+    // Set by the listing pass to match the gallery's other photos of this type
+    #[serde(default)]
+    pub listing_look: Option<LookAdjustment>,
+    // Sky library id; never set automatically, and disclosed in XMP when used
+    #[serde(default)]
+    pub sky_replacement: Option<String>,
+    // Also publish the plan as SVG so it can be zoomed without blur; floor plans only
+    #[serde(default)]
+    pub trace_svg: bool,
+}
+
+// One entry in an image's recipe history. Versions start at 1 and only grow;
+// a revert is a new version carrying an older recipe.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipeRevision {
+    pub version: u32,
+    pub recipe: EditRecipe,
+    pub note: Option<String>,
+    pub reverted_from: Option<u32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl EditRecipe {
+    // RFC 7386 merge patch. Nested objects merge, anything else replaces, and null
+    // clears an optional field (it is rejected for required ones).
+    pub fn merge_patch(&self, patch: &JsonValue) -> Result<Self> {
+        if !patch.is_object() {
+            return Err(AppError::Validation("Recipe patch must be a JSON object".into()));
+        }
+
+        let mut value = serde_json::to_value(self)
+            .map_err(|e| AppError::ImageProcessing(format!("Failed to serialize recipe: {}", e)))?;
+        merge(&mut value, patch);
+
+        let recipe: EditRecipe = serde_json::from_value(value)
+            .map_err(|e| AppError::Validation(format!("Invalid recipe: {}", e)))?;
+        recipe.validate()?;
+        Ok(recipe)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.enhancement.validate()?;
+
+        if let Some(lens) = &self.lens {
+            lens.distortion.validate()?;
+        }
+        if let Some(angles) = &self.perspective {
+            angles.validate()?;
+        }
+        if !(0.0..=1.0).contains(&self.denoise) {
+            return Err(AppError::Validation(format!("denoise must be between 0 and 1, got {}", self.denoise)));
+        }
+        if let Some(look) = &self.listing_look {
+            look.validate()?;
+        }
+        if let Some(crop) = &self.crop {
+            if crop.width == 0 || crop.height == 0 {
+                return Err(AppError::Validation("Crop must have a non-zero size".into()));
+            }
+        }
+        // Warping or cropping an equirectangular frame breaks the 360° wrap-around
+        if self.content_type == ContentType::Panorama && (self.lens.is_some() || self.perspective.is_some() || self.crop.is_some()) {
+            return Err(AppError::Validation("Panoramas can't be lens or perspective corrected or cropped".into()));
+        }
+        // Plans are deskewed on their own; a keystone warp would bend the walls
+        if self.content_type == ContentType::FloorPlan && (self.lens.is_some() || self.perspective.is_some()) {
+            return Err(AppError::Validation("Floor plans are straightened automatically, not lens or perspective corrected".into()));
+        }
+        if self.trace_svg && self.content_type != ContentType::FloorPlan {
+            return Err(AppError::Validation(format!("Only floor plans can be traced to SVG, not {:?}", self.content_type)));
+        }
+        if let Some(sky_id) = &self.sky_replacement {
+            if !matches!(self.content_type, ContentType::Exterior | ContentType::View) {
+                return Err(AppError::Validation(format!(
+                    "Sky replacement is only available for exteriors and views, not {:?}", self.content_type
+                )));
+            }
+            find_sky(sky_id)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn alterations(&self) -> Vec<String> {
+        let mut alterations = Vec::new();
+        if self.sky_replacement.is_some() {
+            alterations.push("sky_replacement".to_string());
+        }
+        alterations
+    }
+}
+
+fn merge(target: &mut JsonValue, patch: &JsonValue) {
+    match (target, patch) {
+        (JsonValue::Object(target), JsonValue::Object(patch)) => {
+            for (key, value) in patch {
+                match target.get_mut(key) {
+                    Some(existing) if existing.is_object() && value.is_object() => merge(existing, value),
+                    _ => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn recipe() -> EditRecipe {
+        EditRecipe {
+            content_type: ContentType::Exterior,
+            preset: "exterior".to_string(),
+            preset_version: 1,
+            processing_version: "2.0".to_string(),
+            enhancement: ImageEnhancementConfig {
+                contrast_boost: 1.2,
+                color_enhancement_strength: 1.2,
+                shadow_recovery: 0.2,
+                highlight_protection: 0.4,
+                sharpening_threshold: 10.0,
+                brightness_adjustment: 0.0,
+                window_recovery_strength: 0.8,
+                white_balance_temp: 0.0,
+                exterior_sky_enhancement: 1.4,
+            },
+            lens: None,
+            perspective: Some(PerspectiveAngles { roll_degrees: 1.5, keystone_degrees: 4.0 }),
+            crop: None,
+            twilight_grade: false,
+            denoise: 0.0,
+            sharpen: true,
+            listing_look: None,
+            sky_replacement: None,
+            trace_svg: false,
+        }
+    }
+
+    #[test]
+    fn test_merge_patch_edits_and_clears_fields() {
+        let patched = recipe()
+            .merge_patch(&json!({
+                "enhancement": { "contrast_boost": 1.4 },
+                "perspective": null,
+                "crop": { "x": 10, "y": 20, "width": 1600, "height": 900 }
+            }))
+            .unwrap();
+
+        assert_eq!(patched.enhancement.contrast_boost, 1.4);
+        assert_eq!(patched.enhancement.shadow_recovery, recipe().enhancement.shadow_recovery);
+        assert!(patched.perspective.is_none());
+        assert_eq!(patched.crop.map(|c| c.width), Some(1600));
+    }
+
+    #[test]
+    fn test_merge_patch_rejects_invalid_values() {
+        assert!(recipe().merge_patch(&json!({ "enhancement": { "contrast_boost": 9.0 } })).is_err());
+        assert!(recipe().merge_patch(&json!({ "perspective": { "roll_degrees": 45.0 } })).is_err());
+        assert!(recipe().merge_patch(&json!({ "denoise": 1.5 })).is_err());
+        assert!(recipe().merge_patch(&json!({ "lens": { "k1": -0.1, "k2": 0.02, "source": { "kind": "estimated" } } })).is_ok());
+        assert!(recipe().merge_patch(&json!({ "lens": { "k1": -2.0, "source": { "kind": "estimated" } } })).is_err());
+        assert!(recipe().merge_patch(&json!({ "enhancement": null })).is_err());
+        assert!(recipe().merge_patch(&json!({ "sky_replacement": "clear_blue" })).is_ok());
+        assert!(recipe().merge_patch(&json!({ "content_type": "Kitchen", "sky_replacement": "clear_blue" })).is_err());
+        assert!(recipe().merge_patch(&json!({ "trace_svg": true })).is_err());
+        assert!(recipe().merge_patch(&json!({ "content_type": "FloorPlan", "perspective": null, "trace_svg": true })).is_ok());
+    }
+}
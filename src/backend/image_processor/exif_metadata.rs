@@ -0,0 +1,235 @@
+use std::io::Cursor;
+use chrono::NaiveDateTime;
+use exif::{Exif, Field, In, Reader, Tag, Value};
+use image::DynamicImage;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, instrument};
+
+use crate::backend::common::types::listing_types::GpsCoordinates;
+
+// Fixes with a worse dilution of precision are treated as unreliable
+const MAX_GOOD_DOP: f64 = 10.0;
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GpsFix {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+    pub dop: Option<f64>,
+}
+
+impl GpsFix {
+    // Cameras without a lock often write 0/0, which we never want to pin a listing to
+    pub fn is_good(&self) -> bool {
+        let in_range = (-90.0..=90.0).contains(&self.latitude)
+            && (-180.0..=180.0).contains(&self.longitude);
+        let null_island = self.latitude == 0.0 && self.longitude == 0.0;
+        let precise = self.dop.map_or(true, |dop| dop <= MAX_GOOD_DOP);
+
+        in_range && !null_island && precise
+    }
+
+    pub fn to_coordinates(&self) -> GpsCoordinates {
+        GpsCoordinates {
+            latitude: self.latitude,
+            longitude: self.longitude,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExifData {
+    pub gps: Option<GpsFix>,
+    pub captured_at: Option<NaiveDateTime>,
+    // Minutes east of UTC for `captured_at`, from OffsetTimeOriginal; newer phones only
+    pub capture_utc_offset: Option<i32>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    // Millimetres, as set on the lens; picks the lens profile's coefficients on zooms
+    pub focal_length: Option<f64>,
+    pub orientation: u16,
+    // Seconds, and EV compensation; used to order exposure brackets
+    pub exposure_time: Option<f64>,
+    pub exposure_bias: Option<f64>,
+}
+
+impl ExifData {
+    pub fn good_gps(&self) -> Option<GpsFix> {
+        self.gps.filter(GpsFix::is_good)
+    }
+}
+
+// Returns None when the container has no EXIF block at all
+#[instrument(skip(data), fields(size = data.len()))]
+pub fn read_exif(data: &[u8]) -> Option<ExifData> {
+    let exif = match Reader::new().read_from_container(&mut Cursor::new(data)) {
+        Ok(exif) => exif,
+        Err(e) => {
+            debug!(error = %e, "No readable EXIF block");
+            return None;
+        }
+    };
+
+    let orientation = exif
+        .get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+        .filter(|o| (1..=8).contains(o))
+        .unwrap_or(1) as u16;
+
+    Some(ExifData {
+        gps: read_gps(&exif),
+        captured_at: read_capture_time(&exif),
+        capture_utc_offset: read_utc_offset(&exif),
+        camera_make: read_string(&exif, Tag::Make),
+        camera_model: read_string(&exif, Tag::Model),
+        lens_model: read_string(&exif, Tag::LensModel),
+        focal_length: read_rational(&exif, Tag::FocalLength),
+        orientation,
+        exposure_time: read_rational(&exif, Tag::ExposureTime),
+        exposure_bias: read_srational(&exif, Tag::ExposureBiasValue),
+    })
+}
+
+fn read_gps(exif: &Exif) -> Option<GpsFix> {
+    let latitude = read_dms(exif.get_field(Tag::GPSLatitude, In::PRIMARY)?)?;
+    let longitude = read_dms(exif.get_field(Tag::GPSLongitude, In::PRIMARY)?)?;
+
+    let lat_sign = match read_string(exif, Tag::GPSLatitudeRef).as_deref() {
+        Some("S") => -1.0,
+        _ => 1.0,
+    };
+    let lon_sign = match read_string(exif, Tag::GPSLongitudeRef).as_deref() {
+        Some("W") => -1.0,
+        _ => 1.0,
+    };
+
+    // GPSAltitudeRef 1 means below sea level
+    let altitude = read_rational(exif, Tag::GPSAltitude).map(|alt| {
+        let below = exif
+            .get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0))
+            == Some(1);
+        if below { -alt } else { alt }
+    });
+
+    Some(GpsFix {
+        latitude: latitude * lat_sign,
+        longitude: longitude * lon_sign,
+        altitude,
+        dop: read_rational(exif, Tag::GPSDOP),
+    })
+}
+
+fn read_dms(field: &Field) -> Option<f64> {
+    match &field.value {
+        Value::Rational(parts) if parts.len() >= 3 => {
+            Some(parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0)
+        }
+        _ => None,
+    }
+}
+
+fn read_rational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(parts) => parts.first().map(|r| r.to_f64()).filter(|v| v.is_finite()),
+        _ => None,
+    }
+}
+
+fn read_srational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::SRational(parts) => parts.first().map(|r| r.to_f64()).filter(|v| v.is_finite()),
+        _ => None,
+    }
+}
+
+fn read_string(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(parts) => parts
+            .first()
+            .map(|bytes| String::from_utf8_lossy(bytes).trim_end_matches('\0').trim().to_string())
+            .filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
+
+// DateTimeOriginal is local camera time; fall back to the file timestamp
+fn read_capture_time(exif: &Exif) -> Option<NaiveDateTime> {
+    [Tag::DateTimeOriginal, Tag::DateTime].iter().find_map(|&tag| {
+        let field = exif.get_field(tag, In::PRIMARY)?;
+        let bytes = match &field.value {
+            Value::Ascii(parts) => parts.first()?,
+            _ => return None,
+        };
+        let dt = exif::DateTime::from_ascii(bytes).ok()?;
+        chrono::NaiveDate::from_ymd_opt(dt.year as i32, dt.month as u32, dt.day as u32)?
+            .and_hms_opt(dt.hour as u32, dt.minute as u32, dt.second as u32)
+    })
+}
+
+// "+09:00" style offsets from EXIF 2.31
+fn read_utc_offset(exif: &Exif) -> Option<i32> {
+    let offset = read_string(exif, Tag::OffsetTimeOriginal).or_else(|| read_string(exif, Tag::OffsetTime))?;
+    let (sign, rest) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':')?;
+    let minutes = hours.parse::<i32>().ok()? * 60 + minutes.parse::<i32>().ok()?;
+    (minutes <= 14 * 60).then_some(sign * minutes)
+}
+
+// Bakes the EXIF orientation into the pixels so enhancement sees the upright frame
+pub fn apply_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// Great-circle distance in metres
+pub fn distance_m(a: &GpsCoordinates, b: &GpsCoordinates) -> f64 {
+    let (lat1, lat2) = (a.latitude.to_radians(), b.latitude.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b.longitude - a.longitude).to_radians();
+
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbImage};
+
+    #[test]
+    fn test_orientation_six_rotates_to_portrait() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(40, 30));
+        assert_eq!(apply_orientation(img, 6).dimensions(), (30, 40));
+    }
+
+    #[test]
+    fn test_gps_fix_rejects_null_island_and_poor_dop() {
+        let fix = GpsFix { latitude: 7.89, longitude: 98.39, altitude: None, dop: Some(2.0) };
+        assert!(fix.is_good());
+        assert!(!GpsFix { latitude: 0.0, longitude: 0.0, ..fix }.is_good());
+        assert!(!GpsFix { dop: Some(50.0), ..fix }.is_good());
+    }
+
+    #[test]
+    fn test_distance_between_nearby_points() {
+        let a = GpsCoordinates { latitude: 7.8804, longitude: 98.3923 };
+        let b = GpsCoordinates { latitude: 7.8894, longitude: 98.3923 };
+        let d = distance_m(&a, &b);
+        assert!((d - 1000.0).abs() < 10.0, "got {}", d);
+    }
+}
@@ -0,0 +1,499 @@
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Rgb, RgbImage};
+use serde::{Serialize, Deserialize};
+use tracing::{info, instrument};
+
+use crate::backend::common::error::error::{Result, AppError};
+
+pub const MIN_BRACKET_FRAMES: usize = 3;
+pub const MAX_BRACKET_FRAMES: usize = 5;
+
+// Handheld brackets rarely drift more than this; 6 pyramid levels search +/-63px
+const MAX_ALIGN_LEVELS: usize = 6;
+const MTB_NOISE_BAND: i16 = 4;
+
+// Mertens et al. weight parameters
+const WELL_EXPOSED_MEAN: f32 = 0.5;
+const WELL_EXPOSED_SIGMA: f32 = 0.2;
+const WEIGHT_EPSILON: f32 = 1e-12;
+
+const BLUR_KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameOffset {
+    pub dx: i32,
+    pub dy: i32,
+}
+
+// One source frame of a fused master, ordered darkest to brightest
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BracketFrame {
+    pub index: usize,
+    pub exposure_time: Option<f64>,
+    pub exposure_bias: Option<f64>,
+    pub mean_luminance: f32,
+    pub offset: FrameOffset,
+    pub is_reference: bool,
+    // Original upload, archived privately next to the master
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct FusionResult {
+    pub image: DynamicImage,
+    // Indexed like the input frames; the reference frame is always (0, 0)
+    pub offsets: Vec<FrameOffset>,
+    pub reference_index: usize,
+    // Mean luminance per input frame, used to order the bracket darkest to brightest
+    pub mean_luminance: Vec<f32>,
+}
+
+// Aligns the bracket to its middle exposure, then blends with Mertens exposure fusion.
+// The output is cropped to the area every frame covers after alignment.
+#[instrument(skip(frames), fields(frames = frames.len()))]
+pub fn fuse_brackets(frames: &[DynamicImage]) -> Result<FusionResult> {
+    if !(MIN_BRACKET_FRAMES..=MAX_BRACKET_FRAMES).contains(&frames.len()) {
+        return Err(AppError::InvalidInput(format!(
+            "Bracket groups need {}-{} frames, got {}",
+            MIN_BRACKET_FRAMES, MAX_BRACKET_FRAMES, frames.len()
+        )));
+    }
+
+    let (width, height) = frames[0].dimensions();
+    if frames.iter().any(|f| f.dimensions() != (width, height)) {
+        return Err(AppError::InvalidInput("All bracket frames must have the same dimensions".into()));
+    }
+
+    let grays: Vec<GrayImage> = frames.iter().map(|f| f.to_luma8()).collect();
+    let mean_luminance: Vec<f32> = grays.iter().map(mean_luma).collect();
+
+    // Middle exposure has the most usable detail in both shadows and highlights
+    let mut by_brightness: Vec<usize> = (0..frames.len()).collect();
+    by_brightness.sort_by(|&a, &b| mean_luminance[a].total_cmp(&mean_luminance[b]));
+    let reference_index = by_brightness[by_brightness.len() / 2];
+
+    let offsets: Vec<FrameOffset> = grays
+        .iter()
+        .enumerate()
+        .map(|(i, gray)| {
+            if i == reference_index {
+                FrameOffset { dx: 0, dy: 0 }
+            } else {
+                estimate_offset(&grays[reference_index], gray)
+            }
+        })
+        .collect();
+    drop(grays);
+
+    let fused = mertens_fusion(frames, &offsets)?;
+    let image = crop_to_common_area(fused, &offsets, width, height);
+
+    info!(
+        reference_index,
+        ?offsets,
+        width = image.width(),
+        height = image.height(),
+        "Fused exposure bracket"
+    );
+
+    Ok(FusionResult {
+        image: DynamicImage::ImageRgb8(image),
+        offsets,
+        reference_index,
+        mean_luminance,
+    })
+}
+
+fn mean_luma(gray: &GrayImage) -> f32 {
+    let total: u64 = gray.as_raw().iter().map(|&p| p as u64).sum();
+    total as f32 / gray.as_raw().len().max(1) as f32
+}
+
+// Median threshold bitmap alignment (Ward 2003). Thresholding at the median makes the
+// bitmaps nearly identical across exposures, so a coarse-to-fine XOR search finds the shift.
+fn estimate_offset(reference: &GrayImage, frame: &GrayImage) -> FrameOffset {
+    let mut ref_levels = vec![reference.clone()];
+    let mut frame_levels = vec![frame.clone()];
+    while ref_levels.len() < MAX_ALIGN_LEVELS {
+        let last = ref_levels.last().unwrap();
+        if last.width().min(last.height()) < 64 {
+            break;
+        }
+        ref_levels.push(half_size(last));
+        frame_levels.push(half_size(frame_levels.last().unwrap()));
+    }
+
+    let (mut dx, mut dy) = (0i32, 0i32);
+    for level in (0..ref_levels.len()).rev() {
+        dx *= 2;
+        dy *= 2;
+
+        let a = ThresholdBitmap::new(&ref_levels[level]);
+        let b = ThresholdBitmap::new(&frame_levels[level]);
+
+        let mut best = (u64::MAX, dx, dy);
+        for sy in -1..=1 {
+            for sx in -1..=1 {
+                let error = a.xor_error(&b, dx + sx, dy + sy);
+                if error < best.0 {
+                    best = (error, dx + sx, dy + sy);
+                }
+            }
+        }
+        dx = best.1;
+        dy = best.2;
+    }
+
+    FrameOffset { dx, dy }
+}
+
+fn half_size(img: &GrayImage) -> GrayImage {
+    let (w, h) = ((img.width() / 2).max(1), (img.height() / 2).max(1));
+    GrayImage::from_fn(w, h, |x, y| {
+        let (x0, y0) = (x * 2, y * 2);
+        let (x1, y1) = ((x0 + 1).min(img.width() - 1), (y0 + 1).min(img.height() - 1));
+        let sum = img.get_pixel(x0, y0)[0] as u16
+            + img.get_pixel(x1, y0)[0] as u16
+            + img.get_pixel(x0, y1)[0] as u16
+            + img.get_pixel(x1, y1)[0] as u16;
+        Luma([(sum / 4) as u8])
+    })
+}
+
+struct ThresholdBitmap {
+    width: i32,
+    height: i32,
+    above: Vec<bool>,
+    // Pixels too close to the median flip between exposures, so they are ignored
+    usable: Vec<bool>,
+}
+
+impl ThresholdBitmap {
+    fn new(img: &GrayImage) -> Self {
+        let median = median_luma(img) as i16;
+        let (above, usable) = img
+            .as_raw()
+            .iter()
+            .map(|&p| (p as i16 > median, (p as i16 - median).abs() > MTB_NOISE_BAND))
+            .unzip();
+
+        Self {
+            width: img.width() as i32,
+            height: img.height() as i32,
+            above,
+            usable,
+        }
+    }
+
+    // Compares self(x, y) against other(x + dx, y + dy) over the overlapping area
+    fn xor_error(&self, other: &ThresholdBitmap, dx: i32, dy: i32) -> u64 {
+        let mut error = 0u64;
+        for y in 0.max(-dy)..self.height.min(self.height - dy) {
+            let row = (y * self.width) as usize;
+            let other_row = ((y + dy) * other.width) as usize;
+            for x in 0.max(-dx)..self.width.min(self.width - dx) {
+                let i = row + x as usize;
+                let j = other_row + (x + dx) as usize;
+                if self.usable[i] && other.usable[j] && self.above[i] != other.above[j] {
+                    error += 1;
+                }
+            }
+        }
+        error
+    }
+}
+
+fn median_luma(img: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for &p in img.as_raw() {
+        histogram[p as usize] += 1;
+    }
+
+    let half = img.as_raw().len() as u64 / 2;
+    let mut seen = 0;
+    for (value, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen > half {
+            return value as u8;
+        }
+    }
+    255
+}
+
+// Single-channel f32 image used for the fusion pyramids
+#[derive(Clone)]
+struct Plane {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+impl Plane {
+    fn new(width: usize, height: usize) -> Self {
+        Self { width, height, data: vec![0.0; width * height] }
+    }
+
+    fn at(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.data[y * self.width + x]
+    }
+
+    fn blur(&self) -> Plane {
+        let mut horizontal = Plane::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                horizontal.data[y * self.width + x] = BLUR_KERNEL
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| w * self.at(x as isize + k as isize - 2, y as isize))
+                    .sum();
+            }
+        }
+
+        let mut out = Plane::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                out.data[y * self.width + x] = BLUR_KERNEL
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| w * horizontal.at(x as isize, y as isize + k as isize - 2))
+                    .sum();
+            }
+        }
+        out
+    }
+
+    fn downsample(&self) -> Plane {
+        let blurred = self.blur();
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut out = Plane::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                out.data[y * width + x] = blurred.data[(y * 2) * self.width + x * 2];
+            }
+        }
+        out
+    }
+
+    // Bilinear, centre-aligned; the Laplacian pyramid only needs it to be consistent
+    fn upsample(&self, width: usize, height: usize) -> Plane {
+        let mut out = Plane::new(width, height);
+        for y in 0..height {
+            let sy = ((y as f32 + 0.5) / 2.0 - 0.5).max(0.0);
+            let (y0, fy) = (sy.floor() as isize, sy.fract());
+            for x in 0..width {
+                let sx = ((x as f32 + 0.5) / 2.0 - 0.5).max(0.0);
+                let (x0, fx) = (sx.floor() as isize, sx.fract());
+                let top = self.at(x0, y0) * (1.0 - fx) + self.at(x0 + 1, y0) * fx;
+                let bottom = self.at(x0, y0 + 1) * (1.0 - fx) + self.at(x0 + 1, y0 + 1) * fx;
+                out.data[y * width + x] = top * (1.0 - fy) + bottom * fy;
+            }
+        }
+        out
+    }
+}
+
+fn pyramid_levels(width: usize, height: usize) -> usize {
+    let mut levels = 1;
+    let mut size = width.min(height);
+    while size >= 16 && levels < 10 {
+        size = size.div_ceil(2);
+        levels += 1;
+    }
+    levels
+}
+
+fn gaussian_pyramid(base: Plane, levels: usize) -> Vec<Plane> {
+    let mut pyramid = vec![base];
+    while pyramid.len() < levels {
+        let next = pyramid.last().unwrap().downsample();
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+fn laplacian_pyramid(base: Plane, levels: usize) -> Vec<Plane> {
+    let mut gaussian = gaussian_pyramid(base, levels);
+    for l in 0..levels - 1 {
+        let expanded = gaussian[l + 1].upsample(gaussian[l].width, gaussian[l].height);
+        for (value, up) in gaussian[l].data.iter_mut().zip(expanded.data) {
+            *value -= up;
+        }
+    }
+    gaussian
+}
+
+fn collapse(mut pyramid: Vec<Plane>) -> Plane {
+    let mut current = pyramid.pop().unwrap();
+    while let Some(mut level) = pyramid.pop() {
+        let expanded = current.upsample(level.width, level.height);
+        for (value, up) in level.data.iter_mut().zip(expanded.data) {
+            *value += up;
+        }
+        current = level;
+    }
+    current
+}
+
+// RGB planes in [0, 1], shifted so they line up with the reference frame
+fn aligned_planes(frame: &DynamicImage, offset: FrameOffset) -> [Plane; 3] {
+    let rgb = frame.to_rgb32f();
+    let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+    let mut planes = [Plane::new(width, height), Plane::new(width, height), Plane::new(width, height)];
+
+    for y in 0..height {
+        let sy = (y as i32 + offset.dy).clamp(0, height as i32 - 1) as u32;
+        for x in 0..width {
+            let sx = (x as i32 + offset.dx).clamp(0, width as i32 - 1) as u32;
+            let pixel = rgb.get_pixel(sx, sy);
+            for c in 0..3 {
+                planes[c].data[y * width + x] = pixel[c];
+            }
+        }
+    }
+    planes
+}
+
+// Contrast x saturation x well-exposedness, per Mertens, Kautz & Van Reeth (2007)
+fn mertens_weight(planes: &[Plane; 3]) -> Plane {
+    let (width, height) = (planes[0].width, planes[0].height);
+    let mut gray = Plane::new(width, height);
+    for i in 0..width * height {
+        gray.data[i] = 0.299 * planes[0].data[i] + 0.587 * planes[1].data[i] + 0.114 * planes[2].data[i];
+    }
+
+    let mut weight = Plane::new(width, height);
+    let two_sigma_sq = 2.0 * WELL_EXPOSED_SIGMA * WELL_EXPOSED_SIGMA;
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let (xi, yi) = (x as isize, y as isize);
+
+            let contrast = (4.0 * gray.data[i]
+                - gray.at(xi - 1, yi)
+                - gray.at(xi + 1, yi)
+                - gray.at(xi, yi - 1)
+                - gray.at(xi, yi + 1))
+                .abs();
+
+            let (r, g, b) = (planes[0].data[i], planes[1].data[i], planes[2].data[i]);
+            let mean = (r + g + b) / 3.0;
+            let saturation = (((r - mean).powi(2) + (g - mean).powi(2) + (b - mean).powi(2)) / 3.0).sqrt();
+
+            let exposedness: f32 = [r, g, b]
+                .iter()
+                .map(|c| (-(c - WELL_EXPOSED_MEAN).powi(2) / two_sigma_sq).exp())
+                .product();
+
+            weight.data[i] = contrast * saturation * exposedness + WEIGHT_EPSILON;
+        }
+    }
+    weight
+}
+
+fn mertens_fusion(frames: &[DynamicImage], offsets: &[FrameOffset]) -> Result<RgbImage> {
+    let (width, height) = frames[0].dimensions();
+    let (width, height) = (width as usize, height as usize);
+    let levels = pyramid_levels(width, height);
+
+    // Weights have to be normalised across frames before blending
+    let weights: Vec<Plane> = frames
+        .iter()
+        .zip(offsets)
+        .map(|(frame, &offset)| mertens_weight(&aligned_planes(frame, offset)))
+        .collect();
+
+    let mut totals = Plane::new(width, height);
+    for weight in &weights {
+        for (total, w) in totals.data.iter_mut().zip(&weight.data) {
+            *total += w;
+        }
+    }
+
+    // Blend level by level, one frame at a time to keep only one frame's pyramids alive
+    let mut blended: Option<[Vec<Plane>; 3]> = None;
+    for ((frame, &offset), mut weight) in frames.iter().zip(offsets).zip(weights) {
+        for (w, total) in weight.data.iter_mut().zip(&totals.data) {
+            *w /= total;
+        }
+        let weight_pyramid = gaussian_pyramid(weight, levels);
+
+        let [r, g, b] = aligned_planes(frame, offset);
+        let channel_pyramids = [
+            laplacian_pyramid(r, levels),
+            laplacian_pyramid(g, levels),
+            laplacian_pyramid(b, levels),
+        ];
+
+        let accumulated = blended.get_or_insert_with(|| {
+            let empty: Vec<Plane> = weight_pyramid.iter().map(|p| Plane::new(p.width, p.height)).collect();
+            [empty.clone(), empty.clone(), empty]
+        });
+
+        for (acc_channel, channel) in accumulated.iter_mut().zip(channel_pyramids) {
+            for ((acc_level, level), w_level) in acc_channel.iter_mut().zip(channel).zip(&weight_pyramid) {
+                for ((acc, value), w) in acc_level.data.iter_mut().zip(level.data).zip(&w_level.data) {
+                    *acc += value * w;
+                }
+            }
+        }
+    }
+
+    let [r, g, b] = blended
+        .ok_or_else(|| AppError::ImageProcessing("Exposure fusion received no frames".into()))?
+        .map(collapse);
+
+    Ok(RgbImage::from_fn(width as u32, height as u32, |x, y| {
+        let i = y as usize * width + x as usize;
+        let to_u8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Rgb([to_u8(r.data[i]), to_u8(g.data[i]), to_u8(b.data[i])])
+    }))
+}
+
+// Edge pixels that some frame had to clamp are smeared, so cut them off
+fn crop_to_common_area(image: RgbImage, offsets: &[FrameOffset], width: u32, height: u32) -> RgbImage {
+    let left = offsets.iter().map(|o| (-o.dx).max(0)).max().unwrap_or(0) as u32;
+    let top = offsets.iter().map(|o| (-o.dy).max(0)).max().unwrap_or(0) as u32;
+    let right = offsets.iter().map(|o| o.dx.max(0)).max().unwrap_or(0) as u32;
+    let bottom = offsets.iter().map(|o| o.dy.max(0)).max().unwrap_or(0) as u32;
+
+    if left + right >= width || top + bottom >= height {
+        return image;
+    }
+    if left + right + top + bottom == 0 {
+        return image;
+    }
+
+    image::imageops::crop_imm(&image, left, top, width - left - right, height - top - bottom).to_image()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene(width: u32, height: u32, gain: f32, shift: (i32, i32)) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let (sx, sy) = (x as i32 - shift.0, y as i32 - shift.1);
+            let checker = ((sx.div_euclid(16) + sy.div_euclid(16)) % 2) as f32;
+            let base = 40.0 + 120.0 * checker + (sx.rem_euclid(16) as f32) * 2.0;
+            let v = (base * gain).clamp(0.0, 255.0) as u8;
+            Rgb([v, v.saturating_sub(20), v / 2])
+        }))
+    }
+
+    #[test]
+    fn test_alignment_recovers_handheld_shift() {
+        let frames = vec![
+            scene(256, 192, 0.5, (3, -2)),
+            scene(256, 192, 1.0, (0, 0)),
+            scene(256, 192, 1.6, (-4, 5)),
+        ];
+
+        let result = fuse_brackets(&frames).unwrap();
+        assert_eq!(result.reference_index, 1);
+        assert_eq!(result.offsets[0], FrameOffset { dx: 3, dy: -2 });
+        assert_eq!(result.offsets[2], FrameOffset { dx: -4, dy: 5 });
+        assert_eq!(result.image.dimensions(), (256 - 4 - 3, 192 - 2 - 5));
+    }
+}
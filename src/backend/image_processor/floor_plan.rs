@@ -0,0 +1,340 @@
+use std::fmt::Write as _;
+use image::{imageops::FilterType, DynamicImage, GrayImage, Luma};
+use imageproc::contours::find_contours;
+use imageproc::contrast::{otsu_level, threshold, ThresholdType};
+use imageproc::geometric_transformations::{rotate_about_center, Interpolation};
+use imageproc::geometry::approximate_polygon_dp;
+use imageproc::region_labelling::{connected_components, Connectivity};
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tracing::{info, instrument};
+
+use crate::backend::common::error::error::Result;
+use super::perspective::CropRect;
+
+const INK: Luma<u8> = Luma([0]);
+const PAPER: Luma<u8> = Luma([255]);
+// Paper brightness is estimated per block; a wall can fill one, so neighbours are pooled
+const BACKGROUND_BLOCK: u32 = 32;
+const BACKGROUND_PERCENTILE: f32 = 0.95;
+// Scanned and photographed plans are rarely off by more than this
+const MAX_SKEW_DEGREES: f32 = 10.0;
+const COARSE_STEP_DEGREES: f32 = 0.5;
+const FINE_STEP_DEGREES: f32 = 0.05;
+// Skew is measured on a reduced copy; walls survive the downscale, text noise doesn't
+const SKEW_SAMPLE_SIZE: u32 = 1024;
+// Ink blobs this small are scanner dust
+const MAX_SPECK_PIXELS: usize = 4;
+// Ink touching the edge and staying inside this band is a scanner shadow or page edge
+const EDGE_BAND: f32 = 0.05;
+const TRIM_MARGIN: f32 = 0.02;
+const MIN_TRIM_MARGIN: u32 = 8;
+// Douglas-Peucker tolerance in pixels for the traced outlines
+const TRACE_EPSILON: f64 = 1.0;
+
+// What normalization did to the plan, kept with the image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FloorPlanNormalization {
+    // Clockwise rotation applied to level the walls
+    pub skew_degrees: f32,
+    // Otsu level the whitened page was binarized at
+    pub threshold: u8,
+    // Linework bounds plus margin, in deskewed pixels; None when nothing was trimmed
+    pub trimmed: Option<CropRect>,
+}
+
+#[derive(Debug)]
+pub struct NormalizedPlan {
+    // Black linework on white, one bit of information per pixel
+    pub image: GrayImage,
+    pub normalization: FloorPlanNormalization,
+}
+
+// Scan or photo of a plan in, clean black-on-white plan out: flatten uneven paper,
+// level the walls, binarize, drop dust and scanner edges, then trim to the linework
+#[instrument(skip(img), fields(width = img.width(), height = img.height()))]
+pub fn normalize(img: &DynamicImage) -> Result<NormalizedPlan> {
+    let whitened = whiten_background(&img.to_luma8());
+
+    let skew_degrees = estimate_skew(&binarize(&whitened).0);
+    let leveled = if skew_degrees.abs() >= FINE_STEP_DEGREES {
+        rotate_about_center(&whitened, skew_degrees.to_radians(), Interpolation::Bilinear, PAPER)
+    } else {
+        whitened
+    };
+
+    let (mut binary, level) = binarize(&leveled);
+    remove_debris(&mut binary);
+
+    let trimmed = linework_bounds(&binary);
+    let image = match trimmed {
+        Some(rect) => image::imageops::crop_imm(&binary, rect.x, rect.y, rect.width, rect.height).to_image(),
+        None => binary,
+    };
+
+    info!(skew_degrees, threshold = level, trimmed = trimmed.is_some(), "Normalized floor plan");
+    Ok(NormalizedPlan {
+        image,
+        normalization: FloorPlanNormalization { skew_degrees, threshold: level, trimmed },
+    })
+}
+
+// Divides out the paper's own brightness so shading, yellowing and phone-photo
+// vignetting all come out white without washing out faint lines
+pub fn whiten_background(gray: &GrayImage) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let (cols, rows) = (width.div_ceil(BACKGROUND_BLOCK), height.div_ceil(BACKGROUND_BLOCK));
+
+    let blocks = GrayImage::from_fn(cols, rows, |bx, by| {
+        let mut hist = [0u32; 256];
+        let (x0, y0) = (bx * BACKGROUND_BLOCK, by * BACKGROUND_BLOCK);
+        for y in y0..(y0 + BACKGROUND_BLOCK).min(height) {
+            for x in x0..(x0 + BACKGROUND_BLOCK).min(width) {
+                hist[gray.get_pixel(x, y)[0] as usize] += 1;
+            }
+        }
+        Luma([percentile(&hist, BACKGROUND_PERCENTILE)])
+    });
+    let pooled = GrayImage::from_fn(cols, rows, |bx, by| {
+        let mut brightest = 0;
+        for y in by.saturating_sub(1)..(by + 2).min(rows) {
+            for x in bx.saturating_sub(1)..(bx + 2).min(cols) {
+                brightest = brightest.max(blocks.get_pixel(x, y)[0]);
+            }
+        }
+        Luma([brightest])
+    });
+    let background = image::imageops::resize(&pooled, width, height, FilterType::Triangle);
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let paper = background.get_pixel(x, y)[0].max(1) as u32;
+        Luma([(gray.get_pixel(x, y)[0] as u32 * 255 / paper).min(255) as u8])
+    })
+}
+
+fn percentile(hist: &[u32; 256], fraction: f32) -> u8 {
+    let total: u32 = hist.iter().sum();
+    let target = (total as f32 * fraction).ceil() as u32;
+    let mut seen = 0;
+    for (value, &count) in hist.iter().enumerate() {
+        seen += count;
+        if seen >= target.max(1) {
+            return value as u8;
+        }
+    }
+    255
+}
+
+fn binarize(gray: &GrayImage) -> (GrayImage, u8) {
+    let level = otsu_level(gray);
+    (threshold(gray, level, ThresholdType::Binary), level)
+}
+
+// Clockwise rotation in degrees that levels the walls. Plans are mostly horizontal
+// and vertical lines, so the right angle is the one whose row and column ink
+// profiles are peakiest.
+pub fn estimate_skew(binary: &GrayImage) -> f32 {
+    let scale = (SKEW_SAMPLE_SIZE as f32 / binary.width().max(binary.height()) as f32).min(1.0);
+    let ink: Vec<(f32, f32)> = binary
+        .enumerate_pixels()
+        .filter(|(_, _, p)| **p == INK)
+        .map(|(x, y, _)| ((x as f32 - binary.width() as f32 / 2.0) * scale, (y as f32 - binary.height() as f32 / 2.0) * scale))
+        .collect();
+    if ink.is_empty() {
+        return 0.0;
+    }
+
+    let best = |candidates: Vec<f32>| -> f32 {
+        // Ties go to the smaller rotation so the result doesn't depend on thread timing
+        candidates
+            .par_iter()
+            .map(|&angle| (angle, profile_sharpness(&ink, angle)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .fold((0.0f32, f64::MIN), |best, (angle, score)| {
+                if score > best.1 || (score == best.1 && angle.abs() < best.0.abs()) { (angle, score) } else { best }
+            })
+            .0
+    };
+
+    let steps = |centre: f32, span: f32, step: f32| -> Vec<f32> {
+        let n = (span / step).round() as i32;
+        (-n..=n).map(|i| centre + i as f32 * step).collect()
+    };
+    let coarse = best(steps(0.0, MAX_SKEW_DEGREES, COARSE_STEP_DEGREES));
+    best(steps(coarse, COARSE_STEP_DEGREES, FINE_STEP_DEGREES))
+}
+
+fn profile_sharpness(ink: &[(f32, f32)], degrees: f32) -> f64 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let offset = SKEW_SAMPLE_SIZE as f32;
+    let bins = 2 * SKEW_SAMPLE_SIZE as usize + 1;
+    let (mut rows, mut cols) = (vec![0u32; bins], vec![0u32; bins]);
+    for &(x, y) in ink {
+        // Where the pixel lands after rotating the page clockwise by `degrees`
+        let (rx, ry) = (x * cos - y * sin, x * sin + y * cos);
+        rows[((ry + offset).round() as usize).min(bins - 1)] += 1;
+        cols[((rx + offset).round() as usize).min(bins - 1)] += 1;
+    }
+    rows.iter().chain(&cols).map(|&c| (c as f64) * (c as f64)).sum()
+}
+
+// Drops dust specks and scanner shadows that hug the page edge
+fn remove_debris(binary: &mut GrayImage) {
+    let (width, height) = binary.dimensions();
+    let labels = connected_components(binary, Connectivity::Eight, PAPER);
+
+    #[derive(Clone, Copy)]
+    struct Component { pixels: usize, min_x: u32, min_y: u32, max_x: u32, max_y: u32 }
+    let mut components: Vec<Option<Component>> = Vec::new();
+    for (x, y, label) in labels.enumerate_pixels() {
+        let label = label[0] as usize;
+        if label == 0 {
+            continue;
+        }
+        if components.len() <= label {
+            components.resize(label + 1, None);
+        }
+        let c = components[label].get_or_insert(Component { pixels: 0, min_x: x, min_y: y, max_x: x, max_y: y });
+        c.pixels += 1;
+        c.min_x = c.min_x.min(x);
+        c.min_y = c.min_y.min(y);
+        c.max_x = c.max_x.max(x);
+        c.max_y = c.max_y.max(y);
+    }
+
+    let (band_x, band_y) = ((width as f32 * EDGE_BAND) as u32, (height as f32 * EDGE_BAND) as u32);
+    let debris: Vec<bool> = components
+        .iter()
+        .map(|c| match c {
+            Some(c) => {
+                let hugs_edge = (c.min_x == 0 && c.max_x < band_x)
+                    || (c.max_x == width - 1 && c.min_x >= width - band_x)
+                    || (c.min_y == 0 && c.max_y < band_y)
+                    || (c.max_y == height - 1 && c.min_y >= height - band_y);
+                c.pixels <= MAX_SPECK_PIXELS || hugs_edge
+            }
+            None => false,
+        })
+        .collect();
+
+    for (x, y, label) in labels.enumerate_pixels() {
+        if debris.get(label[0] as usize).copied().unwrap_or(false) {
+            binary.put_pixel(x, y, PAPER);
+        }
+    }
+}
+
+fn linework_bounds(binary: &GrayImage) -> Option<CropRect> {
+    let (width, height) = binary.dimensions();
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (width, height, 0, 0);
+    for (x, y, p) in binary.enumerate_pixels() {
+        if *p == INK {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    if min_x > max_x {
+        return None;
+    }
+
+    let margin = ((width.max(height) as f32 * TRIM_MARGIN) as u32).max(MIN_TRIM_MARGIN);
+    let (x, y) = (min_x.saturating_sub(margin), min_y.saturating_sub(margin));
+    let rect = CropRect {
+        x,
+        y,
+        width: (max_x + margin + 1).min(width) - x,
+        height: (max_y + margin + 1).min(height) - y,
+    };
+    (rect.width < width || rect.height < height).then_some(rect)
+}
+
+// Vector outline of the linework so plan viewers can zoom without blur. Outlines
+// run through pixel centres; the half-pixel stroke puts them back on pixel edges
+// and keeps one-pixel lines visible.
+pub fn trace_svg(plan: &GrayImage) -> String {
+    let (width, height) = plan.dimensions();
+    let ink = threshold(plan, 127, ThresholdType::BinaryInverted);
+
+    let mut path = String::new();
+    for contour in find_contours::<i32>(&ink) {
+        let points = approximate_polygon_dp(&contour.points, TRACE_EPSILON, true);
+        for (i, p) in points.iter().enumerate() {
+            let _ = write!(path, "{}{} {}", if i == 0 { "M" } else { "L" }, p.x, p.y);
+        }
+        if !points.is_empty() {
+            path.push('Z');
+        }
+    }
+
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {w} {h}" width="{w}" height="{h}">"#,
+            r##"<rect width="{w}" height="{h}" fill="#fff"/>"##,
+            r##"<path transform="translate(0.5 0.5)" fill="#000" fill-rule="evenodd" stroke="#000" stroke-width="1" stroke-linejoin="round" stroke-linecap="round" d="{d}"/>"##,
+            "</svg>"
+        ),
+        w = width,
+        h = height,
+        d = path
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use imageproc::drawing::draw_filled_rect_mut;
+    use imageproc::rect::Rect;
+
+    // Outer walls and one partition on yellowed paper that darkens to the right
+    fn scanned_plan() -> GrayImage {
+        let mut plan = GrayImage::from_fn(800, 600, |x, _| Luma([225 - (x / 10) as u8]));
+        let ink = Luma([40]);
+        draw_filled_rect_mut(&mut plan, Rect::at(150, 120).of_size(500, 6), ink);
+        draw_filled_rect_mut(&mut plan, Rect::at(150, 474).of_size(500, 6), ink);
+        draw_filled_rect_mut(&mut plan, Rect::at(150, 120).of_size(6, 360), ink);
+        draw_filled_rect_mut(&mut plan, Rect::at(644, 120).of_size(6, 360), ink);
+        draw_filled_rect_mut(&mut plan, Rect::at(400, 120).of_size(3, 200), ink);
+        // Scanner shadow down the left edge, and some dust
+        draw_filled_rect_mut(&mut plan, Rect::at(0, 0).of_size(12, 600), Luma([30]));
+        plan.put_pixel(60, 560, ink);
+        plan
+    }
+
+    #[test]
+    fn test_normalize_levels_whitens_and_trims() {
+        let skewed = rotate_about_center(&scanned_plan(), (-3.0f32).to_radians(), Interpolation::Bilinear, Luma([220]));
+        let plan = normalize(&DynamicImage::ImageLuma8(skewed)).unwrap();
+
+        let skew = plan.normalization.skew_degrees;
+        assert!((skew - 3.0).abs() <= 0.2, "estimated skew {}", skew);
+        assert!(plan.image.pixels().all(|p| *p == INK || *p == PAPER));
+
+        // Trimmed to the walls plus margin; the shadow and dust are gone
+        let (width, height) = plan.image.dimensions();
+        assert!((500..=600).contains(&width) && (360..=460).contains(&height), "{}x{}", width, height);
+
+        // Level walls make the busiest row nearly solid
+        let busiest = (0..height)
+            .map(|y| (0..width).filter(|&x| *plan.image.get_pixel(x, y) == INK).count())
+            .max()
+            .unwrap();
+        assert!(busiest as f32 > 0.9 * 500.0, "busiest row has {} ink pixels", busiest);
+    }
+
+    #[test]
+    fn test_trace_outlines_walls_with_holes() {
+        let mut plan = GrayImage::from_pixel(100, 80, PAPER);
+        draw_filled_rect_mut(&mut plan, Rect::at(10, 10).of_size(80, 60), INK);
+        draw_filled_rect_mut(&mut plan, Rect::at(20, 20).of_size(60, 40), PAPER);
+
+        let svg = trace_svg(&plan);
+        assert!(svg.starts_with("<svg") && svg.contains(r#"viewBox="0 0 100 80""#));
+        assert!(svg.contains("evenodd"));
+        // One outer outline and one room, each reduced to its four corners
+        assert_eq!(svg.matches('M').count(), 2, "{}", svg);
+        assert_eq!(svg.matches('L').count(), 6, "{}", svg);
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::HashSet;
+use serde::{Serialize, Deserialize};
+
+use crate::backend::common::error::error::{Result, AppError};
+use super::perceptual_hash::{hamming_distance, NEAR_DUPLICATE_DISTANCE};
+use super::processor::{ContentType, TimeOfDay};
+
+// Order rooms appear in, outside in: what the buyer sees walking through.
+// Panoramas and plans come after the photos; documents are never shown.
+const TYPE_ORDER: [ContentType; 9] = [
+    ContentType::Exterior,
+    ContentType::View,
+    ContentType::LivingRoom,
+    ContentType::Kitchen,
+    ContentType::Bedroom,
+    ContentType::Bathroom,
+    ContentType::OtherInterior,
+    ContentType::Panorama,
+    ContentType::FloorPlan,
+];
+// Cover is taken from the first of these with a photo
+const COVER_TYPES: [ContentType; 5] = [
+    ContentType::Exterior,
+    ContentType::View,
+    ContentType::LivingRoom,
+    ContentType::Kitchen,
+    ContentType::Bedroom,
+];
+
+// Portal thumbnails are landscape crops, and twilight exteriors are what agents
+// pick by hand when they have one
+const LANDSCAPE_BONUS: f32 = 0.1;
+const TWILIGHT_BONUS: f32 = 0.05;
+
+// One stored photo of the listing, as the ranking sees it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GalleryCandidate {
+    pub image_id: String,
+    pub content_type: ContentType,
+    pub time_of_day: Option<TimeOfDay>,
+    // Overall score of the quality report, 0..1. Composition is already its
+    // largest weight (0.3), so it isn't added again on top.
+    pub quality_score: f32,
+    pub perceptual_hash: Option<u64>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl GalleryCandidate {
+    fn cover_score(&self) -> f32 {
+        let mut score = self.quality_score;
+        if self.width > self.height {
+            score += LANDSCAPE_BONUS;
+        }
+        if self.time_of_day == Some(TimeOfDay::Twilight) {
+            score += TWILIGHT_BONUS;
+        }
+        score
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GalleryEntry {
+    pub image_id: String,
+    pub position: usize,
+    pub content_type: ContentType,
+    pub score: f32,
+    // Why the photo sits where it does, for the agent reviewing the order
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GalleryOrder {
+    pub cover: Option<String>,
+    pub entries: Vec<GalleryEntry>,
+    // True when the listing's override decided the cover or part of the order
+    pub overridden: bool,
+}
+
+// Agent's choice for a listing, stored on the listing. `order` pins the first
+// photos; the rest follow in the proposed order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GalleryOverride {
+    pub cover: Option<String>,
+    #[serde(default)]
+    pub order: Vec<String>,
+}
+
+impl GalleryOverride {
+    // Every id must be a photo of the listing, and each placed once; the cover
+    // already leads the gallery, so it can't be pinned again
+    pub fn validate(&self, candidates: &[GalleryCandidate]) -> Result<()> {
+        let known: HashSet<&str> = candidates
+            .iter()
+            .filter(|c| rank(c.content_type).is_some())
+            .map(|c| c.image_id.as_str())
+            .collect();
+        let mut seen: HashSet<&str> = self.cover.iter().map(String::as_str).collect();
+        for id in self.cover.iter().chain(&self.order) {
+            if !known.contains(id.as_str()) {
+                return Err(AppError::Validation(format!("Image {} is not in this listing's gallery", id)));
+            }
+        }
+        for id in &self.order {
+            if !seen.insert(id.as_str()) {
+                return Err(AppError::Validation(format!("Image {} is placed more than once", id)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn rank(content_type: ContentType) -> Option<usize> {
+    TYPE_ORDER.iter().position(|t| *t == content_type)
+}
+
+// Cover first, then the best photo of each room type, then the other photos by
+// type and score, then panoramas and plans. Near-duplicates of a photo already
+// placed go last, so the first few photos show as much of the property as possible.
+pub fn propose(candidates: &[GalleryCandidate]) -> GalleryOrder {
+    let mut pool: Vec<&GalleryCandidate> = candidates.iter().filter(|c| rank(c.content_type).is_some()).collect();
+    // Ties fall back to the id so the order is stable between requests
+    pool.sort_by(|a, b| {
+        rank(a.content_type)
+            .cmp(&rank(b.content_type))
+            .then(b.quality_score.total_cmp(&a.quality_score))
+            .then(a.image_id.cmp(&b.image_id))
+    });
+
+    let mut placed: Vec<(&GalleryCandidate, Vec<String>)> = Vec::new();
+    let mut duplicates: Vec<(&GalleryCandidate, Vec<String>)> = Vec::new();
+
+    let cover = COVER_TYPES.iter().find_map(|content_type| {
+        pool.iter()
+            .filter(|c| c.content_type == *content_type)
+            .max_by(|a, b| a.cover_score().total_cmp(&b.cover_score()).then(b.image_id.cmp(&a.image_id)))
+            .copied()
+    });
+    if let Some(cover) = cover {
+        let mut reasons = vec![format!("Cover: best scoring {:?} photo", cover.content_type)];
+        if cover.width > cover.height {
+            reasons.push("Landscape, fits portal thumbnails".to_string());
+        }
+        if cover.time_of_day == Some(TimeOfDay::Twilight) {
+            reasons.push("Twilight shot".to_string());
+        }
+        placed.push((cover, reasons));
+    }
+
+    let is_placed = |placed: &[(&GalleryCandidate, Vec<String>)], c: &GalleryCandidate| {
+        placed.iter().any(|(p, _)| p.image_id == c.image_id)
+    };
+    let duplicate_of = |placed: &[(&GalleryCandidate, Vec<String>)], c: &GalleryCandidate| {
+        let hash = c.perceptual_hash?;
+        placed.iter().find_map(|(p, _)| {
+            let other = p.perceptual_hash?;
+            (hamming_distance(hash, other) <= NEAR_DUPLICATE_DISTANCE).then(|| p.image_id.clone())
+        })
+    };
+
+    // Best of each photo type, in walk-through order
+    for content_type in TYPE_ORDER.iter().filter(|t| !matches!(t, ContentType::Panorama | ContentType::FloorPlan)) {
+        if placed.iter().any(|(p, _)| p.content_type == *content_type) {
+            continue;
+        }
+        if let Some(best) = pool.iter().find(|c| c.content_type == *content_type && duplicate_of(&placed, c).is_none()) {
+            placed.push((*best, vec![format!("Best {:?} photo", content_type)]));
+        }
+    }
+
+    // Everything else keeps type order, then score
+    for candidate in &pool {
+        if is_placed(&placed, candidate) {
+            continue;
+        }
+        if let Some(original) = duplicate_of(&placed, candidate) {
+            duplicates.push((*candidate, vec![format!("Near-duplicate of {}, moved to the end", original)]));
+            continue;
+        }
+        let reason = match candidate.content_type {
+            ContentType::Panorama | ContentType::FloorPlan => format!("{:?} after the photos", candidate.content_type),
+            content_type => format!("Another {:?} photo", content_type),
+        };
+        placed.push((*candidate, vec![reason]));
+    }
+    placed.extend(duplicates);
+
+    GalleryOrder {
+        cover: cover.map(|c| c.image_id.clone()),
+        entries: placed
+            .into_iter()
+            .enumerate()
+            .map(|(position, (candidate, reasons))| GalleryEntry {
+                image_id: candidate.image_id.clone(),
+                position,
+                content_type: candidate.content_type,
+                score: candidate.quality_score,
+                reasons,
+            })
+            .collect(),
+        overridden: false,
+    }
+}
+
+// Agent's cover and pinned photos go first; ids no longer in the listing are skipped
+pub fn apply_override(proposal: GalleryOrder, choice: &GalleryOverride) -> GalleryOrder {
+    if choice.cover.is_none() && choice.order.is_empty() {
+        return proposal;
+    }
+
+    let mut remaining = proposal.entries;
+    let mut take = |id: &str| remaining.iter().position(|e| e.image_id == id).map(|i| remaining.remove(i));
+
+    let mut entries = Vec::new();
+    if let Some(mut cover) = choice.cover.as_deref().and_then(&mut take) {
+        cover.reasons = vec!["Cover chosen by the agent".to_string()];
+        entries.push(cover);
+    }
+    for id in &choice.order {
+        if let Some(mut entry) = take(id) {
+            entry.reasons = vec!["Placed by the agent".to_string()];
+            entries.push(entry);
+        }
+    }
+    let overridden = !entries.is_empty();
+    entries.extend(remaining);
+    for (position, entry) in entries.iter_mut().enumerate() {
+        entry.position = position;
+    }
+
+    GalleryOrder {
+        cover: entries.first().map(|e| e.image_id.clone()),
+        entries,
+        overridden,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo(id: &str, content_type: ContentType, score: f32, hash: u64) -> GalleryCandidate {
+        GalleryCandidate {
+            image_id: id.to_string(),
+            content_type,
+            time_of_day: Some(TimeOfDay::Day),
+            quality_score: score,
+            perceptual_hash: Some(hash),
+            width: 1920,
+            height: 1280,
+        }
+    }
+
+    fn order(gallery: &GalleryOrder) -> Vec<&str> {
+        gallery.entries.iter().map(|e| e.image_id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_cover_then_one_of_each_room_then_extras() {
+        let mut portrait_front = photo("front-portrait", ContentType::Exterior, 0.85, 0x0f0f_0f0f_0f0f_0f0f);
+        portrait_front.width = 1280;
+        portrait_front.height = 1920;
+        let gallery = propose(&[
+            photo("bed-2", ContentType::Bedroom, 0.6, 0x1111_1111_1111_1111),
+            photo("plan", ContentType::FloorPlan, 0.9, 0x2222_2222_2222_2222),
+            photo("kitchen", ContentType::Kitchen, 0.7, 0x3333_3333_3333_3333),
+            photo("bed-1", ContentType::Bedroom, 0.8, 0x4444_4444_4444_4444),
+            photo("front", ContentType::Exterior, 0.8, 0x5555_5555_5555_5555),
+            portrait_front,
+            photo("living", ContentType::LivingRoom, 0.5, 0x6666_6666_6666_6666),
+            photo("deed", ContentType::TitlePaper, 1.0, 0x7777_7777_7777_7777),
+        ]);
+
+        // The landscape exterior beats the slightly sharper portrait one for the cover
+        assert_eq!(gallery.cover.as_deref(), Some("front"));
+        assert_eq!(
+            order(&gallery),
+            ["front", "living", "kitchen", "bed-1", "front-portrait", "bed-2", "plan"]
+        );
+        assert!(gallery.entries.iter().all(|e| !e.reasons.is_empty()));
+        assert!(!gallery.overridden);
+    }
+
+    #[test]
+    fn test_near_duplicates_go_last() {
+        let hash = 0xaaaa_aaaa_aaaa_aaaa;
+        let gallery = propose(&[
+            photo("living-a", ContentType::LivingRoom, 0.9, hash),
+            photo("living-b", ContentType::LivingRoom, 0.8, hash ^ 0b111),
+            photo("living-c", ContentType::LivingRoom, 0.5, !hash),
+            photo("bath", ContentType::Bathroom, 0.4, 0x1234_5678_9abc_def0),
+        ]);
+
+        // No exterior, so the best living room is the cover
+        assert_eq!(order(&gallery), ["living-a", "bath", "living-c", "living-b"]);
+        assert!(gallery.entries[3].reasons[0].contains("living-a"));
+    }
+
+    #[test]
+    fn test_override_pins_cover_and_order() {
+        let candidates = [
+            photo("front", ContentType::Exterior, 0.9, 0x5555_5555_5555_5555),
+            photo("kitchen", ContentType::Kitchen, 0.7, 0x3333_3333_3333_3333),
+            photo("bath", ContentType::Bathroom, 0.6, 0x1234_5678_9abc_def0),
+        ];
+        let choice = GalleryOverride {
+            cover: Some("kitchen".to_string()),
+            order: vec!["bath".to_string(), "gone".to_string()],
+        };
+        let gallery = apply_override(propose(&candidates), &choice);
+
+        assert_eq!(gallery.cover.as_deref(), Some("kitchen"));
+        assert_eq!(order(&gallery), ["kitchen", "bath", "front"]);
+        assert_eq!(gallery.entries[2].position, 2);
+        assert!(gallery.overridden);
+
+        // Unknown ids are only tolerated once stored, not when the agent sets them
+        assert!(choice.validate(&candidates).is_err());
+        let repeated = GalleryOverride { cover: None, order: vec!["bath".to_string(), "bath".to_string()] };
+        assert!(repeated.validate(&candidates).is_err());
+        let cover_pinned = GalleryOverride { cover: Some("kitchen".to_string()), order: vec!["kitchen".to_string()] };
+        assert!(cover_pinned.validate(&candidates).is_err());
+        let valid = GalleryOverride { cover: Some("kitchen".to_string()), order: vec!["bath".to_string()] };
+        assert!(valid.validate(&candidates).is_ok());
+    }
+}
@@ -0,0 +1,499 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use config::{Config as ConfigBuilder, File, FileFormat};
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage};
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn, instrument};
+
+use crate::backend::common::error::error::{Result, AppError};
+use super::processor::ContentType;
+
+// SSIM window and step; fixed so a score never depends on how rows are split up
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STEP: u32 = 4;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+// Reported for identical images instead of infinity
+const MAX_PSNR: f64 = 100.0;
+// Renders are repeated on a pool this size and compared against a single thread
+const DETERMINISM_THREADS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tolerances {
+    pub min_ssim: f64,
+    pub min_psnr: f64,
+    // Mean per-channel total variation distance between histograms, 0..1
+    pub max_histogram_distance: f64,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Self {
+            min_ssim: 0.98,
+            min_psnr: 35.0,
+            max_histogram_distance: 0.02,
+        }
+    }
+}
+
+// Unset fields fall back to the manifest's tolerances
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ToleranceOverrides {
+    pub min_ssim: Option<f64>,
+    pub min_psnr: Option<f64>,
+    pub max_histogram_distance: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoldenCase {
+    pub name: String,
+    pub content_type: ContentType,
+    // Relative to the manifest
+    pub input: PathBuf,
+    pub approved: PathBuf,
+    #[serde(default)]
+    pub tolerances: ToleranceOverrides,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestFile {
+    #[serde(default)]
+    tolerances: Tolerances,
+    #[serde(default, rename = "case")]
+    cases: Vec<GoldenCase>,
+}
+
+#[derive(Debug)]
+pub struct GoldenManifest {
+    root: PathBuf,
+    pub tolerances: Tolerances,
+    pub cases: Vec<GoldenCase>,
+}
+
+impl GoldenManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file: ManifestFile = ConfigBuilder::builder()
+            .add_source(File::from(path).format(FileFormat::Toml))
+            .build()?
+            .try_deserialize()?;
+
+        let mut names = std::collections::HashSet::new();
+        if let Some(case) = file.cases.iter().find(|c| !names.insert(c.name.as_str())) {
+            return Err(AppError::Configuration(format!("Golden case '{}' is defined twice", case.name)));
+        }
+
+        Ok(Self {
+            root: path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            tolerances: file.tolerances,
+            cases: file.cases,
+        })
+    }
+
+    fn tolerances_for(&self, case: &GoldenCase) -> Tolerances {
+        Tolerances {
+            min_ssim: case.tolerances.min_ssim.unwrap_or(self.tolerances.min_ssim),
+            min_psnr: case.tolerances.min_psnr.unwrap_or(self.tolerances.min_psnr),
+            max_histogram_distance: case.tolerances.max_histogram_distance.unwrap_or(self.tolerances.max_histogram_distance),
+        }
+    }
+
+    // Renders every case and compares it with its approved output
+    #[instrument(skip(self, render), fields(cases = self.cases.len()))]
+    pub fn run<F>(&self, render: F) -> Result<DriftReport>
+    where
+        F: Fn(&[u8], ContentType) -> Result<DynamicImage> + Sync,
+    {
+        let mut results = Vec::with_capacity(self.cases.len());
+        for case in &self.cases {
+            let result = self.run_case(case, &render)?;
+            if result.drifted() {
+                warn!(case = %case.name, reasons = ?result.reasons, "Golden image drifted");
+            }
+            results.push(result);
+        }
+
+        // Documents never go through the enhancement pipeline
+        let uncovered = ContentType::ALL
+            .into_iter()
+            .filter(|ct| ct.is_photo() && !self.cases.iter().any(|c| c.content_type == *ct))
+            .collect();
+        Ok(DriftReport { results, uncovered })
+    }
+
+    fn run_case<F>(&self, case: &GoldenCase, render: &F) -> Result<CaseResult>
+    where
+        F: Fn(&[u8], ContentType) -> Result<DynamicImage> + Sync,
+    {
+        let tolerances = self.tolerances_for(case);
+        let mut result = CaseResult {
+            name: case.name.clone(),
+            content_type: case.content_type,
+            tolerances,
+            comparison: None,
+            reasons: Vec::new(),
+        };
+
+        let rendered = match render_twice(&read(&self.root.join(&case.input))?, case.content_type, render)? {
+            Ok(rendered) => rendered,
+            Err(reason) => {
+                result.reasons.push(reason);
+                return Ok(result);
+            }
+        };
+
+        let approved_path = self.root.join(&case.approved);
+        if !approved_path.exists() {
+            result.reasons.push(format!("no approved output at {}", case.approved.display()));
+            return Ok(result);
+        }
+        let approved = load(&approved_path)?.to_rgb8();
+
+        if approved.dimensions() != rendered.dimensions() {
+            let ((aw, ah), (rw, rh)) = (approved.dimensions(), rendered.dimensions());
+            result.reasons.push(format!("size changed from {}x{} to {}x{}", aw, ah, rw, rh));
+            return Ok(result);
+        }
+
+        let comparison = compare(&approved, &rendered);
+        if comparison.ssim < tolerances.min_ssim {
+            result.reasons.push(format!("SSIM {:.4} below {:.4}", comparison.ssim, tolerances.min_ssim));
+        }
+        if comparison.psnr < tolerances.min_psnr {
+            result.reasons.push(format!("PSNR {:.2} dB below {:.2} dB", comparison.psnr, tolerances.min_psnr));
+        }
+        if comparison.histogram_distance > tolerances.max_histogram_distance {
+            result.reasons.push(format!(
+                "histogram distance {:.4} above {:.4}", comparison.histogram_distance, tolerances.max_histogram_distance
+            ));
+        }
+        result.comparison = Some(comparison);
+        Ok(result)
+    }
+
+    // Replaces the approved outputs with the current renders. Only after the
+    // drift report has been reviewed and the new look accepted.
+    #[instrument(skip(self, render), fields(cases = self.cases.len()))]
+    pub fn bless<F>(&self, render: F) -> Result<()>
+    where
+        F: Fn(&[u8], ContentType) -> Result<DynamicImage> + Sync,
+    {
+        for case in &self.cases {
+            let input = read(&self.root.join(&case.input))?;
+            let rendered = render_twice(&input, case.content_type, &render)?
+                .map_err(|reason| AppError::ImageProcessing(format!("Golden case {}: {}", case.name, reason)))?;
+
+            let path = self.root.join(&case.approved);
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .map_err(|e| AppError::ImageProcessing(format!("Failed to create {}: {}", dir.display(), e)))?;
+            }
+            // PNG so blessing never adds compression loss of its own
+            rendered
+                .save_with_format(&path, ImageFormat::Png)
+                .map_err(|e| AppError::ImageProcessing(format!("Failed to write {}: {}", path.display(), e)))?;
+            info!(case = %case.name, path = %path.display(), "Blessed golden image");
+        }
+        Ok(())
+    }
+}
+
+// Inputs go to the renderer as the uploaded bytes, so their EXIF and ICC data reach it
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| AppError::ImageProcessing(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+fn load(path: &Path) -> Result<DynamicImage> {
+    image::open(path).map_err(|e| AppError::ImageProcessing(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+// Renders on one thread and on a pool; any difference is reported instead of a comparison
+fn render_twice<F>(input: &[u8], content_type: ContentType, render: &F) -> Result<std::result::Result<RgbImage, String>>
+where
+    F: Fn(&[u8], ContentType) -> Result<DynamicImage> + Sync,
+{
+    let in_pool = |threads: usize| -> Result<RgbImage> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| AppError::ImageProcessing(format!("Failed to build thread pool: {}", e)))?;
+        pool.install(|| render(input, content_type)).map(|img| img.to_rgb8())
+    };
+
+    let serial = in_pool(1)?;
+    let parallel = in_pool(DETERMINISM_THREADS)?;
+    if serial != parallel {
+        return Ok(Err(format!("render differs between 1 and {} threads", DETERMINISM_THREADS)));
+    }
+    Ok(Ok(serial))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Comparison {
+    pub ssim: f64,
+    pub psnr: f64,
+    pub histogram_distance: f64,
+}
+
+// Both images must be the same size. Work is split per row and the partial sums
+// are added up in row order, so the scores are the same on any number of threads.
+pub fn compare(a: &RgbImage, b: &RgbImage) -> Comparison {
+    Comparison {
+        ssim: ssim(&luma(a), &luma(b)),
+        psnr: psnr(a, b),
+        histogram_distance: histogram_distance(a, b),
+    }
+}
+
+fn luma(img: &RgbImage) -> GrayImage {
+    DynamicImage::ImageRgb8(img.clone()).to_luma8()
+}
+
+fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    let (width, height) = a.dimensions();
+    if width < SSIM_WINDOW || height < SSIM_WINDOW {
+        return if a == b { 1.0 } else { 0.0 };
+    }
+
+    let rows: Vec<u32> = (0..=height - SSIM_WINDOW).step_by(SSIM_STEP as usize).collect();
+    let row_sums: Vec<(f64, usize)> = rows
+        .par_iter()
+        .map(|&y| {
+            let mut sum = 0.0;
+            let mut count = 0;
+            for x in (0..=width - SSIM_WINDOW).step_by(SSIM_STEP as usize) {
+                sum += window_ssim(a, b, x, y);
+                count += 1;
+            }
+            (sum, count)
+        })
+        .collect();
+
+    let (sum, count) = row_sums.iter().fold((0.0, 0), |(s, c), &(rs, rc)| (s + rs, c + rc));
+    sum / count as f64
+}
+
+fn window_ssim(a: &GrayImage, b: &GrayImage, x0: u32, y0: u32) -> f64 {
+    let n = (SSIM_WINDOW * SSIM_WINDOW) as f64;
+    let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for y in y0..y0 + SSIM_WINDOW {
+        for x in x0..x0 + SSIM_WINDOW {
+            let (pa, pb) = (a.get_pixel(x, y)[0] as f64, b.get_pixel(x, y)[0] as f64);
+            sa += pa;
+            sb += pb;
+            saa += pa * pa;
+            sbb += pb * pb;
+            sab += pa * pb;
+        }
+    }
+
+    let (mean_a, mean_b) = (sa / n, sb / n);
+    let var_a = saa / n - mean_a * mean_a;
+    let var_b = sbb / n - mean_b * mean_b;
+    let covariance = sab / n - mean_a * mean_b;
+
+    ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+        / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2))
+}
+
+fn psnr(a: &RgbImage, b: &RgbImage) -> f64 {
+    let row_bytes = a.width() as usize * 3;
+    let squared_error: u64 = a
+        .as_raw()
+        .par_chunks(row_bytes)
+        .zip(b.as_raw().par_chunks(row_bytes))
+        .map(|(ra, rb)| ra.iter().zip(rb).map(|(&x, &y)| (x as i64 - y as i64).pow(2) as u64).sum::<u64>())
+        .sum();
+
+    if squared_error == 0 {
+        return MAX_PSNR;
+    }
+    let mse = squared_error as f64 / a.as_raw().len() as f64;
+    (10.0 * (255.0 * 255.0 / mse).log10()).min(MAX_PSNR)
+}
+
+fn histogram_distance(a: &RgbImage, b: &RgbImage) -> f64 {
+    let (ha, hb) = (channel_histograms(a), channel_histograms(b));
+    let total = (a.width() * a.height()).max(1) as f64;
+
+    let distance: f64 = (0..3)
+        .map(|c| {
+            let l1: u64 = ha[c].iter().zip(&hb[c]).map(|(&x, &y)| x.abs_diff(y)).sum();
+            0.5 * l1 as f64 / total
+        })
+        .sum();
+    distance / 3.0
+}
+
+fn channel_histograms(img: &RgbImage) -> [[u64; 256]; 3] {
+    img.as_raw()
+        .par_chunks(img.width().max(1) as usize * 3)
+        .map(|row| {
+            let mut hist = [[0u64; 256]; 3];
+            for pixel in row.chunks_exact(3) {
+                (0..3).for_each(|c| hist[c][pixel[c] as usize] += 1);
+            }
+            hist
+        })
+        .reduce(
+            || [[0u64; 256]; 3],
+            |mut acc, hist| {
+                for c in 0..3 {
+                    acc[c].iter_mut().zip(&hist[c]).for_each(|(a, h)| *a += h);
+                }
+                acc
+            },
+        )
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaseResult {
+    pub name: String,
+    pub content_type: ContentType,
+    pub tolerances: Tolerances,
+    // None when the render couldn't be compared; `reasons` says why
+    pub comparison: Option<Comparison>,
+    pub reasons: Vec<String>,
+}
+
+impl CaseResult {
+    pub fn drifted(&self) -> bool {
+        !self.reasons.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftReport {
+    pub results: Vec<CaseResult>,
+    // Content types without a golden case, so regressions there go unseen
+    pub uncovered: Vec<ContentType>,
+}
+
+impl DriftReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| !r.drifted())
+    }
+
+    pub fn drifted(&self) -> impl Iterator<Item = &CaseResult> {
+        self.results.iter().filter(|r| r.drifted())
+    }
+}
+
+impl fmt::Display for DriftReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let drifted = self.drifted().count();
+        writeln!(f, "Golden images: {} checked, {} drifted", self.results.len(), drifted)?;
+        writeln!(f)?;
+        writeln!(f, "| case | content type | SSIM | PSNR (dB) | histogram | result |")?;
+        writeln!(f, "|---|---|---|---|---|---|")?;
+        for result in &self.results {
+            let (ssim, psnr, histogram) = match &result.comparison {
+                Some(c) => (format!("{:.4}", c.ssim), format!("{:.2}", c.psnr), format!("{:.4}", c.histogram_distance)),
+                None => ("-".into(), "-".into(), "-".into()),
+            };
+            let outcome = if result.drifted() { format!("DRIFTED: {}", result.reasons.join("; ")) } else { "ok".into() };
+            writeln!(f, "| {} | {:?} | {} | {} | {} | {} |", result.name, result.content_type, ssim, psnr, histogram, outcome)?;
+        }
+        if !self.uncovered.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "No golden case for: {:?}", self.uncovered)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::image_processor::test_fixtures::same_on_any_thread_count;
+    use image::Rgb;
+
+    fn scene(offset: u8) -> RgbImage {
+        RgbImage::from_fn(96, 64, |x, y| {
+            let v = ((x * 7 + y * 3) % 200) as u8;
+            Rgb([v.saturating_add(offset), v / 2 + 40, 255 - v])
+        })
+    }
+
+    // Every window and pixel in turn, as a single running sum
+    fn serial_compare(a: &RgbImage, b: &RgbImage) -> Comparison {
+        let (la, lb) = (luma(a), luma(b));
+        let (mut ssim_sum, mut windows) = (0.0, 0);
+        for y in (0..=a.height() - SSIM_WINDOW).step_by(SSIM_STEP as usize) {
+            for x in (0..=a.width() - SSIM_WINDOW).step_by(SSIM_STEP as usize) {
+                ssim_sum += window_ssim(&la, &lb, x, y);
+                windows += 1;
+            }
+        }
+
+        let mut squared_error = 0u64;
+        let mut histograms = [[[0u64; 256]; 3]; 2];
+        for (pa, pb) in a.pixels().zip(b.pixels()) {
+            for c in 0..3 {
+                squared_error += (pa[c] as i64 - pb[c] as i64).pow(2) as u64;
+                histograms[0][c][pa[c] as usize] += 1;
+                histograms[1][c][pb[c] as usize] += 1;
+            }
+        }
+        let mse = squared_error as f64 / a.as_raw().len() as f64;
+        let l1: u64 = (0..3)
+            .map(|c| histograms[0][c].iter().zip(&histograms[1][c]).map(|(&x, &y)| x.abs_diff(y)).sum::<u64>())
+            .sum();
+
+        Comparison {
+            ssim: ssim_sum / windows as f64,
+            psnr: if squared_error == 0 { MAX_PSNR } else { (10.0 * (255.0 * 255.0 / mse).log10()).min(MAX_PSNR) },
+            histogram_distance: 0.5 * l1 as f64 / (a.width() * a.height()) as f64 / 3.0,
+        }
+    }
+
+    #[test]
+    fn test_metrics_match_on_any_thread_count() {
+        let (a, b) = (scene(0), scene(12));
+        let identical = compare(&a, &a);
+        assert_eq!(identical.ssim, 1.0);
+        assert_eq!(identical.psnr, MAX_PSNR);
+        assert_eq!(identical.histogram_distance, 0.0);
+
+        let shifted = compare(&a, &b);
+        assert!(shifted.ssim < 1.0 && shifted.psnr < 40.0 && shifted.histogram_distance > 0.0, "{:?}", shifted);
+
+        let parallel = same_on_any_thread_count(|| compare(&a, &b));
+        let serial = serial_compare(&a, &b);
+        assert!((parallel.ssim - serial.ssim).abs() < 1e-12, "{:?} vs {:?}", parallel, serial);
+        assert_eq!(parallel.psnr, serial.psnr);
+        assert!((parallel.histogram_distance - serial.histogram_distance).abs() < 1e-12, "{:?} vs {:?}", parallel, serial);
+    }
+
+    #[test]
+    fn test_suite_reports_drifted_cases() {
+        let root = std::env::temp_dir().join(format!("golden-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        scene(0).save(root.join("room.png")).unwrap();
+        std::fs::write(root.join("manifest.toml"), r#"
+            [[case]]
+            name = "room"
+            content_type = "LivingRoom"
+            input = "room.png"
+            approved = "approved/room.png"
+        "#).unwrap();
+        let manifest = GoldenManifest::load(&root.join("manifest.toml")).unwrap();
+
+        let identity = |data: &[u8], _: ContentType| Ok(image::load_from_memory(data)?);
+        assert!(manifest.run(identity).unwrap().drifted().next().is_some(), "missing approved output passed");
+
+        manifest.bless(identity).unwrap();
+        let report = manifest.run(identity).unwrap();
+        assert!(report.passed(), "{}", report);
+        assert!(report.uncovered.contains(&ContentType::Kitchen));
+        assert!(!report.uncovered.contains(&ContentType::TitlePaper));
+
+        let brighter = |data: &[u8], _: ContentType| Ok(image::load_from_memory(data)?.brighten(20));
+        let report = manifest.run(brighter).unwrap();
+        let drifted: Vec<_> = report.drifted().map(|r| r.name.as_str()).collect();
+        assert_eq!(drifted, ["room"]);
+        assert!(report.to_string().contains("DRIFTED"));
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
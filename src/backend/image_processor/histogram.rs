@@ -1,6 +1,9 @@
 use image::DynamicImage;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use super::memory::rgb_view;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct HistogramStats {
     pub mean: f32,
@@ -176,59 +179,87 @@ fn calculate_shadow_detail(histogram: &[u32]) -> f32 {
         .sum::<f32>().sqrt() / 64.0
 }
 
+// Rows per parallel chunk in the histogram pass
+const HISTOGRAM_ROWS: usize = 32;
+
 pub fn analyze_histogram(img: &DynamicImage) -> Vec<u32> {
-    let rgb_img = img.to_rgb8();
-    let (width, height) = rgb_img.dimensions();
-    let mut histogram = vec![0u32; 256];
-    
+    let rgb_img = rgb_view(img);
+    let stride = rgb_img.width().max(1) as usize * 3;
+
     // Optimized weights for real estate photography
     const R_WEIGHT: f32 = 0.299; // Emphasize red for warm interior tones
     const G_WEIGHT: f32 = 0.587; // Standard green weight for natural perception
     const B_WEIGHT: f32 = 0.114; // Reduce blue influence to prevent window/sky bias
-    
-    // Split image into regions for parallel processing
-    let chunk_size = (height / 4).max(1); // Process in 4 chunks minimum
-    let chunks: Vec<_> = (0..height)
-        .step_by(chunk_size as usize)
-        .map(|y| {
+
+    // Row chunks in parallel; counts are integers, so the merged histogram
+    // doesn't depend on how the rows were split
+    rgb_img.as_raw()
+        .par_chunks(stride * HISTOGRAM_ROWS)
+        .map(|rows| {
             let mut chunk_hist = vec![0u32; 256];
-            let chunk_end = (y + chunk_size).min(height);
-            
-            // Process each chunk
-            for cy in y..chunk_end {
-                for x in 0..width {
-                    let pixel = rgb_img.get_pixel(x, cy);
-                    
-                    // Apply perceptual color weights and gamma correction
-                    let intensity = (
-                        R_WEIGHT * (pixel[0] as f32).powf(2.2) + 
-                        G_WEIGHT * (pixel[1] as f32).powf(2.2) + 
-                        B_WEIGHT * (pixel[2] as f32).powf(2.2)
-                    ).powf(1.0/2.2);
-                    
-                    // HDR-aware intensity mapping
-                    let mapped_intensity = if intensity > 255.0 {
-                        // Soft clipping for HDR content
-                        255.0 - (255.0 / (1.0 + (intensity - 255.0) * 0.1))
-                    } else {
-                        intensity
-                    };
-                    
-                    // Convert to 8-bit with proper rounding
-                    let index = (mapped_intensity + 0.5) as usize;
-                    chunk_hist[index.min(255)] += 1;
-                }
+            for pixel in rows.chunks_exact(3) {
+                // Apply perceptual color weights and gamma correction
+                let intensity = (
+                    R_WEIGHT * (pixel[0] as f32).powf(2.2) + 
+                    G_WEIGHT * (pixel[1] as f32).powf(2.2) + 
+                    B_WEIGHT * (pixel[2] as f32).powf(2.2)
+                ).powf(1.0/2.2);
+
+                // HDR-aware intensity mapping
+                let mapped_intensity = if intensity > 255.0 {
+                    // Soft clipping for HDR content
+                    255.0 - (255.0 / (1.0 + (intensity - 255.0) * 0.1))
+                } else {
+                    intensity
+                };
+
+                // Convert to 8-bit with proper rounding
+                let index = (mapped_intensity + 0.5) as usize;
+                chunk_hist[index.min(255)] += 1;
             }
             chunk_hist
         })
-        .collect();
-    
-    // Combine chunk histograms
-    for chunk in chunks {
-        for (i, &count) in chunk.iter().enumerate() {
-            histogram[i] += count;
+        .reduce(
+            || vec![0u32; 256],
+            // Combine chunk histograms
+            |mut histogram, chunk| {
+                histogram.iter_mut().zip(&chunk).for_each(|(h, c)| *h += c);
+                histogram
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::image_processor::test_fixtures::{noisy, same_on_any_thread_count};
+
+    // The pixel-by-pixel pass the row chunks replaced
+    fn serial_histogram(img: &DynamicImage) -> Vec<u32> {
+        let mut histogram = vec![0u32; 256];
+        for pixel in img.to_rgb8().pixels() {
+            let intensity = (
+                0.299 * (pixel[0] as f32).powf(2.2) +
+                0.587 * (pixel[1] as f32).powf(2.2) +
+                0.114 * (pixel[2] as f32).powf(2.2)
+            ).powf(1.0/2.2);
+            let mapped_intensity = if intensity > 255.0 {
+                255.0 - (255.0 / (1.0 + (intensity - 255.0) * 0.1))
+            } else {
+                intensity
+            };
+            histogram[((mapped_intensity + 0.5) as usize).min(255)] += 1;
         }
+        histogram
     }
-    
-    histogram
-}
\ No newline at end of file
+
+    #[test]
+    fn test_histogram_matches_on_any_thread_count() {
+        let img = noisy(61, HISTOGRAM_ROWS as u32 * 5 + 3);
+        let histogram = same_on_any_thread_count(|| analyze_histogram(&img));
+        assert_eq!(histogram, serial_histogram(&img));
+        assert_eq!(histogram.iter().sum::<u32>(), img.width() * img.height());
+        // RGBA input counts the same pixels the same way
+        assert_eq!(analyze_histogram(&DynamicImage::ImageRgba8(img.to_rgba8())), histogram);
+    }
+}
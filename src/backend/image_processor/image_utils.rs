@@ -1,11 +1,13 @@
-use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, Luma};
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, Rgba, Luma};
 use imageproc::filter::gaussian_blur_f32;
 use imageproc::{
     gradients::sobel_gradients,
     hough::{detect_lines, LineDetectionOptions, PolarLine}
 };
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use crate::backend::common::error::error::Result;
+use crate::backend::image_processor::perspective::estimate_verticals;
 
 
 pub fn unsharp_mask(
@@ -43,19 +45,39 @@ pub fn unsharp_mask(
     output
 }
 
+// Rows per parallel chunk in the edge and whole-image passes
+const PASS_ROWS: u32 = 32;
+
 pub fn detect_edges(img: &DynamicImage) -> ImageBuffer<Luma<u8>, Vec<u8>> {
     let grayscale = img.to_luma8();
-    let gradients = sobel_gradients(&grayscale);
-    
     let (width, height) = grayscale.dimensions();
     let mut edge_image = ImageBuffer::new(width, height);
-    
-    // Enhanced edge detection with noise suppression
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = gradients.get_pixel(x, y);
-            let magnitude = ((pixel[0] as f32).powi(2) + (pixel[1] as f32).powi(2)).sqrt();
-            
+    if width == 0 || height == 0 {
+        return edge_image;
+    }
+    let stride = width as usize;
+
+    // Row chunks in parallel. Sobel reads one row either side, so each chunk
+    // takes its gradients from a tile with those rows added; at the image's top
+    // and bottom the tile clamps exactly as the whole image would.
+    edge_image.par_chunks_mut(stride * PASS_ROWS as usize).enumerate().for_each(|(chunk, rows)| {
+        let y0 = chunk as u32 * PASS_ROWS;
+        let tile_y0 = y0.saturating_sub(1);
+        let tile_y1 = (y0 + (rows.len() / stride) as u32 + 1).min(height);
+        let tile = GrayImage::from_raw(
+            width,
+            tile_y1 - tile_y0,
+            grayscale.as_raw()[tile_y0 as usize * stride..tile_y1 as usize * stride].to_vec(),
+        )
+        .expect("tile is whole rows");
+        let gradients = sobel_gradients(&tile);
+
+        // Enhanced edge detection with noise suppression
+        for (i, edge) in rows.iter_mut().enumerate() {
+            let (x, y) = ((i % stride) as u32, y0 + (i / stride) as u32);
+            // Sobel gives the gradient magnitude directly
+            let magnitude = gradients.get_pixel(x, y - tile_y0)[0] as f32;
+
             // Apply adaptive thresholding for better edge detection
             let threshold = if y < height / 3 {
                 // Lower threshold for upper part (potential sky/ceiling)
@@ -64,17 +86,15 @@ pub fn detect_edges(img: &DynamicImage) -> ImageBuffer<Luma<u8>, Vec<u8>> {
                 // Standard threshold for main content
                 15.0
             };
-            
-            let edge_strength = if magnitude < threshold {
+
+            *edge = if magnitude < threshold {
                 0
             } else {
                 (magnitude.min(255.0)) as u8
             };
-            
-            edge_image.put_pixel(x, y, Luma([edge_strength]));
         }
-    }
-    
+    });
+
     edge_image
 }
 
@@ -244,22 +264,18 @@ pub fn detect_quality_issues(img: &DynamicImage) -> QualityAnalysis {
 
 fn check_blur_level(edges: &ImageBuffer<Luma<u8>, Vec<u8>>) -> bool {
     // Calculate edge strength distribution
-    let total_edges: f32 = edges.pixels()
-        .map(|p| p[0] as f32)
-        .sum::<f32>();
+    let total_edges = edges.as_raw()
+        .par_chunks(edges.width().max(1) as usize * PASS_ROWS as usize)
+        .map(|rows| rows.iter().map(|&p| p as u64).sum::<u64>())
+        .sum::<u64>() as f32;
     let avg_edge_strength = total_edges / (edges.width() * edges.height()) as f32;
     
     avg_edge_strength < 12.0 // Threshold for blur detection
 }
 
 fn check_perspective(img: &DynamicImage) -> bool {
-    let lines = detect_architectural_lines(img).unwrap_or_default();
-    
-    // Check for vertical lines that deviate from 90 degrees
-    lines.iter().any(|line| {
-        let angle = line.angle_in_degrees as f32;
-        (angle - 90.0).abs() > 2.0
-    })
+    // Converging or tilted verticals, from the vanishing point estimate
+    estimate_verticals(img).is_some_and(|estimate| estimate.needs_correction())
 }
 
 fn check_lighting_issues(img: &DynamicImage) -> bool {
@@ -295,22 +311,27 @@ fn check_window_exposure(windows: &[Rect], img: &DynamicImage) -> bool {
     })
 }
 
-fn estimate_noise_level(img: &DynamicImage) -> f32 {
+pub fn estimate_noise_level(img: &DynamicImage) -> f32 {
     // Analyze local variance in smooth regions
     let edges = detect_edges(img);
     let (width, height) = img.dimensions();
-    let mut noise_sum = 0.0;
-    let mut smooth_regions = 0;
-    
-    for y in 1..height-1 {
-        for x in 1..width-1 {
-            if edges.get_pixel(x, y)[0] < 10 {
-                let local_var = calculate_local_variance(img, x, y);
-                noise_sum += local_var;
-                smooth_regions += 1;
+
+    // Per-row sums, added up in row order so the result doesn't depend on the thread count
+    let rows: Vec<(f32, u32)> = (1..height.saturating_sub(1))
+        .into_par_iter()
+        .map(|y| {
+            let mut noise_sum = 0.0;
+            let mut smooth_regions = 0;
+            for x in 1..width.saturating_sub(1) {
+                if edges.get_pixel(x, y)[0] < 10 {
+                    noise_sum += calculate_local_variance(img, x, y);
+                    smooth_regions += 1;
+                }
             }
-        }
-    }
+            (noise_sum, smooth_regions)
+        })
+        .collect();
+    let (noise_sum, smooth_regions) = rows.iter().fold((0.0, 0), |(s, c), &(rs, rc)| (s + rs, c + rc));
     
     if smooth_regions > 0 {
         noise_sum / smooth_regions as f32
@@ -501,23 +522,29 @@ fn analyze_leading_lines(lines: &[PolarLine], width: u32, height: u32) -> f32 {
 fn analyze_visual_balance(img: &DynamicImage) -> f32 {
     let (width, height) = img.dimensions();
     let center_x = width / 2;
-    let mut left_weight = 0.0;
-    let mut right_weight = 0.0;
-    
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            let intensity = 0.299 * pixel[0] as f32 + 
-                          0.587 * pixel[1] as f32 + 
-                          0.114 * pixel[2] as f32;
-            
-            if x < center_x {
-                left_weight += intensity;
-            } else {
-                right_weight += intensity;
+
+    // Per-row weights, added up in row order as in `estimate_noise_level`
+    let rows: Vec<(f32, f32)> = (0..height)
+        .into_par_iter()
+        .map(|y| {
+            let mut left_weight = 0.0;
+            let mut right_weight = 0.0;
+            for x in 0..width {
+                let pixel = img.get_pixel(x, y);
+                let intensity = 0.299 * pixel[0] as f32 + 
+                              0.587 * pixel[1] as f32 + 
+                              0.114 * pixel[2] as f32;
+
+                if x < center_x {
+                    left_weight += intensity;
+                } else {
+                    right_weight += intensity;
+                }
             }
-        }
-    }
+            (left_weight, right_weight)
+        })
+        .collect();
+    let (left_weight, right_weight) = rows.iter().fold((0.0, 0.0), |(l, r), &(rl, rr)| (l + rl, r + rr));
     
     let total_weight = left_weight + right_weight;
     if total_weight > 0.0 {
@@ -708,23 +735,24 @@ fn analyze_lighting_uniformity(img: &DynamicImage) -> f32 {
 
 fn analyze_color_balance(img: &DynamicImage) -> f32 {
     let (width, height) = img.dimensions();
-    let mut r_sum = 0.0;
-    let mut g_sum = 0.0;
-    let mut b_sum = 0.0;
-    
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            r_sum += pixel[0] as f32;
-            g_sum += pixel[1] as f32;
-            b_sum += pixel[2] as f32;
-        }
-    }
+
+    // Integer channel sums, so the row split doesn't matter
+    let [r_sum, g_sum, b_sum] = (0..height)
+        .into_par_iter()
+        .map(|y| {
+            let mut sums = [0u64; 3];
+            for x in 0..width {
+                let pixel = img.get_pixel(x, y);
+                (0..3).for_each(|c| sums[c] += pixel[c] as u64);
+            }
+            sums
+        })
+        .reduce(|| [0u64; 3], |a, b| [a[0] + b[0], a[1] + b[1], a[2] + b[2]]);
     
     let total = (width * height) as f32;
-    let r_mean = r_sum / total;
-    let g_mean = g_sum / total;
-    let b_mean = b_sum / total;
+    let r_mean = r_sum as f32 / total;
+    let g_mean = g_sum as f32 / total;
+    let b_mean = b_sum as f32 / total;
     
     // Check for color cast
     let max_diff = (r_mean - g_mean).abs().max((g_mean - b_mean).abs())
@@ -749,14 +777,25 @@ fn analyze_detail_preservation(img: &DynamicImage) -> f32 {
 }
 
 fn calculate_edge_histogram(edges: &ImageBuffer<Luma<u8>, Vec<u8>>) -> Vec<u32> {
-    let mut histogram = vec![0u32; 256];
-    for pixel in edges.pixels() {
-        histogram[pixel[0] as usize] += 1;
-    }
-    histogram
+    edges.as_raw()
+        .par_chunks(edges.width().max(1) as usize * PASS_ROWS as usize)
+        .map(|rows| {
+            let mut histogram = vec![0u32; 256];
+            for &pixel in rows {
+                histogram[pixel as usize] += 1;
+            }
+            histogram
+        })
+        .reduce(
+            || vec![0u32; 256],
+            |mut histogram, chunk| {
+                histogram.iter_mut().zip(&chunk).for_each(|(h, c)| *h += c);
+                histogram
+            },
+        )
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct QualityAnalysis {
     pub is_blurry: bool,
     pub has_perspective_issues: bool,
@@ -769,4 +808,115 @@ pub struct QualityAnalysis {
     pub lighting_uniformity: f32,
     pub color_balance: f32,
     pub detail_preservation: f32,
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::image_processor::test_fixtures::{grain, same_on_any_thread_count};
+    use image::{Rgb, RgbImage};
+
+    fn room() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(90, PASS_ROWS * 3 + 7, |x, y| {
+            let wall = if x % 30 < 3 { 40 } else { 180 };
+            let noise = grain(x, y, 13) as u8;
+            Rgb([wall + noise, wall - noise, (wall / 2).wrapping_add(noise)])
+        }))
+    }
+
+    // The pixel-by-pixel passes the row chunks replaced. Float sums come out in a
+    // different order, so those are compared to within rounding.
+    mod serial {
+        use super::*;
+
+        pub fn is_blurry(edges: &ImageBuffer<Luma<u8>, Vec<u8>>) -> bool {
+            let total_edges: f32 = edges.pixels().map(|p| p[0] as f32).sum();
+            (total_edges / (edges.width() * edges.height()) as f32) < 12.0
+        }
+
+        pub fn noise_level(img: &DynamicImage) -> f32 {
+            let edges = detect_edges(img);
+            let (width, height) = img.dimensions();
+            let mut noise_sum = 0.0;
+            let mut smooth_regions = 0;
+            for y in 1..height - 1 {
+                for x in 1..width - 1 {
+                    if edges.get_pixel(x, y)[0] < 10 {
+                        noise_sum += calculate_local_variance(img, x, y);
+                        smooth_regions += 1;
+                    }
+                }
+            }
+            noise_sum / smooth_regions as f32
+        }
+
+        pub fn visual_balance(img: &DynamicImage) -> f32 {
+            let (width, height) = img.dimensions();
+            let mut left_weight = 0.0;
+            let mut right_weight = 0.0;
+            for y in 0..height {
+                for x in 0..width {
+                    let pixel = img.get_pixel(x, y);
+                    let intensity = 0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32;
+                    if x < width / 2 {
+                        left_weight += intensity;
+                    } else {
+                        right_weight += intensity;
+                    }
+                }
+            }
+            1.0 - (left_weight - right_weight).abs() / (left_weight + right_weight)
+        }
+
+        pub fn color_balance(img: &DynamicImage) -> f32 {
+            let mut sums = [0.0f32; 3];
+            for (_, _, pixel) in img.pixels() {
+                (0..3).for_each(|c| sums[c] += pixel[c] as f32);
+            }
+            let total = (img.width() * img.height()) as f32;
+            let [r_mean, g_mean, b_mean] = sums.map(|sum| sum / total);
+            let max_diff = (r_mean - g_mean).abs().max((g_mean - b_mean).abs()).max((b_mean - r_mean).abs());
+            1.0 - (max_diff / 128.0).min(1.0)
+        }
+
+        pub fn edge_histogram(edges: &ImageBuffer<Luma<u8>, Vec<u8>>) -> Vec<u32> {
+            let mut histogram = vec![0u32; 256];
+            for pixel in edges.pixels() {
+                histogram[pixel[0] as usize] += 1;
+            }
+            histogram
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() <= expected.abs() * 1e-4, "{} vs {}", actual, expected);
+    }
+
+    #[test]
+    fn test_edges_match_whole_image_sobel() {
+        let img = room();
+        let gradients = sobel_gradients(&img.to_luma8());
+        let height = img.height();
+
+        let edges = detect_edges(&img);
+        for (x, y, edge) in edges.enumerate_pixels() {
+            let magnitude = gradients.get_pixel(x, y)[0] as f32;
+            let threshold = if y < height / 3 { 10.0 } else { 15.0 };
+            let expected = if magnitude < threshold { 0 } else { magnitude.min(255.0) as u8 };
+            assert_eq!(edge[0], expected, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn test_quality_analysis_matches_on_any_thread_count() {
+        let img = room();
+        let analysis = same_on_any_thread_count(|| detect_quality_issues(&img));
+        assert_close(analysis.noise_level, serial::noise_level(&img));
+        assert_close(analysis.color_balance, serial::color_balance(&img));
+
+        // Passes that only feed into the scores
+        let edges = detect_edges(&img);
+        assert_eq!(same_on_any_thread_count(|| check_blur_level(&edges)), serial::is_blurry(&edges));
+        assert_eq!(same_on_any_thread_count(|| calculate_edge_histogram(&edges)), serial::edge_histogram(&edges));
+        assert_close(same_on_any_thread_count(|| analyze_visual_balance(&img)), serial::visual_balance(&img));
+    }
+}
@@ -17,6 +17,8 @@ use super::image_utils::QualityAnalysis;
 pub struct ImageJobScheduler {
     file_manager: Arc<FileManager>,
     embedding_service: Arc<OpenAIEmbedding>,
+    // Shared with the upload path, so jobs use the same stages and memory budget
+    processor: Arc<ImageProcessor>,
     batch_size: usize,
 }
 
@@ -60,8 +62,7 @@ impl ImageJobScheduler {
         let batch_id = config.batch_id.clone();
         
         let file = self.file_manager.get_file(image_id).await?;
-        let processor = ImageProcessor::new();
-        let analysis = processor.analyze_image(&file).await?;
+        let analysis = self.processor.analyze_image(&file).await?;
         
         let embedding = self.embedding_service
             .generate_embedding(&analysis.description)
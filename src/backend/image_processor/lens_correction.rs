@@ -0,0 +1,502 @@
+use image::{imageops::FilterType, DynamicImage, GenericImageView, GrayImage, Rgba, RgbaImage};
+use imageproc::edges::canny;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info, instrument};
+
+use crate::backend::common::error::error::{Result, AppError};
+use super::exif_metadata::ExifData;
+
+// The estimate runs on a downscaled copy; the model is normalised, so it carries over
+const WORK_SIZE: u32 = 1024;
+const CANNY_LOW: f32 = 40.0;
+const CANNY_HIGH: f32 = 100.0;
+// Edge points fed to the straightness score; more only adds time
+const MAX_EDGE_POINTS: usize = 6000;
+
+// k1 search range for the estimate: from strong barrel (fisheye-like 12 mm) to
+// mild pincushion, coarse grid first, then refined around the best step
+const ESTIMATE_K1_MIN: f64 = -0.30;
+const ESTIMATE_K1_MAX: f64 = 0.06;
+const COARSE_STEP: f64 = 0.01;
+const FINE_STEP: f64 = 0.002;
+// Below this the lines are straight enough as shot
+const MIN_ESTIMATED_K1: f64 = 0.015;
+// Required improvement in line concentration over no correction; weaker results
+// usually come from curved furniture or foliage rather than the lens
+const MIN_STRAIGHTNESS_GAIN: f64 = 1.08;
+
+// Bounds for profiles and hand edits; outside them the radial map folds over
+const MAX_K1: f32 = 0.5;
+const MAX_K2: f32 = 0.3;
+// Rows resampled per parallel task
+const WARP_ROWS: usize = 64;
+// Residual in normalised units, about a tenth of a pixel at the working size
+const UNDISTORT_TOLERANCE: f64 = 1e-4;
+
+// Radial polynomial in coordinates centred on the image and scaled by half its
+// diagonal: a point at radius r in the corrected image comes from radius
+// r * (1 + k1 r² + k2 r⁴) in the photo. Barrel distortion has k1 < 0.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LensDistortion {
+    pub k1: f32,
+    #[serde(default)]
+    pub k2: f32,
+}
+
+impl LensDistortion {
+    pub fn validate(&self) -> Result<()> {
+        if !self.k1.is_finite() || self.k1.abs() > MAX_K1 || !self.k2.is_finite() || self.k2.abs() > MAX_K2 {
+            return Err(AppError::Validation(format!(
+                "Lens distortion needs |k1| <= {} and |k2| <= {}, got k1 = {}, k2 = {}",
+                MAX_K1, MAX_K2, self.k1, self.k2
+            )));
+        }
+        Ok(())
+    }
+
+    fn factor(&self, r2: f64) -> f64 {
+        1.0 + self.k1 as f64 * r2 + self.k2 as f64 * r2 * r2
+    }
+
+    // Where a corrected point was in the photo
+    fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        let f = self.factor(x * x + y * y);
+        (x * f, y * f)
+    }
+
+    // Where a photo point lands once corrected; fixed-point iteration converges
+    // quickly for the distortion any real lens has. None past the radius where a
+    // strong barrel map folds over, since no corrected point lands there.
+    fn undistort(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (mut ux, mut uy) = (x, y);
+        for _ in 0..8 {
+            let f = self.factor(ux * ux + uy * uy);
+            if f <= 0.0 {
+                return None;
+            }
+            (ux, uy) = (x / f, y / f);
+        }
+        let (dx, dy) = self.distort(ux, uy);
+        ((dx - x).hypot(dy - y) < UNDISTORT_TOLERANCE).then_some((ux, uy))
+    }
+
+    // The map has to keep growing with radius out to the corners, or the
+    // correction would fold the image over itself
+    fn monotonic_to(&self, r: f64) -> bool {
+        let r2 = r * r;
+        1.0 + 3.0 * self.k1 as f64 * r2 + 5.0 * self.k2 as f64 * r2 * r2 > 0.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LensSource {
+    // Matched a bundled profile by EXIF lens or camera model
+    Profile { name: String },
+    // No profile matched; fitted by straightening the photo's own lines
+    Estimated,
+}
+
+// Chosen automatically and stored in the recipe, so re-renders apply the same map
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LensCorrection {
+    #[serde(flatten)]
+    pub distortion: LensDistortion,
+    pub source: LensSource,
+}
+
+// Reported with the result; `zoom` is how much of the photo's width survived the
+// fill (above 1 means the correction pulled more of the frame in)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppliedLensCorrection {
+    #[serde(flatten)]
+    pub correction: LensCorrection,
+    pub zoom: f32,
+}
+
+pub struct LensProfile {
+    pub name: &'static str,
+    // Case-insensitive substrings of the EXIF LensModel, or of Model for fixed-lens cameras
+    pub lens_models: &'static [&'static str],
+    pub camera_models: &'static [&'static str],
+    // Long side over short side of the frame the coefficients were calibrated on
+    pub aspect_ratio: f32,
+    // (focal length mm, k1, k2), ascending; interpolated for zooms. In lensfun's
+    // poly5 convention, radius 1 at half the short side, so entries compare
+    // directly with its database; see `from_short_side`.
+    pub coefficients: &'static [(f32, f32, f32)],
+}
+
+impl LensProfile {
+    fn matches(&self, exif: &ExifData) -> bool {
+        let contains = |value: &Option<String>, patterns: &[&str]| {
+            value.as_deref().is_some_and(|value| {
+                let value = value.to_lowercase();
+                patterns.iter().any(|pattern| value.contains(&pattern.to_lowercase()))
+            })
+        };
+        contains(&exif.lens_model, self.lens_models) || contains(&exif.camera_model, self.camera_models)
+    }
+
+    // Without a focal length, zooms are assumed at their widest, which is how interiors are shot
+    pub fn distortion_at(&self, focal_length: Option<f64>) -> LensDistortion {
+        let first = self.coefficients[0];
+        let focal = focal_length.map_or(first.0, |f| f as f32);
+        let upper = self.coefficients.iter().position(|&(f, _, _)| f >= focal);
+        let (k1, k2) = match upper {
+            Some(0) => (first.1, first.2),
+            Some(i) => {
+                let (f0, a1, a2) = self.coefficients[i - 1];
+                let (f1, b1, b2) = self.coefficients[i];
+                let t = (focal - f0) / (f1 - f0);
+                (a1 + (b1 - a1) * t, a2 + (b2 - a2) * t)
+            }
+            None => {
+                let last = self.coefficients[self.coefficients.len() - 1];
+                (last.1, last.2)
+            }
+        };
+        LensDistortion::from_short_side(k1, k2, self.aspect_ratio)
+    }
+}
+
+impl LensDistortion {
+    // Rescales poly5 coefficients normalised to half the short side (lensfun)
+    // to half the diagonal. A point at diagonal radius r sits at r * s in short-side
+    // units, s² = 1 + aspect², so k1 r_s² = k1 s² r² and k2 r_s⁴ = k2 s⁴ r⁴.
+    pub fn from_short_side(k1: f32, k2: f32, aspect_ratio: f32) -> Self {
+        let s2 = 1.0 + aspect_ratio * aspect_ratio;
+        LensDistortion { k1: k1 * s2, k2: k2 * s2 * s2 }
+    }
+}
+
+// Ultra-wide lenses common in property photography, at the focal lengths where
+// their distortion changes most
+pub const LENS_PROFILES: &[LensProfile] = &[
+    LensProfile {
+        name: "Canon EF 16-35mm f/4L IS USM",
+        lens_models: &["EF16-35mm f/4L"],
+        camera_models: &[],
+        aspect_ratio: 1.5,
+        coefficients: &[(16.0, -0.0323, 0.0027), (20.0, -0.0160, 0.0011), (24.0, -0.0055, 0.0004), (35.0, 0.0037, -0.0002)],
+    },
+    LensProfile {
+        name: "Canon EF-S 10-18mm f/4.5-5.6 IS STM",
+        lens_models: &["EF-S10-18mm"],
+        camera_models: &[],
+        aspect_ratio: 1.5,
+        coefficients: &[(10.0, -0.0437, 0.0039), (14.0, -0.0188, 0.0014), (18.0, -0.0043, 0.0003)],
+    },
+    LensProfile {
+        name: "Sony FE 16-35mm F4 ZA OSS",
+        lens_models: &["FE 16-35mm F4"],
+        camera_models: &[],
+        aspect_ratio: 1.5,
+        coefficients: &[(16.0, -0.0363, 0.0032), (24.0, -0.0074, 0.0006), (35.0, 0.0046, -0.0003)],
+    },
+    LensProfile {
+        name: "Sony E 10-18mm F4 OSS",
+        lens_models: &["E 10-18mm F4"],
+        camera_models: &[],
+        aspect_ratio: 1.5,
+        coefficients: &[(10.0, -0.0508, 0.0045), (14.0, -0.0222, 0.0018), (18.0, -0.0065, 0.0005)],
+    },
+    LensProfile {
+        name: "Nikon AF-S 16-35mm f/4G ED VR",
+        lens_models: &["16-35mm f/4G", "16.0-35.0 mm f/4.0"],
+        camera_models: &[],
+        aspect_ratio: 1.5,
+        coefficients: &[(16.0, -0.0345, 0.0028), (24.0, -0.0062, 0.0005), (35.0, 0.0040, -0.0002)],
+    },
+    LensProfile {
+        name: "Sigma 14-24mm F2.8 DG DN | Art",
+        lens_models: &["14-24mm F2.8 DG DN"],
+        camera_models: &[],
+        aspect_ratio: 1.5,
+        coefficients: &[(14.0, -0.0209, 0.0018), (18.0, -0.0092, 0.0008), (24.0, -0.0018, 0.0001)],
+    },
+    LensProfile {
+        name: "DJI Mavic Air 2",
+        lens_models: &[],
+        camera_models: &["FC3170"],
+        aspect_ratio: 4.0 / 3.0,
+        coefficients: &[(4.5, -0.0295, 0.0027)],
+    },
+    LensProfile {
+        name: "GoPro HERO (linear off)",
+        lens_models: &[],
+        camera_models: &["HERO9 Black", "HERO10 Black", "HERO11 Black"],
+        aspect_ratio: 4.0 / 3.0,
+        coefficients: &[(2.9, -0.1044, 0.0123)],
+    },
+];
+
+pub fn find_profile(exif: &ExifData) -> Option<&'static LensProfile> {
+    LENS_PROFILES.iter().find(|profile| profile.matches(exif))
+}
+
+// Profile first; without one the distortion is estimated from the photo itself,
+// and None means its lines are already straight enough
+#[instrument(skip(img, exif))]
+pub fn auto_correction(img: &DynamicImage, exif: Option<&ExifData>) -> Option<LensCorrection> {
+    if let Some((exif, profile)) = exif.and_then(|exif| find_profile(exif).map(|profile| (exif, profile))) {
+        let distortion = profile.distortion_at(exif.focal_length);
+        debug!(profile = profile.name, ?distortion, "Matched lens profile");
+        return Some(LensCorrection {
+            distortion,
+            source: LensSource::Profile { name: profile.name.to_string() },
+        });
+    }
+
+    estimate_distortion(img).map(|distortion| LensCorrection { distortion, source: LensSource::Estimated })
+}
+
+// Fits k1 by making the photo's edges as straight as possible: each candidate
+// undistorts the edge points, which are then voted into a Hough accumulator.
+// Straight lines pile their votes into few bins, so the sum of squared votes
+// peaks at the right coefficient.
+pub fn estimate_distortion(img: &DynamicImage) -> Option<LensDistortion> {
+    let (width, height) = img.dimensions();
+    let scale = (WORK_SIZE as f32 / width.max(height) as f32).min(1.0);
+    let small = if scale < 1.0 {
+        img.resize(
+            (width as f32 * scale).round() as u32,
+            (height as f32 * scale).round() as u32,
+            FilterType::Triangle,
+        )
+        .to_luma8()
+    } else {
+        img.to_luma8()
+    };
+
+    let points = edge_points(&small);
+    if points.len() < MAX_EDGE_POINTS / 10 {
+        debug!(points = points.len(), "Too few edges to estimate lens distortion");
+        return None;
+    }
+
+    let score = |k1: f64| straightness(&points, LensDistortion { k1: k1 as f32, k2: 0.0 });
+    let baseline = score(0.0);
+    let steps = ((ESTIMATE_K1_MAX - ESTIMATE_K1_MIN) / COARSE_STEP).round() as usize;
+    let (coarse, _) = (0..=steps)
+        .into_par_iter()
+        .map(|i| {
+            let k1 = ESTIMATE_K1_MIN + i as f64 * COARSE_STEP;
+            (k1, score(k1))
+        })
+        .reduce(|| (0.0, baseline), |a, b| if b.1 > a.1 { b } else { a });
+
+    let fine_steps = (COARSE_STEP / FINE_STEP).round() as i32;
+    let (k1, best) = (-fine_steps..=fine_steps)
+        .into_par_iter()
+        .map(|i| {
+            let k1 = coarse + i as f64 * FINE_STEP;
+            (k1, score(k1))
+        })
+        .reduce(|| (coarse, score(coarse)), |a, b| if b.1 > a.1 { b } else { a });
+
+    let gain = best / baseline.max(f64::EPSILON);
+    debug!(k1, gain, points = points.len(), "Estimated lens distortion");
+    (k1.abs() >= MIN_ESTIMATED_K1 && gain >= MIN_STRAIGHTNESS_GAIN).then_some(LensDistortion { k1: k1 as f32, k2: 0.0 })
+}
+
+// Canny edge pixels in normalised coordinates, thinned evenly to MAX_EDGE_POINTS
+fn edge_points(gray: &GrayImage) -> Vec<(f64, f64)> {
+    let (width, height) = gray.dimensions();
+    let edges = canny(gray, CANNY_LOW, CANNY_HIGH);
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let radius = cx.hypot(cy);
+
+    let all: Vec<(f64, f64)> = edges
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p[0] > 0)
+        .map(|(x, y, _)| ((x as f64 + 0.5 - cx) / radius, (y as f64 + 0.5 - cy) / radius))
+        .collect();
+    let stride = all.len().div_ceil(MAX_EDGE_POINTS).max(1);
+    all.into_iter().step_by(stride).collect()
+}
+
+// Sum of cubed Hough votes per point; one-degree angle bins, and distance bins
+// about a pixel wide at the working size. Cubing lets the few full bins of the
+// lines outweigh the votes every point spreads over all the other angles.
+fn straightness(points: &[(f64, f64)], distortion: LensDistortion) -> f64 {
+    const ANGLES: usize = 180;
+    const DISTANCE_BINS: usize = 1024;
+    let trig: Vec<(f64, f64)> = (0..ANGLES).map(|a| (a as f64).to_radians().sin_cos()).collect();
+    // Undistorted points stay within this radius for any k1 in the search range
+    let max_rho = 1.5;
+
+    // Points the candidate can't invert don't vote, so it loses their share
+    let undistorted: Vec<(f64, f64)> = points.iter().filter_map(|&(x, y)| distortion.undistort(x, y)).collect();
+    if undistorted.is_empty() {
+        return 0.0;
+    }
+    // Back to the photo's spread: a correction that only shrinks the points would
+    // otherwise crowd them into fewer bins and win for that alone
+    let rms = |points: &[(f64, f64)]| (points.iter().map(|(x, y)| x * x + y * y).sum::<f64>() / points.len() as f64).sqrt();
+    let scale = rms(points) / rms(&undistorted).max(f64::EPSILON);
+
+    let mut votes = vec![0u32; ANGLES * DISTANCE_BINS];
+    for &(ux, uy) in &undistorted {
+        for (angle, &(sin, cos)) in trig.iter().enumerate() {
+            let rho = (ux * cos + uy * sin) * scale;
+            let bin = ((rho + max_rho) / (2.0 * max_rho) * DISTANCE_BINS as f64) as usize;
+            if bin < DISTANCE_BINS {
+                votes[angle * DISTANCE_BINS + bin] += 1;
+            }
+        }
+    }
+    votes.iter().map(|&v| (v as f64).powi(3)).sum::<f64>() / points.len() as f64
+}
+
+// Resamples the photo through the radial map at the same size. The frame is
+// zoomed so every output pixel has a source; barrel corrections zoom out (more
+// of the photo fits), pincushion ones in.
+#[instrument(skip(img))]
+pub fn apply_correction(img: &RgbaImage, correction: &LensCorrection) -> Result<(RgbaImage, AppliedLensCorrection)> {
+    let distortion = correction.distortion;
+    distortion.validate()?;
+
+    let (width, height) = img.dimensions();
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let radius = cx.hypot(cy);
+    let zoom = fill_zoom(distortion, cx / radius, cy / radius)
+        .ok_or_else(|| AppError::ImageProcessing("Lens correction leaves no fully covered frame".into()))?;
+
+    let stride = width as usize * 4;
+    let mut out = vec![0u8; stride * height as usize];
+    out.par_chunks_mut(stride * WARP_ROWS).enumerate().for_each(|(chunk, rows)| {
+        for (row, line) in rows.chunks_mut(stride).enumerate() {
+            let y = (chunk * WARP_ROWS + row) as f64 + 0.5;
+            for x in 0..width as usize {
+                let q = ((x as f64 + 0.5 - cx) / radius * zoom, (y - cy) / radius * zoom);
+                let (px, py) = distortion.distort(q.0, q.1);
+                let pixel = sample_bilinear(img, px * radius + cx - 0.5, py * radius + cy - 0.5);
+                line[x * 4..x * 4 + 4].copy_from_slice(&pixel.0);
+            }
+        }
+    });
+
+    let corrected = RgbaImage::from_raw(width, height, out)
+        .ok_or_else(|| AppError::ImageProcessing("Lens correction produced a malformed buffer".into()))?;
+    let applied = AppliedLensCorrection { correction: correction.clone(), zoom: zoom as f32 };
+    info!(?applied, "Applied lens distortion correction");
+    Ok((corrected, applied))
+}
+
+// Largest zoom whose frame border still maps inside the photo. Checked along the
+// border only, since the map is monotonic in radius.
+fn fill_zoom(distortion: LensDistortion, half_width: f64, half_height: f64) -> Option<f64> {
+    const BORDER_SAMPLES: usize = 64;
+    let border: Vec<(f64, f64)> = (0..=BORDER_SAMPLES)
+        .flat_map(|i| {
+            let t = i as f64 / BORDER_SAMPLES as f64 * 2.0 - 1.0;
+            [
+                (t * half_width, half_height),
+                (t * half_width, -half_height),
+                (half_width, t * half_height),
+                (-half_width, t * half_height),
+            ]
+        })
+        .collect();
+    let covered = |zoom: f64| {
+        distortion.monotonic_to(zoom) && border.iter().all(|&(x, y)| {
+            let (px, py) = distortion.distort(x * zoom, y * zoom);
+            px.abs() <= half_width && py.abs() <= half_height
+        })
+    };
+
+    let (mut low, mut high) = (0.25, 2.0);
+    if !covered(low) {
+        return None;
+    }
+    for _ in 0..40 {
+        let mid = (low + high) / 2.0;
+        if covered(mid) { low = mid } else { high = mid }
+    }
+    Some(low)
+}
+
+fn sample_bilinear(img: &RgbaImage, x: f64, y: f64) -> Rgba<u8> {
+    let (width, height) = img.dimensions();
+    let x = x.clamp(0.0, (width - 1) as f64);
+    let y = y.clamp(0.0, (height - 1) as f64);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+    let (a, b, c, d) = (img.get_pixel(x0, y0), img.get_pixel(x1, y0), img.get_pixel(x0, y1), img.get_pixel(x1, y1));
+    let mut out = [0u8; 4];
+    for (i, channel) in out.iter_mut().enumerate() {
+        let top = a[i] as f64 * (1.0 - fx) + b[i] as f64 * fx;
+        let bottom = c[i] as f64 * (1.0 - fx) + d[i] as f64 * fx;
+        *channel = (top * (1.0 - fy) + bottom * fy).round().clamp(0.0, 255.0) as u8;
+    }
+    Rgba(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A grid of straight dark lines, photographed through a lens with the given distortion
+    fn distorted_grid(width: u32, height: u32, distortion: LensDistortion) -> RgbaImage {
+        let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+        let radius = cx.hypot(cy);
+        RgbaImage::from_fn(width, height, |x, y| {
+            let (ux, uy) = distortion.undistort((x as f64 + 0.5 - cx) / radius, (y as f64 + 0.5 - cy) / radius).unwrap();
+            let (gx, gy) = (ux * radius + cx, uy * radius + cy);
+            let on_line = gx.rem_euclid(64.0) < 3.0 || gy.rem_euclid(64.0) < 3.0;
+            if on_line { Rgba([30, 30, 30, 255]) } else { Rgba([225, 225, 225, 255]) }
+        })
+    }
+
+    #[test]
+    fn test_barrel_distortion_is_estimated_and_straightened() {
+        let lens = LensDistortion { k1: -0.12, k2: 0.0 };
+        let photo = distorted_grid(900, 600, lens);
+
+        let estimated = estimate_distortion(&DynamicImage::ImageRgba8(photo.clone())).unwrap();
+        assert!((estimated.k1 - lens.k1).abs() < 0.03, "k1 {}", estimated.k1);
+
+        let correction = LensCorrection { distortion: estimated, source: LensSource::Estimated };
+        let (corrected, applied) = apply_correction(&photo, &correction).unwrap();
+        assert_eq!(corrected.dimensions(), photo.dimensions());
+        assert!(applied.zoom > 1.0, "barrel corrections zoom out, got {}", applied.zoom);
+        assert!(estimate_distortion(&DynamicImage::ImageRgba8(corrected)).is_none());
+
+        let straight = distorted_grid(900, 600, LensDistortion { k1: 0.0, k2: 0.0 });
+        assert!(estimate_distortion(&DynamicImage::ImageRgba8(straight)).is_none());
+    }
+
+    #[test]
+    fn test_profiles_match_by_lens_and_interpolate_focal_length() {
+        let exif = ExifData {
+            lens_model: Some("FE 16-35mm F4 ZA OSS".to_string()),
+            focal_length: Some(20.0),
+            ..ExifData::default()
+        };
+        let profile = find_profile(&exif).unwrap();
+        assert_eq!(profile.name, "Sony FE 16-35mm F4 ZA OSS");
+        let k1 = profile.distortion_at(exif.focal_length).k1;
+        let widest = LensDistortion::from_short_side(-0.0363, 0.0032, 1.5);
+        let mid = LensDistortion::from_short_side((-0.0363 + -0.0074) / 2.0, 0.0, 1.5);
+        assert!((k1 - mid.k1).abs() < 1e-4, "k1 {}", k1);
+        assert_eq!(profile.distortion_at(None), widest);
+
+        let drone = ExifData { camera_model: Some("FC3170".to_string()), ..ExifData::default() };
+        assert_eq!(find_profile(&drone).map(|p| p.name), Some("DJI Mavic Air 2"));
+        assert!(find_profile(&ExifData::default()).is_none());
+    }
+
+    #[test]
+    fn test_short_side_coefficients_map_the_same_points() {
+        // A corner of a 3:2 frame: radius 1 on the diagonal, sqrt(3.25) in short-side units
+        let (k1, k2) = (-0.04, 0.005);
+        let diagonal = LensDistortion::from_short_side(k1, k2, 1.5);
+        let r2: f64 = 3.25;
+        let short_side = 1.0 + k1 as f64 * r2 + k2 as f64 * r2 * r2;
+        assert!((diagonal.factor(1.0) - short_side).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+use crate::backend::common::error::error::{Result, AppError};
+use crate::backend::common::types::id_types::ImageId;
+use super::processor::ContentType;
+
+// A group needs this many photos before it has a look to match
+const MIN_GROUP: usize = 2;
+// Share of the gap to the group target closed in one pass; the rest is left so
+// a deliberately moody shot isn't flattened into the others
+const PULL: f32 = 0.75;
+// Per-image limits, in the grade's own units (white balance factor, HSL lightness
+// points); also the bounds for hand edits
+pub const MAX_TEMPERATURE_NUDGE: f32 = 0.08;
+pub const MAX_BRIGHTNESS_NUDGE: f32 = 8.0;
+// Smaller nudges aren't visible and not worth a re-render
+const MIN_TEMPERATURE_NUDGE: f32 = 0.01;
+const MIN_BRIGHTNESS_NUDGE: f32 = 1.0;
+
+// Colour temperature and exposure of one enhanced photo, from
+// `detect_color_temperature` and `calculate_room_brightness`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct LookStats {
+    // (mean red - mean blue) * 2, channels in 0..1; positive is warm
+    pub temperature: f32,
+    // Mean luma in 0..1
+    pub brightness: f32,
+}
+
+// Listing-level correction toward the other photos of the same content type,
+// applied at the end of the grade
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LookAdjustment {
+    // White balance factor, as `adjust_white_balance` takes it
+    pub temperature: f32,
+    // HSL lightness points, as `adjust_brightness` takes them
+    pub brightness: f32,
+}
+
+impl LookAdjustment {
+    pub fn validate(&self) -> Result<()> {
+        if self.temperature.abs() > MAX_TEMPERATURE_NUDGE || self.brightness.abs() > MAX_BRIGHTNESS_NUDGE {
+            return Err(AppError::Validation(format!(
+                "listing_look must stay within ±{} temperature and ±{} brightness",
+                MAX_TEMPERATURE_NUDGE, MAX_BRIGHTNESS_NUDGE
+            )));
+        }
+        Ok(())
+    }
+}
+
+// One harmonized photo, for the batch report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LookAdjustmentRecord {
+    pub image_id: ImageId,
+    pub content_type: ContentType,
+    pub before: LookStats,
+    pub target: LookStats,
+    pub after: LookStats,
+    pub adjustment: LookAdjustment,
+}
+
+// Median look of each content type with enough photos. Medians, so one odd
+// shot doesn't drag the rest toward it. Plans and documents have no look to match.
+pub fn group_targets(looks: impl IntoIterator<Item = (ContentType, LookStats)>) -> HashMap<ContentType, LookStats> {
+    let mut groups: HashMap<ContentType, Vec<LookStats>> = HashMap::new();
+    for (content_type, look) in looks {
+        if content_type.is_photo() {
+            groups.entry(content_type).or_default().push(look);
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, looks)| looks.len() >= MIN_GROUP)
+        .map(|(content_type, looks)| {
+            let target = LookStats {
+                temperature: median(looks.iter().map(|look| look.temperature).collect()),
+                brightness: median(looks.iter().map(|look| look.brightness).collect()),
+            };
+            (content_type, target)
+        })
+        .collect()
+}
+
+// Nudge moving `look` toward `target`, or None if the photo already fits in
+pub fn adjustment_toward(look: LookStats, target: LookStats) -> Option<LookAdjustment> {
+    // Scaling red up and blue down by t moves the temperature measure by about
+    // 2t on a neutral room; lightness points are hundredths of brightness
+    let temperature = ((target.temperature - look.temperature) * PULL / 2.0)
+        .clamp(-MAX_TEMPERATURE_NUDGE, MAX_TEMPERATURE_NUDGE);
+    let brightness = ((target.brightness - look.brightness) * PULL * 100.0)
+        .clamp(-MAX_BRIGHTNESS_NUDGE, MAX_BRIGHTNESS_NUDGE);
+
+    if temperature.abs() < MIN_TEMPERATURE_NUDGE && brightness.abs() < MIN_BRIGHTNESS_NUDGE {
+        return None;
+    }
+    Some(LookAdjustment { temperature, brightness })
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn look(temperature: f32, brightness: f32) -> LookStats {
+        LookStats { temperature, brightness }
+    }
+
+    #[test]
+    fn test_targets_are_group_medians() {
+        let targets = group_targets([
+            (ContentType::Bedroom, look(0.10, 0.40)),
+            (ContentType::Bedroom, look(0.02, 0.55)),
+            (ContentType::Bedroom, look(0.30, 0.50)),
+            (ContentType::Kitchen, look(-0.05, 0.60)),
+            (ContentType::FloorPlan, look(0.0, 0.90)),
+            (ContentType::FloorPlan, look(0.0, 0.95)),
+        ]);
+
+        assert_eq!(targets.get(&ContentType::Bedroom), Some(&look(0.10, 0.50)));
+        // A lone kitchen has nothing to match, and plans aren't graded
+        assert!(!targets.contains_key(&ContentType::Kitchen));
+        assert!(!targets.contains_key(&ContentType::FloorPlan));
+    }
+
+    #[test]
+    fn test_adjustments_are_limited_and_skip_close_matches() {
+        let target = look(0.10, 0.50);
+        assert_eq!(adjustment_toward(look(0.105, 0.505), target), None);
+
+        let warmer = adjustment_toward(look(0.02, 0.50), target).unwrap();
+        assert!((warmer.temperature - 0.03).abs() < 1e-6);
+        assert_eq!(warmer.brightness, 0.0);
+
+        let dark_and_cold = adjustment_toward(look(-0.40, 0.10), target).unwrap();
+        assert_eq!(dark_and_cold.temperature, MAX_TEMPERATURE_NUDGE);
+        assert_eq!(dark_and_cold.brightness, MAX_BRIGHTNESS_NUDGE);
+        assert!(dark_and_cold.validate().is_ok());
+    }
+}
@@ -0,0 +1,469 @@
+use std::borrow::Cow;
+use std::fs::OpenOptions;
+use std::io::Cursor;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use image::{DynamicImage, GenericImageView, ImageBuffer, ImageDecoder, ImageReader, Rgba, RgbImage, RgbaImage};
+use memmap2::MmapMut;
+use rayon::prelude::*;
+
+use crate::backend::common::error::error::{Result, AppError};
+use super::derivatives::renditions_bytes;
+
+// Rows per band in the tiled stages. An 8K band is about 2 MB, and the halo
+// rows re-read per band stay a small fraction of the work.
+pub const BAND_ROWS: u32 = 64;
+
+// Band tiles, encoder scratch and analysis temporaries on top of the pixel buffers
+const WORKING_OVERHEAD: usize = 64 * 1024 * 1024;
+// The edge map the quality analysis keeps, plus the luma plane and RGB copy
+// each check makes of the upload
+const ANALYSIS_BYTES_PER_PIXEL: usize = 6;
+
+// Mertens fusion keeps a normalised f32 weight per frame plus the blended
+// pyramids and one frame's working planes and pyramids at a time
+const FUSION_BYTES_PER_PIXEL: usize = 68;
+const FUSION_BYTES_PER_PIXEL_PER_FRAME: usize = 4;
+
+static NEXT_MAP: AtomicUsize = AtomicUsize::new(0);
+
+// Live pixel buffers of one job, checked against the memory it reserved.
+// Memory-mapped working buffers are not charged: they are file-backed, so
+// under pressure the kernel writes them out instead of the worker being killed.
+pub struct JobMemory {
+    budget: usize,
+    mmap_threshold: usize,
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl JobMemory {
+    pub fn new(budget: usize, mmap_threshold: usize) -> Self {
+        Self {
+            budget,
+            mmap_threshold,
+            current: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    // For tools like the golden-image suite that run outside any worker
+    pub fn unbounded() -> Self {
+        Self::new(usize::MAX, usize::MAX)
+    }
+
+    pub fn charge(&self, bytes: usize) -> Result<MemoryCharge<'_>> {
+        let mut charge = MemoryCharge { memory: self, bytes: 0 };
+        charge.resize(bytes)?;
+        Ok(charge)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    fn add(&self, bytes: usize) -> Result<()> {
+        let current = self.current.fetch_add(bytes, Ordering::Relaxed).saturating_add(bytes);
+        if current > self.budget {
+            self.current.fetch_sub(bytes, Ordering::Relaxed);
+            return Err(AppError::MemoryBudgetExceeded { required: current, budget: self.budget });
+        }
+        self.peak.fetch_max(current, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn release(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+// Released when dropped, so it should live exactly as long as the buffer it covers
+pub struct MemoryCharge<'a> {
+    memory: &'a JobMemory,
+    bytes: usize,
+}
+
+impl MemoryCharge<'_> {
+    // Stages that replace a buffer grow the charge to cover input and output
+    // while they run, then shrink it to the output once the input is gone
+    pub fn resize(&mut self, bytes: usize) -> Result<()> {
+        if bytes > self.bytes {
+            self.memory.add(bytes - self.bytes)?;
+        } else {
+            self.memory.release(self.bytes - bytes);
+        }
+        self.bytes = bytes;
+        Ok(())
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for MemoryCharge<'_> {
+    fn drop(&mut self) {
+        self.memory.release(self.bytes);
+    }
+}
+
+pub enum PixelStore {
+    Heap(Vec<u8>),
+    // Backed by an unlinked temp file
+    Mapped(MmapMut),
+}
+
+impl PixelStore {
+    fn zeroed(len: usize, memory: &JobMemory) -> Result<Self> {
+        if len < memory.mmap_threshold {
+            return Ok(PixelStore::Heap(vec![0; len]));
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "work_{}_{}.rgba",
+            std::process::id(),
+            NEXT_MAP.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        // The mapping keeps the pages; unlinking now means a dying worker leaves nothing behind
+        std::fs::remove_file(&path)?;
+        file.set_len(len as u64)?;
+        // SAFETY: the file was created above and unlinked, so nothing else can resize or write it
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(PixelStore::Mapped(map))
+    }
+}
+
+impl Deref for PixelStore {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            PixelStore::Heap(data) => data,
+            PixelStore::Mapped(map) => map,
+        }
+    }
+}
+
+impl DerefMut for PixelStore {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            PixelStore::Heap(data) => data,
+            PixelStore::Mapped(map) => map,
+        }
+    }
+}
+
+// RGBA8 buffer for the per-pixel stages
+pub type WorkImage = ImageBuffer<Rgba<u8>, PixelStore>;
+
+// Converts band by band, so only one band of converted pixels per thread is ever on the heap
+pub fn to_work_image(img: &DynamicImage, memory: &JobMemory) -> Result<WorkImage> {
+    let (width, height) = img.dimensions();
+    let stride = width as usize * 4;
+    let mut store = PixelStore::zeroed(stride * height as usize, memory)?;
+
+    if stride > 0 {
+        store.par_chunks_mut(stride * BAND_ROWS as usize).enumerate().for_each(|(band, rows)| {
+            let y0 = band as u32 * BAND_ROWS;
+            let band_height = (rows.len() / stride) as u32;
+            match img {
+                DynamicImage::ImageRgba8(rgba) => {
+                    let start = y0 as usize * stride;
+                    rows.copy_from_slice(&rgba.as_raw()[start..start + rows.len()]);
+                }
+                // Same conversion as `to_rgba8`, one band at a time
+                _ => rows.copy_from_slice(img.crop_imm(0, y0, width, band_height).to_rgba8().as_raw()),
+            }
+        });
+    }
+
+    Ok(ImageBuffer::from_raw(width, height, store).expect("store is sized for the image"))
+}
+
+// Back to an ordinary image for encoding and the watermark
+pub fn into_dynamic(work: WorkImage) -> DynamicImage {
+    let (width, height) = work.dimensions();
+    let data = match work.into_raw() {
+        PixelStore::Heap(data) => data,
+        PixelStore::Mapped(map) => map.to_vec(),
+    };
+    DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).expect("store is sized for the image"))
+}
+
+// Per-pixel stages: each call gets a band's rows and the image row it starts at.
+// Bands run in parallel, so a stage must not depend on the order they run in.
+pub fn for_each_band(img: &mut WorkImage, f: impl Fn(u32, &mut [u8]) + Sync) {
+    let stride = img.width() as usize * 4;
+    if stride == 0 {
+        return;
+    }
+    img.par_chunks_mut(stride * BAND_ROWS as usize)
+        .enumerate()
+        .for_each(|(band, rows)| f(band as u32 * BAND_ROWS, rows));
+}
+
+// A band plus up to `halo` rows either side, as they were before any band was written
+pub struct HaloBand {
+    pub tile: RgbaImage,
+    // Image row of the band's first row
+    pub y: u32,
+    // Tile row of the band's first row
+    pub top: u32,
+}
+
+// Neighbourhood stages (convolutions, edge maps). Results match running the
+// stage over the whole image as long as it reads no further than `halo` rows.
+// Bands run a group at a time, one per thread: the group's tiles are cut
+// before any of its bands is written, so the output doesn't depend on how
+// many threads there are.
+pub fn for_each_band_with_halo(
+    img: &mut WorkImage,
+    halo: u32,
+    memory: &JobMemory,
+    f: impl Fn(HaloBand, &mut [u8]) -> Result<()> + Sync,
+) -> Result<()> {
+    let (width, height) = img.dimensions();
+    let stride = width as usize * 4;
+    if stride == 0 {
+        return Ok(());
+    }
+    let group = rayon::current_num_threads().max(1) as u32;
+    let group_rows = BAND_ROWS * group;
+    // The group's tiles, plus the saved rows above this group and the next
+    let _tiles = memory.charge(stride * (group_rows + (2 * group + 2) * halo) as usize)?;
+
+    // Rows above the current group, saved before the previous group overwrote them
+    let mut above: Vec<u8> = Vec::new();
+    let mut g0 = 0;
+    while g0 < height {
+        let g1 = (g0 + group_rows).min(height);
+        let bands: Vec<HaloBand> = (g0..g1)
+            .step_by(BAND_ROWS as usize)
+            .map(|y0| {
+                let tile_end = (y0 + BAND_ROWS + halo).min(height);
+                let (tile, top) = if y0 == g0 {
+                    let mut tile = Vec::with_capacity(above.len() + (tile_end - y0) as usize * stride);
+                    tile.extend_from_slice(&above);
+                    tile.extend_from_slice(&(**img)[y0 as usize * stride..tile_end as usize * stride]);
+                    (tile, (above.len() / stride) as u32)
+                } else {
+                    // The rows above belong to a band of this group, not yet written
+                    let tile_start = y0.saturating_sub(halo);
+                    ((**img)[tile_start as usize * stride..tile_end as usize * stride].to_vec(), y0 - tile_start)
+                };
+                HaloBand {
+                    tile: RgbaImage::from_raw(width, (tile.len() / stride) as u32, tile).expect("tile is whole rows"),
+                    y: y0,
+                    top,
+                }
+            })
+            .collect();
+
+        let last = bands.last().expect("a group has at least one band");
+        let last_y0 = last.y - last.top;
+        let next_start = g1.saturating_sub(halo).max(last_y0);
+        above = last.tile.as_raw()[(next_start - last_y0) as usize * stride..(g1 - last_y0) as usize * stride].to_vec();
+
+        (**img)[g0 as usize * stride..g1 as usize * stride]
+            .par_chunks_mut(stride * BAND_ROWS as usize)
+            .zip(bands)
+            .try_for_each(|(rows, band)| f(band, rows))?;
+        g0 = g1;
+    }
+    Ok(())
+}
+
+// Most uploads decode to RGB8 already; the analysis passes borrow those instead of copying
+pub fn rgb_view(img: &DynamicImage) -> Cow<'_, RgbImage> {
+    match img.as_rgb8() {
+        Some(rgb) => Cow::Borrowed(rgb),
+        None => Cow::Owned(img.to_rgb8()),
+    }
+}
+
+pub fn image_bytes(img: &DynamicImage) -> usize {
+    img.as_bytes().len()
+}
+
+pub fn rgba_bytes(width: u32, height: u32) -> usize {
+    width as usize * height as usize * 4
+}
+
+// Working buffers of the content, time-of-day and quality analysis of an upload
+pub fn analysis_bytes(width: u32, height: u32) -> usize {
+    width as usize * height as usize * ANALYSIS_BYTES_PER_PIXEL
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImageProbe {
+    pub width: u32,
+    pub height: u32,
+    pub decoded_bytes: usize,
+}
+
+// Size and decoded footprint from the header alone, before anything is decoded
+pub fn probe_image(data: &[u8]) -> Result<ImageProbe> {
+    let decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    Ok(ImageProbe { width, height, decoded_bytes: decoder.total_bytes() as usize })
+}
+
+// Peak tracked memory of one upload: the largest of decode plus EXIF rotation,
+// decode plus the RGBA conversion or the analysis buffers, a geometry stage's
+// input and output, or the final image plus its watermarked copy or the gallery
+// renditions. Panorama tiles and sky replacement are added by the caller, which
+// knows whether they run.
+pub fn estimate_job_memory(probe: &ImageProbe, encoded_len: usize) -> usize {
+    let rgba = rgba_bytes(probe.width, probe.height);
+    let pixels = (probe.decoded_bytes * 2)
+        .max(probe.decoded_bytes + rgba)
+        .max(probe.decoded_bytes + analysis_bytes(probe.width, probe.height))
+        .max(rgba * 2)
+        .max(rgba + renditions_bytes(probe.width, probe.height));
+    pixels + encoded_len + WORKING_OVERHEAD
+}
+
+// Decoding and resizing, the only stages that see an upload the dimension policy
+// resizes at its own size: the decoded upload with its rotated or colour-converted
+// copy, then with the resized copy, or two of those after an upscale's sharpening
+pub fn estimate_resize_memory(probe: &ImageProbe, resized: &ImageProbe, encoded_len: usize) -> usize {
+    let pixels = (probe.decoded_bytes * 2)
+        .max(probe.decoded_bytes + resized.decoded_bytes)
+        .max(resized.decoded_bytes * 2);
+    pixels + encoded_len + WORKING_OVERHEAD
+}
+
+// Peak of a stage that holds `working` bytes beside one RGBA copy of the image
+pub fn estimate_stage_memory(width: u32, height: u32, working: usize, encoded_len: usize) -> usize {
+    rgba_bytes(width, height) + working + encoded_len + WORKING_OVERHEAD
+}
+
+// Working set of `fuse_brackets` on top of the decoded frames
+pub fn fusion_bytes(pixels: usize, frames: usize) -> usize {
+    pixels * (FUSION_BYTES_PER_PIXEL + FUSION_BYTES_PER_PIXEL_PER_FRAME * frames)
+}
+
+// Fusion holds every decoded frame and its f32 planes; the fused master then
+// runs through the single-upload pipeline
+pub fn estimate_bracket_memory(frames: &[ImageProbe], encoded_len: usize) -> usize {
+    let Some(first) = frames.first() else {
+        return WORKING_OVERHEAD;
+    };
+    let pixels = first.width as usize * first.height as usize;
+    let decoded: usize = frames.iter().map(|f| f.decoded_bytes).sum();
+    let fusion = decoded + fusion_bytes(pixels, frames.len());
+    let master = ImageProbe { decoded_bytes: pixels * 3, ..*first };
+    fusion.max(estimate_job_memory(&master, 0)) + encoded_len + WORKING_OVERHEAD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::image_processor::test_fixtures::noisy;
+
+    // 3x3 mean of red, written into green
+    fn box_blur(tile: &RgbaImage, x: u32, y: u32) -> u8 {
+        let (w, h) = tile.dimensions();
+        let mut sum = 0u32;
+        for dy in -1i32..=1 {
+            for dx in -1i32..=1 {
+                let sx = (x as i32 + dx).clamp(0, w as i32 - 1) as u32;
+                let sy = (y as i32 + dy).clamp(0, h as i32 - 1) as u32;
+                sum += tile.get_pixel(sx, sy)[0] as u32;
+            }
+        }
+        (sum / 9) as u8
+    }
+
+    #[test]
+    fn test_banded_stages_match_whole_image() {
+        // Mapped store, and a height that leaves a short last band
+        let memory = JobMemory::new(usize::MAX, 0);
+        let img = noisy(37, BAND_ROWS * 2 + 5);
+        let whole = img.to_rgba8();
+        let expected = RgbaImage::from_fn(whole.width(), whole.height(), |x, y| {
+            let p = whole.get_pixel(x, y);
+            Rgba([p[0], box_blur(&whole, x, y), p[2], p[3]])
+        });
+
+        // Groups of one band, of two and a remainder, and all three bands in one group
+        for threads in [1, 2, 7] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let mut work = pool.install(|| to_work_image(&img, &memory)).unwrap();
+            assert!(matches!(work.as_raw(), PixelStore::Mapped(_)));
+            assert_eq!(&*work, whole.as_raw().as_slice());
+
+            // Writes the same channel it reads, so a band reading its neighbour's output would show
+            pool.install(|| {
+                for_each_band_with_halo(&mut work, 1, &memory, |band, rows| {
+                    for (i, pixel) in rows.chunks_exact_mut(4).enumerate() {
+                        let (x, y) = (i as u32 % band.tile.width(), band.top + i as u32 / band.tile.width());
+                        pixel[1] = box_blur(&band.tile, x, y);
+                        pixel[0] = 0;
+                    }
+                    Ok(())
+                })
+            })
+            .unwrap();
+            let starts = std::sync::Mutex::new(Vec::new());
+            pool.install(|| {
+                for_each_band(&mut work, |y, rows| {
+                    starts.lock().unwrap().push(y);
+                    for pixel in rows.chunks_exact_mut(4) {
+                        pixel[2] = 255 - pixel[2];
+                    }
+                })
+            });
+            let mut starts = starts.into_inner().unwrap();
+            starts.sort();
+            assert_eq!(starts, vec![0, BAND_ROWS, BAND_ROWS * 2]);
+
+            let result = into_dynamic(work).to_rgba8();
+            for (x, y, pixel) in result.enumerate_pixels() {
+                let p = expected.get_pixel(x, y);
+                assert_eq!(*pixel, Rgba([0, p[1], 255 - p[2], 255]), "{} threads ({}, {})", threads, x, y);
+            }
+        }
+        assert_eq!(memory.current.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_budget_is_enforced_and_peak_recorded() {
+        let memory = JobMemory::new(1000, usize::MAX);
+        let decoded = memory.charge(400).unwrap();
+        let mut working = memory.charge(400).unwrap();
+        assert!(matches!(
+            memory.charge(300),
+            Err(AppError::MemoryBudgetExceeded { required: 1100, budget: 1000 })
+        ));
+
+        // A failed charge leaves nothing behind
+        working.resize(600).unwrap();
+        drop(decoded);
+        working.resize(200).unwrap();
+        assert_eq!(memory.current.load(Ordering::Relaxed), 200);
+        drop(working);
+        assert_eq!(memory.current.load(Ordering::Relaxed), 0);
+        assert_eq!(memory.peak(), 1000);
+    }
+
+    #[test]
+    fn test_estimate_covers_analysis_and_renditions() {
+        // Decoded RGB8: analysis on the upload outweighs two RGBA copies
+        let probe = ImageProbe { width: 4000, height: 3000, decoded_bytes: 4000 * 3000 * 3 };
+        let estimate = estimate_job_memory(&probe, 0);
+        assert!(estimate >= probe.decoded_bytes + analysis_bytes(4000, 3000));
+        assert!(estimate >= rgba_bytes(4000, 3000) + renditions_bytes(4000, 3000));
+
+        // Too narrow for any gallery size
+        assert_eq!(renditions_bytes(300, 200), 0);
+        assert_eq!(renditions_bytes(1920, 1080), rgba_bytes(1920, 1080) * 2);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use rexiv2::Metadata as XmpMetadata;
+use tracing::{debug, info, instrument};
+
+use crate::backend::common::{
+    config::MetadataPolicyConfig,
+    error::error::Result,
+};
+
+const NEURAL_REEF_XMP_NS: &str = "https://neuralreef.com/ns/xmp/1.0/";
+
+// Enforces the metadata policy on anything that leaves the private prefix
+pub struct MetadataScrubber {
+    policy: MetadataPolicyConfig,
+}
+
+impl MetadataScrubber {
+    pub fn new(policy: MetadataPolicyConfig) -> Result<Self> {
+        policy.validate()?;
+
+        // exiv2 drops tags in unknown namespaces, so ours has to be registered first.
+        // Registering twice fails harmlessly, which happens when several scrubbers exist.
+        if let Err(e) = rexiv2::register_xmp_namespace(NEURAL_REEF_XMP_NS, "neural-reef") {
+            debug!(error = %e, "neural-reef XMP namespace already registered");
+        }
+
+        Ok(Self { policy })
+    }
+
+    // Returns a copy of the file with every tag outside the whitelist removed
+    #[instrument(skip(self, data), fields(size = data.len()))]
+    pub fn scrub(&self, data: &[u8], extension: &str) -> Result<Vec<u8>> {
+        let temp_path = std::env::temp_dir().join(format!("scrub_{}.{}", uuid7::uuid7(), extension));
+        std::fs::write(&temp_path, data)?;
+
+        let result = self.scrub_file(&temp_path);
+        let scrubbed = result.and_then(|removed| {
+            let data = std::fs::read(&temp_path)?;
+            info!(removed, "Scrubbed metadata for publishing");
+            Ok(data)
+        });
+
+        std::fs::remove_file(&temp_path)?;
+        scrubbed
+    }
+
+    fn scrub_file(&self, path: &std::path::Path) -> Result<usize> {
+        let meta = XmpMetadata::new_from_path(path)?;
+
+        let mut tags = meta.get_exif_tags()?;
+        tags.extend(meta.get_iptc_tags()?);
+        tags.extend(meta.get_xmp_tags()?);
+
+        let mut removed = 0;
+        for tag in tags.iter().filter(|tag| !self.policy.keeps(tag)) {
+            if meta.clear_tag(tag) {
+                removed += 1;
+            }
+        }
+
+        // The embedded EXIF thumbnail is uncropped and carries its own copy of the scene
+        if self.policy.strip_thumbnail {
+            meta.erase_thumbnail();
+        }
+        meta.delete_gps_info();
+
+        meta.save_to_file(path)?;
+        Ok(removed)
+    }
+}
+
+// Every EXIF/IPTC/XMP tag of the upload as text, kept on the private master record
+pub fn extract_source_metadata(data: &[u8]) -> BTreeMap<String, String> {
+    let meta = match XmpMetadata::new_from_buffer(data) {
+        Ok(meta) => meta,
+        Err(e) => {
+            debug!(error = %e, "Upload has no readable metadata");
+            return BTreeMap::new();
+        }
+    };
+
+    let mut tags = meta.get_exif_tags().unwrap_or_default();
+    tags.extend(meta.get_iptc_tags().unwrap_or_default());
+    tags.extend(meta.get_xmp_tags().unwrap_or_default());
+
+    tags.into_iter()
+        .filter_map(|tag| {
+            let value = meta.get_tag_interpreted_string(&tag).ok()?;
+            Some((tag, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+    use crate::backend::image_processor::derivatives::encode_mozjpeg;
+
+    fn jpeg_with_owner_metadata() -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 48, image::Rgb([120, 140, 160])));
+        let jpeg = encode_mozjpeg(&img, 80.0).unwrap();
+
+        let path = std::env::temp_dir().join(format!("scrub_test_{}.jpg", uuid7::uuid7()));
+        std::fs::write(&path, &jpeg).unwrap();
+
+        let meta = XmpMetadata::new_from_path(&path).unwrap();
+        meta.set_gps_info(&rexiv2::GpsInfo { latitude: 7.8804, longitude: 98.3923, altitude: 12.0 }).unwrap();
+        meta.set_tag_string("Exif.Image.Model", "Owner Phone").unwrap();
+        meta.set_tag_string("Exif.Photo.BodySerialNumber", "SN-0042").unwrap();
+        meta.set_tag_string("Xmp.exif.GPSLatitude", "7,52.824N").unwrap();
+        meta.set_tag_string("Xmp.dc.title", "Living room").unwrap();
+        meta.save_to_file(&path).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_no_gps_tag_survives_scrub() {
+        let scrubber = MetadataScrubber::new(MetadataPolicyConfig::default()).unwrap();
+        let source = jpeg_with_owner_metadata();
+        assert!(extract_source_metadata(&source).keys().any(|tag| tag.contains("GPS")));
+
+        let published = scrubber.scrub(&source, "jpg").unwrap();
+        let meta = XmpMetadata::new_from_buffer(&published).unwrap();
+
+        assert!(meta.get_gps_info().is_none());
+        let mut tags = meta.get_exif_tags().unwrap();
+        tags.extend(meta.get_iptc_tags().unwrap());
+        tags.extend(meta.get_xmp_tags().unwrap());
+        assert!(!tags.iter().any(|tag| tag.contains("GPS")), "GPS tag survived: {:?}", tags);
+        assert!(!meta.has_tag("Exif.Photo.BodySerialNumber"));
+        assert!(meta.has_tag("Xmp.dc.title"));
+    }
+
+    #[test]
+    fn test_policy_rejects_gps_whitelist() {
+        let policy = MetadataPolicyConfig {
+            keep_prefixes: vec!["Exif.".to_string()],
+            strip_thumbnail: true,
+        };
+        assert!(MetadataScrubber::new(policy).is_err());
+    }
+}
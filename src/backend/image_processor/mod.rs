@@ -6,8 +6,36 @@ pub mod upload_processor;
 pub mod color;
 pub mod histogram;
 pub mod quality_report;
+pub mod watermark;
+pub mod derivatives;
+pub mod exif_metadata;
+pub mod metadata_scrub;
+pub mod perceptual_hash;
+pub mod exposure_fusion;
+pub mod perspective;
+pub mod edit_recipe;
+pub mod presets;
+pub mod content_classifier;
+pub mod sky_replacement;
+pub mod golden;
+pub mod quality_gate;
+pub mod panorama;
+pub mod floor_plan;
+pub mod redaction;
+pub mod memory;
+pub mod dimension_policy;
+pub mod color_management;
+pub mod lens_correction;
+pub mod denoise;
+pub mod listing_look;
+pub mod batch_report;
+pub mod time_of_day;
+pub mod gallery_order;
+#[cfg(test)]
+mod test_fixtures;
 
 // Only expose what's needed
 pub use processor::ImageProcessor;
 pub use job_scheduler::ImageJobScheduler;
-pub use upload_processor::UploadProcessor;
\ No newline at end of file
+pub use upload_processor::UploadProcessor;
+pub use watermark::Watermarker;
\ No newline at end of file
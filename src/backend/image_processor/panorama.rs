@@ -0,0 +1,316 @@
+use std::f32::consts::PI;
+use image::{imageops::FilterType, DynamicImage, Rgb, RgbImage};
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use tracing::{info, instrument};
+
+use crate::backend::common::error::error::{Result, AppError};
+use super::derivatives::encode_webp;
+
+// Equirectangular frames are 2:1; allow for a few pixels of stitching slack
+const ASPECT_TOLERANCE: f32 = 0.01;
+// Tile edge used by the web viewers' multi-resolution format
+pub const TILE_SIZE: u32 = 512;
+// Beyond this the top level only adds tiles, not detail most sensors have
+const MAX_FACE_SIZE: u32 = 4096;
+// Flat preview: a 16:9 look straight ahead, wide enough to read the room
+const PREVIEW_WIDTH: u32 = 1280;
+const PREVIEW_HEIGHT: u32 = 720;
+const PREVIEW_FOV_DEGREES: f32 = 100.0;
+
+pub fn is_equirectangular(width: u32, height: u32) -> bool {
+    height > 0 && (width as f32 / height as f32 - 2.0).abs() <= 2.0 * ASPECT_TOLERANCE
+}
+
+// Face letters follow the viewers' `%s` placeholder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CubeFace {
+    #[serde(rename = "f")]
+    Front,
+    #[serde(rename = "r")]
+    Right,
+    #[serde(rename = "b")]
+    Back,
+    #[serde(rename = "l")]
+    Left,
+    #[serde(rename = "u")]
+    Up,
+    #[serde(rename = "d")]
+    Down,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::Front,
+        CubeFace::Right,
+        CubeFace::Back,
+        CubeFace::Left,
+        CubeFace::Up,
+        CubeFace::Down,
+    ];
+
+    pub fn letter(&self) -> char {
+        match self {
+            CubeFace::Front => 'f',
+            CubeFace::Right => 'r',
+            CubeFace::Back => 'b',
+            CubeFace::Left => 'l',
+            CubeFace::Up => 'u',
+            CubeFace::Down => 'd',
+        }
+    }
+
+    // View direction for a point on the face, with `a` to the right and `b` down,
+    // both -1..1. Y is up and the front face looks along +Z.
+    fn direction(&self, a: f32, b: f32) -> [f32; 3] {
+        match self {
+            CubeFace::Front => [a, -b, 1.0],
+            CubeFace::Right => [1.0, -b, -a],
+            CubeFace::Back => [-a, -b, -1.0],
+            CubeFace::Left => [-1.0, -b, a],
+            CubeFace::Up => [a, 1.0, b],
+            CubeFace::Down => [a, -1.0, -b],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PanoramaTile {
+    pub level: u32,
+    pub face: CubeFace,
+    pub x: u32,
+    pub y: u32,
+    pub data: Vec<u8>,
+}
+
+// Cube-map tile pyramid. Level 1 fits each face in one tile; every level after
+// doubles the resolution up to `face_size` at `levels`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PanoramaTiles {
+    pub face_size: u32,
+    pub tile_size: u32,
+    pub levels: u32,
+    #[serde(skip)]
+    pub tiles: Vec<PanoramaTile>,
+    // Flat WebP preview; set by the processor once it is watermarked
+    #[serde(skip)]
+    pub preview: Vec<u8>,
+}
+
+// Edge length of each face at levels 1..=n
+pub fn level_sizes(face_size: u32) -> Vec<u32> {
+    let mut sizes = vec![face_size];
+    while *sizes.last().unwrap_or(&0) > TILE_SIZE {
+        let size = sizes.last().unwrap_or(&0).div_ceil(2);
+        sizes.push(size);
+    }
+    sizes.reverse();
+    sizes
+}
+
+// Matches the equirectangular resolution at the centre of each face
+fn face_size(width: u32) -> u32 {
+    ((width as f32 / PI).round() as u32).clamp(1, MAX_FACE_SIZE)
+}
+
+// Pixel buffers `build_tiles` holds at once: an RGB copy of the panorama and,
+// for every face being tiled in parallel, the full face and one scaled level
+pub fn tiles_bytes(width: u32, height: u32) -> usize {
+    let face = face_size(width) as usize;
+    width as usize * height as usize * 3 + CubeFace::ALL.len() * face * face * 3 * 2
+}
+
+// Every tile goes through `publish` (given the file extension), since all of them
+// are public. Levels with faces over `max_face` are left out; the smallest always stays.
+#[instrument(skip(img, publish), fields(width = img.width(), height = img.height()))]
+pub fn build_tiles(
+    img: &DynamicImage,
+    max_face: Option<u32>,
+    publish: impl Fn(&[u8], &str) -> Result<Vec<u8>> + Sync,
+) -> Result<PanoramaTiles> {
+    let (width, height) = (img.width(), img.height());
+    if !is_equirectangular(width, height) {
+        return Err(AppError::Validation(format!(
+            "Panoramas must be 2:1 equirectangular, got {}x{}", width, height
+        )));
+    }
+
+    let face_size = face_size(width);
+    let mut sizes = level_sizes(face_size);
+    if let Some(max_face) = max_face {
+        let keep = sizes.iter().filter(|&&size| size <= max_face).count().max(1);
+        sizes.truncate(keep);
+    }
+    let source = img.to_rgb8();
+
+    let tiles = CubeFace::ALL
+        .par_iter()
+        .map(|&face| {
+            let full = DynamicImage::ImageRgb8(cube_face(&source, face, face_size));
+            let mut tiles = Vec::new();
+            for (index, &size) in sizes.iter().enumerate() {
+                let scaled = if size == face_size { full.clone() } else { full.resize_exact(size, size, FilterType::Lanczos3) };
+                for y in 0..size.div_ceil(TILE_SIZE) {
+                    for x in 0..size.div_ceil(TILE_SIZE) {
+                        let tile = scaled.crop_imm(
+                            x * TILE_SIZE,
+                            y * TILE_SIZE,
+                            TILE_SIZE.min(size - x * TILE_SIZE),
+                            TILE_SIZE.min(size - y * TILE_SIZE),
+                        );
+                        tiles.push(PanoramaTile {
+                            level: index as u32 + 1,
+                            face,
+                            x,
+                            y,
+                            data: publish(&encode_webp(&tile)?, "webp")?,
+                        });
+                    }
+                }
+            }
+            Ok(tiles)
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    // Viewers size the cube from the top published level
+    let face_size = sizes[sizes.len() - 1];
+    info!(face_size, levels = sizes.len(), tiles = tiles.len(), "Built panorama tile pyramid");
+    Ok(PanoramaTiles {
+        face_size,
+        tile_size: TILE_SIZE,
+        levels: sizes.len() as u32,
+        tiles,
+        preview: Vec::new(),
+    })
+}
+
+pub fn cube_face(source: &RgbImage, face: CubeFace, size: u32) -> RgbImage {
+    let step = 2.0 / size as f32;
+    RgbImage::from_fn(size, size, |i, j| {
+        let a = (i as f32 + 0.5) * step - 1.0;
+        let b = (j as f32 + 0.5) * step - 1.0;
+        sample(source, face.direction(a, b))
+    })
+}
+
+// Undistorted view straight ahead, for galleries and link previews
+pub fn flat_preview(img: &DynamicImage) -> DynamicImage {
+    let source = img.to_rgb8();
+    let half_width = (PREVIEW_FOV_DEGREES.to_radians() / 2.0).tan();
+    let half_height = half_width * PREVIEW_HEIGHT as f32 / PREVIEW_WIDTH as f32;
+
+    let preview = RgbImage::from_fn(PREVIEW_WIDTH, PREVIEW_HEIGHT, |i, j| {
+        let a = ((i as f32 + 0.5) / PREVIEW_WIDTH as f32 * 2.0 - 1.0) * half_width;
+        let b = ((j as f32 + 0.5) / PREVIEW_HEIGHT as f32 * 2.0 - 1.0) * half_height;
+        sample(&source, CubeFace::Front.direction(a, b))
+    });
+    DynamicImage::ImageRgb8(preview)
+}
+
+// Bilinear lookup of a view direction, wrapping around the 360° seam
+fn sample(source: &RgbImage, [x, y, z]: [f32; 3]) -> Rgb<u8> {
+    let (width, height) = source.dimensions();
+    let longitude = x.atan2(z);
+    let latitude = (y / (x * x + y * y + z * z).sqrt()).asin();
+
+    let u = (longitude / (2.0 * PI) + 0.5) * width as f32 - 0.5;
+    let v = ((0.5 - latitude / PI) * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+
+    let (u0, v0) = (u.floor(), v.floor());
+    let (fu, fv) = (u - u0, v - v0);
+    let wrap = |u: f32| (u as i64).rem_euclid(width as i64) as u32;
+    let (x0, x1) = (wrap(u0), wrap(u0 + 1.0));
+    let (y0, y1) = (v0 as u32, (v0 as u32 + 1).min(height - 1));
+
+    let (p00, p10, p01, p11) = (
+        source.get_pixel(x0, y0),
+        source.get_pixel(x1, y0),
+        source.get_pixel(x0, y1),
+        source.get_pixel(x1, y1),
+    );
+    Rgb([0, 1, 2].map(|c| {
+        let top = p00[c] as f32 * (1.0 - fu) + p10[c] as f32 * fu;
+        let bottom = p01[c] as f32 * (1.0 - fu) + p11[c] as f32 * fu;
+        (top * (1.0 - fv) + bottom * fv).round() as u8
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Four coloured quarters of longitude, white above the horizon band and black below
+    fn compass() -> RgbImage {
+        RgbImage::from_fn(2048, 1024, |x, y| {
+            if y < 128 {
+                Rgb([255, 255, 255])
+            } else if y >= 896 {
+                Rgb([0, 0, 0])
+            } else {
+                match (x + 256) / 512 % 4 {
+                    0 => Rgb([0, 0, 255]),   // back, split by the seam
+                    1 => Rgb([255, 0, 0]),   // left
+                    2 => Rgb([0, 255, 0]),   // front, centre of the frame
+                    _ => Rgb([255, 255, 0]), // right
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_cube_faces_look_the_right_way() {
+        let source = compass();
+        let centre = |face| *cube_face(&source, face, 64).get_pixel(32, 32);
+
+        assert_eq!(centre(CubeFace::Front), Rgb([0, 255, 0]));
+        assert_eq!(centre(CubeFace::Right), Rgb([255, 255, 0]));
+        assert_eq!(centre(CubeFace::Back), Rgb([0, 0, 255]));
+        assert_eq!(centre(CubeFace::Left), Rgb([255, 0, 0]));
+        assert_eq!(centre(CubeFace::Up), Rgb([255, 255, 255]));
+        assert_eq!(centre(CubeFace::Down), Rgb([0, 0, 0]));
+
+        // The flat preview looks ahead too
+        let preview = flat_preview(&DynamicImage::ImageRgb8(source)).to_rgb8();
+        assert_eq!(*preview.get_pixel(PREVIEW_WIDTH / 2, PREVIEW_HEIGHT / 2), Rgb([0, 255, 0]));
+    }
+
+    #[test]
+    fn test_pyramid_levels_and_aspect() {
+        assert_eq!(level_sizes(2608), vec![326, 652, 1304, 2608]);
+        assert_eq!(level_sizes(400), vec![400]);
+
+        assert!(is_equirectangular(8192, 4096));
+        assert!(is_equirectangular(6000, 3001));
+        assert!(!is_equirectangular(3840, 2160));
+    }
+
+    #[test]
+    fn test_every_tile_is_published() {
+        let published = std::sync::atomic::AtomicUsize::new(0);
+        let publish = |data: &[u8], extension: &str| -> Result<Vec<u8>> {
+            assert_eq!(extension, "webp");
+            published.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok([data, b"published"].concat())
+        };
+
+        let panorama = build_tiles(&DynamicImage::ImageRgb8(compass()), None, publish).unwrap();
+        assert_eq!(published.into_inner(), panorama.tiles.len());
+        assert!(panorama.tiles.iter().all(|tile| tile.data.ends_with(b"published")));
+    }
+
+    #[test]
+    fn test_tile_levels_stop_at_the_face_limit() {
+        let publish = |data: &[u8], _: &str| -> Result<Vec<u8>> { Ok(data.to_vec()) };
+        let source = DynamicImage::ImageRgb8(compass());
+
+        // 2048 wide gives 652 px faces: levels of 326 and 652
+        let full = build_tiles(&source, None, publish).unwrap();
+        assert_eq!((full.face_size, full.levels), (652, 2));
+        let capped = build_tiles(&source, Some(512), publish).unwrap();
+        assert_eq!((capped.face_size, capped.levels), (326, 1));
+        assert!(capped.tiles.iter().all(|tile| tile.level == 1));
+    }
+}
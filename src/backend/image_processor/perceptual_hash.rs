@@ -0,0 +1,62 @@
+use image::{DynamicImage, imageops::FilterType};
+
+// Hashes within this many differing bits are treated as the same photo
+pub const NEAR_DUPLICATE_DISTANCE: u32 = 6;
+
+// dHash: compare neighbouring pixels of a 9x8 grayscale thumbnail, one bit per pair.
+// Survives re-encoding, resizing and mild colour edits, which is what reposted photos go through.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | (left > right) as u64;
+        }
+    }
+    hash
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+pub fn to_hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+pub fn from_hex(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex, 16).ok()
+}
+
+// One key per byte. Two hashes within NEAR_DUPLICATE_DISTANCE bits must share at least
+// one byte, so an indexed CONTAINSANY lookup finds every candidate without a full scan.
+pub fn hash_bands(hash: u64) -> Vec<String> {
+    hash.to_be_bytes()
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| format!("{}:{:02x}", i, byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_reencoded_photo_is_near_duplicate() {
+        let original = DynamicImage::ImageRgb8(RgbImage::from_fn(320, 240, |x, y| {
+            Rgb([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8])
+        }));
+        let resized = original.resize_exact(160, 120, FilterType::Lanczos3).brighten(8);
+        let other = original.fliph();
+
+        let hash = dhash(&original);
+        assert!(hamming_distance(hash, dhash(&resized)) <= NEAR_DUPLICATE_DISTANCE);
+        assert!(hamming_distance(hash, dhash(&other)) > NEAR_DUPLICATE_DISTANCE);
+        assert_eq!(from_hex(&to_hex(hash)), Some(hash));
+    }
+}
@@ -0,0 +1,394 @@
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Rgba, RgbaImage, imageops::FilterType};
+use imageproc::{
+    geometric_transformations::{warp, Interpolation, Projection},
+    gradients::{horizontal_sobel, vertical_sobel},
+    hough::{detect_lines, LineDetectionOptions, PolarLine},
+};
+use nalgebra::{Matrix3, SymmetricEigen, Vector3};
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info, instrument};
+
+use crate::backend::common::error::error::{Result, AppError};
+
+// Line detection runs on a downscaled copy; the geometry is scale-free
+const WORK_SIZE: u32 = 1024;
+const MIN_EDGE_GRADIENT: i32 = 60;
+const MAX_LINE_TILT_DEG: u32 = 20;
+const MIN_VERTICAL_LINES: usize = 3;
+
+// Hough angles come in whole degrees, so anything under one bin is noise. Above the
+// maximums the lines are probably not architectural or the tilt is intentional.
+const MIN_CORRECTION_DEG: f64 = 0.75;
+const MAX_ROLL_DEG: f64 = 10.0;
+const MAX_KEYSTONE_DEG: f64 = 15.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// The two angles that define a correction; enough to re-render it exactly
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PerspectiveAngles {
+    // Camera roll, positive when the image was rotated clockwise to level it
+    pub roll_degrees: f32,
+    // Lean of the verticals at the left/right image edge before correction,
+    // positive when they converge towards the top (camera tilted up)
+    pub keystone_degrees: f32,
+}
+
+impl PerspectiveAngles {
+    pub fn validate(&self) -> Result<()> {
+        if !(-MAX_ROLL_DEG..=MAX_ROLL_DEG).contains(&(self.roll_degrees as f64)) {
+            return Err(AppError::Validation(format!(
+                "Perspective roll must be within ±{}°, got {}°", MAX_ROLL_DEG, self.roll_degrees
+            )));
+        }
+        if !(-MAX_KEYSTONE_DEG..=MAX_KEYSTONE_DEG).contains(&(self.keystone_degrees as f64)) {
+            return Err(AppError::Validation(format!(
+                "Perspective keystone must be within ±{}°, got {}°", MAX_KEYSTONE_DEG, self.keystone_degrees
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Reported in the quality report so agents can see what was straightened
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PerspectiveCorrection {
+    #[serde(flatten)]
+    pub angles: PerspectiveAngles,
+    pub crop: CropRect,
+}
+
+// Vertical vanishing point in coordinates centred on the image and scaled by half
+// its height, so the numbers do not depend on resolution
+#[derive(Debug, Clone, Copy)]
+pub struct VerticalEstimate {
+    roll: f64,
+    vanishing_y: Option<f64>,
+    aspect: f64,
+    pub lines_used: usize,
+}
+
+impl VerticalEstimate {
+    pub fn roll_degrees(&self) -> f64 {
+        self.roll.to_degrees()
+    }
+
+    // A vanishing point above the image (negative y) means the verticals converge upwards
+    pub fn keystone_degrees(&self) -> f64 {
+        self.vanishing_y
+            .map_or(0.0, |vy| -(self.aspect / vy).atan().to_degrees())
+    }
+
+    pub fn needs_correction(&self) -> bool {
+        self.roll_degrees().abs() >= MIN_CORRECTION_DEG || self.keystone_degrees().abs() >= MIN_CORRECTION_DEG
+    }
+
+    fn within_limits(&self) -> bool {
+        self.roll_degrees().abs() <= MAX_ROLL_DEG && self.keystone_degrees().abs() <= MAX_KEYSTONE_DEG
+    }
+
+    pub fn angles(&self) -> PerspectiveAngles {
+        PerspectiveAngles {
+            roll_degrees: self.roll_degrees() as f32,
+            keystone_degrees: self.keystone_degrees() as f32,
+        }
+    }
+
+    fn from_angles(angles: PerspectiveAngles, width: u32, height: u32) -> Self {
+        let aspect = width as f64 / height as f64;
+        let keystone = (angles.keystone_degrees as f64).to_radians();
+        Self {
+            roll: (angles.roll_degrees as f64).to_radians(),
+            vanishing_y: (keystone != 0.0).then(|| -aspect / keystone.tan()),
+            aspect,
+            lines_used: 0,
+        }
+    }
+
+    // Homography in normalised coordinates: level the roll, then push the vanishing
+    // point to infinity so the converging verticals become parallel
+    fn normalized_homography(&self) -> Matrix3<f64> {
+        let (sin, cos) = self.roll.sin_cos();
+        let rotation = Matrix3::new(
+            cos, -sin, 0.0,
+            sin, cos, 0.0,
+            0.0, 0.0, 1.0,
+        );
+
+        let keystone = match self.vanishing_y {
+            Some(vy) => Matrix3::new(
+                1.0, 0.0, 0.0,
+                0.0, 1.0, 0.0,
+                0.0, -1.0 / vy, 1.0,
+            ),
+            None => Matrix3::identity(),
+        };
+
+        keystone * rotation
+    }
+}
+
+// Near-vertical Hough lines in full-resolution pixel coordinates
+pub fn detect_vertical_lines(img: &DynamicImage) -> Vec<PolarLine> {
+    let (width, height) = img.dimensions();
+    let scale = (WORK_SIZE as f32 / width.max(height) as f32).min(1.0);
+    let small = if scale < 1.0 {
+        img.resize(
+            (width as f32 * scale).round() as u32,
+            (height as f32 * scale).round() as u32,
+            FilterType::Triangle,
+        )
+        .to_luma8()
+    } else {
+        img.to_luma8()
+    };
+
+    let edges = vertical_edge_map(&small);
+    let options = LineDetectionOptions {
+        vote_threshold: (small.height() / 5).max(20),
+        suppression_radius: 6,
+    };
+
+    detect_lines(&edges, options)
+        .into_iter()
+        .filter(|line| {
+            line.angle_in_degrees <= MAX_LINE_TILT_DEG || line.angle_in_degrees >= 180 - MAX_LINE_TILT_DEG
+        })
+        .map(|line| PolarLine { r: line.r / scale, ..line })
+        .collect()
+}
+
+// Keep strong edges whose gradient is mostly horizontal, i.e. vertical structures
+fn vertical_edge_map(gray: &GrayImage) -> GrayImage {
+    let gx = horizontal_sobel(gray);
+    let gy = vertical_sobel(gray);
+
+    GrayImage::from_fn(gray.width(), gray.height(), |x, y| {
+        let dx = (gx.get_pixel(x, y)[0] as i32).abs();
+        let dy = (gy.get_pixel(x, y)[0] as i32).abs();
+        Luma([if dx > MIN_EDGE_GRADIENT && dx > 2 * dy { 255 } else { 0 }])
+    })
+}
+
+pub fn estimate_verticals(img: &DynamicImage) -> Option<VerticalEstimate> {
+    let (width, height) = img.dimensions();
+    let lines = detect_vertical_lines(img);
+    if lines.len() < MIN_VERTICAL_LINES {
+        debug!(lines = lines.len(), "Not enough vertical lines for perspective estimate");
+        return None;
+    }
+
+    let (cx, cy, s) = (width as f64 / 2.0, height as f64 / 2.0, height as f64 / 2.0);
+
+    // Least-squares intersection: the smallest eigenvector of sum(l * l^T)
+    let mut scatter = Matrix3::<f64>::zeros();
+    for line in &lines {
+        let (sin, cos) = (line.angle_in_degrees as f64).to_radians().sin_cos();
+        let l = Vector3::new(cos, sin, (cx * cos + cy * sin - line.r as f64) / s);
+        scatter += l * l.transpose();
+    }
+
+    let eigen = SymmetricEigen::new(scatter);
+    let smallest = eigen.eigenvalues.imin();
+    let vp = eigen.eigenvectors.column(smallest).into_owned();
+
+    // A point at (or practically at) infinity means the lines are already parallel
+    let finite = vp[2].abs() > 1e-6 * vp.xy().norm();
+    let (px, py) = if finite { (vp[0] / vp[2], vp[1] / vp[2]) } else { (vp[0], vp[1]) };
+
+    // Rotate about the centre so the vanishing point lands on the vertical axis,
+    // picking the direction that keeps the image upright
+    let roll = if py >= 0.0 { px.atan2(py) } else { (-px).atan2(-py) };
+    let vanishing_y = finite.then(|| px.hypot(py) * py.signum()).filter(|vy| vy.abs() < 1e4);
+
+    Some(VerticalEstimate {
+        roll,
+        vanishing_y,
+        aspect: width as f64 / height as f64,
+        lines_used: lines.len(),
+    })
+}
+
+// Angles the pipeline would correct on its own, or None when the verticals are
+// straight enough or the estimate is outside what we trust
+pub fn auto_angles(img: &DynamicImage) -> Option<PerspectiveAngles> {
+    let estimate = estimate_verticals(img)?;
+    if estimate.needs_correction() && estimate.within_limits() {
+        return Some(estimate.angles());
+    }
+
+    debug!(
+        roll = estimate.roll_degrees(),
+        keystone = estimate.keystone_degrees(),
+        lines = estimate.lines_used,
+        "Skipping perspective correction"
+    );
+    None
+}
+
+// Two-point vertical correction. Returns None when no correction is warranted.
+#[instrument(skip(img))]
+pub fn correct_verticals(img: &RgbaImage) -> Result<Option<(RgbaImage, PerspectiveCorrection)>> {
+    match auto_angles(&DynamicImage::ImageRgba8(img.clone())) {
+        Some(angles) => apply_angles(img, angles).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Warps with the given angles and crops to the largest fully covered rectangle
+pub fn apply_angles(img: &RgbaImage, angles: PerspectiveAngles) -> Result<(RgbaImage, PerspectiveCorrection)> {
+    angles.validate()?;
+
+    let (width, height) = img.dimensions();
+    let estimate = VerticalEstimate::from_angles(angles, width, height);
+    let homography = pixel_homography(&estimate, width, height);
+    let projection = to_projection(&homography)?;
+
+    let warped = warp(img, &projection, Interpolation::Bilinear, Rgba([0, 0, 0, 0]));
+
+    let inverse = homography
+        .try_inverse()
+        .ok_or_else(|| AppError::ImageProcessing("Perspective homography is not invertible".into()))?;
+    let crop = largest_valid_rect(&inverse, width, height)
+        .ok_or_else(|| AppError::ImageProcessing("Perspective correction left no valid area".into()))?;
+
+    let cropped = image::imageops::crop_imm(&warped, crop.x, crop.y, crop.width, crop.height).to_image();
+
+    let correction = PerspectiveCorrection { angles, crop };
+    info!(?correction, "Applied vertical perspective correction");
+
+    Ok((cropped, correction))
+}
+
+fn pixel_homography(estimate: &VerticalEstimate, width: u32, height: u32) -> Matrix3<f64> {
+    let (cx, cy, s) = (width as f64 / 2.0, height as f64 / 2.0, height as f64 / 2.0);
+    let to_normalized = Matrix3::new(
+        1.0 / s, 0.0, -cx / s,
+        0.0, 1.0 / s, -cy / s,
+        0.0, 0.0, 1.0,
+    );
+    let to_pixels = Matrix3::new(
+        s, 0.0, cx,
+        0.0, s, cy,
+        0.0, 0.0, 1.0,
+    );
+    to_pixels * estimate.normalized_homography() * to_normalized
+}
+
+fn to_projection(h: &Matrix3<f64>) -> Result<Projection> {
+    let m: Vec<f32> = h.transpose().iter().map(|&v| v as f32).collect();
+    Projection::from_matrix([m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8]])
+        .ok_or_else(|| AppError::ImageProcessing("Failed to create projection matrix".into()))
+}
+
+// Largest axis-aligned rectangle whose pixels all come from inside the source image.
+// Evaluated on a coarse grid and shrunk by one cell so the edges stay conservative.
+fn largest_valid_rect(inverse: &Matrix3<f64>, width: u32, height: u32) -> Option<CropRect> {
+    let cell = (width.max(height) as f64 / WORK_SIZE as f64).max(1.0);
+    let cols = (width as f64 / cell).floor() as usize;
+    let rows = (height as f64 / cell).floor() as usize;
+    if cols < 3 || rows < 3 {
+        return None;
+    }
+
+    let (max_x, max_y) = ((width - 1) as f64, (height - 1) as f64);
+    let valid = |col: usize, row: usize| {
+        // All four corners of the cell must map back inside the source
+        [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)].iter().all(|(ox, oy)| {
+            let p = inverse * Vector3::new((col as f64 + ox) * cell, (row as f64 + oy) * cell, 1.0);
+            let (x, y) = (p[0] / p[2], p[1] / p[2]);
+            p[2] > 0.0 && (0.0..=max_x).contains(&x) && (0.0..=max_y).contains(&y)
+        })
+    };
+
+    // Maximal rectangle via per-row histograms and a monotonic stack
+    let mut heights = vec![0usize; cols];
+    let mut best = (0usize, 0usize, 0usize, 0usize, 0usize); // area, col, row, cols, rows
+    for row in 0..rows {
+        for (col, h) in heights.iter_mut().enumerate() {
+            *h = if valid(col, row) { *h + 1 } else { 0 };
+        }
+
+        let mut stack: Vec<usize> = Vec::new();
+        for col in 0..=cols {
+            let current = if col < cols { heights[col] } else { 0 };
+            while let Some(&top) = stack.last() {
+                if heights[top] < current {
+                    break;
+                }
+                stack.pop();
+                let left = stack.last().map_or(0, |&l| l + 1);
+                let area = heights[top] * (col - left);
+                if area > best.0 {
+                    best = (area, left, row + 1 - heights[top], col - left, heights[top]);
+                }
+            }
+            stack.push(col);
+        }
+    }
+
+    let (area, col, row, span_cols, span_rows) = best;
+    if area == 0 || span_cols < 3 || span_rows < 3 {
+        return None;
+    }
+
+    let x = ((col + 1) as f64 * cell).ceil() as u32;
+    let y = ((row + 1) as f64 * cell).ceil() as u32;
+    let right = (((col + span_cols - 1) as f64 * cell).floor() as u32).min(width);
+    let bottom = (((row + span_rows - 1) as f64 * cell).floor() as u32).min(height);
+
+    (right > x && bottom > y).then(|| CropRect {
+        x,
+        y,
+        width: right - x,
+        height: bottom - y,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Vertical stripes pinched towards the top, like a building shot tilting upwards
+    fn converging_stripes(width: u32, height: u32, pinch: f32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let cx = width as f32 / 2.0;
+            let t = 1.0 - pinch * (1.0 - y as f32 / height as f32);
+            let source_x = cx + (x as f32 - cx) / t;
+            let stripe = (source_x / 40.0).floor() as i32 % 2 == 0;
+            let v = if stripe { 230 } else { 30 };
+            Rgba([v, v, v, 255])
+        })
+    }
+
+    #[test]
+    fn test_converging_verticals_are_corrected_and_cropped() {
+        let img = converging_stripes(800, 600, 0.15);
+        let estimate = estimate_verticals(&DynamicImage::ImageRgba8(img.clone())).unwrap();
+        assert!(estimate.roll_degrees().abs() < 1.0, "roll {}", estimate.roll_degrees());
+        assert!(estimate.keystone_degrees() > 2.0, "keystone {}", estimate.keystone_degrees());
+
+        let (corrected, correction) = correct_verticals(&img).unwrap().unwrap();
+        assert_eq!(corrected.dimensions(), (correction.crop.width, correction.crop.height));
+        assert!(corrected.pixels().all(|p| p[3] == 255));
+
+        // Replaying the recorded angles gives the same result
+        let (replayed, _) = apply_angles(&img, correction.angles).unwrap();
+        assert_eq!(replayed, corrected);
+
+        let after = estimate_verticals(&DynamicImage::ImageRgba8(corrected));
+        assert!(after.is_none_or(|e| !e.needs_correction()));
+    }
+
+    #[test]
+    fn test_straight_verticals_are_left_alone() {
+        let img = converging_stripes(800, 600, 0.0);
+        assert!(correct_verticals(&img).unwrap().is_none());
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use config::{Config as ConfigBuilder, File, FileFormat};
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn, instrument};
+
+use crate::backend::common::{
+    config::PresetConfig,
+    error::error::{Result, AppError},
+};
+use super::processor::{ContentType, ImageEnhancementConfig, TimeOfDay};
+
+// Analysis results a preset adjustment can be conditioned on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalysisFlag {
+    HasWindow,
+    HasSky,
+    IsUnderexposed,
+    IsOverexposed,
+    IsYellowCast,
+    NeedsSharpening,
+}
+
+impl AnalysisFlag {
+    pub const ALL: [AnalysisFlag; 6] = [
+        AnalysisFlag::HasWindow,
+        AnalysisFlag::HasSky,
+        AnalysisFlag::IsUnderexposed,
+        AnalysisFlag::IsOverexposed,
+        AnalysisFlag::IsYellowCast,
+        AnalysisFlag::NeedsSharpening,
+    ];
+}
+
+// Partial enhancement config; unset fields are left alone
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EnhancementOverrides {
+    pub contrast_boost: Option<f32>,
+    pub color_enhancement_strength: Option<f32>,
+    pub shadow_recovery: Option<f32>,
+    pub highlight_protection: Option<f32>,
+    pub sharpening_threshold: Option<f32>,
+    pub brightness_adjustment: Option<f32>,
+    pub window_recovery_strength: Option<f32>,
+    pub white_balance_temp: Option<f32>,
+    pub exterior_sky_enhancement: Option<f32>,
+}
+
+impl EnhancementOverrides {
+    fn values(&self) -> [Option<f32>; 9] {
+        [
+            self.contrast_boost,
+            self.color_enhancement_strength,
+            self.shadow_recovery,
+            self.highlight_protection,
+            self.sharpening_threshold,
+            self.brightness_adjustment,
+            self.window_recovery_strength,
+            self.white_balance_temp,
+            self.exterior_sky_enhancement,
+        ]
+    }
+
+    fn set(&self, config: &mut ImageEnhancementConfig) {
+        for (target, value) in fields_mut(config).into_iter().zip(self.values()) {
+            if let Some(value) = value {
+                *target = value;
+            }
+        }
+    }
+
+    fn add(&self, config: &mut ImageEnhancementConfig) {
+        for (target, value) in fields_mut(config).into_iter().zip(self.values()) {
+            if let Some(value) = value {
+                *target += value;
+            }
+        }
+    }
+}
+
+fn fields_mut(config: &mut ImageEnhancementConfig) -> [&mut f32; 9] {
+    [
+        &mut config.contrast_boost,
+        &mut config.color_enhancement_strength,
+        &mut config.shadow_recovery,
+        &mut config.highlight_protection,
+        &mut config.sharpening_threshold,
+        &mut config.brightness_adjustment,
+        &mut config.window_recovery_strength,
+        &mut config.white_balance_temp,
+        &mut config.exterior_sky_enhancement,
+    ]
+}
+
+// Applies when every flag in `when` is set: `set` replaces values, `add` offsets them
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresetAdjustment {
+    pub when: Vec<AnalysisFlag>,
+    #[serde(default)]
+    pub set: EnhancementOverrides,
+    #[serde(default)]
+    pub add: EnhancementOverrides,
+}
+
+impl PresetAdjustment {
+    fn apply(&self, config: &mut ImageEnhancementConfig, flags: &impl Fn(AnalysisFlag) -> bool) {
+        if self.when.iter().all(|&flag| flags(flag)) {
+            self.set.set(config);
+            self.add.add(config);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresetDefinition {
+    pub name: String,
+    // Bump when the values change; recorded in XMP and in each edit recipe
+    pub version: u32,
+    // Empty matches every content type / time of day
+    #[serde(default)]
+    pub content_types: Vec<ContentType>,
+    #[serde(default)]
+    pub time_of_day: Vec<TimeOfDay>,
+    pub country: Option<String>,
+    pub agency: Option<String>,
+    pub enhancement: ImageEnhancementConfig,
+    #[serde(default)]
+    pub adjust: Vec<PresetAdjustment>,
+}
+
+impl PresetDefinition {
+    fn matches(&self, content_type: ContentType, time_of_day: TimeOfDay, country: Option<&str>, agency: Option<&str>) -> bool {
+        let country_matches = match (&self.country, country) {
+            (None, _) => true,
+            (Some(wanted), Some(country)) => wanted.eq_ignore_ascii_case(country),
+            (Some(_), None) => false,
+        };
+        let agency_matches = match (&self.agency, agency) {
+            (None, _) => true,
+            (Some(wanted), Some(agency)) => wanted == agency,
+            (Some(_), None) => false,
+        };
+
+        (self.content_types.is_empty() || self.content_types.contains(&content_type))
+            && (self.time_of_day.is_empty() || self.time_of_day.contains(&time_of_day))
+            && country_matches
+            && agency_matches
+    }
+
+    // Agency beats country beats content type beats time of day
+    fn specificity(&self) -> u8 {
+        (self.agency.is_some() as u8) << 3
+            | (self.country.is_some() as u8) << 2
+            | (!self.content_types.is_empty() as u8) << 1
+            | !self.time_of_day.is_empty() as u8
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PresetFile {
+    #[serde(rename = "preset")]
+    presets: Vec<PresetDefinition>,
+    // Applied after the chosen preset's own adjustments, whichever preset it is
+    #[serde(default)]
+    global_adjust: Vec<PresetAdjustment>,
+}
+
+// Resolved values plus what produced them, for the edit recipe and XMP
+#[derive(Debug, Clone)]
+pub struct SelectedPreset {
+    pub name: String,
+    pub version: u32,
+    pub enhancement: ImageEnhancementConfig,
+}
+
+#[derive(Debug)]
+pub struct PresetLibrary {
+    presets: Vec<PresetDefinition>,
+    global_adjust: Vec<PresetAdjustment>,
+}
+
+impl PresetLibrary {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let source = File::from(path).format(FileFormat::Toml);
+        Self::build(ConfigBuilder::builder().add_source(source))
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        Self::build(ConfigBuilder::builder().add_source(File::from_str(toml, FileFormat::Toml)))
+    }
+
+    fn build(builder: config::ConfigBuilder<config::builder::DefaultState>) -> Result<Self> {
+        let file: PresetFile = builder.build()?.try_deserialize()?;
+        let library = Self {
+            presets: file.presets,
+            global_adjust: file.global_adjust,
+        };
+        library.validate()?;
+        Ok(library)
+    }
+
+    pub fn len(&self) -> usize {
+        self.presets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.presets.is_empty()
+    }
+
+    // Most specific match wins; ties go to whichever comes first in the file
+    pub fn select(
+        &self,
+        content_type: ContentType,
+        time_of_day: TimeOfDay,
+        country: Option<&str>,
+        agency: Option<&str>,
+        flags: impl Fn(AnalysisFlag) -> bool,
+    ) -> Result<SelectedPreset> {
+        let preset = self.find(content_type, time_of_day, country, agency).ok_or_else(|| {
+            AppError::Configuration(format!("No preset for {:?} at {:?}", content_type, time_of_day))
+        })?;
+
+        Ok(SelectedPreset {
+            name: preset.name.clone(),
+            version: preset.version,
+            enhancement: self.resolve(preset, &flags),
+        })
+    }
+
+    fn find(
+        &self,
+        content_type: ContentType,
+        time_of_day: TimeOfDay,
+        country: Option<&str>,
+        agency: Option<&str>,
+    ) -> Option<&PresetDefinition> {
+        self.presets
+            .iter()
+            .filter(|p| p.matches(content_type, time_of_day, country, agency))
+            .fold(None, |best: Option<&PresetDefinition>, p| match best {
+                Some(best) if best.specificity() >= p.specificity() => Some(best),
+                _ => Some(p),
+            })
+    }
+
+    fn resolve(&self, preset: &PresetDefinition, flags: &impl Fn(AnalysisFlag) -> bool) -> ImageEnhancementConfig {
+        let mut config = preset.enhancement.clone();
+        for adjustment in preset.adjust.iter().chain(&self.global_adjust) {
+            adjustment.apply(&mut config, flags);
+        }
+        config
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.presets.is_empty() {
+            return Err(AppError::Configuration("Preset file defines no presets".into()));
+        }
+
+        let mut names = HashSet::new();
+        for preset in &self.presets {
+            if preset.name.is_empty() || !names.insert(preset.name.as_str()) {
+                return Err(AppError::Configuration(format!("Preset name '{}' is empty or duplicated", preset.name)));
+            }
+            if preset.version == 0 {
+                return Err(AppError::Configuration(format!("Preset {} needs a version of at least 1", preset.name)));
+            }
+            if preset.adjust.iter().chain(&self.global_adjust).any(|a| a.when.is_empty()) {
+                return Err(AppError::Configuration(format!(
+                    "Preset {} has an adjustment without conditions; put those values in enhancement",
+                    preset.name
+                )));
+            }
+
+            // Every combination of analysis flags has to resolve to values in range
+            for mask in 0..1u32 << AnalysisFlag::ALL.len() {
+                let flags = |flag: AnalysisFlag| {
+                    let bit = AnalysisFlag::ALL.iter().position(|&f| f == flag).unwrap_or(0);
+                    mask & (1 << bit) != 0
+                };
+                self.resolve(preset, &flags).validate().map_err(|e| {
+                    let active: Vec<_> = AnalysisFlag::ALL.iter().filter(|&&f| flags(f)).collect();
+                    AppError::Configuration(format!("Preset {} with {:?}: {}", preset.name, active, e))
+                })?;
+            }
+        }
+
+        // Without a market or agency every photo still has to land on some preset
+        for content_type in ContentType::ALL {
+            for time_of_day in TimeOfDay::ALL {
+                if self.find(content_type, time_of_day, None, None).is_none() {
+                    return Err(AppError::Configuration(format!(
+                        "No generic preset covers {:?} at {:?}", content_type, time_of_day
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct LoadedPresets {
+    library: Arc<PresetLibrary>,
+    modified: Option<SystemTime>,
+}
+
+// Presets shared by the processor. Edits to the file are picked up by the reload task;
+// an invalid edit is rejected and the previous presets stay active.
+pub struct PresetStore {
+    path: PathBuf,
+    country: Option<String>,
+    reload_interval: Duration,
+    loaded: RwLock<LoadedPresets>,
+}
+
+impl PresetStore {
+    // Fails on a missing or invalid file so a broken preset never makes it past startup
+    #[instrument(skip(config), fields(path = %config.path))]
+    pub fn load(config: &PresetConfig) -> Result<Self> {
+        config.validate()?;
+        let path = PathBuf::from(&config.path);
+        let modified = modified_time(&path);
+        let library = PresetLibrary::from_file(&path)?;
+        info!(presets = library.len(), "Loaded enhancement presets");
+
+        Ok(Self {
+            path,
+            country: config.country.clone(),
+            reload_interval: Duration::from_secs(config.reload_interval_secs),
+            loaded: RwLock::new(LoadedPresets {
+                library: Arc::new(library),
+                modified,
+            }),
+        })
+    }
+
+    pub fn library(&self) -> Arc<PresetLibrary> {
+        let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+        loaded.library.clone()
+    }
+
+    pub fn select(
+        &self,
+        content_type: ContentType,
+        time_of_day: TimeOfDay,
+        agency: Option<&str>,
+        flags: impl Fn(AnalysisFlag) -> bool,
+    ) -> Result<SelectedPreset> {
+        self.library().select(content_type, time_of_day, self.country.as_deref(), agency, flags)
+    }
+
+    // Returns whether new presets were swapped in
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let modified = modified_time(&self.path);
+        {
+            let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+            if modified == loaded.modified {
+                return Ok(false);
+            }
+        }
+
+        let library = PresetLibrary::from_file(&self.path);
+        let mut loaded = self.loaded.write().unwrap_or_else(|e| e.into_inner());
+        // Remember the attempt either way so a bad file is reported once, not every tick
+        loaded.modified = modified;
+        let library = library?;
+        info!(presets = library.len(), path = %self.path.display(), "Reloaded enhancement presets");
+        loaded.library = Arc::new(library);
+        Ok(true)
+    }
+
+    pub fn spawn_reload(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.reload_interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.reload_if_changed() {
+                    warn!(error = %e, path = %self.path.display(), "Rejected preset file, keeping previous presets");
+                }
+            }
+        })
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESETS: &str = include_str!("../../../config/presets.toml");
+
+    #[test]
+    fn test_bundled_presets_select_most_specific() {
+        let library = PresetLibrary::from_toml(PRESETS).unwrap();
+        let none = |_: AnalysisFlag| false;
+
+        let kitchen = library.select(ContentType::Kitchen, TimeOfDay::Day, None, None, none).unwrap();
+        assert_eq!(kitchen.name, "kitchen_bath");
+        assert_eq!(kitchen.enhancement.sharpening_threshold, 0.4);
+
+        let dusk = library.select(ContentType::Exterior, TimeOfDay::Twilight, None, None, none).unwrap();
+        assert_eq!(dusk.name, "exterior_twilight");
+
+        let floor_plan = library.select(ContentType::FloorPlan, TimeOfDay::Night, None, None, none).unwrap();
+        assert_eq!(floor_plan.name, "default");
+
+        // Room adjustments run before the global fixes
+        let dark = library
+            .select(ContentType::LivingRoom, TimeOfDay::Day, None, None, |f| f == AnalysisFlag::IsUnderexposed)
+            .unwrap();
+        assert_eq!(dark.enhancement.shadow_recovery, 0.6 + 0.2);
+        assert_eq!(dark.enhancement.brightness_adjustment, 15.0 + 10.0);
+    }
+
+    #[test]
+    fn test_invalid_presets_are_rejected() {
+        let agency_only = PRESETS.replace("name = \"default\"", "name = \"default\"\nagency = \"AG1\"");
+        assert!(PresetLibrary::from_toml(&agency_only).is_err());
+
+        let out_of_range = PRESETS.replacen("contrast_boost = 1.2", "contrast_boost = 7.0", 1);
+        assert!(PresetLibrary::from_toml(&out_of_range).is_err());
+    }
+}
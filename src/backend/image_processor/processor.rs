@@ -1,7 +1,7 @@
 use std::sync::Arc;
-use image::{DynamicImage, ImageFormat, GenericImageView, ImageBuffer, Rgba, Luma};
-use webp::Encoder;
-use tracing::{info, instrument};
+use std::collections::BTreeMap;
+use image::{DynamicImage, ImageFormat, GenericImageView, ImageBuffer, Luma, GrayImage};
+use tracing::{info, warn, instrument};
 
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
@@ -9,58 +9,111 @@ use rexiv2::Metadata as XmpMetadata;
 use crate::backend::image_processor::color::{Rgb as ColorRgb, ImageEnhancement};
 use crate::backend::image_processor::image_utils::{ 
     detect_edges, 
-    detect_architectural_lines,
     detect_quality_issues,
     QualityAnalysis
 };
 use crate::backend::image_processor::histogram::{get_histogram_statistics, analyze_histogram};
+use crate::backend::image_processor::watermark::Watermarker;
+use crate::backend::image_processor::derivatives::{generate_renditions, renditions_bytes, Rendition};
+use crate::backend::image_processor::exif_metadata::{read_exif, apply_orientation, ExifData};
+use crate::backend::image_processor::metadata_scrub::{MetadataScrubber, extract_source_metadata};
+use crate::backend::image_processor::perceptual_hash::dhash;
+use crate::backend::image_processor::exposure_fusion::{fuse_brackets, BracketFrame};
+use crate::backend::image_processor::perspective::{auto_angles, apply_angles, estimate_verticals, CropRect, PerspectiveCorrection};
+use crate::backend::image_processor::edit_recipe::EditRecipe;
+use crate::backend::image_processor::presets::{AnalysisFlag, PresetStore, SelectedPreset};
+use crate::backend::image_processor::content_classifier::{
+    classify_analysis, classify_local, ClassificationSource, ContentClassification, ContentSignals, LOCAL_ACCEPT_CONFIDENCE,
+};
+use crate::backend::image_processor::sky_replacement::{find_sky, is_sky_color, replace_sky, replacement_bytes, SkyReplacement};
+use crate::backend::image_processor::panorama::{build_tiles, flat_preview, is_equirectangular, tiles_bytes, PanoramaTiles};
+use crate::backend::image_processor::derivatives::{encode_webp, encode_webp_lossless};
+use crate::backend::image_processor::floor_plan::{normalize as normalize_floor_plan, trace_svg, FloorPlanNormalization};
+use crate::backend::image_processor::memory::{
+    analysis_bytes, estimate_bracket_memory, estimate_job_memory, estimate_resize_memory, estimate_stage_memory, fusion_bytes, for_each_band, for_each_band_with_halo, image_bytes, into_dynamic,
+    probe_image, rgb_view, rgba_bytes, to_work_image, ImageProbe, JobMemory, WorkImage,
+};
+use crate::backend::image_processor::dimension_policy::{DimensionPlan, DimensionPolicy};
+use crate::backend::image_processor::lens_correction::{
+    apply_correction as apply_lens_correction, auto_correction as auto_lens_correction, AppliedLensCorrection,
+};
+use crate::backend::image_processor::denoise::{denoise, denoise_strength};
+use crate::backend::image_processor::listing_look::{adjustment_toward, group_targets, LookAdjustment, LookAdjustmentRecord, LookStats};
+use crate::backend::image_processor::batch_report::BatchQualityReport;
+use crate::backend::image_processor::time_of_day::{
+    capture_sun, classify as classify_time_of_day, sky_signals, ImageSignals, TimeOfDayClassification,
+};
+use crate::backend::common::types::listing_types::GpsCoordinates;
+use crate::backend::image_processor::color_management::{read_icc_profile, to_srgb, SourceColorSpace};
+use crate::backend::f_ai_core::resource_manager::{MemoryReservation, ResourceConfig, ResourceManager};
+use crate::backend::llm_caller::BatchAnalysisService;
+use crate::backend::monitoring::metrics::LLMMetrics;
+use crate::backend::common::config::{
+    Config, DimensionPolicyConfig, MetadataPolicyConfig, PresetConfig, QualityGateConfig, WatermarkConfig,
+};
+use crate::backend::common::validation::image_validation::{ALLOWED_FORMATS, MAX_FILE_SIZE};
+use crate::backend::image_processor::quality_report::{QualityDecision, QualityReport};
+use crate::backend::image_processor::quality_gate::QualityGate;
 use imageproc::{
     gradients::sobel_gradients,
     filter::gaussian_blur_f32,
-    geometric_transformations::{Projection, Interpolation, warp},
     hough::PolarLine,
 };
-use prometheus::{HistogramVec, register_histogram_vec};
+use prometheus::{HistogramVec, Registry, register_histogram_vec_with_registry, exponential_buckets};
 use std::hash::Hash;
 use uuid7::Uuid as Uuid7;
 use futures::future::try_join_all;
 
 use crate::backend::common::{
-    error::error::{Result, AppError, ImageError, ImageValidationError},
+    error::error::{Result, AppError},
     types::id_types::{ListingId, ImageId, BatchId},
-    validation::image_validation::{MAX_WIDTH, MAX_HEIGHT},
 };
 
+// Recorded in XMP and in every edit recipe
+pub const PROCESSING_VERSION: &str = "2.0";
+
 // Add this struct if not defined in b2_storage.rs
 pub struct ImageMetrics {
     pub image_processing_duration: HistogramVec,
     pub batch_processing_duration: HistogramVec,
     pub image_size_bytes: HistogramVec,
     pub image_dimensions: HistogramVec,
+    pub peak_memory_bytes: HistogramVec,
 }
 
 impl ImageMetrics {
-    pub fn new() -> Result<Self> {
+    pub fn new(registry: &Registry) -> Result<Self> {
         Ok(Self {
-            image_processing_duration: register_histogram_vec!(
+            image_processing_duration: register_histogram_vec_with_registry!(
                 "image_processing_duration_seconds",
                 "Time spent processing individual images",
-                &["content_type", "operation"]
+                &["content_type", "operation"],
+                registry
             )?,
-            batch_processing_duration: register_histogram_vec!(
+            batch_processing_duration: register_histogram_vec_with_registry!(
                 "batch_processing_duration_seconds",
                 "Time spent processing image batches",
-                &["listing_id"]
+                &["listing_id"],
+                registry
             )?,
-            image_size_bytes: register_histogram_vec!(
+            image_size_bytes: register_histogram_vec_with_registry!(
                 "image_size_bytes",
                 "Size of processed images in bytes",
-                &["content_type"]
+                &["content_type"],
+                registry
             )?,
-            image_dimensions: register_histogram_vec!(
+            image_dimensions: register_histogram_vec_with_registry!(
                 "image_dimensions_pixels",
                 "Image dimensions after processing",
-                &["dimension"]
+                &["dimension"],
+                registry
+            )?,
+            peak_memory_bytes: register_histogram_vec_with_registry!(
+                "image_job_peak_memory_bytes",
+                "Peak pixel-buffer memory held by one image job",
+                &["content_type"],
+                exponential_buckets(16.0 * 1024.0 * 1024.0, 2.0, 8)?,
+                registry
             )?,
         })
     }
@@ -80,53 +133,677 @@ pub enum ContentType {
     SPAContract,
     Reservation,
     RentalAgreement,
-    ListingAgreement,   
+    ListingAgreement,
+    // 360° equirectangular photo
+    Panorama,
+}
+
+impl ContentType {
+    pub const ALL: [ContentType; 14] = [
+        ContentType::LivingRoom,
+        ContentType::Bedroom,
+        ContentType::Kitchen,
+        ContentType::Bathroom,
+        ContentType::OtherInterior,
+        ContentType::Exterior,
+        ContentType::View,
+        ContentType::FloorPlan,
+        ContentType::TitlePaper,
+        ContentType::SPAContract,
+        ContentType::Reservation,
+        ContentType::RentalAgreement,
+        ContentType::ListingAgreement,
+        ContentType::Panorama,
+    ];
+
+    // Photographs, as opposed to plans and scanned documents
+    pub fn is_photo(&self) -> bool {
+        matches!(
+            self,
+            ContentType::LivingRoom
+                | ContentType::Bedroom
+                | ContentType::Kitchen
+                | ContentType::Bathroom
+                | ContentType::OtherInterior
+                | ContentType::Exterior
+                | ContentType::View
+                | ContentType::Panorama
+        )
+    }
+
+    // Deeds and contracts; these are stored encrypted and never published
+    pub fn is_document(&self) -> bool {
+        matches!(
+            self,
+            ContentType::TitlePaper
+                | ContentType::SPAContract
+                | ContentType::Reservation
+                | ContentType::RentalAgreement
+                | ContentType::ListingAgreement
+        )
+    }
 }
 
 pub struct ImageProcessor {
     metrics: Arc<ImageMetrics>,
     max_size: usize,
     supported_formats: Vec<ImageFormat>,
-    enhancement_config: ImageEnhancementConfig 
-}
-// Add this enum to select presets
-pub enum ImagePreset {
-    Interior,
-    Exterior,
-    Twilight,
-    Default,
+    presets: Arc<PresetStore>,
+    watermarker: Watermarker,
+    scrubber: MetadataScrubber,
+    quality_gate: QualityGate,
+    // Size rules per content type; shared with the upload validation and the chunked upload path
+    dimension_policy: Arc<DimensionPolicy>,
+    // Per-job memory budgets, shared with everything else on this worker
+    resources: Arc<ResourceManager>,
+    // Classifies untagged uploads the local signals can't place; None without an OpenAI key
+    content_llm: Option<Arc<BatchAnalysisService>>,
 }
 
 impl ImageProcessor {
+    // Builds every stage from config, so a bad watermark, preset file or budget
+    // fails at startup rather than on the first upload
+    pub fn new(config: &Config) -> Result<Self> {
+        let resources = ResourceManager::new(ResourceConfig {
+            image_memory: config.image_memory.clone(),
+            ..ResourceConfig::default()
+        })?;
+        let content_llm = (!config.openai.api_key.is_empty()).then(|| {
+            let metrics = Arc::new(LLMMetrics::new(prometheus::default_registry()));
+            Arc::new(BatchAnalysisService::new(config.openai.clone(), metrics))
+        });
+        if content_llm.is_none() {
+            warn!("No OpenAI key; untagged uploads are classified from local signals only");
+        }
+
+        let processor = Self {
+            metrics: Arc::new(ImageMetrics::new(prometheus::default_registry())?),
+            max_size: MAX_FILE_SIZE,
+            supported_formats: ALLOWED_FORMATS.to_vec(),
+            presets: Arc::new(PresetStore::load(&config.presets)?),
+            watermarker: Watermarker::new(config.watermark.clone())?,
+            scrubber: MetadataScrubber::new(config.metadata_policy.clone())?,
+            quality_gate: QualityGate::new(config.quality_gate.clone())?,
+            dimension_policy: Arc::new(DimensionPolicy::new(config.dimension_policy.clone())?),
+            resources: Arc::new(resources),
+            content_llm,
+        };
+        processor.check_rules_fit_memory()?;
+        Ok(processor)
+    }
+
+    // Default settings for every stage and the bundled presets, without OpenAI.
+    // For the golden-image suite, which has no deployment config to load. Metrics
+    // go to a private registry so several processors can live in one process.
+    pub fn with_defaults() -> Result<Self> {
+        let processor = Self {
+            metrics: Arc::new(ImageMetrics::new(&Registry::new())?),
+            max_size: MAX_FILE_SIZE,
+            supported_formats: ALLOWED_FORMATS.to_vec(),
+            presets: Arc::new(PresetStore::load(&PresetConfig::default())?),
+            watermarker: Watermarker::new(WatermarkConfig::default())?,
+            scrubber: MetadataScrubber::new(MetadataPolicyConfig::default())?,
+            quality_gate: QualityGate::new(QualityGateConfig::default())?,
+            dimension_policy: Arc::new(DimensionPolicy::new(DimensionPolicyConfig::default())?),
+            resources: Arc::new(ResourceManager::new(ResourceConfig::default())?),
+            content_llm: None,
+        };
+        processor.check_rules_fit_memory()?;
+        Ok(processor)
+    }
+
+    // Picks up edits to the preset file without a restart; a file that fails to
+    // parse is logged and the presets already loaded stay in use
+    pub fn spawn_preset_reload(&self) -> tokio::task::JoinHandle<()> {
+        self.presets.clone().spawn_reload()
+    }
+
+    // A rule that lets through images no job can reserve memory for would only
+    // fail them after upload. Sized for 8-bit RGB, which is what cameras deliver.
+    fn check_rules_fit_memory(&self) -> Result<()> {
+        let budget = self.resources.max_job_bytes();
+        for content_type in std::iter::once(None).chain(ContentType::ALL.into_iter().map(Some)) {
+            let (scope, width, height) = self.dimension_policy.largest_allowed(content_type);
+            let probe = ImageProbe { width, height, decoded_bytes: width as usize * height as usize * 3 };
+            let estimate = self.estimate_upload_memory(&probe, content_type, 0);
+            if estimate > budget {
+                return Err(AppError::Configuration(format!(
+                    "{} allows {}x{}, which needs about {} MiB, over image_memory.max_job_mb ({} MiB)",
+                    scope, width, height, estimate.div_ceil(1024 * 1024), budget / (1024 * 1024)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Untagged uploads are classified first; check `classification` on the result
+    // for how sure that was. An upload the quality gate turns away is an outcome
+    // rather than an error, so the rest of a batch carries on without it.
     #[instrument(skip(self, image_data))]
     pub async fn process_image(
         &self,
         listing_id: &ListingId,
         image_data: Vec<u8>,
+        content_type: Option<ContentType>,
+        agency_id: Option<&str>,
+        listing_pin: Option<&GpsCoordinates>,
+    ) -> Result<UploadOutcome> {
+        // Sized from the header, so an upload too big for this worker is never decoded
+        let probe = probe_image(&image_data)?;
+        self.dimension_policy.precheck(content_type, probe.width, probe.height)?;
+        let estimate = self.estimate_upload_memory(&probe, content_type, image_data.len());
+        let (_reservation, memory) = self.reserve_job_memory(estimate).await?;
+
+        let upload = self.decode_upload(&image_data, &memory)?;
+        // The upload keeps its own copy as the original
+        drop(image_data);
+        let classification = self.classify_upload(&upload.img, content_type, &memory).await?;
+        let content_type = classification.content_type;
+        let processed = self.process_decoded(listing_id, upload, classification, agency_id, listing_pin, &memory);
+        self.record_peak_memory(content_type, &memory);
+        processed
+    }
+
+    pub fn dimension_policy(&self) -> &Arc<DimensionPolicy> {
+        &self.dimension_policy
+    }
+
+    // The job estimate at the largest size the policy could resize this upload to,
+    // and the decode and resize at the upload's own size. An untagged 2:1 upload
+    // may turn out to be a panorama, so it is sized for the tiles.
+    fn estimate_upload_memory(&self, probe: &ImageProbe, content_type: Option<ContentType>, encoded_len: usize) -> usize {
+        let (width, height) = self.dimension_policy.largest_output(content_type, probe.width, probe.height);
+        let mut estimate = if (width, height) == (probe.width, probe.height) {
+            estimate_job_memory(probe, encoded_len)
+        } else {
+            let bytes_per_pixel = probe.decoded_bytes / (probe.width as usize * probe.height as usize).max(1);
+            let resized = ImageProbe { width, height, decoded_bytes: bytes_per_pixel * width as usize * height as usize };
+            estimate_resize_memory(probe, &resized, encoded_len).max(estimate_job_memory(&resized, encoded_len))
+        };
+        if content_type.map_or(is_equirectangular(width, height), |content_type| content_type == ContentType::Panorama) {
+            estimate = estimate.max(estimate_stage_memory(width, height, tiles_bytes(width, height), encoded_len));
+        }
+        estimate
+    }
+
+    async fn reserve_job_memory(&self, estimate: usize) -> Result<(MemoryReservation, JobMemory)> {
+        let reservation = self.resources.reserve_image_memory(estimate).await?;
+        let memory = JobMemory::new(reservation.bytes(), self.resources.mmap_threshold());
+        Ok((reservation, memory))
+    }
+
+    fn record_peak_memory(&self, content_type: ContentType, memory: &JobMemory) {
+        let content_type = format!("{:?}", content_type);
+        self.metrics
+            .peak_memory_bytes
+            .with_label_values(&[content_type.as_str()])
+            .observe(memory.peak() as f64);
+    }
+
+    // Uploader's tag wins. Otherwise local signals, then the image analysis when
+    // those are unsure; an LLM failure falls back to the local guess.
+    async fn classify_upload(
+        &self,
+        img: &DynamicImage,
+        content_type: Option<ContentType>,
+        memory: &JobMemory,
+    ) -> Result<ContentClassification> {
+        if let Some(content_type) = content_type {
+            return Ok(ContentClassification::from_uploader(content_type));
+        }
+        let _analysis = memory.charge(analysis_bytes(img.width(), img.height()))?;
+
+        let sky_fraction = self.detect_sky_region(img)?;
+        let local = classify_local(&ContentSignals::measure(img, sky_fraction));
+        let Some(llm) = self.content_llm.as_ref().filter(|_| local.confidence < LOCAL_ACCEPT_CONFIDENCE) else {
+            return Ok(local);
+        };
+
+        let preview = encode_webp(&img.thumbnail(1024, 1024))?;
+        match llm.analyze_single("upload.webp".to_string(), preview).await {
+            Ok(analysis) => Ok(local.combine(classify_analysis(&analysis))),
+            Err(e) => {
+                warn!(error = %e, "Image analysis failed, using local content type guess");
+                Ok(local)
+            }
+        }
+    }
+
+    // Fuses a 3-5 frame exposure bracket into one master, which then runs through
+    // the same enhancement and publishing steps as a single upload
+    #[instrument(skip(self, frames), fields(frames = frames.len()))]
+    pub async fn process_bracket_group(
+        &self,
+        listing_id: &ListingId,
+        mut frames: Vec<Vec<u8>>,
+        content_type: Option<ContentType>,
+        agency_id: Option<&str>,
+        listing_pin: Option<&GpsCoordinates>,
+    ) -> Result<UploadOutcome> {
+        let probes = frames.iter().map(|data| probe_image(data)).collect::<Result<Vec<_>>>()?;
+        for probe in &probes {
+            self.dimension_policy.precheck(content_type, probe.width, probe.height)?;
+        }
+        let encoded_len = frames.iter().map(Vec::len).sum();
+        // The fused master is RGB8 at the frame size, and is resized like a single upload
+        let estimate = match probes.first() {
+            Some(first) => {
+                let master = ImageProbe { decoded_bytes: first.width as usize * first.height as usize * 3, ..*first };
+                estimate_bracket_memory(&probes, encoded_len).max(self.estimate_upload_memory(&master, content_type, encoded_len))
+            }
+            None => estimate_bracket_memory(&probes, encoded_len),
+        };
+        let (_reservation, memory) = self.reserve_job_memory(estimate).await?;
+
+        let mut uploads = frames
+            .iter()
+            .map(|data| self.decode_upload(data, &memory))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Moved out rather than cloned; the reference upload gets the fused master instead
+        let images: Vec<DynamicImage> = uploads.iter_mut().map(|u| std::mem::take(&mut u.img)).collect();
+        let fusion = {
+            let pixels = images.first().map_or(0, |img| img.width() as usize * img.height() as usize);
+            let _working = memory.charge(images.iter().map(image_bytes).sum::<usize>() + fusion_bytes(pixels, images.len()))?;
+            fuse_brackets(&images)?
+        };
+        drop(images);
+
+        let mut order: Vec<usize> = (0..uploads.len()).collect();
+        order.sort_by(|&a, &b| fusion.mean_luminance[a].total_cmp(&fusion.mean_luminance[b]));
+
+        let bracket_frames: Vec<BracketFrame> = order
+            .iter()
+            .enumerate()
+            .map(|(index, &i)| {
+                let exif = uploads[i].exif.as_ref();
+                BracketFrame {
+                    index,
+                    exposure_time: exif.and_then(|e| e.exposure_time),
+                    exposure_bias: exif.and_then(|e| e.exposure_bias),
+                    mean_luminance: fusion.mean_luminance[i],
+                    offset: fusion.offsets[i],
+                    is_reference: i == fusion.reference_index,
+                    data: std::mem::take(&mut frames[i]),
+                }
+            })
+            .collect();
+
+        // The reference frame's EXIF (GPS, capture time, camera) describes the master.
+        // The fused frame is the original re-renders start from, kept lossless.
+        let reference = uploads.swap_remove(fusion.reference_index);
+        drop(uploads);
+        let mut original = Vec::new();
+        fusion.image.write_to(&mut std::io::Cursor::new(&mut original), ImageFormat::Png)?;
+        let upload = DecodedUpload { img: fusion.image, original, ..reference };
+        let classification = self.classify_upload(&upload.img, content_type, &memory).await?;
+        let content_type = classification.content_type;
+        let outcome = self.process_decoded(listing_id, upload, classification, agency_id, listing_pin, &memory);
+        self.record_peak_memory(content_type, &memory);
+        Ok(match outcome? {
+            UploadOutcome::Processed(mut processed) => {
+                processed.bracket_frames = bracket_frames;
+                UploadOutcome::Processed(processed)
+            }
+            outcome => outcome,
+        })
+    }
+
+    // Resizes the upload as its content type's rule says, before anything measures
+    // it; recipes store crops in pixels of the resized image. The original is kept
+    // as uploaded, so re-renders go through here again.
+    fn fit_dimensions(&self, upload: DecodedUpload, content_type: ContentType, memory: &JobMemory) -> Result<DecodedUpload> {
+        let (width, height) = upload.img.dimensions();
+        let plan = self.dimension_policy.plan(Some(content_type), width, height)?;
+        if plan == DimensionPlan::Keep {
+            return Ok(upload);
+        }
+
+        let (new_width, new_height) = plan.size(width, height);
+        let bytes = image_bytes(&upload.img);
+        let resized = bytes / (width as usize * height as usize).max(1) * new_width as usize * new_height as usize;
+        // Sharpening after an upscale makes a second copy at the new size
+        let _resizing = memory.charge((bytes + resized).max(resized * 2))?;
+        info!(width, height, new_width, new_height, ?content_type, "Resizing upload to fit its dimension rule");
+        let img = plan.apply(upload.img);
+        Ok(DecodedUpload { img, ..upload })
+    }
+
+    fn decode_upload(&self, image_data: &[u8], memory: &JobMemory) -> Result<DecodedUpload> {
+        // EXIF has to be read from the original bytes, the WebP output drops it
+        let exif = read_exif(image_data);
+        let source_metadata = extract_source_metadata(image_data);
+
+        // Auto-rotate and convert to sRGB before anything looks at the pixels;
+        // each needs a second copy while it runs
+        let img = image::load_from_memory(image_data)?;
+        let mut decoded = memory.charge(image_bytes(&img))?;
+        let img = match &exif {
+            Some(exif) if (2..=8).contains(&exif.orientation) => {
+                decoded.resize(image_bytes(&img) * 2)?;
+                apply_orientation(img, exif.orientation)
+            }
+            _ => img,
+        };
+        let icc = read_icc_profile(image_data);
+        if icc.is_some() {
+            decoded.resize(image_bytes(&img) * 2)?;
+        }
+        let (img, color_space) = to_srgb(img, icc.as_deref())?;
+        drop(decoded);
+
+        Ok(DecodedUpload { img, original: image_data.to_vec(), exif, source_metadata, color_space })
+    }
+
+    fn process_decoded(
+        &self,
+        listing_id: &ListingId,
+        upload: DecodedUpload,
+        classification: ContentClassification,
+        agency_id: Option<&str>,
+        listing_pin: Option<&GpsCoordinates>,
+        memory: &JobMemory,
+    ) -> Result<UploadOutcome> {
+        // The uploader said it's paperwork, so it belongs in /documents; a guess is held below
+        if classification.content_type.is_document() && classification.source == ClassificationSource::Uploader {
+            return Err(private_document_error(classification.content_type));
+        }
+        // Size limits come from the dimension policy; 360° photos also have to be 2:1
+        let (width, height) = upload.img.dimensions();
+        if classification.content_type == ContentType::Panorama && !is_equirectangular(width, height) {
+            return Err(AppError::InvalidInput(format!(
+                "Panoramas must be 2:1 equirectangular, got {}x{}", width, height
+            )));
+        }
+        let upload = self.fit_dimensions(upload, classification.content_type, memory)?;
+
+        let analysis = memory.charge(analysis_bytes(upload.img.width(), upload.img.height()))?;
+        let time_of_day = self.classify_time_of_day(&upload.img, classification.content_type, upload.exif.as_ref(), listing_pin)?;
+        let quality_analysis = detect_quality_issues(&upload.img);
+        let recipe = self.auto_recipe(
+            &upload.img, classification.content_type, upload.exif.as_ref(), time_of_day.time_of_day, agency_id,
+            quality_analysis.noise_level,
+        )?;
+        // A photo mistaken for a deed must not fail the upload, and a deed mistaken for
+        // a photo must not be published, so a guessed document stays private until reviewed
+        if classification.content_type.is_document() {
+            return Ok(UploadOutcome::HeldForReview(HeldUpload {
+                id: ImageId::generate(),
+                listing_id: listing_id.clone(),
+                original: upload.original,
+                recipe,
+                classification,
+                quality: QualityReport::from_analysis(&quality_analysis),
+            }));
+        }
+        // Only new uploads are gated; re-renders of stored images keep their report.
+        // Judged before enhancement and publishing, which a rejected upload would waste;
+        // verticals the recipe straightens don't count against it.
+        let verdict = self.quality_gate.evaluate(
+            QualityReport::from_analysis(&quality_analysis).with_straightened_verticals(recipe.perspective),
+            &get_histogram_statistics(&analyze_histogram(&upload.img)),
+            classification.content_type,
+            agency_id,
+        );
+        if verdict.decision == QualityDecision::Reject {
+            return Ok(UploadOutcome::Rejected(Box::new(verdict)));
+        }
+        drop(analysis);
+        let mut processed = self.render(listing_id, ImageId::generate(), upload, recipe, quality_analysis, agency_id, memory)?;
+        processed.classification = Some(classification);
+        processed.time_of_day = Some(time_of_day);
+        Ok(UploadOutcome::Processed(processed))
+    }
+
+    // Re-renders an existing image from its untouched original with an edited recipe.
+    // The result keeps the image id so it replaces the stored master and renditions.
+    #[instrument(skip(self, original, recipe))]
+    pub async fn rerender(
+        &self,
+        listing_id: &ListingId,
+        image_id: ImageId,
+        original: &[u8],
+        mut recipe: EditRecipe,
+        agency_id: Option<&str>,
+    ) -> Result<ProcessedImage> {
+        recipe.validate()?;
+        recipe.processing_version = PROCESSING_VERSION.to_string();
+
+        let (_reservation, memory) = self.reserve_job_memory(self.estimate_render_memory(original, &recipe)?).await?;
+        let upload = self.decode_upload(original, &memory)?;
+        let upload = self.fit_dimensions(upload, recipe.content_type, &memory)?;
+        let content_type = recipe.content_type;
+        let analysis = memory.charge(analysis_bytes(upload.img.width(), upload.img.height()))?;
+        let quality_analysis = detect_quality_issues(&upload.img);
+        drop(analysis);
+        let processed = self.render(listing_id, image_id, upload, recipe, quality_analysis, agency_id, &memory);
+        self.record_peak_memory(content_type, &memory);
+        processed
+    }
+
+    // Renders a just-processed image again with a listing look nudge. Nothing about
+    // the upload has changed, so its quality analysis is reused and only the
+    // enhancement and encoding run again.
+    async fn rerender_look(
+        &self,
+        image: &ProcessedImage,
+        adjustment: LookAdjustment,
+        agency_id: Option<&str>,
+    ) -> Result<ProcessedImage> {
+        let mut recipe = image.recipe.clone();
+        recipe.listing_look = Some(adjustment);
+
+        let (_reservation, memory) = self.reserve_job_memory(self.estimate_render_memory(&image.original, &recipe)?).await?;
+        let upload = self.decode_upload(&image.original, &memory)?;
+        let upload = self.fit_dimensions(upload, recipe.content_type, &memory)?;
+        let content_type = recipe.content_type;
+        let processed = self.render(
+            &image.listing_id, image.id.clone(), upload, recipe, image.quality_analysis.clone(), agency_id, &memory,
+        );
+        self.record_peak_memory(content_type, &memory);
+        processed
+    }
+
+    fn estimate_render_memory(&self, original: &[u8], recipe: &EditRecipe) -> Result<usize> {
+        let probe = probe_image(original)?;
+        let estimate = self.estimate_upload_memory(&probe, Some(recipe.content_type), original.len());
+        // Only edits ask for a new sky; it runs on the geometry stage's input and output
+        if recipe.sky_replacement.is_none() {
+            return Ok(estimate);
+        }
+        let (width, height) = self.dimension_policy.largest_output(Some(recipe.content_type), probe.width, probe.height);
+        let working = rgba_bytes(width, height) + replacement_bytes(width, height);
+        Ok(estimate.max(estimate_stage_memory(width, height, working, original.len())))
+    }
+
+    // A reviewer corrected the content type. Presets depend on it, so the recipe
+    // is picked again from scratch for the new type.
+    #[instrument(skip(self, original))]
+    pub async fn reclassify(
+        &self,
+        listing_id: &ListingId,
+        image_id: ImageId,
+        original: &[u8],
         content_type: ContentType,
+        agency_id: Option<&str>,
+        listing_pin: Option<&GpsCoordinates>,
     ) -> Result<ProcessedImage> {
-        let image_id = ImageId::generate();
-        let filename = format!("{}-{}.webp", listing_id.as_str(), image_id.as_str());
-        
-        // Validate dimensions (1080p-4K)
-        let img = image::load_from_memory(&image_data)?;
-        let (width, height) = img.dimensions();
-        if width < 1920 || height < 1080 || width > 3840 || height > 2160 {
-            return Err(AppError::InvalidInput("Image dimensions must be between 1080p and 4K".into()));
+        let probe = probe_image(original)?;
+        let estimate = self.estimate_upload_memory(&probe, Some(content_type), original.len());
+        let (_reservation, memory) = self.reserve_job_memory(estimate).await?;
+        let upload = self.decode_upload(original, &memory)?;
+        let upload = self.fit_dimensions(upload, content_type, &memory)?;
+        // Whether the sky can overrule the clock depends on the type, so this is redone too
+        let analysis = memory.charge(analysis_bytes(upload.img.width(), upload.img.height()))?;
+        let time_of_day = self.classify_time_of_day(&upload.img, content_type, upload.exif.as_ref(), listing_pin)?;
+        let quality_analysis = detect_quality_issues(&upload.img);
+        let recipe = self.auto_recipe(
+            &upload.img, content_type, upload.exif.as_ref(), time_of_day.time_of_day, agency_id, quality_analysis.noise_level,
+        )?;
+        drop(analysis);
+        let processed = self.render(listing_id, image_id, upload, recipe, quality_analysis, agency_id, &memory);
+        self.record_peak_memory(content_type, &memory);
+        let mut processed = processed?;
+        processed.classification = Some(ContentClassification::from_reviewer(content_type));
+        processed.time_of_day = Some(time_of_day);
+        Ok(processed)
+    }
+
+    // Decoding and the enhancement stages alone, as an upload without an agency would
+    // get them: EXIF orientation, ICC conversion and lens profiles apply as they do
+    // for real uploads. No encoding, watermark or storage; this is what the
+    // golden-image suite compares.
+    pub fn render_for_regression(&self, image_data: &[u8], content_type: ContentType) -> Result<DynamicImage> {
+        let memory = JobMemory::unbounded();
+        let upload = self.decode_upload(image_data, &memory)?;
+        let exif = upload.exif.as_ref();
+        let time_of_day = self.classify_time_of_day(&upload.img, content_type, exif, None)?;
+        let noise_level = detect_quality_issues(&upload.img).noise_level;
+        let recipe = self.auto_recipe(&upload.img, content_type, exif, time_of_day.time_of_day, None, noise_level)?;
+        Ok(self.enhance_image(upload.img, &recipe, &memory)?.image)
+    }
+
+    // Parameters the pipeline picks on its own, stored so editors can adjust them later
+    fn auto_recipe(
+        &self,
+        img: &DynamicImage,
+        content_type: ContentType,
+        exif: Option<&ExifData>,
+        time_of_day: TimeOfDay,
+        agency_id: Option<&str>,
+        // From the upload's quality analysis, measured before the shadow lift brings the grain up
+        noise_level: f32,
+    ) -> Result<EditRecipe> {
+        let is_panorama = content_type == ContentType::Panorama;
+        let is_floor_plan = content_type == ContentType::FloorPlan;
+
+        // Wide-angle bending throws off the vertical line and perspective checks,
+        // so everything below looks at the lens-corrected frame
+        let lens = (content_type.is_photo() && !is_panorama)
+            .then(|| auto_lens_correction(img, exif))
+            .flatten();
+        let corrected = match &lens {
+            Some(lens) => Some(DynamicImage::ImageRgba8(apply_lens_correction(&img.to_rgba8(), lens)?.0)),
+            None => None,
+        };
+        let img = corrected.as_ref().unwrap_or(img);
+
+        let analysis = self.analyze_image(img)?;
+        let preset = self.get_room_specific_config(&content_type, &analysis, time_of_day, agency_id)?;
+        let denoise = if is_floor_plan { 0.0 } else { denoise_strength(noise_level, preset.enhancement.shadow_recovery) };
+
+        Ok(EditRecipe {
+            content_type,
+            preset: preset.name,
+            preset_version: preset.version,
+            processing_version: PROCESSING_VERSION.to_string(),
+            enhancement: preset.enhancement,
+            lens,
+            // Equirectangular lines are curved by design, and sharpening shows up as seams
+            // in the viewer, so panoramas get tone and colour only. Plans have their own pipeline.
+            perspective: (analysis.needs_perspective_correction && !is_panorama && !is_floor_plan)
+                .then(|| auto_angles(img))
+                .flatten(),
+            crop: None,
+            twilight_grade: time_of_day == TimeOfDay::Twilight && !is_floor_plan,
+            denoise,
+            sharpen: analysis.needs_sharpening && !is_panorama && !is_floor_plan,
+            // Set later by the listing pass, once the rest of the gallery is known
+            listing_look: None,
+            sky_replacement: None,
+            trace_svg: false,
+        })
+    }
+
+    fn render(
+        &self,
+        listing_id: &ListingId,
+        image_id: ImageId,
+        upload: DecodedUpload,
+        recipe: EditRecipe,
+        quality_analysis: QualityAnalysis,
+        agency_id: Option<&str>,
+        memory: &JobMemory,
+    ) -> Result<ProcessedImage> {
+        // Every path to a published file comes through here, so this is the one check
+        // that keeps deeds and contracts out of the public bucket
+        if recipe.content_type.is_document() {
+            return Err(private_document_error(recipe.content_type));
         }
+        let DecodedUpload { img, original, exif, source_metadata, color_space } = upload;
+        let filename = format!("{}-{}.webp", listing_id.as_str(), image_id.as_str());
 
-        // Clone img before first use
-        let enhanced = self.enhance_image(img.clone(), content_type)?;
-        
-        // Convert to WebP with 0.9 quality
-        let webp_data = self.convert_to_webp(&enhanced, 0.9)?;
+        // Everything that looks at the upload itself runs before enhancement,
+        // which then takes the decoded image over instead of copying it
+        let decoded = memory.charge(image_bytes(&img))?;
+        // Hash the upright upload so re-posts match regardless of our enhancement
+        let perceptual_hash = dhash(&img);
+        let luminance = get_histogram_statistics(&analyze_histogram(&img));
+        drop(decoded);
+
+        let Enhanced { image: enhanced, lens_correction, perspective_correction, sky_replacement, floor_plan, floor_plan_svg } =
+            self.enhance_image(img, &recipe, memory)?;
+        let _enhanced = memory.charge(image_bytes(&enhanced))?;
+        // Perspective correction and cropping change the size, so report the enhanced one
+        let (width, height) = enhanced.dimensions();
+        // Measured on the output, for the listing-level pass
+        let look = self.measure_look(&enhanced, memory)?;
+
+        // Plans are flat black on white, where lossy WebP leaves grey fringes on every line
+        let encode = |img: &DynamicImage| -> Result<Vec<u8>> {
+            if floor_plan.is_some() { encode_webp_lossless(img) } else { encode_webp(img) }
+        };
+        let webp_data = encode(&enhanced)?;
         
         // Add XMP metadata
-        let metadata = self.create_metadata(listing_id, &image_id, &filename, content_type.clone())?;
-        let final_data = self.add_xmp_metadata(&webp_data, &metadata)?;
+        let metadata = self.create_metadata(listing_id, &image_id, &filename, &recipe, exif.as_ref(), color_space)?;
+        let final_data = self.add_xmp_metadata(&webp_data, "webp", &metadata)?;
+        // Every public file carries the same identification and alteration disclosure,
+        // then loses whatever else the metadata policy doesn't keep
+        let publish = |data: &[u8], extension: &str| -> Result<Vec<u8>> {
+            self.scrubber.scrub(&self.add_xmp_metadata(data, extension, &metadata)?, extension)
+        };
 
-        // Use original img for quality analysis
-        let quality_analysis = detect_quality_issues(&img);
+        // The clean master stays private, only the watermarked copy gets published
+        let watermarked = self.watermarker.is_enabled();
+        let published_data = if watermarked {
+            let _watermarked = memory.charge(image_bytes(&enhanced))?;
+            let watermarked = self.watermarker.apply(&enhanced, agency_id)?;
+            publish(&encode(&watermarked)?, "webp")?
+        } else {
+            publish(&webp_data, "webp")?
+        };
+
+        // Gallery sizes for srcset, published alongside the full-size variant
+        let watermarker = watermarked.then_some(&self.watermarker);
+        let renditions = {
+            let _renditions = memory.charge(renditions_bytes(width, height))?;
+            generate_renditions(&enhanced, watermarker, agency_id, publish)?
+        };
+
+        // Cube-map tiles for the 360° viewer, plus a flat look ahead for galleries.
+        // Only the preview is watermarked; a mark on the tiles would repeat on every face,
+        // so unmarked tiles stop at the resolution the watermark config allows.
+        let panorama = if recipe.content_type == ContentType::Panorama {
+            let _tiles = memory.charge(tiles_bytes(width, height))?;
+            let mut panorama = build_tiles(&enhanced, self.watermarker.panorama_tile_max_face(), publish)?;
+            let preview = flat_preview(&enhanced);
+            let preview = match watermarker {
+                Some(watermarker) => watermarker.apply(&preview, agency_id)?,
+                None => preview,
+            };
+            panorama.preview = publish(&encode_webp(&preview)?, "webp")?;
+            Some(panorama)
+        } else {
+            None
+        };
+
+        let quality = self.quality_gate.evaluate(
+            QualityReport::from_analysis(&quality_analysis).with_perspective_correction(perspective_correction),
+            &luminance,
+            recipe.content_type,
+            agency_id,
+        );
         
         Ok(ProcessedImage {
             id: image_id,
@@ -134,77 +811,158 @@ impl ImageProcessor {
             filename,
             size: final_data.len() as i64,
             data: final_data,
+            published_data,
+            watermarked,
+            renditions,
             width,
             height,
-            content_type,
+            content_type: recipe.content_type,
             quality_analysis,
+            quality,
+            look,
+            exif,
+            source_metadata,
+            source_color_space: color_space,
+            perceptual_hash,
+            bracket_frames: Vec::new(),
+            lens_correction,
+            perspective_correction,
+            sky_replacement,
+            panorama,
+            floor_plan,
+            floor_plan_svg,
+            original,
+            recipe,
+            classification: None,
+            time_of_day: None,
         })
     }
 
-    fn enhance_image(&self, img: DynamicImage, content_type: ContentType) -> Result<DynamicImage> {
-        // Quick analysis of the image
-        let analysis = self.analyze_image(&img)?;
-        let config = self.get_room_specific_config(&content_type, &analysis);
-        
-        let mut img_buffer = img.to_rgba8();
-        
-        // Apply lens distortion correction if needed
-        if analysis.needs_perspective_correction {
-            img_buffer = self.correct_perspective(img_buffer)?;
+    // Geometry stages resample the whole frame and run on an in-memory copy; the
+    // per-pixel and neighbourhood stages then run band by band, in parallel, over a
+    // working buffer that is memory-mapped for large images
+    fn enhance_image(
+        &self,
+        img: DynamicImage,
+        recipe: &EditRecipe,
+        memory: &JobMemory,
+    ) -> Result<Enhanced> {
+        let content_type = recipe.content_type;
+        let config = &recipe.enhancement;
+        let mut held = memory.charge(image_bytes(&img))?;
+
+        // Photo grading only muddies scanned linework
+        if content_type == ContentType::FloorPlan {
+            // Whitened, deskewed and binarized copies, one byte per pixel each
+            held.resize(held.bytes() + img.width() as usize * img.height() as usize * 3)?;
+            return self.enhance_floor_plan(&img, recipe);
         }
 
-        // Apply local contrast enhancement for architectural details
-        if content_type == ContentType::Exterior || content_type == ContentType::FloorPlan {
-            img_buffer = self.enhance_architectural_details(img_buffer)?;
-        }
+        let mut lens_correction = None;
+        let mut perspective_correction = None;
+        let mut sky_replacement = None;
+        let img = if recipe.lens.is_some() || recipe.perspective.is_some() || recipe.crop.is_some() || recipe.sky_replacement.is_some() {
+            let converted = rgba_bytes(img.width(), img.height());
+            held.resize(held.bytes() + converted)?;
+            let mut img_buffer = img.into_rgba8();
+            held.resize(converted)?;
+
+            // Unbend wide-angle barrel distortion first; the perspective angles were
+            // measured on the lens-corrected frame
+            if let Some(lens) = &recipe.lens {
+                held.resize(converted * 2)?;
+                let (corrected, applied) = apply_lens_correction(&img_buffer, lens)?;
+                img_buffer = corrected;
+                held.resize(converted)?;
+                lens_correction = Some(applied);
+            }
 
-        // Process each pixel with enhanced color management
-        for pixel in img_buffer.pixels_mut() {
-            let mut rgb = ColorRgb {
-                r: pixel[0],
-                g: pixel[1],
-                b: pixel[2],
-            };
+            // Straighten converging verticals
+            if let Some(angles) = recipe.perspective {
+                held.resize(converted * 2)?;
+                let (corrected, correction) = apply_angles(&img_buffer, angles)?;
+                img_buffer = corrected;
+                held.resize(rgba_bytes(img_buffer.width(), img_buffer.height()))?;
+                perspective_correction = Some(correction);
+            }
 
-            // Apply room-specific color enhancements
-            match content_type {
-                ContentType::Kitchen | ContentType::Bathroom => {
-                    // Enhance whites and reduce yellow cast
-                    rgb.adjust_white_balance(-0.1, 0.0);
-                },
-                ContentType::LivingRoom | ContentType::Bedroom => {
-                    // Warmer, more inviting tones
-                    rgb.adjust_white_balance(0.05, 0.0);
-                },
-                ContentType::Exterior if analysis.is_twilight => {
-                    // Enhance blue hour colors
-                    rgb.adjust_white_balance(-0.15, 0.0);
-                    rgb.adjust_saturation(1.2);
-                },
-                _ => {}
+            if let Some(crop) = recipe.crop {
+                held.resize(held.bytes() * 2)?;
+                img_buffer = apply_crop(&img_buffer, crop)?;
+                held.resize(rgba_bytes(img_buffer.width(), img_buffer.height()))?;
             }
 
-            // Apply global enhancements
-            rgb.adjust_contrast(config.contrast_boost);
-            rgb.adjust_saturation(config.color_enhancement_strength);
-            rgb.adjust_shadows(config.shadow_recovery);
-            rgb.adjust_highlights(-config.highlight_protection);
+            // Opt-in only; the recipe limits it to exteriors and views. Done before grading
+            // so the new sky and the relit foreground get the same tone treatment.
+            if let Some(sky_id) = &recipe.sky_replacement {
+                held.resize(held.bytes() * 2 + replacement_bytes(img_buffer.width(), img_buffer.height()))?;
+                let (replaced, replacement) = replace_sky(&img_buffer, find_sky(sky_id)?)?;
+                img_buffer = replaced;
+                held.resize(rgba_bytes(img_buffer.width(), img_buffer.height()))?;
+                sky_replacement = Some(replacement);
+            }
 
-            pixel[0] = rgb.r;
-            pixel[1] = rgb.g;
-            pixel[2] = rgb.b;
+            DynamicImage::ImageRgba8(img_buffer)
+        } else {
+            img
+        };
+
+        let mut work = to_work_image(&img, memory)?;
+        drop(img);
+        drop(held);
+
+        // Grain comes out first, so the edge boost and sharpening don't amplify it
+        if recipe.denoise > 0.0 {
+            denoise(&mut work, recipe.denoise, config.shadow_recovery, memory)?;
+        }
+
+        // Apply local contrast enhancement for architectural details
+        if content_type == ContentType::Exterior {
+            enhance_architectural_details(&mut work, memory)?;
         }
 
+        // Process each pixel with enhanced color management
+        grade(&mut work, recipe);
+
         // Final pass for global adjustments
-        if analysis.needs_sharpening {
-            img_buffer = self.apply_smart_sharpening(img_buffer, &config)?;
+        if recipe.sharpen {
+            apply_smart_sharpening(&mut work, config, memory)?;
         }
 
-        Ok(DynamicImage::ImageRgba8(img_buffer))
+        // Encoding and the watermark need it back on the heap
+        let _output = memory.charge(rgba_bytes(work.width(), work.height()))?;
+        Ok(Enhanced {
+            image: into_dynamic(work),
+            lens_correction,
+            perspective_correction,
+            sky_replacement,
+            floor_plan: None,
+            floor_plan_svg: None,
+        })
+    }
+
+    // Deskew, whiten, binarize and trim; the editor's crop then applies to the clean plan
+    fn enhance_floor_plan(&self, img: &DynamicImage, recipe: &EditRecipe) -> Result<Enhanced> {
+        let normalized = normalize_floor_plan(img)?;
+        let mut plan = normalized.image;
+        if let Some(crop) = recipe.crop {
+            plan = apply_crop(&plan, crop)?;
+        }
+        let floor_plan_svg = recipe.trace_svg.then(|| trace_svg(&plan));
+
+        Ok(Enhanced {
+            image: DynamicImage::ImageLuma8(plan),
+            lens_correction: None,
+            perspective_correction: None,
+            sky_replacement: None,
+            floor_plan: Some(normalized.normalization),
+            floor_plan_svg,
+        })
     }
 
-    fn add_xmp_metadata(&self, data: &[u8], metadata: &ImageMetadata) -> Result<Vec<u8>> {
-        let temp_path = std::env::temp_dir().join(format!("temp_{}.webp", metadata.image_id));
+    fn add_xmp_metadata(&self, data: &[u8], extension: &str, metadata: &ImageMetadata) -> Result<Vec<u8>> {
+        // Tiles are tagged in parallel, so each call gets its own file
+        let temp_path = std::env::temp_dir().join(format!("xmp_{}.{}", uuid7::uuid7(), extension));
         std::fs::write(&temp_path, data)?;
         
         let xmp = XmpMetadata::new_from_path(&temp_path)?;
@@ -213,6 +971,15 @@ impl ImageProcessor {
         xmp.set_tag_string("Xmp.dc.created", &metadata.created_at.to_rfc3339())?;
         xmp.set_tag_string("Xmp.neural-reef.listingId", &metadata.listing_id.to_string())?;
         xmp.set_tag_string("Xmp.neural-reef.processingVersion", &metadata.processing_version)?;
+        xmp.set_tag_string("Xmp.neural-reef.preset", &metadata.enhancement_preset)?;
+        xmp.set_tag_string("Xmp.neural-reef.presetVersion", &metadata.preset_version.to_string())?;
+        xmp.set_tag_string("Xmp.neural-reef.sourceColorSpace", &format!("{:?}", metadata.source_color_space))?;
+        // Disclosure: anything beyond tone and geometry fixes is listed here
+        if !metadata.alterations.is_empty() {
+            let alterations: Vec<&str> = metadata.alterations.iter().map(String::as_str).collect();
+            xmp.set_tag_string("Xmp.neural-reef.altered", "True")?;
+            xmp.set_tag_multiple_strings("Xmp.neural-reef.alterations", &alterations)?;
+        }
         xmp.save_to_file(&temp_path)?;
         
         let final_data = std::fs::read(&temp_path)?;
@@ -221,79 +988,37 @@ impl ImageProcessor {
         Ok(final_data)
     }
 
-    fn optimize_image(&self, img: DynamicImage) -> Result<DynamicImage> {
-        let (width, height) = img.dimensions();
-        
-        // Check if resizing needed
-        if width > MAX_WIDTH || height > MAX_HEIGHT {
-            let ratio = f64::min(
-                MAX_WIDTH as f64 / width as f64,
-                MAX_HEIGHT as f64 / height as f64
-            );
-            let new_width = (width as f64 * ratio) as u32;
-            let new_height = (height as f64 * ratio) as u32;
-            
-            Ok(img.resize(new_width, new_height, image::imageops::FilterType::Lanczos3))
-        } else {
-            Ok(img)
-        }
-    }
-
-    fn convert_to_webp(&self, img: &DynamicImage, quality: f32) -> Result<Vec<u8>> {
-        let encoder = Encoder::from_image(img)
-            .map_err(|e| AppError::ImageError(ImageError::ConversionError(e.to_string())))?;
-            
-        let memory = encoder.encode(quality);
-        Ok(memory.to_vec())
-    }
-
-    // Add method to switch presets based on scene type
-    pub fn set_enhancement_preset(&mut self, preset: ImagePreset) {
-        self.enhancement_config = match preset {
-            ImagePreset::Interior => ImageEnhancementConfig::interior_preset(),
-            ImagePreset::Exterior => ImageEnhancementConfig::exterior_preset(),
-            ImagePreset::Twilight => ImageEnhancementConfig::twilight_preset(),
-            ImagePreset::Default => ImageEnhancementConfig::default(),
-        };
-    }
-
-    fn create_metadata(&self, listing_id: &ListingId, image_id: &ImageId, filename: &str, content_type: ContentType) -> Result<ImageMetadata> {
+    fn create_metadata(
+        &self,
+        listing_id: &ListingId,
+        image_id: &ImageId,
+        filename: &str,
+        recipe: &EditRecipe,
+        exif: Option<&ExifData>,
+        source_color_space: SourceColorSpace,
+    ) -> Result<ImageMetadata> {
         Ok(ImageMetadata {
             image_id: image_id.to_uuid7()?,
             listing_id: listing_id.clone(),
             filename: filename.to_string(),
-            content_type: content_type.clone(),
+            content_type: recipe.content_type,
             dimensions: (0, 0),
             file_size: 0,
             image_data: Vec::new(),
-            processing_version: "2.0".to_string(),
-            enhancement_preset: match content_type {
-                ContentType::LivingRoom | ContentType::Bedroom | ContentType::Kitchen | ContentType::Bathroom => "interior",
-                ContentType::Exterior | ContentType::View => "exterior",
-                _ => "default",
-            }.to_string(),
-            gps_coordinates: None, // Will be added later in the pipeline
+            processing_version: recipe.processing_version.clone(),
+            enhancement_preset: recipe.preset.clone(),
+            preset_version: recipe.preset_version,
+            alterations: recipe.alterations(),
+            source_color_space,
+            gps_coordinates: exif
+                .and_then(ExifData::good_gps)
+                .map(|fix| (fix.latitude, fix.longitude)),
             processing_status: ProcessingStatus::Pending,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         })
     }
 
-    fn get_enhancement_config(&self, content_type: &ContentType, time_of_day: Option<TimeOfDay>) -> ImageEnhancementConfig {
-        match (content_type, time_of_day) {
-            (ContentType::Exterior | ContentType::View, Some(TimeOfDay::Twilight)) => {
-                ImageEnhancementConfig::twilight_preset()
-            },
-            (ContentType::Exterior | ContentType::View, _) => {
-                ImageEnhancementConfig::exterior_preset()
-            },
-            (ContentType::LivingRoom | ContentType::Bedroom | ContentType::Kitchen | ContentType::Bathroom, _) => {
-                ImageEnhancementConfig::interior_preset()
-            },
-            _ => ImageEnhancementConfig::default(),
-        }
-    }
-
     async fn validate_and_extract_metadata(&self, data: &[u8]) -> Result<Option<XmpMetadata>> {
         if let Ok(xmp) = XmpMetadata::new_from_buffer(data) {
             // Validate existing metadata
@@ -308,7 +1033,7 @@ impl ImageProcessor {
     pub async fn process_batch(
         &self,
         config: BatchProcessingConfig,
-        data: Vec<(Vec<u8>, ContentType)>,
+        data: Vec<(Vec<u8>, Option<ContentType>)>,
     ) -> Result<Vec<ProcessedImage>> {
         info!(
             listing_id = %config.listing_id,
@@ -316,16 +1041,11 @@ impl ImageProcessor {
             "Processing image batch"
         );
 
-        // Validate dimensions
-        for (image_data, _) in &data {
-            let img = image::load_from_memory(image_data)?;
-            let (width, height) = img.dimensions();
-            if width < 1920 || height < 1080 || width > 3840 || height > 2160 {
-                return Err(AppError::ImageValidation(ImageValidationError::InvalidDimensions { 
-                    width, 
-                    height 
-                }));
-            }
+        // Validate dimensions up front so one bad file fails the batch before any work
+        for (image_data, content_type) in &data {
+            // Header only; the images are decoded one job at a time once memory is reserved
+            let probe = probe_image(image_data)?;
+            self.dimension_policy.precheck(*content_type, probe.width, probe.height)?;
         }
 
         // Process images with proper async handling
@@ -333,10 +1053,23 @@ impl ImageProcessor {
             .map(|(image_data, content_type)| self.process_image(
                 &config.listing_id,
                 image_data,
-                content_type
+                content_type,
+                config.agency_id.as_deref(),
+                config.listing_pin.as_ref(),
             ));
         
-        let processed = try_join_all(futures).await?;
+        let mut processed = Vec::new();
+        for outcome in try_join_all(futures).await? {
+            match outcome {
+                UploadOutcome::Processed(image) => processed.push(image),
+                UploadOutcome::Rejected(report) => {
+                    warn!(batch_id = %config.batch_id, summary = %report.summary(), "Upload rejected by the quality gate");
+                }
+                UploadOutcome::HeldForReview(upload) => {
+                    warn!(batch_id = %config.batch_id, content_type = ?upload.classification.content_type, "Upload looks like a document, skipped");
+                }
+            }
+        }
         self.update_batch_status(&config.batch_id, &processed)?;
         Ok(processed)
     }
@@ -374,63 +1107,15 @@ impl ImageProcessor {
         Ok(())
     }
 
-    // Add specialized real estate presets
-    pub fn get_room_specific_config(&self, content_type: &ContentType, analysis: &ImageAnalysis) -> ImageEnhancementConfig {
-        let mut config = match content_type {
-            ContentType::LivingRoom | ContentType::Bedroom => {
-                let mut config = ImageEnhancementConfig::interior_preset();
-                
-                // Fine-tune based on histogram analysis
-                if analysis.has_window {
-                    // Reduce window recovery if not severely overexposed
-                    config.window_recovery_strength = if analysis.is_overexposed { 1.5 } else { 1.2 };
-                    config.highlight_protection = if analysis.is_overexposed { 0.95 } else { 0.85 };
-                }
-                
-                if analysis.is_underexposed {
-                    // Progressive shadow recovery based on severity
-                    config.shadow_recovery = if analysis.is_yellow_cast { 0.4 } else { 0.6 };
-                    config.brightness_adjustment = if analysis.needs_sharpening { 12.0 } else { 15.0 };
-                }
-                
-                config
-            },
-            ContentType::Kitchen | ContentType::Bathroom => {
-                let mut config = ImageEnhancementConfig::interior_preset();
-                // Enhance reflective surfaces
-                config.sharpening_threshold = 0.4;
-                config.highlight_protection = 0.92;
-                config.white_balance_temp = -0.1; // Cooler for cleaner look
-                config
-            },
-            ContentType::Exterior => {
-                let mut config = ImageEnhancementConfig::exterior_preset();
-                if analysis.has_sky {
-                    config.exterior_sky_enhancement = 1.4;
-                    config.highlight_protection = 0.85;
-                }
-                if analysis.is_twilight {
-                    config = ImageEnhancementConfig::twilight_preset();
-                }
-                config
-            },
-            _ => ImageEnhancementConfig::default(),
-        };
-
-        // Apply global fixes for common real estate photo issues
-        if analysis.is_underexposed {
-            config.brightness_adjustment += 10.0;
-            config.shadow_recovery += 0.2;
-        }
-        if analysis.is_overexposed {
-            config.highlight_protection += 0.1;
-            config.brightness_adjustment -= 5.0;
-        }
-        if analysis.is_yellow_cast {
-            config.white_balance_temp -= 0.15;
-        }
-
-        config
+    // Presets and their analysis-driven tweaks live in config/presets.toml
+    pub fn get_room_specific_config(
+        &self,
+        content_type: &ContentType,
+        analysis: &ImageAnalysis,
+        time_of_day: TimeOfDay,
+        agency_id: Option<&str>,
+    ) -> Result<SelectedPreset> {
+        self.presets.select(*content_type, time_of_day, agency_id, |flag| analysis.has(flag))
     }
 
     // Add these helper methods
@@ -455,12 +1140,6 @@ impl ImageProcessor {
         let has_sky = self.detect_sky_region(img)? > 0.15 && 
                      stats.light_fraction > 0.2;
         
-        // More sophisticated twilight detection
-        let is_twilight = stats.mean < 100.0 && 
-                         stats.window_probability > 0.15 &&
-                         stats.shadow_detail < 0.3 &&
-                         self.detect_twilight_conditions(img)?;
-
         // Enhanced sharpening detection using edge analysis
         let edges = detect_edges(img);
         let avg_edge_strength = edges.pixels()
@@ -469,11 +1148,8 @@ impl ImageProcessor {
         let needs_sharpening = avg_edge_strength < 30.0 || stats.std_dev < 20.0;
 
         // Perspective correction check
-        let needs_perspective_correction = if let Ok(lines) = detect_architectural_lines(img) {
-            !lines.is_empty() && lines.iter().any(|l| (l.angle_degrees() - 90.0).abs() > 2.0)
-        } else {
-            false
-        };
+        let needs_perspective_correction = estimate_verticals(img)
+            .is_some_and(|estimate| estimate.needs_correction());
 
         Ok(ImageAnalysis {
             is_underexposed,
@@ -481,7 +1157,6 @@ impl ImageProcessor {
             is_yellow_cast,
             has_window,
             has_sky,
-            is_twilight,
             needs_perspective_correction,
             needs_sharpening,
         })
@@ -499,16 +1174,7 @@ impl ImageProcessor {
     }
 
     fn detect_edges(&self, img: &DynamicImage) -> Result<DynamicImage> {
-        let gray = img.to_luma8();
-        let sobel = imageproc::gradients::sobel_gradients(&gray);
-        
-        // Convert 16-bit to 8-bit by scaling
-        let converted = ImageBuffer::from_fn(sobel.width(), sobel.height(), |x, y| {
-            let pixel = sobel.get_pixel(x, y)[0];
-            Luma([((pixel as f32) / 256.0) as u8])
-        });
-        
-        Ok(DynamicImage::ImageLuma8(converted))
+        Ok(DynamicImage::ImageLuma8(gradient_edges(img)))
     }
 
     fn find_vertical_lines(&self, edges: &DynamicImage) -> Result<Vec<Line>> {
@@ -549,97 +1215,9 @@ impl ImageProcessor {
         Ok(max_convergence / std::f32::consts::PI)
     }
 
-    fn apply_smart_sharpening(&self, img: ImageBuffer<Rgba<u8>, Vec<u8>>, config: &ImageEnhancementConfig) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
-        let kernel = [
-            [-1.0, -1.0, -1.0],
-            [-1.0,  9.0, -1.0],
-            [-1.0, -1.0, -1.0],
-        ];
-
-        let mut output = img.clone();
-        let (width, height) = img.dimensions();
-
-        for y in 1..height-1 {
-            for x in 1..width-1 {
-                let mut new_pixel = [0.0; 4];
-                
-                // Apply convolution with edge detection
-                for (ky, krow) in kernel.iter().enumerate() {
-                    for (kx, &k) in krow.iter().enumerate() {
-                        let px = img.get_pixel(x + kx as u32 - 1, y + ky as u32 - 1);
-                        for c in 0..3 {
-                            new_pixel[c] += k * px[c] as f32;
-                        }
-                    }
-                }
-
-                // Apply sharpening threshold
-                let pixel = output.get_pixel_mut(x, y);
-                for c in 0..3 {
-                    let mut val = new_pixel[c].max(0.0).min(255.0) as u8;
-                    let diff = (val as i32 - pixel[c] as i32).abs();
-                    if diff < config.sharpening_threshold as i32 {
-                        val = pixel[c];
-                    }
-                    pixel[c] = val;
-                }
-                pixel[3] = 255; // Preserve alpha
-            }
-        }
-
-        Ok(output)
-    }
-
-    fn enhance_architectural_details(&self, img: ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
-        let edges = self.detect_edges(&DynamicImage::ImageRgba8(img.clone()))?;
-        let mut enhanced = img.clone();
-
-        // Enhance edges while preserving architectural details
-        for (x, y, pixel) in enhanced.enumerate_pixels_mut() {
-            let edge_strength = edges.get_pixel(x, y)[0] as f32 / 255.0;
-            if edge_strength > 0.1 {
-                // Enhance contrast along edges
-                for c in 0..3 {
-                    let val = pixel[c] as f32;
-                    pixel[c] = (val * (1.0 + edge_strength * 0.3)).min(255.0) as u8;
-                }
-            }
-        }
-
-        Ok(enhanced)
-    }
-
-    fn correct_perspective(&self, img: ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
-        let edges = self.detect_edges(&DynamicImage::ImageRgba8(img.clone()))?;
-        let lines = self.find_vertical_lines(&edges)?;
-        
-        if lines.is_empty() {
-            return Ok(img);
-        }
-
-        let avg_angle = lines.iter()
-            .map(|line| line.theta)
-            .sum::<f32>() / lines.len() as f32;
-
-        // Create affine transformation matrix
-        let transform = Projection::from_matrix([
-            avg_angle.cos(), -avg_angle.sin(), 0.0,
-            avg_angle.sin(), avg_angle.cos(), 0.0,
-            0.0, 0.0, 1.0
-        ]).ok_or_else(|| AppError::ImageProcessing("Failed to create projection matrix".into()))?;
-
-        // Apply transformation
-        Ok(warp(
-            &img,
-            &transform,
-            Interpolation::Bilinear,
-            Rgba([0, 0, 0, 0])
-        ))
-    }
-
     // Add these new methods
     fn analyze_channels(&self, img: &DynamicImage) -> Result<ChannelAnalysis> {
-        let rgb = img.to_rgb8();
+        let rgb = rgb_view(img);
         let mut r_hist = vec![0u32; 256];
         let mut g_hist = vec![0u32; 256];
         let mut b_hist = vec![0u32; 256];
@@ -766,7 +1344,7 @@ impl ImageProcessor {
     }
 
     fn detect_window_regions(&self, img: &DynamicImage) -> Result<Vec<WindowRegion>> {
-        let rgb = img.to_rgb8();
+        let rgb = rgb_view(img);
         let mut windows = Vec::new();
         let (width, height) = rgb.dimensions();
         let block_size = 32;  // Analysis block size
@@ -843,7 +1421,7 @@ impl ImageProcessor {
     }
 
     fn analyze_interior_lighting(&self, img: &DynamicImage) -> Result<InteriorLighting> {
-        let rgb = img.to_rgb8();
+        let rgb = rgb_view(img);
         let light_sources = Vec::new();
         let mut ambient_level = 0.0;
         let mut samples = 0;
@@ -896,7 +1474,7 @@ impl ImageProcessor {
     }
 
     fn detect_color_temperature(&self, img: &DynamicImage) -> Result<ColorTemperature> {
-        let rgb = img.to_rgb8();
+        let rgb = rgb_view(img);
         let mut r_sum = 0.0;
         let mut g_sum = 0.0;
         let mut b_sum = 0.0;
@@ -918,6 +1496,17 @@ impl ImageProcessor {
         })
     }
 
+    fn measure_look(&self, img: &DynamicImage, memory: &JobMemory) -> Result<LookStats> {
+        // RGB and luma copies for the two measurements
+        let _copies = memory.charge(img.width() as usize * img.height() as usize * 4)?;
+        let temperature = self.detect_color_temperature(img)?;
+        let brightness = self.calculate_room_brightness(img)?;
+        Ok(LookStats {
+            temperature: temperature.temperature_offset,
+            brightness: brightness.overall_brightness,
+        })
+    }
+
     fn calculate_room_brightness(&self, img: &DynamicImage) -> Result<RoomBrightness> {
         let gray = img.to_luma8();
         let mut histogram = vec![0u32; 256];
@@ -985,7 +1574,7 @@ impl ImageProcessor {
     }
 
     fn detect_sky_region(&self, img: &DynamicImage) -> Result<f32> {
-        let rgb = img.to_rgb8();
+        let rgb = rgb_view(img);
         let height = img.height() as f32;
         let width = img.width() as f32;
         let mut sky_pixels = 0;
@@ -994,7 +1583,7 @@ impl ImageProcessor {
         for y in 0..(height as u32 / 3) {
             for x in 0..img.width() {
                 let pixel = rgb.get_pixel(x, y);
-                if self.is_sky_color(pixel[0], pixel[1], pixel[2]) {
+                if is_sky_color(pixel[0], pixel[1], pixel[2]) {
                     sky_pixels += 1;
                 }
             }
@@ -1089,7 +1678,7 @@ impl ImageProcessor {
     }
 
     fn detect_color_cast(&self, img: &DynamicImage) -> Result<f32> {
-        let rgb = img.to_rgb8();
+        let rgb = rgb_view(img);
         let mut r_sum = 0.0;
         let mut g_sum = 0.0;
         let mut b_sum = 0.0;
@@ -1110,8 +1699,32 @@ impl ImageProcessor {
         Ok(((r_sum + g_sum) / 2.0 - b_sum).max(0.0))
     }
 
+    // Day, twilight or night for preset selection. The sun's elevation at the EXIF
+    // capture time comes from the photo's own GPS, or the listing pin without one.
+    fn classify_time_of_day(
+        &self,
+        img: &DynamicImage,
+        content_type: ContentType,
+        exif: Option<&ExifData>,
+        listing_pin: Option<&GpsCoordinates>,
+    ) -> Result<TimeOfDayClassification> {
+        let stats = get_histogram_statistics(&analyze_histogram(img));
+        let signals = ImageSignals {
+            mean_luma: stats.mean,
+            lit_windows: stats.window_probability,
+            blue_hour: self.detect_twilight_conditions(img)?,
+            sky: sky_signals(img),
+        };
+        let location = exif
+            .and_then(ExifData::good_gps)
+            .map(|fix| fix.to_coordinates())
+            .or_else(|| listing_pin.cloned());
+        let sun = exif.zip(location).and_then(|(exif, location)| capture_sun(exif, &location));
+        Ok(classify_time_of_day(sun, &signals, content_type))
+    }
+
     fn detect_twilight_conditions(&self, img: &DynamicImage) -> Result<bool> {
-        let rgb = img.to_rgb8();
+        let rgb = rgb_view(img);
         let mut blue_dominance = 0.0;
         let total_pixels = (rgb.width() * rgb.height()) as f32;
 
@@ -1141,23 +1754,6 @@ impl ImageProcessor {
         Ok(convergence > 0.05)  // 5% threshold for perspective distortion
     }
 
-    fn is_sky_color(&self, r: u8, g: u8, b: u8) -> bool {
-        let (r, g, b) = (r as f32, g as f32, b as f32);
-        
-        // Check for blue sky
-        let is_blue = b > r && b > g && b > 100.0;
-        
-        // Check for white/gray clouds
-        let is_cloud = (r + g + b) / 3.0 > 200.0 && 
-            (r - g).abs() < 20.0 && 
-            (r - b).abs() < 20.0;
-            
-        // Check for sunset colors
-        let is_sunset = r > 180.0 && g > 100.0 && b < 150.0;
-        
-        is_blue || is_cloud || is_sunset
-    }
-
     fn balance_window_exposure(&self, img: &mut DynamicImage, window: &WindowRegion) -> Result<()> {
         let mut buffer = img.to_rgba8();
         
@@ -1237,9 +1833,210 @@ pub struct ProcessedImage {
     pub content_type: ContentType,
     pub size: i64,
     pub data: Vec<u8>,
+    // Watermarked when enabled and always scrubbed per the metadata policy
+    pub published_data: Vec<u8>,
+    pub watermarked: bool,
+    pub renditions: Vec<Rendition>,
     pub width: u32,
     pub height: u32,
     pub quality_analysis: QualityAnalysis,
+    // Issues and recommendations for the photographer, with the gate's decision
+    pub quality: QualityReport,
+    // Colour temperature and exposure of the output, matched across the listing
+    pub look: LookStats,
+    pub exif: Option<ExifData>,
+    // Unscrubbed upload metadata, only ever stored on the private master record
+    pub source_metadata: BTreeMap<String, String>,
+    // Profile the upload was converted from; every output is sRGB
+    pub source_color_space: SourceColorSpace,
+    pub perceptual_hash: u64,
+    // Source frames when the master was fused from an exposure bracket
+    pub bracket_frames: Vec<BracketFrame>,
+    pub lens_correction: Option<AppliedLensCorrection>,
+    pub perspective_correction: Option<PerspectiveCorrection>,
+    pub sky_replacement: Option<SkyReplacement>,
+    pub panorama: Option<PanoramaTiles>,
+    pub floor_plan: Option<FloorPlanNormalization>,
+    // Traced outline when the recipe asks for one
+    #[serde(skip)]
+    pub floor_plan_svg: Option<String>,
+    // Untouched upload (or the fused bracket), kept privately for re-rendering
+    #[serde(skip)]
+    pub original: Vec<u8>,
+    pub recipe: EditRecipe,
+    // How the content type was chosen; None on re-renders, which keep the stored one
+    pub classification: Option<ContentClassification>,
+    // Picked the preset; None on recipe re-renders, which keep the stored one
+    pub time_of_day: Option<TimeOfDayClassification>,
+}
+
+struct Enhanced {
+    image: DynamicImage,
+    lens_correction: Option<AppliedLensCorrection>,
+    perspective_correction: Option<PerspectiveCorrection>,
+    sky_replacement: Option<SkyReplacement>,
+    floor_plan: Option<FloorPlanNormalization>,
+    floor_plan_svg: Option<String>,
+}
+
+fn apply_crop<P: image::Pixel + 'static>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    crop: CropRect,
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+    let (width, height) = img.dimensions();
+    if crop.x + crop.width > width || crop.y + crop.height > height {
+        return Err(AppError::Validation(format!(
+            "Crop {}x{}+{}+{} exceeds the {}x{} image", crop.width, crop.height, crop.x, crop.y, width, height
+        )));
+    }
+    Ok(image::imageops::crop_imm(img, crop.x, crop.y, crop.width, crop.height).to_image())
+}
+
+// The banded enhancement stages. Free functions so the stage benchmarks can run
+// them without a processor; each runs its bands in parallel.
+
+// Per-pixel colour grade for the recipe's content type
+pub fn grade(work: &mut WorkImage, recipe: &EditRecipe) {
+    let content_type = recipe.content_type;
+    let config = &recipe.enhancement;
+    let listing_look = recipe.listing_look;
+
+    for_each_band(work, |_, rows| {
+        for pixel in rows.chunks_exact_mut(4) {
+            let mut rgb = ColorRgb {
+                r: pixel[0],
+                g: pixel[1],
+                b: pixel[2],
+            };
+
+            // Apply room-specific color enhancements
+            match content_type {
+                ContentType::Kitchen | ContentType::Bathroom => {
+                    // Enhance whites and reduce yellow cast
+                    rgb.adjust_white_balance(-0.1, 0.0);
+                },
+                ContentType::LivingRoom | ContentType::Bedroom => {
+                    // Warmer, more inviting tones
+                    rgb.adjust_white_balance(0.05, 0.0);
+                },
+                ContentType::Exterior if recipe.twilight_grade => {
+                    // Enhance blue hour colors
+                    rgb.adjust_white_balance(-0.15, 0.0);
+                    rgb.adjust_saturation(1.2);
+                },
+                _ => {}
+            }
+
+            // Apply global enhancements
+            rgb.adjust_contrast(config.contrast_boost);
+            rgb.adjust_saturation(config.color_enhancement_strength);
+            rgb.adjust_shadows(config.shadow_recovery);
+            rgb.adjust_highlights(-config.highlight_protection);
+
+            // Nudge toward the listing's other photos of this content type
+            if let Some(look) = listing_look {
+                rgb.adjust_white_balance(look.temperature, 0.0);
+                rgb.adjust_brightness(look.brightness);
+            }
+
+            pixel[0] = rgb.r;
+            pixel[1] = rgb.g;
+            pixel[2] = rgb.b;
+        }
+    });
+}
+
+// Share of the above-threshold detail added back by the unsharp mask
+const SHARPEN_AMOUNT: f32 = 0.6;
+
+// Unsharp mask on luminance against a 3x3 binomial blur. Detail up to the threshold
+// is left alone and only the excess is boosted, so the one-level steps of a smooth
+// sky or wall stay steps instead of turning into dark and bright lines, and fine
+// texture isn't pushed into pixel noise. Luminance only, so edges don't pick up colour.
+pub fn apply_smart_sharpening(img: &mut WorkImage, config: &ImageEnhancementConfig, memory: &JobMemory) -> Result<()> {
+    let (width, height) = img.dimensions();
+    let stride = width as usize * 4;
+    let threshold = config.sharpening_threshold;
+
+    // The blur reads one row either side, from the unsharpened band tile
+    for_each_band_with_halo(img, 1, memory, |band, rows| {
+        let luma = |x: u32, y: u32| {
+            let px = band.tile.get_pixel(x, y);
+            0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32
+        };
+
+        for (row, output) in rows.chunks_exact_mut(stride).enumerate() {
+            let y = band.y + row as u32;
+            if y == 0 || y + 1 >= height {
+                continue;
+            }
+            let ty = band.top + row as u32;
+
+            for x in 1..width.saturating_sub(1) {
+                let mut blurred = 0.0;
+                for (dy, wy) in [1.0, 2.0, 1.0].into_iter().enumerate() {
+                    for (dx, wx) in [1.0, 2.0, 1.0].into_iter().enumerate() {
+                        blurred += wy * wx * luma(x + dx as u32 - 1, ty + dy as u32 - 1);
+                    }
+                }
+                let detail = luma(x, ty) - blurred / 16.0;
+                let boost = SHARPEN_AMOUNT * (detail - detail.clamp(-threshold, threshold));
+                if boost == 0.0 {
+                    continue;
+                }
+
+                let pixel = &mut output[x as usize * 4..x as usize * 4 + 4];
+                for value in &mut pixel[..3] {
+                    *value = (*value as f32 + boost).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+pub fn enhance_architectural_details(img: &mut WorkImage, memory: &JobMemory) -> Result<()> {
+    let width = img.width();
+
+    // Sobel reads one row either side
+    for_each_band_with_halo(img, 1, memory, |band, rows| {
+        let top = band.top;
+        let edges = gradient_edges(&DynamicImage::ImageRgba8(band.tile));
+
+        // Enhance edges while preserving architectural details
+        for (i, pixel) in rows.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i as u32 % width, top + i as u32 / width);
+            let edge_strength = edges.get_pixel(x, y)[0] as f32 / 255.0;
+            if edge_strength > 0.1 {
+                // Enhance contrast along edges
+                for c in 0..3 {
+                    let val = pixel[c] as f32;
+                    pixel[c] = (val * (1.0 + edge_strength * 0.3)).min(255.0) as u8;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+// Sobel magnitude scaled down to 8 bits
+fn gradient_edges(img: &DynamicImage) -> GrayImage {
+    let gray = img.to_luma8();
+    let sobel = imageproc::gradients::sobel_gradients(&gray);
+
+    // Convert 16-bit to 8-bit by scaling
+    ImageBuffer::from_fn(sobel.width(), sobel.height(), |x, y| {
+        let pixel = sobel.get_pixel(x, y)[0];
+        Luma([((pixel as f32) / 256.0) as u8])
+    })
+}
+
+struct DecodedUpload {
+    img: DynamicImage,
+    original: Vec<u8>,
+    exif: Option<ExifData>,
+    source_metadata: BTreeMap<String, String>,
+    color_space: SourceColorSpace,
 }
 
 #[derive(Debug, Clone)]
@@ -1253,26 +2050,37 @@ pub struct ImageMetadata {
     pub image_data: Vec<u8>,
     pub processing_version: String,
     pub enhancement_preset: String,
+    pub preset_version: u32,
+    // Content changes a buyer should be told about, e.g. "sky_replacement"
+    pub alterations: Vec<String>,
+    pub source_color_space: SourceColorSpace,
     pub gps_coordinates: Option<(f64, f64)>,
     pub processing_status: ProcessingStatus,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TimeOfDay {
     Day,
     Twilight,
     Night,
 }
 
+impl TimeOfDay {
+    pub const ALL: [TimeOfDay; 3] = [TimeOfDay::Day, TimeOfDay::Twilight, TimeOfDay::Night];
+}
+
 #[derive(Debug, Clone)]
 pub struct BatchProcessingConfig {
     pub listing_id: ListingId,
     pub batch_id: BatchId,
+    pub agency_id: Option<String>,
     pub room_groups: Vec<RoomGroup>,
-    pub quality: f32,  // WebP quality (0.9)
+    pub quality: f32,  // WebP quality, 0-100
     pub processing_version: String,
+    // Sunset times for photos without their own GPS
+    pub listing_pin: Option<GpsCoordinates>,
 }
 
 #[derive(Debug, Clone)]
@@ -1299,11 +2107,23 @@ struct ImageAnalysis {
     is_yellow_cast: bool,
     has_window: bool,
     has_sky: bool,
-    is_twilight: bool,
     needs_perspective_correction: bool,
     needs_sharpening: bool,
 }
 
+impl ImageAnalysis {
+    fn has(&self, flag: AnalysisFlag) -> bool {
+        match flag {
+            AnalysisFlag::HasWindow => self.has_window,
+            AnalysisFlag::HasSky => self.has_sky,
+            AnalysisFlag::IsUnderexposed => self.is_underexposed,
+            AnalysisFlag::IsOverexposed => self.is_overexposed,
+            AnalysisFlag::IsYellowCast => self.is_yellow_cast,
+            AnalysisFlag::NeedsSharpening => self.needs_sharpening,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Line {
     rho: f32,
@@ -1420,39 +2240,161 @@ impl ImageProcessor {
     pub async fn process_listing_batch(
         &self,
         batch_metadata: BatchMetadata,
-        images: Vec<(Vec<u8>, ContentType)>
-    ) -> Result<Vec<ProcessedImage>> {
+        files: Vec<UploadFile>,
+    ) -> Result<ListingBatch> {
         info!(
             listing_id = %batch_metadata.listing_id,
             "Starting batch processing pipeline"
         );
 
-        // Validate dimensions
-        for (image_data, _) in &images {
-            let img = image::load_from_memory(image_data)?;
-            let (width, height) = img.dimensions();
-            if width < 1920 || height < 1080 || width > 3840 || height > 2160 {
-                return Err(AppError::ImageValidation(ImageValidationError::InvalidDimensions { 
-                    width, 
-                    height 
-                }));
-            }
+        // Validate dimensions up front so one bad file fails the batch before any work
+        for file in &files {
+            // Header only; the images are decoded one job at a time once memory is reserved
+            let probe = probe_image(&file.data)?;
+            self.dimension_policy.precheck(file.content_type, probe.width, probe.height)?;
         }
 
         // Process images with proper async handling
-        let futures = images.into_iter()
-            .map(|(image_data, content_type)| self.process_image(
+        let (filenames, futures): (Vec<_>, Vec<_>) = files.into_iter()
+            .map(|file| (file.filename, self.process_image(
                 &batch_metadata.listing_id,
-                image_data,
-                content_type
-            ));
-        
-        let processed = try_join_all(futures).await?;
-        Ok(processed)
+                file.data,
+                file.content_type,
+                batch_metadata.agency_id.as_deref(),
+                batch_metadata.listing_pin.as_ref(),
+            )))
+            .unzip();
+
+        // Rejected and held files are reported on their own; only accepted ones are harmonized
+        let mut processed = Vec::new();
+        let mut rejected = Vec::new();
+        let mut held = Vec::new();
+        for (index, (filename, outcome)) in filenames.into_iter().zip(try_join_all(futures).await?).enumerate() {
+            match outcome {
+                UploadOutcome::Processed(image) => processed.push(image),
+                UploadOutcome::Rejected(report) => rejected.push(RejectedUpload { index, filename, report: *report }),
+                UploadOutcome::HeldForReview(upload) => held.push(HeldFile { index, filename, upload }),
+            }
+        }
+        let (images, look_adjustments) = self
+            .harmonize_listing(&batch_metadata.listing_id, processed, batch_metadata.agency_id.as_deref())
+            .await?;
+
+        let mut report = BatchQualityReport::new();
+        for image in &images {
+            report.add_report(&image.quality, &format!("{:?}", image.content_type));
+        }
+        for adjustment in look_adjustments {
+            report.add_look_adjustment(adjustment);
+        }
+        Ok(ListingBatch { images, rejected, held, report })
+    }
+
+    // Each photo is enhanced on its own, so white balance and exposure drift across
+    // a gallery. Nudges every photo toward the median look of its content type and
+    // re-renders the ones that move; the nudge is kept in the recipe.
+    pub async fn harmonize_listing(
+        &self,
+        listing_id: &ListingId,
+        mut images: Vec<ProcessedImage>,
+        agency_id: Option<&str>,
+    ) -> Result<(Vec<ProcessedImage>, Vec<LookAdjustmentRecord>)> {
+        let targets = group_targets(images.iter().map(|image| (image.content_type, image.look)));
+        let nudges: Vec<_> = images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, image)| {
+                let target = *targets.get(&image.content_type)?;
+                adjustment_toward(image.look, target).map(|adjustment| (index, target, adjustment))
+            })
+            .collect();
+
+        let futures = nudges.iter().map(|&(index, _, adjustment)| self.rerender_look(&images[index], adjustment, agency_id));
+        let harmonized = try_join_all(futures).await?;
+
+        let mut records = Vec::with_capacity(nudges.len());
+        for ((index, target, adjustment), mut image) in nudges.into_iter().zip(harmonized) {
+            let before = &mut images[index];
+            records.push(LookAdjustmentRecord {
+                image_id: image.id.clone(),
+                content_type: image.content_type,
+                before: before.look,
+                target,
+                after: image.look,
+                adjustment,
+            });
+            // Re-renders don't know how the type or the time of day was chosen, or what frames were fused
+            image.classification = before.classification.take();
+            image.time_of_day = before.time_of_day.take();
+            image.bracket_frames = std::mem::take(&mut before.bracket_frames);
+            *before = image;
+        }
+
+        info!(listing_id = %listing_id, adjusted = records.len(), groups = targets.len(), "Harmonized listing look");
+        Ok((images, records))
     }
 }
 
-#[derive(Debug, Clone)]
+fn private_document_error(content_type: ContentType) -> AppError {
+    AppError::Validation(format!("{:?} is a private document; upload it through /documents instead", content_type))
+}
+
+// One file of an upload, as it was sent
+#[derive(Debug)]
+pub struct UploadFile {
+    pub filename: String,
+    pub data: Vec<u8>,
+    // Omit to have the file classified
+    pub content_type: Option<ContentType>,
+}
+
+#[derive(Debug)]
+pub enum UploadOutcome {
+    Processed(ProcessedImage),
+    // Turned away by the quality gate before enhancement; the report says why
+    Rejected(Box<QualityReport>),
+    // Classified as a document without the uploader saying so; nothing is rendered
+    HeldForReview(HeldUpload),
+}
+
+// An upload kept private until a reviewer confirms its type. The recipe is the one
+// a photo of that type would get, so the review can re-render it like any image.
+#[derive(Debug)]
+pub struct HeldUpload {
+    pub id: ImageId,
+    pub listing_id: ListingId,
+    pub original: Vec<u8>,
+    pub recipe: EditRecipe,
+    pub classification: ContentClassification,
+    pub quality: QualityReport,
+}
+
+// A held file of a batch, by its place in the upload
+#[derive(Debug)]
+pub struct HeldFile {
+    pub index: usize,
+    pub filename: String,
+    pub upload: HeldUpload,
+}
+
+// A file of a batch the quality gate turned away, by its place in the upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejectedUpload {
+    pub index: usize,
+    pub filename: String,
+    pub report: QualityReport,
+}
+
+// A processed listing batch and its report
+#[derive(Debug)]
+pub struct ListingBatch {
+    pub images: Vec<ProcessedImage>,
+    pub rejected: Vec<RejectedUpload>,
+    pub held: Vec<HeldFile>,
+    pub report: BatchQualityReport,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ImageEnhancementConfig {
     pub contrast_boost: f32,
     pub color_enhancement_strength: f32,
@@ -1466,60 +2408,29 @@ pub struct ImageEnhancementConfig {
 }
 
 impl ImageEnhancementConfig {
-    pub fn default() -> Self {
-        Self {
-            contrast_boost: 1.0,
-            color_enhancement_strength: 1.0,
-            shadow_recovery: 0.3,
-            highlight_protection: 0.2,
-            sharpening_threshold: 10.0,
-            brightness_adjustment: 0.0,
-            window_recovery_strength: 1.0,
-            white_balance_temp: 0.0,
-            exterior_sky_enhancement: 1.0,
-        }
-    }
-
-    pub fn interior_preset() -> Self {
-        Self {
-            contrast_boost: 1.1,
-            color_enhancement_strength: 1.1,
-            shadow_recovery: 0.4,
-            highlight_protection: 0.3,
-            sharpening_threshold: 10.0,
-            brightness_adjustment: 0.0,
-            window_recovery_strength: 1.5,
-            white_balance_temp: -0.05,
-            exterior_sky_enhancement: 1.0,
-        }
-    }
+    // Bounds for preset and hand-edited values
+    pub fn validate(&self) -> Result<()> {
+        let limits = [
+            ("contrast_boost", self.contrast_boost, 0.5, 2.0),
+            ("color_enhancement_strength", self.color_enhancement_strength, 0.0, 2.0),
+            ("shadow_recovery", self.shadow_recovery, 0.0, 1.0),
+            ("highlight_protection", self.highlight_protection, 0.0, 1.5),
+            ("sharpening_threshold", self.sharpening_threshold, 0.0, 50.0),
+            ("brightness_adjustment", self.brightness_adjustment, -50.0, 50.0),
+            ("window_recovery_strength", self.window_recovery_strength, 0.0, 3.0),
+            ("white_balance_temp", self.white_balance_temp, -1.0, 1.0),
+            ("exterior_sky_enhancement", self.exterior_sky_enhancement, 0.0, 2.0),
+        ];
 
-    pub fn exterior_preset() -> Self {
-        Self {
-            contrast_boost: 1.2,
-            color_enhancement_strength: 1.2,
-            shadow_recovery: 0.2,
-            highlight_protection: 0.4,
-            sharpening_threshold: 10.0,
-            brightness_adjustment: 0.0,
-            window_recovery_strength: 0.8,
-            white_balance_temp: 0.0,
-            exterior_sky_enhancement: 1.4,
+        for (name, value, min, max) in limits {
+            if !(min..=max).contains(&value) {
+                return Err(AppError::Validation(format!(
+                    "enhancement.{} must be between {} and {}, got {}", name, min, max, value
+                )));
+            }
         }
-    }
 
-    pub fn twilight_preset() -> Self {
-        Self {
-            contrast_boost: 1.3,
-            color_enhancement_strength: 1.3,
-            shadow_recovery: 0.5,
-            highlight_protection: 0.2,
-            sharpening_threshold: 10.0,
-            brightness_adjustment: 0.0,
-            window_recovery_strength: 1.2,
-            white_balance_temp: -0.1,
-            exterior_sky_enhancement: 1.2,
-        }
+        Ok(())
     }
 }
 
@@ -1527,9 +2438,157 @@ impl ImageEnhancementConfig {
 pub struct BatchMetadata {
     pub listing_id: ListingId,
     pub batch_id: BatchId,
+    pub agency_id: Option<String>,
     pub room_groups: Vec<RoomGroup>,
     pub quality: f32,  // WebP quality (0.9)
     pub processing_version: String,
+    // Sunset times for photos without their own GPS
+    pub listing_pin: Option<GpsCoordinates>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use crate::backend::image_processor::test_fixtures::noisy;
+
+    fn dim_blurry_room() -> Vec<u8> {
+        // A dim room with nothing in focus
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(1920, 1080, |_, y| {
+            let wall = if y < 700 { 60 } else { 30 };
+            Rgb([wall, wall, wall / 2])
+        }));
+        let mut data = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Jpeg).unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_blurry_dark_upload_is_rejected() {
+        let processor = ImageProcessor::with_defaults().unwrap();
+        let result = processor.process_image(&ListingId::generate(), dim_blurry_room(), Some(ContentType::LivingRoom), None, None).await;
+        let Ok(UploadOutcome::Rejected(report)) = result else {
+            panic!("expected a rejection, got {:?}", result.map(|_| "a processed image"));
+        };
+        assert_eq!(report.decision, QualityDecision::Reject);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_file_does_not_fail_the_batch() {
+        let processor = ImageProcessor::with_defaults().unwrap();
+        let mut sharp = Vec::new();
+        noisy(1920, 1080).write_to(&mut std::io::Cursor::new(&mut sharp), ImageFormat::Jpeg).unwrap();
+
+        let now = Utc::now();
+        let metadata = BatchMetadata {
+            listing_id: ListingId::generate(),
+            batch_id: BatchId::generate(),
+            agency_id: None,
+            room_groups: Vec::new(),
+            quality: 0.9,
+            processing_version: PROCESSING_VERSION.to_string(),
+            listing_pin: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let files = vec![
+            UploadFile { filename: "sharp.jpg".into(), data: sharp, content_type: Some(ContentType::LivingRoom) },
+            UploadFile { filename: "dim.jpg".into(), data: dim_blurry_room(), content_type: Some(ContentType::LivingRoom) },
+        ];
+
+        let batch = processor.process_listing_batch(metadata, files).await.unwrap();
+        assert_eq!(batch.images.len(), 1);
+        assert_eq!(batch.rejected.len(), 1);
+        assert_eq!((batch.rejected[0].index, batch.rejected[0].filename.as_str()), (1, "dim.jpg"));
+        assert_eq!(batch.rejected[0].report.decision, QualityDecision::Reject);
+    }
+
+    #[tokio::test]
+    async fn test_guessed_documents_are_held_and_declared_ones_refused() {
+        let processor = ImageProcessor::with_defaults().unwrap();
+        // Lines of short "words" on white paper, which the local signals can only guess at
+        let page = DynamicImage::ImageRgb8(RgbImage::from_fn(1920, 1080, |x, y| {
+            let ink = (100..1820).contains(&x) && (80..1000).contains(&y) && y % 40 < 10 && (x / 6) % 7 < 5;
+            if ink { Rgb([20, 20, 20]) } else { Rgb([250, 250, 248]) }
+        }));
+        let mut data = Vec::new();
+        page.write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Jpeg).unwrap();
+        let listing_id = ListingId::generate();
+
+        let guessed = processor.process_image(&listing_id, data.clone(), None, None, None).await;
+        let Ok(UploadOutcome::HeldForReview(held)) = guessed else {
+            panic!("expected the page to be held, got {:?}", guessed.map(|_| "another outcome"));
+        };
+        assert_eq!(held.classification.source, ClassificationSource::Heuristic);
+        assert!(held.classification.content_type.is_document());
+        assert_eq!(held.original, data);
+
+        let declared = processor.process_image(&listing_id, data, Some(ContentType::TitlePaper), None, None).await;
+        let Err(AppError::Validation(message)) = declared else {
+            panic!("expected the declared deed to be refused, got {:?}", declared.map(|_| "an outcome"));
+        };
+        assert!(message.contains("/documents"), "{}", message);
+    }
+
+    #[test]
+    fn test_sharpening_boosts_edges_but_not_gradient_steps() {
+        let config = ImageEnhancementConfig {
+            contrast_boost: 1.0,
+            color_enhancement_strength: 1.0,
+            shadow_recovery: 0.0,
+            highlight_protection: 0.0,
+            sharpening_threshold: 10.0,
+            brightness_adjustment: 0.0,
+            window_recovery_strength: 0.0,
+            white_balance_temp: 0.0,
+            exterior_sky_enhancement: 0.0,
+        };
+        // A sky that brightens four levels every 8 rows, as a JPEG gradient does, and
+        // a hard wall edge at x = 100
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(200, 120, |x, y| {
+            let sky = 150 + 4 * (y / 8) as u8;
+            if x < 100 { Rgb([sky, sky, sky + 40]) } else { Rgb([60, 50, 40]) }
+        }));
+        let memory = JobMemory::unbounded();
+        let mut work = to_work_image(&img, &memory).unwrap();
+        apply_smart_sharpening(&mut work, &config, &memory).unwrap();
+        let sharpened = into_dynamic(work).to_rgb8();
+        let original = img.to_rgb8();
+
+        for y in 1..119 {
+            assert_eq!(sharpened.get_pixel(50, y), original.get_pixel(50, y), "sky changed at row {}", y);
+        }
+        assert!(sharpened.get_pixel(99, 60)[0] > original.get_pixel(99, 60)[0]);
+        assert!(sharpened.get_pixel(100, 60)[0] < original.get_pixel(100, 60)[0]);
+    }
+
+    #[tokio::test]
+    async fn test_harmonized_images_keep_their_time_of_day() {
+        let mut processor = ImageProcessor::with_defaults().unwrap();
+        // The reference photos are under the default minimum size, and too smooth
+        // for the blur check
+        let mut policy = DimensionPolicyConfig::default();
+        policy.default.min_long_edge = 960;
+        policy.default.min_short_edge = 640;
+        processor.dimension_policy = Arc::new(DimensionPolicy::new(policy).unwrap());
+        processor.quality_gate = QualityGate::new(QualityGateConfig { enabled: false, ..QualityGateConfig::default() }).unwrap();
+
+        let listing_id = ListingId::generate();
+        // Three rooms shot in different light, all tagged as one type so they share a target
+        let mut images = Vec::new();
+        for name in ["living_room", "bedroom", "kitchen"] {
+            let data = std::fs::read(format!("tests/golden/inputs/{}.jpg", name)).unwrap();
+            match processor.process_image(&listing_id, data, Some(ContentType::LivingRoom), None, None).await.unwrap() {
+                UploadOutcome::Processed(image) => images.push(image),
+                UploadOutcome::Rejected(report) => panic!("{} was rejected: {}", name, report.summary()),
+                UploadOutcome::HeldForReview(held) => panic!("{} was held as {:?}", name, held.classification.content_type),
+            }
+        }
+
+        let (images, records) = processor.harmonize_listing(&listing_id, images, None).await.unwrap();
+        assert!(!records.is_empty());
+        assert!(images.iter().all(|image| image.time_of_day.is_some()));
+    }
+}
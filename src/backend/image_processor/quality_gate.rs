@@ -0,0 +1,163 @@
+use tracing::info;
+
+use crate::backend::common::{
+    config::QualityGateConfig,
+    error::error::Result,
+};
+use super::histogram::HistogramStats;
+use super::processor::ContentType;
+use super::quality_report::{IssueSeverity, QualityDecision, QualityReport};
+
+// Applies the per-agency thresholds to a quality report. Rejected uploads are not
+// published; the report goes back to the photographer so they can reshoot on site.
+pub struct QualityGate {
+    config: QualityGateConfig,
+}
+
+impl QualityGate {
+    pub fn new(config: QualityGateConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+
+    pub fn evaluate(
+        &self,
+        mut report: QualityReport,
+        luminance: &HistogramStats,
+        content_type: ContentType,
+        agency_id: Option<&str>,
+    ) -> QualityReport {
+        // Scans and plans don't have photographic quality to judge, and equirectangular
+        // projection bends every line, which the perspective and composition checks read as faults
+        if !self.config.enabled || !content_type.is_photo() || content_type == ContentType::Panorama {
+            return report;
+        }
+        let thresholds = self.config.thresholds_for(agency_id);
+
+        let mut rejections = Vec::new();
+        let mut warnings = Vec::new();
+
+        if report.overall_score < thresholds.min_quality_score {
+            rejections.push(format!(
+                "Quality score {:.2} is below the minimum of {:.2}", report.overall_score, thresholds.min_quality_score
+            ));
+        } else if report.overall_score < thresholds.warn_quality_score {
+            warnings.push(format!(
+                "Quality score {:.2} is below the recommended {:.2}", report.overall_score, thresholds.warn_quality_score
+            ));
+        }
+
+        for issue in &report.issues {
+            match issue.severity {
+                IssueSeverity::Critical if thresholds.reject_on_critical => rejections.push(issue.description.clone()),
+                IssueSeverity::Critical | IssueSeverity::Major => warnings.push(issue.description.clone()),
+                IssueSeverity::Minor => {}
+            }
+        }
+
+        if luminance.mean < thresholds.min_mean_brightness {
+            warnings.push(format!("Image is too dark (mean brightness {:.0})", luminance.mean));
+            report.recommendations.push("Turn on all lights and open the curtains, or shoot an exposure bracket".to_string());
+        } else if luminance.exposure_bias.abs() > thresholds.max_exposure_bias {
+            let direction = if luminance.exposure_bias > 0.0 { "over" } else { "under" };
+            warnings.push(format!("Image is {}exposed (bias {:+.2})", direction, luminance.exposure_bias));
+            report.recommendations.push(format!("Adjust exposure compensation to correct the {}exposure", direction));
+        }
+        if luminance.highlight_clipping > thresholds.max_highlight_clipping {
+            warnings.push(format!("{:.0}% of the highlights are clipped", luminance.highlight_clipping * 100.0));
+            report.recommendations.push("Expose for the brightest area or shoot an exposure bracket".to_string());
+        }
+
+        report.decision = if !rejections.is_empty() {
+            QualityDecision::Reject
+        } else if !warnings.is_empty() {
+            QualityDecision::AcceptWithWarning
+        } else {
+            QualityDecision::Accept
+        };
+        report.gate_reasons = rejections.into_iter().chain(warnings).collect();
+
+        if report.decision != QualityDecision::Accept {
+            info!(decision = ?report.decision, reasons = ?report.gate_reasons, agency_id, "Quality gate flagged upload");
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::common::config::QualityThresholds;
+    use crate::backend::image_processor::image_utils::QualityAnalysis;
+
+    fn analysis(is_blurry: bool) -> QualityAnalysis {
+        QualityAnalysis {
+            is_blurry,
+            has_perspective_issues: false,
+            has_poor_lighting: false,
+            window_overexposure: false,
+            noise_level: 0.0,
+            composition_score: 0.8,
+            vertical_alignment: 0.9,
+            room_depth_score: 0.8,
+            lighting_uniformity: 0.8,
+            color_balance: 0.9,
+            detail_preservation: 0.8,
+        }
+    }
+
+    fn luminance(mean: f32) -> HistogramStats {
+        HistogramStats {
+            mean,
+            median: mean as u8,
+            std_dev: 50.0,
+            peaks: Vec::new(),
+            total_pixels: 100,
+            dark_fraction: 0.1,
+            light_fraction: 0.1,
+            mid_fraction: 0.8,
+            contrast_ratio: 50.0,
+            exposure_bias: (mean - 127.0) / 127.0,
+            highlight_clipping: 0.0,
+            shadow_detail: 0.5,
+            window_probability: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_gate_rejects_warns_and_accepts() {
+        let gate = QualityGate::new(QualityGateConfig::default()).unwrap();
+        let evaluate = |blurry: bool, mean: f32, content_type: ContentType| {
+            gate.evaluate(QualityReport::from_analysis(&analysis(blurry)), &luminance(mean), content_type, None)
+        };
+
+        let blurry = evaluate(true, 120.0, ContentType::Kitchen);
+        assert_eq!(blurry.decision, QualityDecision::Reject);
+        assert!(!blurry.recommendations.is_empty());
+
+        let dark = evaluate(false, 35.0, ContentType::Bedroom);
+        assert_eq!(dark.decision, QualityDecision::AcceptWithWarning);
+        assert!(dark.gate_reasons[0].contains("too dark"), "{:?}", dark.gate_reasons);
+
+        assert_eq!(evaluate(false, 120.0, ContentType::LivingRoom).decision, QualityDecision::Accept);
+        // Documents pass through untouched
+        assert_eq!(evaluate(true, 35.0, ContentType::TitlePaper).decision, QualityDecision::Accept);
+    }
+
+    #[test]
+    fn test_agency_thresholds_override_default() {
+        let mut config = QualityGateConfig::default();
+        config.agencies.insert("strict".to_string(), QualityThresholds {
+            min_quality_score: 0.9,
+            warn_quality_score: 0.95,
+            ..QualityThresholds::default()
+        });
+        let gate = QualityGate::new(config).unwrap();
+
+        let report = || QualityReport::from_analysis(&analysis(false));
+        let default = gate.evaluate(report(), &luminance(120.0), ContentType::Exterior, Some("other"));
+        let strict = gate.evaluate(report(), &luminance(120.0), ContentType::Exterior, Some("strict"));
+        assert_eq!(default.decision, QualityDecision::Accept);
+        assert_eq!(strict.decision, QualityDecision::Reject);
+    }
+}
@@ -1,28 +1,42 @@
 use super::image_utils::QualityAnalysis;
-use serde::Serialize;
+use super::perspective::{PerspectiveAngles, PerspectiveCorrection};
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QualityReport {
     pub overall_score: f32,
     pub issues: Vec<QualityIssue>,
     pub recommendations: Vec<String>,
+    // What the pipeline straightened, if anything
+    pub perspective_correction: Option<PerspectiveCorrection>,
+    // Set by the quality gate; `gate_reasons` says what led to it
+    pub decision: QualityDecision,
+    pub gate_reasons: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityDecision {
+    Accept,
+    AcceptWithWarning,
+    Reject,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QualityIssue {
     pub severity: IssueSeverity,
     pub category: IssueCategory,
     pub description: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IssueSeverity {
     Critical,
     Major,
     Minor,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum IssueCategory {
     Blur,
     Perspective,
@@ -109,7 +123,40 @@ impl QualityReport {
             overall_score,
             issues,
             recommendations,
+            perspective_correction: None,
+            decision: QualityDecision::Accept,
+            gate_reasons: Vec::new(),
+        }
+    }
+
+    // Verticals fixed in post no longer need a reshoot, so the issue drops to minor
+    pub fn with_straightened_verticals(mut self, angles: Option<PerspectiveAngles>) -> Self {
+        if let Some(angles) = angles {
+            for issue in self.issues.iter_mut().filter(|i| matches!(i.category, IssueCategory::Perspective)) {
+                issue.severity = IssueSeverity::Minor;
+                issue.description = format!(
+                    "Vertical lines corrected automatically ({:.1}° roll, {:.1}° keystone)",
+                    angles.roll_degrees, angles.keystone_degrees
+                );
+            }
+        }
+        self
+    }
+
+    pub fn with_perspective_correction(self, correction: Option<PerspectiveCorrection>) -> Self {
+        let mut report = self.with_straightened_verticals(correction.as_ref().map(|c| c.angles));
+        report.perspective_correction = correction;
+        report
+    }
+
+    // One line for error messages and logs: why, then what to do about it
+    pub fn summary(&self) -> String {
+        let mut summary = self.gate_reasons.join("; ");
+        if !self.recommendations.is_empty() {
+            summary.push_str(". Try: ");
+            summary.push_str(&self.recommendations.join("; "));
         }
+        summary
     }
 }
 
@@ -0,0 +1,184 @@
+use std::io::Cursor;
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use pdfium_render::prelude::{Pdfium, PdfiumError, PdfPageObjectsCommon, PdfPagePaperSize, PdfPoints, PdfRenderConfig};
+use serde::{Serialize, Deserialize};
+use tracing::{info, instrument};
+
+use crate::backend::common::error::error::{Result, AppError};
+use super::exif_metadata::{read_exif, apply_orientation};
+
+const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+
+// PDF pages are redacted as images at this resolution; small print stays legible
+const PDF_RENDER_DPI: f32 = 200.0;
+const POINTS_PER_INCH: f32 = 72.0;
+
+// A rectangle to black out, as fractions of the upright page so it doesn't
+// depend on the size the page was shown at when it was drawn
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RedactionRegion {
+    // Zero-based; scans only have page 0
+    #[serde(default)]
+    pub page: u32,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl RedactionRegion {
+    pub fn validate(&self) -> Result<()> {
+        let fractions = [self.x, self.y, self.width, self.height];
+        if fractions.iter().any(|v| !v.is_finite() || !(0.0..=1.0).contains(v)) {
+            return Err(AppError::Validation("Redaction regions must be fractions of the page (0.0 - 1.0)".into()));
+        }
+        if self.width == 0.0 || self.height == 0.0 {
+            return Err(AppError::Validation("Redaction regions must have a non-zero size".into()));
+        }
+        if self.x + self.width > 1.0 + f32::EPSILON || self.y + self.height > 1.0 + f32::EPSILON {
+            return Err(AppError::Validation("Redaction regions must lie within the page".into()));
+        }
+        Ok(())
+    }
+
+    // Rounded outwards, so a region never leaves a sliver of what it covers
+    fn pixel_bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (w, h) = (width as f32, height as f32);
+        (
+            (self.x * w).floor() as u32,
+            (self.y * h).floor() as u32,
+            (((self.x + self.width) * w).ceil() as u32).min(width),
+            (((self.y + self.height) * h).ceil() as u32).min(height),
+        )
+    }
+}
+
+// Burns the regions into an upright copy of a scanned page and re-encodes it as
+// PNG. Nothing under a region survives, and the copy carries no metadata.
+#[instrument(skip(data, regions), fields(regions = regions.len()))]
+pub fn redact_scan(data: &[u8], regions: &[RedactionRegion]) -> Result<Vec<u8>> {
+    for region in regions {
+        region.validate()?;
+    }
+
+    let img = image::load_from_memory(data)?;
+    let img = match read_exif(data) {
+        Some(exif) => apply_orientation(img, exif.orientation),
+        None => img,
+    };
+    if let Some(region) = regions.iter().find(|r| r.page != 0) {
+        return Err(AppError::Validation(format!("A scan has one page, got a region on page {}", region.page + 1)));
+    }
+    let mut page = img.to_rgb8();
+    let (width, height) = page.dimensions();
+    black_out(&mut page, regions);
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(page).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    info!(width, height, regions = regions.len(), "Redacted scanned document");
+    Ok(png)
+}
+
+// Binds PDFium from `library_dir`, or the system library when unset. Bound once per
+// process: PDFium's library state is global, so instances must not come and go.
+pub fn bind_pdfium(library_dir: Option<&str>) -> Result<Pdfium> {
+    let bindings = match library_dir {
+        Some(dir) => Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(dir)),
+        None => Pdfium::bind_to_system_library(),
+    }
+    .map_err(|e| AppError::Configuration(format!("Failed to load PDFium for PDF redaction: {}", e)))?;
+    Ok(Pdfium::new(bindings))
+}
+
+// Rebuilds a PDF from images of its pages with the regions burned in. Text, links,
+// form fields, attachments and metadata are all left behind, so nothing under a
+// region can be selected, searched or extracted from the copy.
+#[instrument(skip(pdfium, data, regions), fields(regions = regions.len()))]
+pub fn redact_pdf(pdfium: &Pdfium, data: &[u8], regions: &[RedactionRegion]) -> Result<Vec<u8>> {
+    for region in regions {
+        region.validate()?;
+    }
+
+    let source = pdfium.load_pdf_from_byte_slice(data, None).map_err(pdf_error)?;
+    let pages = source.pages().len() as u32;
+    if let Some(region) = regions.iter().find(|r| r.page >= pages) {
+        return Err(AppError::Validation(format!(
+            "Redaction region on page {} but the document has {} pages", region.page + 1, pages
+        )));
+    }
+
+    let mut redacted = pdfium.create_new_pdf().map_err(pdf_error)?;
+    let render = PdfRenderConfig::new().scale_page_by_factor(PDF_RENDER_DPI / POINTS_PER_INCH);
+    for (index, page) in source.pages().iter().enumerate() {
+        // Rendered upright, so the regions land where the owner drew them
+        let mut image = page.render_with_config(&render).map_err(pdf_error)?.as_image().to_rgb8();
+        let on_page: Vec<RedactionRegion> = regions.iter().filter(|r| r.page == index as u32).copied().collect();
+        black_out(&mut image, &on_page);
+
+        let to_points = |pixels: u32| PdfPoints::new(pixels as f32 * POINTS_PER_INCH / PDF_RENDER_DPI);
+        let (width, height) = (to_points(image.width()), to_points(image.height()));
+        let mut copy = redacted.pages_mut()
+            .create_page_at_end(PdfPagePaperSize::Custom(width, height))
+            .map_err(pdf_error)?;
+        copy.objects_mut()
+            .create_image_object(PdfPoints::ZERO, PdfPoints::ZERO, &DynamicImage::ImageRgb8(image), Some(width), Some(height))
+            .map_err(pdf_error)?;
+    }
+
+    let pdf = redacted.save_to_bytes().map_err(pdf_error)?;
+    info!(pages, regions = regions.len(), "Redacted PDF document");
+    Ok(pdf)
+}
+
+fn black_out(page: &mut RgbImage, regions: &[RedactionRegion]) {
+    let (width, height) = page.dimensions();
+    for region in regions {
+        let (x0, y0, x1, y1) = region.pixel_bounds(width, height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                page.put_pixel(x, y, BLACK);
+            }
+        }
+    }
+}
+
+fn pdf_error(e: PdfiumError) -> AppError {
+    AppError::Validation(format!("Failed to redact the PDF: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn test_regions_are_blacked_out_and_nothing_else() {
+        let page = RgbImage::from_fn(200, 100, |x, y| Rgb([200, (x % 50) as u8 + 100, (y % 50) as u8 + 100]));
+        let mut scan = Vec::new();
+        DynamicImage::ImageRgb8(page.clone()).write_to(&mut Cursor::new(&mut scan), ImageFormat::Png).unwrap();
+
+        // The ID number sits in the top right quarter
+        let region = RedactionRegion { page: 0, x: 0.5, y: 0.0, width: 0.5, height: 0.5 };
+        let redacted = image::load_from_memory(&redact_scan(&scan, &[region]).unwrap()).unwrap().to_rgb8();
+
+        assert_eq!(redacted.dimensions(), (200, 100));
+        assert!(redact_scan(&scan, &[RedactionRegion { page: 1, ..region }]).is_err());
+        for (x, y, pixel) in redacted.enumerate_pixels() {
+            if x >= 100 && y < 50 {
+                assert_eq!(*pixel, BLACK, "({}, {}) not redacted", x, y);
+            } else {
+                assert_eq!(pixel, page.get_pixel(x, y), "({}, {}) changed", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_regions_are_rejected() {
+        let region = |x, y, width, height| RedactionRegion { page: 0, x, y, width, height };
+        assert!(region(0.1, 0.1, 0.5, 0.5).validate().is_ok());
+        assert!(region(0.6, 0.0, 0.5, 0.5).validate().is_err());
+        assert!(region(0.0, 0.0, 0.0, 0.5).validate().is_err());
+        assert!(region(-0.1, 0.0, 0.5, 0.5).validate().is_err());
+        assert!(region(f32::NAN, 0.0, 0.5, 0.5).validate().is_err());
+    }
+}
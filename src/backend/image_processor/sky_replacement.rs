@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+use image::{imageops::FilterType, GrayImage, Luma, RgbaImage};
+use imageproc::{
+    filter::gaussian_blur_f32,
+    gradients::{horizontal_sobel, vertical_sobel},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::backend::common::error::error::{Result, AppError};
+use super::processor::TimeOfDay;
+
+// Segmentation runs at this size; the mask is refined at full resolution
+const WORK_SIZE: u32 = 512;
+// Sobel magnitude above which a pixel is structure, not sky
+const MAX_SKY_GRADIENT: f32 = 120.0;
+// Less sky than this and a replacement would be a few patches between rooftops
+const MIN_SKY_FRACTION: f32 = 0.05;
+// How far the foreground moves towards the new sky's colour, and the cap on it
+const RELIGHT_STRENGTH: f32 = 0.3;
+const MAX_RELIGHT_GAIN: f32 = 0.15;
+
+pub struct SkyAsset {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub time_of_day: TimeOfDay,
+    data: &'static [u8],
+}
+
+pub const SKY_LIBRARY: &[SkyAsset] = &[
+    SkyAsset {
+        id: "clear_blue",
+        name: "Clear blue",
+        time_of_day: TimeOfDay::Day,
+        data: include_bytes!("../assets/skies/clear_blue.jpg"),
+    },
+    SkyAsset {
+        id: "scattered_clouds",
+        name: "Scattered clouds",
+        time_of_day: TimeOfDay::Day,
+        data: include_bytes!("../assets/skies/scattered_clouds.jpg"),
+    },
+    SkyAsset {
+        id: "golden_hour",
+        name: "Golden hour",
+        time_of_day: TimeOfDay::Twilight,
+        data: include_bytes!("../assets/skies/golden_hour.jpg"),
+    },
+];
+
+pub fn find_sky(id: &str) -> Result<&'static SkyAsset> {
+    SKY_LIBRARY
+        .iter()
+        .find(|sky| sky.id == id)
+        .ok_or_else(|| AppError::Validation(format!("Unknown sky '{}'", id)))
+}
+
+// What was swapped in, recorded on the image for disclosure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkyReplacement {
+    pub sky_id: String,
+    // Share of the image that is now the new sky
+    pub sky_fraction: f32,
+    // Per-channel gain applied to the foreground to match the new light
+    pub foreground_gain: [f32; 3],
+}
+
+pub fn is_sky_color(r: u8, g: u8, b: u8) -> bool {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+
+    // Check for blue sky
+    let is_blue = b > r && b > g && b > 100.0;
+
+    // Check for white/gray clouds
+    let is_cloud = (r + g + b) / 3.0 > 200.0 &&
+        (r - g).abs() < 20.0 &&
+        (r - b).abs() < 20.0;
+
+    // Check for sunset colors
+    let is_sunset = r > 180.0 && g > 100.0 && b < 150.0;
+
+    is_blue || is_cloud || is_sunset
+}
+
+// Overcast skies are duller than the cloud check above expects, but flat and grey
+fn is_overcast_color(r: u8, g: u8, b: u8) -> bool {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    (r as u16 + g as u16 + b as u16) / 3 > 150 && max - min < 25
+}
+
+// Soft sky mask, 255 = sky. Smooth sky-coloured pixels connected to the top edge
+// are sky; the blurred boundary is then pulled onto the real edge by checking
+// whether each pixel's colour sits nearer the sky or the foreground.
+pub fn sky_mask(img: &RgbaImage) -> GrayImage {
+    let (width, height) = img.dimensions();
+    // Downsampled straight from the borrowed image, so no full-size copy is made
+    let scale = WORK_SIZE as f64 / width.max(height) as f64;
+    let small = image::imageops::resize(
+        img,
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+        FilterType::Triangle,
+    );
+    let (sw, sh) = small.dimensions();
+
+    let gray = image::imageops::grayscale(&small);
+    let gx = horizontal_sobel(&gray);
+    let gy = vertical_sobel(&gray);
+    let candidate = |x: u32, y: u32| {
+        let p = small.get_pixel(x, y);
+        let gradient = (gx.get_pixel(x, y)[0] as f32).hypot(gy.get_pixel(x, y)[0] as f32);
+        gradient < MAX_SKY_GRADIENT && (is_sky_color(p[0], p[1], p[2]) || is_overcast_color(p[0], p[1], p[2]))
+    };
+
+    let mut coarse = GrayImage::new(sw, sh);
+    let mut queue: VecDeque<(u32, u32)> = (0..sw).filter(|&x| candidate(x, 0)).map(|x| (x, 0)).collect();
+    for &(x, y) in &queue {
+        coarse.put_pixel(x, y, Luma([255]));
+    }
+    while let Some((x, y)) = queue.pop_front() {
+        let neighbours = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        for (nx, ny) in neighbours {
+            if nx < sw && ny < sh && coarse.get_pixel(nx, ny)[0] == 0 && candidate(nx, ny) {
+                coarse.put_pixel(nx, ny, Luma([255]));
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    let coarse = gaussian_blur_f32(&coarse, 1.5);
+    let mut mask = image::imageops::resize(&coarse, width, height, FilterType::Triangle);
+    refine_edges(img, &mut mask);
+    mask
+}
+
+fn refine_edges(img: &RgbaImage, mask: &mut GrayImage) {
+    let (Some(sky), Some(foreground)) = (
+        mean_color(img, mask, |alpha| alpha >= 250),
+        mean_color(img, mask, |alpha| alpha <= 5),
+    ) else {
+        return;
+    };
+
+    let axis = [0, 1, 2].map(|c| sky[c] - foreground[c]);
+    let length_sq: f32 = axis.iter().map(|a| a * a).sum();
+    // Sky and foreground too alike for colour to say where the edge is
+    if length_sq < 400.0 {
+        return;
+    }
+
+    for (pixel, alpha) in img.pixels().zip(mask.pixels_mut()) {
+        if alpha[0] <= 5 || alpha[0] >= 250 {
+            continue;
+        }
+        let t: f32 = (0..3).map(|c| (pixel[c] as f32 - foreground[c]) * axis[c]).sum::<f32>() / length_sq;
+        alpha[0] = (t.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+}
+
+fn mean_color(img: &RgbaImage, mask: &GrayImage, select: impl Fn(u8) -> bool) -> Option<[f32; 3]> {
+    let mut sum = [0.0f64; 3];
+    let mut count = 0usize;
+    for (pixel, alpha) in img.pixels().zip(mask.pixels()) {
+        if select(alpha[0]) {
+            (0..3).for_each(|c| sum[c] += pixel[c] as f64);
+            count += 1;
+        }
+    }
+    (count > 0).then(|| sum.map(|s| (s / count as f64) as f32))
+}
+
+// Working buffers of `replace_sky` beside its input and output: the full-size
+// mask and the stretched sky, one and three bytes a pixel
+pub fn replacement_bytes(width: u32, height: u32) -> usize {
+    width as usize * height as usize * 4
+}
+
+// Composites `sky` over the detected sky and shifts the foreground towards its light
+pub fn replace_sky(img: &RgbaImage, sky: &SkyAsset) -> Result<(RgbaImage, SkyReplacement)> {
+    let (width, height) = img.dimensions();
+    let mask = sky_mask(img);
+
+    let sky_fraction = mask.pixels().map(|a| a[0] as f64).sum::<f64>() / (255.0 * (width * height) as f64);
+    if (sky_fraction as f32) < MIN_SKY_FRACTION {
+        return Err(AppError::Validation(format!(
+            "Only {:.1}% of the image is sky, too little to replace", sky_fraction * 100.0
+        )));
+    }
+
+    // Stretch the new sky over the rows that have sky, so its horizon sits on ours
+    let sky_rows = (0..height)
+        .rev()
+        .find(|&y| (0..width).any(|x| mask.get_pixel(x, y)[0] > 127))
+        .map_or(height, |y| y + 1)
+        .max(height / 4);
+    let new_sky = image::load_from_memory(sky.data)
+        .map_err(|e| AppError::ImageProcessing(format!("Failed to decode sky {}: {}", sky.id, e)))?
+        .resize_to_fill(width, sky_rows, FilterType::Triangle)
+        .to_rgb8();
+
+    let old_mean = mean_color(img, &mask, |alpha| alpha >= 250).unwrap_or([128.0; 3]);
+    let new_mean = {
+        let mut sum = [0.0f64; 3];
+        let mut weight = 0.0f64;
+        for (x, y, alpha) in mask.enumerate_pixels() {
+            if alpha[0] > 0 && y < sky_rows {
+                let p = new_sky.get_pixel(x, y);
+                (0..3).for_each(|c| sum[c] += p[c] as f64 * alpha[0] as f64);
+                weight += alpha[0] as f64;
+            }
+        }
+        sum.map(|s| (s / weight.max(1.0)) as f32)
+    };
+    let gain = [0, 1, 2].map(|c| {
+        ((new_mean[c] + 1.0) / (old_mean[c] + 1.0))
+            .powf(RELIGHT_STRENGTH)
+            .clamp(1.0 - MAX_RELIGHT_GAIN, 1.0 + MAX_RELIGHT_GAIN)
+    });
+
+    let mut output = img.clone();
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let alpha = mask.get_pixel(x, y)[0] as f32 / 255.0;
+        let sky_pixel = new_sky.get_pixel(x, y.min(sky_rows - 1));
+        for c in 0..3 {
+            let foreground = (pixel[c] as f32 * gain[c]).min(255.0);
+            pixel[c] = (alpha * sky_pixel[c] as f32 + (1.0 - alpha) * foreground).round() as u8;
+        }
+    }
+
+    Ok((output, SkyReplacement {
+        sky_id: sky.id.to_string(),
+        sky_fraction: sky_fraction as f32,
+        foreground_gain: gain,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    // Overcast sky over a brick-coloured gable with some texture
+    fn overcast_house() -> RgbaImage {
+        RgbaImage::from_fn(1200, 800, |x, y| {
+            let roof = 320 + (x as i32 - 600).unsigned_abs() / 3;
+            if y < roof.min(500) {
+                Rgba([188, 190, 194, 255])
+            } else {
+                let brick = if (x / 24 + y / 12) % 2 == 0 { 0 } else { 30 };
+                Rgba([150 - brick, 90 - brick / 2, 70, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_overcast_sky_is_replaced_and_house_kept() {
+        let img = overcast_house();
+        let (replaced, replacement) = replace_sky(&img, find_sky("clear_blue").unwrap()).unwrap();
+
+        assert!((0.3..0.6).contains(&replacement.sky_fraction), "{:?}", replacement);
+        let sky = replaced.get_pixel(600, 40);
+        assert!(sky[2] as i32 > sky[0] as i32 + 40, "sky not replaced: {:?}", sky);
+
+        // Foreground is relit, not repainted
+        let (before, after) = (img.get_pixel(600, 700), replaced.get_pixel(600, 700));
+        for c in 0..3 {
+            assert!((before[c] as i32 - after[c] as i32).abs() <= 25, "{:?} -> {:?}", before, after);
+        }
+    }
+
+    #[test]
+    fn test_image_without_sky_is_rejected() {
+        let interior = RgbaImage::from_pixel(800, 600, Rgba([120, 80, 60, 255]));
+        assert!(replace_sky(&interior, find_sky("golden_hour").unwrap()).is_err());
+        assert!(find_sky("aurora").is_err());
+    }
+}
@@ -0,0 +1,33 @@
+// Synthetic frames and thread-pool checks shared by the unit tests
+use std::fmt::Debug;
+use image::{DynamicImage, Rgb, RgbImage};
+
+// Pool size for the parallel side of the thread-count checks; odd, so rows don't
+// split evenly across it
+pub const PARALLEL_THREADS: usize = 7;
+
+// Grain in 0..range from a fixed hash of the position, so every run sees the same pixels
+pub fn grain(x: u32, y: u32, range: u32) -> u32 {
+    (x * 7919 + y * 104729) % range
+}
+
+// Every channel level scattered over the frame
+pub fn noisy(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let v = grain(x, y, 251);
+        Rgb([v as u8, (v * 3 % 256) as u8, (255 - v) as u8])
+    }))
+}
+
+pub fn on_threads<T: Send>(threads: usize, f: impl FnOnce() -> T + Send) -> T {
+    rayon::ThreadPoolBuilder::new().num_threads(threads).build().expect("thread pool").install(f)
+}
+
+// Runs `f` on one thread and on a pool and requires the same result from both.
+// Agreeing with itself doesn't make a pass right, so callers check what's
+// returned against the serial implementation the pass replaced.
+pub fn same_on_any_thread_count<T: PartialEq + Debug + Send>(f: impl Fn() -> T + Sync) -> T {
+    let single = on_threads(1, &f);
+    assert_eq!(single, on_threads(PARALLEL_THREADS, &f), "result depends on the thread count");
+    single
+}
@@ -0,0 +1,278 @@
+use std::f64::consts::PI;
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike};
+use image::{imageops::FilterType, DynamicImage};
+use serde::{Serialize, Deserialize};
+
+use crate::backend::common::types::listing_types::GpsCoordinates;
+use super::exif_metadata::ExifData;
+use super::processor::{ContentType, TimeOfDay};
+
+// Sun elevation (degrees) at sunset, with refraction and the disc's radius, and
+// where the blue-hour look is gone and the sky reads as black
+const SUNSET_ELEVATION: f64 = -0.833;
+const TWILIGHT_END_ELEVATION: f64 = -10.0;
+// Closer than this to either boundary, a camera clock a little off could put the
+// photo on the wrong side
+const CLOCK_MARGIN_DEGREES: f64 = 3.0;
+// Image evidence needed to overrule the clock near a boundary
+const IMAGE_OVERRULE_CONFIDENCE: f32 = 0.6;
+// A zone guessed from longitude can be an hour or two out (daylight saving, zones
+// that don't follow their meridian), so such a clock is only worth this much and
+// a confident image overrules it at any sun elevation
+const GUESSED_ZONE_CONFIDENCE: f32 = 0.5;
+
+// Sky measurements run on a small copy of the top third
+const SKY_SAMPLE_SIZE: u32 = 256;
+const NIGHT_SKY_LUMA: f32 = 35.0;
+const NIGHT_MEAN_LUMA: f32 = 70.0;
+const TWILIGHT_SKY_BLUENESS: f32 = 0.12;
+const TWILIGHT_SKY_MAX_LUMA: f32 = 150.0;
+const TWILIGHT_MEAN_LUMA: f32 = 110.0;
+const LIT_WINDOWS: f32 = 0.15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeOfDaySource {
+    // Sun position at the EXIF capture time and location
+    Clock,
+    // Histogram and sky colour; no usable capture time or location
+    Image,
+    // Both agreed
+    Both,
+}
+
+// Picks the preset and is stored on the image for search filters
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimeOfDayClassification {
+    pub time_of_day: TimeOfDay,
+    pub source: TimeOfDaySource,
+    pub confidence: f32,
+    // Degrees above the horizon at capture; negative after sunset
+    pub sun_elevation: Option<f32>,
+}
+
+// The top third of the frame, where an exterior's sky is
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SkySignals {
+    pub luma: f32,
+    // Mean of blue over the larger of red and green, 0..1; deep at blue hour
+    pub blueness: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ImageSignals {
+    // From the histogram statistics
+    pub mean_luma: f32,
+    pub lit_windows: f32,
+    // `detect_twilight_conditions` over the whole frame
+    pub blue_hour: bool,
+    pub sky: SkySignals,
+}
+
+pub fn sky_signals(img: &DynamicImage) -> SkySignals {
+    let top = img.crop_imm(0, 0, img.width(), (img.height() / 3).max(1));
+    let sample = top.resize(SKY_SAMPLE_SIZE, SKY_SAMPLE_SIZE, FilterType::Triangle).to_rgb8();
+    let count = (sample.width() * sample.height()).max(1) as f32;
+
+    let (luma, blueness) = sample.pixels().fold((0.0, 0.0), |(luma, blueness), p| {
+        let [r, g, b] = p.0.map(f32::from);
+        (luma + 0.299 * r + 0.587 * g + 0.114 * b, blueness + (b - r.max(g)).max(0.0) / 255.0)
+    });
+    SkySignals { luma: luma / count, blueness: blueness / count }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaptureSun {
+    // Degrees above the horizon
+    pub elevation: f64,
+    // No offset tag, so the time zone came from the longitude
+    pub zone_guessed: bool,
+}
+
+// Sun position when the photo has a capture time and we know roughly where it was taken
+pub fn capture_sun(exif: &ExifData, location: &GpsCoordinates) -> Option<CaptureSun> {
+    let local = exif.captured_at?;
+    // EXIF capture times are local; without an offset tag, assume the zone the
+    // longitude falls in
+    let offset_minutes = exif
+        .capture_utc_offset
+        .unwrap_or_else(|| (location.longitude / 15.0).round() as i32 * 60);
+    let utc = local - Duration::minutes(offset_minutes as i64);
+    Some(CaptureSun {
+        elevation: sun_elevation(utc, location.latitude, location.longitude),
+        zone_guessed: exif.capture_utc_offset.is_none(),
+    })
+}
+
+// NOAA's low-precision solar position; good to a fraction of a degree, far finer
+// than the twilight bands need
+pub fn sun_elevation(utc: NaiveDateTime, latitude: f64, longitude: f64) -> f64 {
+    let hour = utc.hour() as f64 + utc.minute() as f64 / 60.0 + utc.second() as f64 / 3600.0;
+    let gamma = 2.0 * PI / 365.0 * (utc.ordinal() as f64 - 1.0 + (hour - 12.0) / 24.0);
+
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos() + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+    let solar_minutes = hour * 60.0 + equation_of_time + 4.0 * longitude;
+    let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+    let latitude = latitude.to_radians();
+    let cos_zenith = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+fn from_sun(elevation: f64) -> TimeOfDay {
+    if elevation > SUNSET_ELEVATION {
+        TimeOfDay::Day
+    } else if elevation > TWILIGHT_END_ELEVATION {
+        TimeOfDay::Twilight
+    } else {
+        TimeOfDay::Night
+    }
+}
+
+// What the pixels alone suggest, and how sure that is
+fn from_image(signals: &ImageSignals) -> (TimeOfDay, f32) {
+    let sky = signals.sky;
+    if sky.luma < NIGHT_SKY_LUMA && signals.mean_luma < NIGHT_MEAN_LUMA {
+        return (TimeOfDay::Night, 0.7);
+    }
+
+    let blue_sky = sky.blueness > TWILIGHT_SKY_BLUENESS && sky.luma < TWILIGHT_SKY_MAX_LUMA;
+    if (signals.blue_hour || blue_sky) && signals.mean_luma < TWILIGHT_MEAN_LUMA {
+        // Lit windows against a blue sky is the classic twilight exterior
+        let confidence = if signals.lit_windows > LIT_WINDOWS { 0.8 } else { 0.6 };
+        return (TimeOfDay::Twilight, confidence);
+    }
+
+    (TimeOfDay::Day, if sky.luma > TWILIGHT_SKY_MAX_LUMA { 0.7 } else { 0.5 })
+}
+
+// The clock decides when there is one, except near sunset or the end of twilight
+// (anywhere, if the zone was guessed) on photos that show the sky, where a clear
+// look in the image wins. Interiors show too little sky to argue with the clock.
+pub fn classify(sun: Option<CaptureSun>, signals: &ImageSignals, content_type: ContentType) -> TimeOfDayClassification {
+    let (seen, seen_confidence) = from_image(signals);
+    let Some(CaptureSun { elevation, zone_guessed }) = sun else {
+        return TimeOfDayClassification {
+            time_of_day: seen,
+            source: TimeOfDaySource::Image,
+            confidence: seen_confidence,
+            sun_elevation: None,
+        };
+    };
+
+    let clock = from_sun(elevation);
+    let margin = (elevation - SUNSET_ELEVATION).abs().min((elevation - TWILIGHT_END_ELEVATION).abs());
+    let mut clock_confidence = (0.6 + margin / 20.0).min(0.95) as f32;
+    if zone_guessed {
+        clock_confidence = clock_confidence.min(GUESSED_ZONE_CONFIDENCE);
+    }
+    let shows_sky = matches!(content_type, ContentType::Exterior | ContentType::View | ContentType::Panorama);
+    let clock_unsure = zone_guessed || margin < CLOCK_MARGIN_DEGREES;
+
+    let (time_of_day, source, confidence) = if clock == seen {
+        (clock, TimeOfDaySource::Both, clock_confidence.max(seen_confidence))
+    } else if shows_sky && clock_unsure && seen_confidence >= IMAGE_OVERRULE_CONFIDENCE {
+        (seen, TimeOfDaySource::Image, seen_confidence)
+    } else {
+        (clock, TimeOfDaySource::Clock, clock_confidence)
+    };
+
+    TimeOfDayClassification { time_of_day, source, confidence, sun_elevation: Some(elevation as f32) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use image::{Rgb, RgbImage};
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, min, 0).unwrap()
+    }
+
+    fn clock(elevation: f64) -> Option<CaptureSun> {
+        Some(CaptureSun { elevation, zone_guessed: false })
+    }
+
+    fn signals(mean_luma: f32, sky_luma: f32, blueness: f32) -> ImageSignals {
+        ImageSignals { mean_luma, lit_windows: 0.2, blue_hour: false, sky: SkySignals { luma: sky_luma, blueness } }
+    }
+
+    #[test]
+    fn test_sun_elevation_around_sunset() {
+        // Lisbon, midsummer: sunset about 20:05 UTC
+        let (lat, lon) = (38.72, -9.14);
+        assert!(sun_elevation(at(2024, 6, 21, 12, 30), lat, lon) > 70.0);
+        assert!(sun_elevation(at(2024, 6, 21, 19, 30), lat, lon) > 0.0);
+        let dusk = sun_elevation(at(2024, 6, 21, 20, 30), lat, lon);
+        assert!((TWILIGHT_END_ELEVATION..SUNSET_ELEVATION).contains(&dusk), "{}", dusk);
+        assert!(sun_elevation(at(2024, 6, 21, 23, 30), lat, lon) < TWILIGHT_END_ELEVATION);
+    }
+
+    #[test]
+    fn test_local_capture_time_uses_offset_or_longitude() {
+        let lisbon = GpsCoordinates { latitude: 38.72, longitude: -9.14 };
+        // 21:30 summer time is 20:30 UTC, blue hour
+        let exif = ExifData { captured_at: Some(at(2024, 6, 21, 21, 30)), capture_utc_offset: Some(60), ..ExifData::default() };
+        let tagged = capture_sun(&exif, &lisbon).unwrap();
+        assert_eq!((from_sun(tagged.elevation), tagged.zone_guessed), (TimeOfDay::Twilight, false));
+        // Without the tag the zone comes from longitude, UTC-1 here: 22:30 UTC is night
+        let guessed = capture_sun(&ExifData { capture_utc_offset: None, ..exif }, &lisbon).unwrap();
+        assert_eq!((from_sun(guessed.elevation), guessed.zone_guessed), (TimeOfDay::Night, true));
+        assert_eq!(capture_sun(&ExifData::default(), &lisbon), None);
+    }
+
+    #[test]
+    fn test_clock_wins_unless_the_sky_clearly_disagrees_near_a_boundary() {
+        let bright_day = signals(140.0, 200.0, 0.05);
+        let blue_hour = signals(80.0, 90.0, 0.2);
+
+        // No clock: the image decides
+        let seen = classify(None, &blue_hour, ContentType::Exterior);
+        assert_eq!((seen.time_of_day, seen.source), (TimeOfDay::Twilight, TimeOfDaySource::Image));
+
+        // Clock well into the night beats a bright-looking interior
+        let night = classify(clock(-25.0), &bright_day, ContentType::LivingRoom);
+        assert_eq!((night.time_of_day, night.source), (TimeOfDay::Night, TimeOfDaySource::Clock));
+
+        // Just before sunset by the clock, but the exterior's sky is deep blue
+        let overruled = classify(clock(0.5), &blue_hour, ContentType::Exterior);
+        assert_eq!((overruled.time_of_day, overruled.source), (TimeOfDay::Twilight, TimeOfDaySource::Image));
+        // The same call on an interior stays with the clock
+        assert_eq!(classify(clock(0.5), &blue_hour, ContentType::Bedroom).time_of_day, TimeOfDay::Day);
+
+        let agreed = classify(clock(-5.0), &blue_hour, ContentType::Exterior);
+        assert_eq!((agreed.time_of_day, agreed.source), (TimeOfDay::Twilight, TimeOfDaySource::Both));
+    }
+
+    #[test]
+    fn test_guessed_zone_gives_way_to_a_clear_sky() {
+        let bright_day = signals(140.0, 200.0, 0.05);
+        let guessed = Some(CaptureSun { elevation: -20.0, zone_guessed: true });
+
+        // Well past twilight by a guessed clock, but the exterior is in daylight
+        let overruled = classify(guessed, &bright_day, ContentType::Exterior);
+        assert_eq!((overruled.time_of_day, overruled.source), (TimeOfDay::Day, TimeOfDaySource::Image));
+        // Interiors keep the clock, without much confidence in it
+        let interior = classify(guessed, &bright_day, ContentType::Kitchen);
+        assert_eq!(interior.time_of_day, TimeOfDay::Night);
+        assert!(interior.confidence <= GUESSED_ZONE_CONFIDENCE);
+        // A tagged clock that far from a boundary stands
+        assert_eq!(classify(clock(-20.0), &bright_day, ContentType::Exterior).time_of_day, TimeOfDay::Night);
+    }
+
+    #[test]
+    fn test_sky_signals_read_the_top_third() {
+        // Deep blue sky over a dark facade
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(300, 300, |_, y| {
+            if y < 100 { Rgb([30, 60, 140]) } else { Rgb([40, 35, 30]) }
+        }));
+        let sky = sky_signals(&img);
+        assert!(sky.blueness > TWILIGHT_SKY_BLUENESS, "{:?}", sky);
+        assert!(sky.luma > NIGHT_SKY_LUMA && sky.luma < TWILIGHT_SKY_MAX_LUMA, "{:?}", sky);
+    }
+}
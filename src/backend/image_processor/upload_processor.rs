@@ -11,6 +11,7 @@ use crate::backend::{
         listing_types::ListingStatus,
     },
     f_ai_database::image_service::ImageService,
+    image_processor::exif_metadata::read_exif,
     trans_storage::file_manager::FileManager,
 };
 
@@ -68,13 +69,20 @@ impl UploadProcessor {
 
         // Generate image ID and path
         let image_id = uuid7::uuid7().to_string();
+
+        // Coordinates sent by the client win over the camera's EXIF fix
+        let gps_coordinates = job.gps_coordinates.or_else(|| {
+            read_exif(&assembled_data)
+                .and_then(|exif| exif.good_gps())
+                .map(|fix| (fix.latitude, fix.longitude))
+        });
         
         // Let FileManager handle the file operations
         self.file_manager.store_temp_file(
             &job.listing_id,
             &image_id,
             &assembled_data,
-            job.gps_coordinates,
+            gps_coordinates,
         ).await?;
 
         // Queue for analysis
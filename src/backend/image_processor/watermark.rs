@@ -0,0 +1,182 @@
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use tracing::{info, instrument};
+
+use crate::backend::common::{
+    config::{WatermarkConfig, WatermarkCorner, WatermarkStyle},
+    error::error::{Result, AppError},
+};
+
+const WATERMARK_SVG: &[u8] = include_bytes!("../assets/watermark.svg");
+
+pub struct Watermarker {
+    tree: usvg::Tree,
+    config: WatermarkConfig,
+}
+
+impl Watermarker {
+    pub fn new(config: WatermarkConfig) -> Result<Self> {
+        config.validate()?;
+
+        // The bundled watermark renders text, so system fonts must be available
+        let mut options = usvg::Options::default();
+        options.fontdb_mut().load_system_fonts();
+
+        let tree = usvg::Tree::from_data(WATERMARK_SVG, &options)
+            .map_err(|e| AppError::ImageProcessing(format!("Failed to parse watermark SVG: {}", e)))?;
+
+        Ok(Self { tree, config })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    // Largest panorama face that may be published as unmarked tiles, if any limit applies
+    pub fn panorama_tile_max_face(&self) -> Option<u32> {
+        self.config.enabled.then_some(self.config.panorama_tile_max_face)
+    }
+
+    #[instrument(skip(self, img))]
+    pub fn apply(&self, img: &DynamicImage, agency_id: Option<&str>) -> Result<DynamicImage> {
+        let style = self.config.style_for(agency_id);
+        let (width, height) = img.dimensions();
+
+        let overlay = self.rasterize(width, style)?;
+        let (x, y) = placement(width, height, overlay.width(), overlay.height(), style);
+
+        let mut output = img.to_rgba8();
+        blend_premultiplied(&mut output, &overlay, x, y, style.opacity);
+
+        info!(
+            width = overlay.width(),
+            height = overlay.height(),
+            corner = ?style.corner,
+            "Applied watermark"
+        );
+
+        Ok(DynamicImage::ImageRgba8(output))
+    }
+
+    // Render the SVG scaled relative to the target image width
+    fn rasterize(&self, image_width: u32, style: &WatermarkStyle) -> Result<tiny_skia::Pixmap> {
+        let svg_size = self.tree.size();
+        let target_width = ((image_width as f32 * style.width_ratio) as u32)
+            .max(style.min_width)
+            .min(image_width);
+
+        let scale = target_width as f32 / svg_size.width();
+        let pixmap_width = (svg_size.width() * scale).ceil() as u32;
+        let pixmap_height = (svg_size.height() * scale).ceil() as u32;
+
+        let mut pixmap = tiny_skia::Pixmap::new(pixmap_width.max(1), pixmap_height.max(1))
+            .ok_or_else(|| AppError::ImageProcessing("Failed to allocate watermark pixmap".into()))?;
+
+        resvg::render(
+            &self.tree,
+            tiny_skia::Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+
+        Ok(pixmap)
+    }
+}
+
+fn placement(width: u32, height: u32, mark_width: u32, mark_height: u32, style: &WatermarkStyle) -> (u32, u32) {
+    let margin = (width.min(height) as f32 * style.margin_ratio) as u32;
+    let right = width.saturating_sub(mark_width + margin);
+    let bottom = height.saturating_sub(mark_height + margin);
+
+    match style.corner {
+        WatermarkCorner::TopLeft => (margin, margin),
+        WatermarkCorner::TopRight => (right, margin),
+        WatermarkCorner::BottomLeft => (margin, bottom),
+        WatermarkCorner::BottomRight => (right, bottom),
+        WatermarkCorner::Center => (
+            width.saturating_sub(mark_width) / 2,
+            height.saturating_sub(mark_height) / 2,
+        ),
+    }
+}
+
+// tiny-skia pixmaps are premultiplied RGBA, so the source-over blend skips the alpha multiply
+fn blend_premultiplied(dst: &mut RgbaImage, overlay: &tiny_skia::Pixmap, x: u32, y: u32, opacity: f32) {
+    let data = overlay.data();
+    let overlay_width = overlay.width();
+
+    for oy in 0..overlay.height() {
+        for ox in 0..overlay_width {
+            let (px, py) = (x + ox, y + oy);
+            if px >= dst.width() || py >= dst.height() {
+                continue;
+            }
+
+            let idx = ((oy * overlay_width + ox) * 4) as usize;
+            let src_alpha = data[idx + 3] as f32 / 255.0 * opacity;
+            if src_alpha <= 0.0 {
+                continue;
+            }
+
+            let pixel = dst.get_pixel_mut(px, py);
+            for c in 0..3 {
+                let src = data[idx + c] as f32 * opacity;
+                let blended = src + pixel[c] as f32 * (1.0 - src_alpha);
+                pixel[c] = blended.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn style(corner: WatermarkCorner) -> WatermarkStyle {
+        WatermarkStyle { corner, ..WatermarkStyle::default() }
+    }
+
+    // Solid premultiplied square at the given alpha
+    fn square(size: u32, value: u8, alpha: u8) -> tiny_skia::Pixmap {
+        let mut pixmap = tiny_skia::Pixmap::new(size, size).unwrap();
+        let premultiplied = (value as u32 * alpha as u32 / 255) as u8;
+        for pixel in pixmap.data_mut().chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[premultiplied, premultiplied, premultiplied, alpha]);
+        }
+        pixmap
+    }
+
+    #[test]
+    fn test_placement_keeps_the_margin_in_every_corner() {
+        // Margin is 3% of the shorter side: 30px on 2000x1000
+        assert_eq!(placement(2000, 1000, 200, 100, &style(WatermarkCorner::TopLeft)), (30, 30));
+        assert_eq!(placement(2000, 1000, 200, 100, &style(WatermarkCorner::TopRight)), (1770, 30));
+        assert_eq!(placement(2000, 1000, 200, 100, &style(WatermarkCorner::BottomLeft)), (30, 870));
+        assert_eq!(placement(2000, 1000, 200, 100, &style(WatermarkCorner::BottomRight)), (1770, 870));
+        assert_eq!(placement(2000, 1000, 200, 100, &style(WatermarkCorner::Center)), (900, 450));
+        // A mark wider than the image is pinned to the edge, not wrapped around
+        assert_eq!(placement(100, 100, 300, 50, &style(WatermarkCorner::BottomRight)), (0, 47));
+    }
+
+    #[test]
+    fn test_blend_applies_opacity_and_clips_at_the_edge() {
+        let mut dst = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+        blend_premultiplied(&mut dst, &square(4, 255, 255), 8, 8, 0.5);
+
+        assert_eq!(dst.get_pixel(9, 9), &Rgba([128, 128, 128, 255]));
+        assert_eq!(dst.get_pixel(7, 7), &Rgba([0, 0, 0, 255]));
+
+        // Transparent overlay pixels leave the photo untouched
+        let mut dst = RgbaImage::from_pixel(4, 4, Rgba([40, 80, 120, 255]));
+        blend_premultiplied(&mut dst, &square(4, 255, 0), 0, 0, 1.0);
+        assert!(dst.pixels().all(|p| *p == Rgba([40, 80, 120, 255])));
+    }
+
+    #[test]
+    fn test_apply_keeps_dimensions() {
+        let watermarker = Watermarker::new(WatermarkConfig::default()).unwrap();
+        let img = DynamicImage::ImageRgb8(image::RgbImage::new(640, 480));
+        let marked = watermarker.apply(&img, None).unwrap();
+        assert_eq!(marked.dimensions(), (640, 480));
+    }
+}
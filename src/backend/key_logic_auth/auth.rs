@@ -18,6 +18,13 @@ use crate::backend::{
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 type BoxFuture<T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + Send>>;
 
+// Who made the request, resolved from the API key. Handlers that need to know
+// take it as `Extension<Caller>`.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub user_id: String,
+}
+
 #[derive(Clone)]
 pub struct RequireAuth;
 
@@ -69,15 +76,14 @@ where
 
             // Validate key using KeyService
             if let Some(state) = state {
-                let is_valid = state.key_service.validate_key(api_key).await
-                    .map_err(BoxError::from)?;
-
-                if !is_valid {
+                let Some(user_id) = state.key_service.key_owner(api_key).await
+                    .map_err(BoxError::from)? else {
                     warn!("Invalid API key attempt");
                     return Err(BoxError::from(AppError::Unauthorized));
-                }
+                };
 
                 info!("Authenticated request with valid API key");
+                request.extensions_mut().insert(Caller { user_id });
                 Ok(service.call(request).await?)
             } else {
                 Err(BoxError::from(AppError::Internal("Missing app state".into())))
@@ -47,6 +47,11 @@ impl KeyService {
     }
 
     pub async fn validate_key(&self, key: &str) -> Result<bool> {
+        Ok(self.key_owner(key).await?.is_some())
+    }
+
+    // User the key belongs to, or None when it is unknown, revoked or expired
+    pub async fn key_owner(&self, key: &str) -> Result<Option<String>> {
         let api_key: Option<ApiKey> = self.db.client()
             .query("SELECT * FROM api_keys WHERE key = $key AND revoked = false")
             .bind(("key", key))
@@ -57,12 +62,12 @@ impl KeyService {
             Some(key) => {
                 if let Some(expires_at) = key.expires_at {
                     if expires_at < Utc::now() {
-                        return Ok(false);
+                        return Ok(None);
                     }
                 }
-                Ok(true)
+                Ok(Some(key.user_id))
             }
-            None => Ok(false)
+            None => Ok(None)
         }
     }
 
@@ -117,7 +117,7 @@ impl BatchAnalysisService {
         })
     }
 
-    async fn analyze_single(&self, filename: String, data: Vec<u8>) -> Result<ImageAnalysis> {
+    pub async fn analyze_single(&self, filename: String, data: Vec<u8>) -> Result<ImageAnalysis> {
         let base64_image = BASE64.encode(&data);
         
         let request = CreateChatCompletionRequest {
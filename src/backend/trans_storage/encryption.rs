@@ -0,0 +1,109 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::backend::common::{
+    config::DocumentConfig,
+    error::error::{Result, AppError},
+};
+
+// AES-256-GCM for documents at rest. Sealed blobs are `nonce || ciphertext || tag`,
+// and the object key is authenticated with them so a blob can't be moved to
+// another document's path and still decrypt.
+pub struct DocumentCipher {
+    key: LessSafeKey,
+    key_id: String,
+    rng: SystemRandom,
+}
+
+impl DocumentCipher {
+    pub fn new(config: &DocumentConfig) -> Result<Self> {
+        config.validate()?;
+        let key = STANDARD
+            .decode(config.encryption_key.trim())
+            .map_err(|e| AppError::Configuration(format!("documents.encryption_key is not base64: {}", e)))?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_| AppError::Configuration("documents.encryption_key must be 32 bytes".into()))?;
+
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            key_id: config.key_id.clone(),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8], object_key: &str) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| AppError::Internal("Failed to generate a nonce".into()))?;
+
+        let mut body = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(object_key.as_bytes()), &mut body)
+            .map_err(|_| AppError::Internal("Failed to encrypt document".into()))?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + body.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&body);
+        Ok(sealed)
+    }
+
+    pub fn decrypt(&self, sealed: &[u8], object_key: &str) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + AES_256_GCM.tag_len() {
+            return Err(AppError::Storage(format!("Encrypted document at {} is truncated", object_key)));
+        }
+        let (nonce, body) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| AppError::Internal("Invalid nonce length".into()))?;
+
+        let mut body = body.to_vec();
+        let plaintext_len = self.key
+            .open_in_place(nonce, Aad::from(object_key.as_bytes()), &mut body)
+            .map_err(|_| AppError::Storage(format!("Document at {} failed authentication", object_key)))?
+            .len();
+        body.truncate(plaintext_len);
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> DocumentCipher {
+        DocumentCipher::new(&DocumentConfig {
+            encryption_key: STANDARD.encode([7u8; 32]),
+            ..DocumentConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_and_tamper_detection() {
+        let cipher = cipher();
+        let document = b"%PDF-1.7 title deed".to_vec();
+        let sealed = cipher.encrypt(&document, "private/a/original.pdf.enc").unwrap();
+
+        assert!(!sealed.windows(document.len()).any(|w| w == document));
+        assert_eq!(cipher.decrypt(&sealed, "private/a/original.pdf.enc").unwrap(), document);
+
+        // Moved to another path, or flipped a bit
+        assert!(cipher.decrypt(&sealed, "private/b/original.pdf.enc").is_err());
+        let mut tampered = sealed.clone();
+        tampered[NONCE_LEN + 2] ^= 1;
+        assert!(cipher.decrypt(&tampered, "private/a/original.pdf.enc").is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_keys() {
+        let config = |key: &str| DocumentConfig { encryption_key: key.to_string(), ..DocumentConfig::default() };
+        assert!(DocumentCipher::new(&config("")).is_err());
+        assert!(DocumentCipher::new(&config("not base64!")).is_err());
+        assert!(DocumentCipher::new(&config(&STANDARD.encode([1u8; 16]))).is_err());
+    }
+}
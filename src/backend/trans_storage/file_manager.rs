@@ -1,11 +1,14 @@
 use std::sync::Arc;
 use tokio::fs;
-use image::{DynamicImage, imageops::FilterType};
+use image::{DynamicImage, ImageFormat};
 use tracing::{info, instrument};
 use anyhow::{Result, anyhow};
 use webp::{Encoder, WebPMemory};
 
 use crate::backend::trans_storage::b2_storage::B2Storage;
+use crate::backend::image_processor::exif_metadata::{read_exif, apply_orientation};
+use crate::backend::image_processor::dimension_policy::DimensionPolicy;
+use crate::backend::image_processor::color_management::{read_icc_profile, tag_srgb, to_srgb};
 use crate::backend::common::error::error::AppError;
 
 #[derive(Debug)]
@@ -14,18 +17,21 @@ pub struct ProcessedFile {
     pub data: Vec<u8>,
     pub width: u32,
     pub height: u32,
+    pub gps_coordinates: Option<(f64, f64)>,
 }
 
 pub struct FileManager {
     temp_root: String,
     b2_storage: Arc<B2Storage>,
+    dimension_policy: Arc<DimensionPolicy>,
 }
 
 impl FileManager {
-    pub fn new(temp_root: String, b2_storage: Arc<B2Storage>) -> Self {
+    pub fn new(temp_root: String, b2_storage: Arc<B2Storage>, dimension_policy: Arc<DimensionPolicy>) -> Self {
         Self {
             temp_root,
             b2_storage,
+            dimension_policy,
         }
     }
 
@@ -41,8 +47,15 @@ impl FileManager {
         let temp_dir = format!("{}/temp/{}", self.temp_root, listing_id);
         fs::create_dir_all(&temp_dir).await?;
 
-        // Process image
+        // Process image, upright and in sRGB first since the WebP copy loses the
+        // orientation tag and the source profile
+        let exif = read_exif(data);
         let image = image::load_from_memory(data)?;
+        let image = match &exif {
+            Some(exif) => apply_orientation(image, exif.orientation),
+            None => image,
+        };
+        let (image, _) = to_srgb(image, read_icc_profile(data).as_deref())?;
         let processed = self.process_image_dimensions(image)?;
         
         // Convert to WebP
@@ -58,27 +71,20 @@ impl FileManager {
             data: webp_data,
             width: processed.width(),
             height: processed.height(),
+            gps_coordinates: gps,
         })
     }
 
+    // Chunked uploads carry no content type, so they get the policy's default rule
     fn process_image_dimensions(&self, image: DynamicImage) -> Result<DynamicImage> {
-        const MIN_HEIGHT: u32 = 1080;
-        const MAX_HEIGHT: u32 = 2160; // 4K
-
-        let height = image.height();
-        if height < MIN_HEIGHT {
-            Ok(image.resize(0, MIN_HEIGHT, FilterType::Lanczos3))
-        } else if height > MAX_HEIGHT {
-            Ok(image.resize(0, MAX_HEIGHT, FilterType::Lanczos3))
-        } else {
-            Ok(image)
-        }
+        let plan = self.dimension_policy.plan(None, image.width(), image.height())?;
+        Ok(plan.apply(image))
     }
 
     fn convert_to_webp(&self, image: &DynamicImage) -> Result<Vec<u8>> {
         let encoder = Encoder::from_image(image)?;
         let encoded: WebPMemory = encoder.encode(90.0);
-        Ok(encoded.to_vec())
+        Ok(tag_srgb(encoded.to_vec(), ImageFormat::WebP)?)
     }
 
     #[instrument(skip(self))]
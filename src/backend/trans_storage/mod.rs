@@ -1,6 +1,8 @@
 pub mod file_manager;
 pub mod b2_storage;
 pub mod b2_storage_ext;
+pub mod storage_keys;
+pub mod encryption;
 
 pub use file_manager::FileManager;
 pub use b2_storage::B2Storage;
@@ -0,0 +1,70 @@
+// Object key layout inside the listing bucket.
+// Everything under `private/` is never served publicly; `public/` is what listing pages link to.
+
+pub const PRIVATE_PREFIX: &str = "private";
+pub const PUBLIC_PREFIX: &str = "public";
+
+pub fn master_key(listing_id: &str, image_id: &str) -> String {
+    format!("{}/listings/{}/images/{}/master.webp", PRIVATE_PREFIX, listing_id, image_id)
+}
+
+// Untouched upload that edit recipes re-render from
+pub fn original_key(listing_id: &str, image_id: &str, extension: &str) -> String {
+    format!("{}/listings/{}/images/{}/original.{}", PRIVATE_PREFIX, listing_id, image_id, extension)
+}
+
+pub fn published_key(listing_id: &str, image_id: &str) -> String {
+    format!("{}/listings/{}/images/{}.webp", PUBLIC_PREFIX, listing_id, image_id)
+}
+
+// e.g. public/listings/FL_x/images/FI_y/w640.webp
+pub fn rendition_key(listing_id: &str, image_id: &str, width: u32, extension: &str) -> String {
+    format!("{}/listings/{}/images/{}/w{}.{}", PUBLIC_PREFIX, listing_id, image_id, width, extension)
+}
+
+// Original frames of a fused exposure bracket, kept next to the master
+pub fn bracket_frame_key(listing_id: &str, image_id: &str, index: usize, extension: &str) -> String {
+    format!("{}/listings/{}/images/{}/brackets/{}.{}", PRIVATE_PREFIX, listing_id, image_id, index, extension)
+}
+
+// Root of a 360° image's tile pyramid; tiles sit at `{level}/{face}{y}_{x}.webp` below it
+pub fn panorama_base_key(listing_id: &str, image_id: &str) -> String {
+    format!("{}/listings/{}/images/{}/pano", PUBLIC_PREFIX, listing_id, image_id)
+}
+
+pub fn panorama_tile_key(listing_id: &str, image_id: &str, level: u32, face: char, y: u32, x: u32) -> String {
+    format!("{}/{}/{}{}_{}.webp", panorama_base_key(listing_id, image_id), level, face, y, x)
+}
+
+pub fn panorama_preview_key(listing_id: &str, image_id: &str) -> String {
+    format!("{}/listings/{}/images/{}/preview.webp", PUBLIC_PREFIX, listing_id, image_id)
+}
+
+// Vector trace of a floor plan, for zooming without blur
+pub fn floor_plan_svg_key(listing_id: &str, image_id: &str) -> String {
+    format!("{}/listings/{}/images/{}/plan.svg", PUBLIC_PREFIX, listing_id, image_id)
+}
+
+// Encrypted private documents; `name` is `original.{ext}` or `redacted.png`
+pub fn document_key(listing_id: &str, document_id: &str, name: &str) -> String {
+    format!("{}/listings/{}/documents/{}/{}.enc", PRIVATE_PREFIX, listing_id, document_id, name)
+}
+
+// The prefix is a whole path segment; `privateer/...` is not private
+pub fn is_private(key: &str) -> bool {
+    key.strip_prefix(PRIVATE_PREFIX).is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_private_prefix_is_a_whole_segment() {
+        assert!(is_private(&master_key("FL_1", "FI_1")));
+        assert!(is_private(&document_key("FL_1", "FD_1", "original.pdf")));
+        assert!(!is_private(&published_key("FL_1", "FI_1")));
+        assert!(!is_private("privateer/listings/FL_1/images/FI_1.webp"));
+        assert!(!is_private("private"));
+    }
+}
@@ -6,13 +6,17 @@ use tracing::{info, error, instrument};
 use std::sync::Arc;
 use std::collections::HashMap;
 use anyhow::Result;
+use bytes::Bytes;
 
 use crate::backend::{
     common::types::{
         image_types::{ImageUploadSession, ImageChunk, UploadStatus},
+        id_types::ListingId,
         website_sections::WebsiteSections,
     },
+    common::validation::image_validation::{validate_image, MAX_FILE_SIZE},
     f_ai_core::state::AppState,
+    image_processor::{processor::UploadFile, quality_report::QualityReport},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +42,9 @@ pub enum WebSocketMessage {
     UploadProgress {
         session_id: String,
         status: UploadStatus,
+        // Issues and recommendations once the image is processed, so the photographer can reshoot on site
+        #[serde(skip_serializing_if = "Option::is_none")]
+        quality: Option<QualityReport>,
     },
     
     // Errors
@@ -50,6 +57,8 @@ pub enum WebSocketMessage {
 pub struct WebSocketHandler {
     state: Arc<AppState>,
     upload_sessions: Arc<tokio::sync::RwLock<HashMap<String, ImageUploadSession>>>,
+    // Chunks received so far, by session; joined in sequence order on the final chunk
+    upload_chunks: Arc<tokio::sync::RwLock<HashMap<String, Vec<ImageChunk>>>>,
 }
 
 impl WebSocketHandler {
@@ -61,6 +70,7 @@ impl WebSocketHandler {
         let handler = Arc::new(Self {
             state,
             upload_sessions: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+            upload_chunks: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
         });
 
         // Handle incoming messages
@@ -168,28 +178,64 @@ impl WebSocketHandler {
                 .clone()
         };
 
-        // Update session status
-        if let UploadStatus::Initialized = session.status {
-            session.status = UploadStatus::Uploading {
-                chunks_received: 0,
-                total_chunks: 0, // Will be updated as we receive chunks
-            };
-        }
-
         // Store chunk data
-        self.state.store_image_chunk(&chunk).await?;
+        let (session_id, sequence, is_final) = (chunk.session_id.clone(), chunk.sequence, chunk.is_final);
+        let chunks_received = {
+            let mut uploads = self.upload_chunks.write().await;
+            let chunks = uploads.entry(session_id.clone()).or_default();
+            chunks.push(chunk);
+            // Checked as chunks arrive, so an oversized upload is never buffered whole
+            let size: usize = chunks.iter().map(|chunk| chunk.data.len()).sum();
+            if size > MAX_FILE_SIZE {
+                uploads.remove(&session_id);
+                return Err(anyhow::anyhow!("Upload exceeds {} bytes", MAX_FILE_SIZE));
+            }
+            chunks.len() as u32
+        };
+
+        // Update session status
+        session.status = UploadStatus::Uploading {
+            chunks_received,
+            total_chunks: 0, // Unknown until the final chunk
+        };
 
         // Send chunk received acknowledgment
         let response = WebSocketMessage::ChunkReceived {
-            session_id: chunk.session_id,
-            sequence: chunk.sequence,
+            session_id,
+            sequence,
         };
         tx.send(Message::Text(serde_json::to_string(&response)?)).await?;
 
         // If this is the final chunk, start processing
-        if chunk.is_final {
+        let mut quality = None;
+        if is_final {
             session.status = UploadStatus::Processing;
-            self.state.process_image_upload(&session).await?;
+            let mut chunks = self.upload_chunks.write().await
+                .remove(&session.session_id)
+                .unwrap_or_default();
+            chunks.sort_by_key(|chunk| chunk.sequence);
+            let data = Bytes::from(chunks.into_iter().flat_map(|chunk| chunk.data).collect::<Vec<_>>());
+
+            // Same checks as a multipart upload; the session carries no filename or content type
+            validate_image(&data, &session.session_id, self.state.image_processor.dimension_policy(), None)?;
+            let file = UploadFile {
+                filename: session.session_id.clone(),
+                data: data.to_vec(),
+                content_type: None,
+            };
+            let listing_id = ListingId::from_string(session.listing_id.clone())?;
+            let mut response = self.state.process_upload(&listing_id, vec![file], None).await?;
+
+            // One file per session, so it was stored, rejected or held
+            if let Some(rejection) = response.rejected.pop() {
+                session.status = UploadStatus::Failed { reason: rejection.report.summary() };
+                quality = Some(rejection.report);
+            } else if let Some(held) = response.held.pop() {
+                session.status = UploadStatus::HeldForReview { reason: held.classification.reason };
+            } else if let Some(stored) = response.images.pop() {
+                session.status = UploadStatus::Completed;
+                quality = stored.quality;
+            }
         }
 
         // Update session
@@ -202,6 +248,7 @@ impl WebSocketHandler {
         let status_msg = WebSocketMessage::UploadProgress {
             session_id: session.session_id,
             status: session.status,
+            quality,
         };
         tx.send(Message::Text(serde_json::to_string(&status_msg)?)).await?;
 
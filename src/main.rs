@@ -5,7 +5,7 @@ use axum::{
     Extension,
 };
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{info, warn};
 use anyhow::Result;
 
 use crate::{
@@ -25,6 +25,7 @@ use crate::{
             listing_model::ListingService,
             image_service::ImageService,
             image_model::ImageModel,
+            document_model::DocumentModel,
         },
         image_processor::{
             ImageProcessor,
@@ -86,14 +87,24 @@ async fn main() -> anyhow::Result<()> {
     
     // Pipeline 2: Image Processing
     let image_model = Arc::new(ImageModel::new(db_manager.clone(), storage.clone()));
-    let image_service = Arc::new(ImageService::new(image_model));
-    let image_processor = ImageProcessor::new(&config);
+    let image_service = Arc::new(ImageService::new(image_model.clone()));
+    let image_processor = Arc::new(ImageProcessor::new(&config)?);
+    image_processor.spawn_preset_reload();
     let batch_processor = BatchProcessor::new(
-        image_processor,
+        image_processor.clone(),
         image_service.clone(),
         storage.clone(),
     );
 
+    // Private documents: encrypted at rest, never go through the image pipeline.
+    // Without a key they can't be stored at all, so the routes are switched off.
+    let document_service = if config.documents.encryption_key.is_empty() {
+        warn!("documents.encryption_key is not set; the /documents routes are disabled");
+        None
+    } else {
+        Some(Arc::new(DocumentModel::new(db_manager.clone(), storage.clone(), &config.documents)?))
+    };
+
     // Initialize AI services
     let llm_client = LLMClient::new(&config);
     let embedding_service = EmbeddingService::new(llm_client.clone());
@@ -125,6 +136,9 @@ async fn main() -> anyhow::Result<()> {
         key_service,
         email_service,
         batch_processor,
+        image_processor,
+        image_model,
+        document_service,
     )?;
     
     // Build router with all components
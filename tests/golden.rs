@@ -0,0 +1,22 @@
+use std::path::Path;
+
+use f_ai_backend::backend::image_processor::golden::GoldenManifest;
+use f_ai_backend::backend::image_processor::processor::{ContentType, ImageProcessor};
+
+// Renders every case in tests/golden/manifest.toml through decoding and the enhancement pipeline.
+// GOLDEN_BLESS=1 overwrites the approved outputs instead; review the drift report first.
+#[test]
+fn golden_images_match_approved_outputs() {
+    let manifest = GoldenManifest::load(Path::new("tests/golden/manifest.toml")).unwrap();
+    let processor = ImageProcessor::with_defaults().unwrap();
+    let render = |data: &[u8], content_type: ContentType| processor.render_for_regression(data, content_type);
+
+    if std::env::var_os("GOLDEN_BLESS").is_some() {
+        manifest.bless(render).unwrap();
+        return;
+    }
+
+    let report = manifest.run(render).unwrap();
+    assert!(report.passed(), "{}", report);
+    assert!(report.uncovered.is_empty(), "no golden case for {:?}", report.uncovered);
+}